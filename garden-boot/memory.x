@@ -0,0 +1,9 @@
+MEMORY
+{
+  /* Between the Feather M0's own bootloader and the firmware. RAM is kept to
+     the top 4k so the panic record the firmware keeps near the bottom
+     survives a reset through here */
+  FLASH (rx) : ORIGIN = 0x00000000 + 8K, LENGTH = 8K
+  RAM (xrw)  : ORIGIN = 0x20000000 + 28K, LENGTH = 4K
+}
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
@@ -0,0 +1,41 @@
+//! Sits between the Feather's own bootloader and the firmware, swapping in
+//! firmware that came over the air and swapping it back out if it never gets
+//! through to the base station. How is in `garden_core::firmware`.
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::entry;
+use garden_core::firmware::{self, Journal, Layout};
+use garden_flash::Flash;
+
+const LAYOUT: Layout = Layout::SAMD21;
+
+#[entry]
+fn main() -> ! {
+    let p = atsamd21g::Peripherals::take().unwrap();
+    let mut flash = Flash::new(p.NVMCTRL);
+    let mut journal = Journal::open(&flash, LAYOUT);
+
+    // every step is noted down, so starting over carries on where it failed
+    if firmware::boot(&mut flash, &mut journal).is_err() {
+        SCB::sys_reset();
+    }
+
+    unsafe {
+        // nothing's been flashed there yet
+        if core::ptr::read_volatile(LAYOUT.app as *const u32) == 0xffff_ffff {
+            loop {
+                cortex_m::asm::wfi();
+            }
+        }
+
+        (*SCB::PTR).vtor.write(LAYOUT.app);
+        cortex_m::asm::bootload(LAYOUT.app as *const u32)
+    }
+}
+
+#[panic_handler]
+fn on_panic(_info: &core::panic::PanicInfo) -> ! {
+    SCB::sys_reset();
+}
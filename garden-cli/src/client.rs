@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use futures::{SinkExt, StreamExt};
+use garden_shared::{
+    BME688SensorReport, ChannelStats, CrashReport, DeviceConfig, DeviceStatus, Diagnostics,
+    FirmwareProgress, LinkStatus, MoistureSensorReport, PanelMessage, RadioHealth, StatusFlags,
+    UiCommand,
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// How long the base station has to go quiet for after connecting before we
+/// assume it has told us everything it knows
+const SETTLE: Duration = Duration::from_millis(300);
+
+/// What the base station has told us about the device so far
+#[derive(Default)]
+pub struct State {
+    pub status: Option<DeviceStatus>,
+    pub desired: Option<StatusFlags>,
+    pub config: Option<DeviceConfig>,
+    pub diagnostics: Option<Diagnostics>,
+    /// Milliseconds since the unix epoch and the report, oldest first
+    pub crashes: Vec<(u64, CrashReport)>,
+    pub moisture: Option<(u64, MoistureSensorReport)>,
+    pub bme: Option<(u64, BME688SensorReport)>,
+    pub link: Option<LinkStatus>,
+    /// How the base station's own radio is doing
+    pub radio: Option<RadioHealth>,
+    /// Who's been using the channel, if the base station is sniffing
+    pub channel: Option<ChannelStats>,
+    /// The firmware on its way to the device and where the device is with it
+    pub firmware: Option<FirmwareProgress>,
+}
+
+impl State {
+    fn apply(&mut self, msg: &PanelMessage) {
+        match msg {
+            PanelMessage::Hello | PanelMessage::Watering(_) | PanelMessage::RadioReport { .. } => {}
+            PanelMessage::Status(status) => self.status = Some(*status),
+            PanelMessage::DesiredStatus(flags) => self.desired = Some(*flags),
+            PanelMessage::Config(config) => self.config = Some(*config),
+            PanelMessage::Diagnostics(diagnostics) => self.diagnostics = Some(diagnostics.clone()),
+            PanelMessage::CrashReport { at, report } => self.crashes.push((*at, report.clone())),
+            PanelMessage::Moisture { at, report } => self.moisture = Some((*at, report.clone())),
+            PanelMessage::Bme { at, report } => self.bme = Some((*at, report.clone())),
+            PanelMessage::Link(link) => self.link = Some(*link),
+            PanelMessage::Radio(health) => self.radio = Some(*health),
+            PanelMessage::Channel(stats) => self.channel = Some(stats.clone()),
+            PanelMessage::Firmware(progress) => self.firmware = Some(*progress),
+        }
+    }
+}
+
+/// Talks to the base station over the same websocket as the panel
+pub struct Client {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    pub state: State,
+}
+
+impl Client {
+    /// Connect and take in what the base station already knows
+    pub async fn connect(url: &str) -> Result<Self> {
+        let (ws, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(|e| eyre!("Failed to connect to {}: {}", url, e))?;
+
+        let mut client = Self {
+            ws,
+            state: State::default(),
+        };
+
+        while tokio::time::timeout(SETTLE, client.next()).await.is_ok() {}
+
+        Ok(client)
+    }
+
+    pub async fn send(&mut self, cmd: UiCommand) -> Result<()> {
+        self.ws
+            .send(Message::Text(serde_json::to_string(&cmd)?))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Hand firmware to the base station to send on to the device
+    pub async fn upload(&mut self, image: Vec<u8>) -> Result<()> {
+        self.ws.send(Message::Binary(image)).await?;
+
+        Ok(())
+    }
+
+    /// The next message from the base station, which is also applied to
+    /// [`Client::state`]
+    pub async fn next(&mut self) -> Result<PanelMessage> {
+        loop {
+            let msg = self
+                .ws
+                .next()
+                .await
+                .ok_or_else(|| eyre!("The base station hung up"))??;
+
+            if let Message::Text(t) = msg {
+                let msg = serde_json::from_str(&t)?;
+                self.state.apply(&msg);
+                return Ok(msg);
+            }
+        }
+    }
+
+    pub async fn close(mut self) -> Result<()> {
+        self.ws.close(None).await?;
+        Ok(())
+    }
+
+    /// Skip over messages until one matches, giving up after `timeout`
+    pub async fn wait_for<T>(
+        &mut self,
+        timeout: Duration,
+        mut pred: impl FnMut(&PanelMessage) -> Option<T>,
+    ) -> Result<Option<T>> {
+        let found = tokio::time::timeout(timeout, async {
+            loop {
+                if let Some(it) = pred(&self.next().await?) {
+                    return Ok::<_, color_eyre::Report>(it);
+                }
+            }
+        })
+        .await;
+
+        match found {
+            Ok(it) => Ok(Some(it?)),
+            Err(_) => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Turning frames copied out of logs or captures back into something readable
+
+use color_eyre::eyre::{bail, eyre};
+use color_eyre::Result;
+use garden_shared::{Command, Message, Transmission};
+
+pub enum Decoded {
+    Message(Transmission<Message>),
+    Command(Transmission<Command>),
+}
+
+/// Read a frame written as hex (optionally with a `0x` prefix or spaces
+/// between bytes) or base64
+pub fn parse_frame(s: &str) -> Result<Vec<u8>> {
+    let s = s.trim();
+    if s.is_empty() {
+        bail!("Frame is empty");
+    }
+
+    let hex = s.strip_prefix("0x").unwrap_or(s).replace([' ', ':'], "");
+    let is_hex = !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit());
+    let whole_bytes = hex.len().is_multiple_of(2);
+
+    // base64 has no prefix or separators, so this was hex that lost a digit
+    if is_hex && !whole_bytes && hex.len() != s.len() {
+        bail!("Hex frame has an odd number of digits");
+    }
+
+    if is_hex && whole_bytes {
+        return (0..hex.len())
+            .step_by(2)
+            .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+            .collect();
+    }
+
+    base64::decode(s).map_err(|e| eyre!("Frame is neither hex nor base64: {}", e))
+}
+
+/// Decode a frame from the device, or from the base station if `command` is
+/// set. Without it, frames that don't fit a message are tried as a command.
+pub fn decode(frame: &[u8], command: bool) -> Result<Decoded> {
+    fn exact<'a, T: serde::Deserialize<'a>>(frame: &'a [u8]) -> Result<T> {
+        let (it, rest) = postcard::take_from_bytes(frame)?;
+        if !rest.is_empty() {
+            bail!("{} bytes left over", rest.len());
+        }
+        Ok(it)
+    }
+
+    if command {
+        return Ok(Decoded::Command(exact(frame)?));
+    }
+
+    match exact(frame) {
+        Ok(msg) => Ok(Decoded::Message(msg)),
+        Err(msg_err) => match exact(frame) {
+            Ok(cmd) => Ok(Decoded::Command(cmd)),
+            Err(cmd_err) => bail!("Not a message ({}) or a command ({})", msg_err, cmd_err),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use garden_shared::{DevAddr, DeviceStatus, FrameTime, Route, StatusFlags, BASE_ADDR};
+
+    use super::*;
+
+    fn frame<T>(msg: T) -> Transmission<T> {
+        Transmission {
+            src: DevAddr(0x69),
+            route: Route::to(BASE_ADDR),
+            seq: 3,
+            time: FrameTime::now(1_000, false),
+            link: None,
+            msg,
+        }
+    }
+
+    fn status() -> Vec<u8> {
+        let msg = Message::StatusUpdate(DeviceStatus {
+            flags: StatusFlags::PUMP_ON,
+        });
+        postcard::to_stdvec(&frame(msg)).unwrap()
+    }
+
+    #[test]
+    fn parses_hex_however_its_written() {
+        for s in ["0a0bff", "0x0a0bff", "0a 0b ff", "0a:0b:ff", " 0A0BFF\n"] {
+            assert_eq!(parse_frame(s).unwrap(), [0x0a, 0x0b, 0xff], "{}", s);
+        }
+    }
+
+    #[test]
+    fn parses_base64() {
+        assert_eq!(parse_frame("Cgv/").unwrap(), [0x0a, 0x0b, 0xff]);
+    }
+
+    #[test]
+    fn rejects_odd_length_hex() {
+        assert!(parse_frame("0x0a0bf").is_err());
+        assert!(parse_frame("0a 0b f").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_base64() {
+        assert!(parse_frame("not a frame!").is_err());
+        assert!(parse_frame("Cgv/=").is_err());
+        assert!(parse_frame(" ").is_err());
+    }
+
+    #[test]
+    fn decodes_a_message() {
+        match decode(&status(), false).unwrap() {
+            Decoded::Message(t) => {
+                assert_eq!(t.seq, 3);
+                assert!(matches!(
+                    t.msg,
+                    Message::StatusUpdate(DeviceStatus { flags }) if flags == StatusFlags::PUMP_ON
+                ));
+            }
+            Decoded::Command(_) => panic!("decoded as a command"),
+        }
+    }
+
+    #[test]
+    fn decodes_a_command_when_asked() {
+        let bytes = postcard::to_stdvec(&frame(Command::Ack(3))).unwrap();
+        match decode(&bytes, true).unwrap() {
+            Decoded::Command(t) => assert!(matches!(t.msg, Command::Ack(3))),
+            Decoded::Message(_) => panic!("decoded as a message"),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let bytes = status();
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len], false).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = postcard::to_stdvec(&frame(Command::Ack(3))).unwrap();
+        bytes.push(0);
+        assert!(decode(&bytes, true).is_err());
+    }
+}
@@ -0,0 +1,867 @@
+mod client;
+mod decode;
+
+use std::time::Duration;
+
+use chrono::{Local, TimeZone};
+use client::Client;
+use color_eyre::eyre::{bail, eyre};
+use color_eyre::Result;
+use decode::Decoded;
+use garden_shared::{
+    sx127x_register_name, ChannelStats, ConfigField, DeviceConfig, FirmwareProgress, FirmwareState,
+    LinkQuality, PanelMessage, RadioCheck, RadioHealth, RadioReport, RadioState, StatusFlags,
+    UiCommand, WateringConfig, WateringEvent, SX127X_VERSION,
+};
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+const USAGE: &str = "\
+Usage: garden-cli [options] <command>
+
+Commands:
+  status                      What the device is doing and its latest readings
+  tail                        Print events from the device as they happen
+  pump on|off                 Switch the pump and wait for the device to follow
+  valve open|close            Switch the valve and wait for the device to follow
+  reset                       Reset the device and wait for it to come back
+  config                      Show the device's config
+  watering                    Show the automatic watering rules
+  watering enable|disable     Turn automatic watering on or off
+  watering set <key> <value>  Change watering rules, any number of pairs of:
+                                threshold <level>   start watering when drier than this
+                                hysteresis <level>  stop this far below the threshold
+                                max-run <time>      longest a run may last, like 5m
+                                min-interval <time> shortest gap between runs, like 1h
+                                sensors <n,..>      moisture sensors to average, like 0,2
+                                outputs <pump,valve> what to switch on while watering
+  firmware [status]           How sending new firmware to the device is going
+  firmware upload <file>      Send new firmware to the device, a .bin made by
+                              cargo objcopy --release -- -O binary
+  firmware cancel             Stop sending new firmware
+  radio self-test|registers|noise
+                              Check the base station's own radio, read out its
+                              registers or listen for the noise floor
+  decode [--command] <frame>  Decode a hex or base64 frame, as a command from
+                              the base station if --command is given
+
+Options:
+  --base <addr>      Where the base station is [default: 127.0.0.1:3000]
+  --timeout <time>   How long to wait for the device to confirm [default: 2m]
+
+Commands are only sent once the device next gets in touch, which can take
+as long as its status interval. Firmware goes a piece at a time within the
+duty cycle, so it can take the best part of a day.
+";
+
+struct Args {
+    base: String,
+    timeout: Duration,
+    command: Vec<String>,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = Args {
+        base: "127.0.0.1:3000".to_owned(),
+        timeout: Duration::from_secs(120),
+        command: Vec::new(),
+    };
+
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let mut value = || {
+            argv.next()
+                .ok_or_else(|| eyre!("{} needs a value\n\n{}", arg, USAGE))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "--base" => args.base = value()?,
+            "--timeout" => {
+                let value = value()?;
+                args.timeout =
+                    parse_duration(&value).ok_or_else(|| eyre!("Bad duration: {}", value))?;
+            }
+            _ => {
+                args.command.push(arg);
+                args.command.extend(argv);
+                break;
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+/// Parse a duration like `500ms`, `90s`, `5m`, `2h` or `1h30m`
+fn parse_duration(mut s: &str) -> Option<Duration> {
+    let mut total = 0.0;
+
+    while !s.is_empty() {
+        let split = s.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let (value, rest) = s.split_at(split);
+        let value = value.parse::<f64>().ok()?;
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (unit, rest) = rest.split_at(unit_len);
+
+        let scale = match unit {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3_600.0,
+            _ => return None,
+        };
+
+        total += value * scale;
+        s = rest;
+    }
+
+    Some(Duration::from_secs_f64(total))
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, 0) => format!("{}m", m),
+        (0, m, s) => format!("{}m{}s", m, s),
+        (h, 0, 0) => format!("{}h", h),
+        (h, m, _) => format!("{}h{}m", h, m),
+    }
+}
+
+/// Milliseconds since the unix epoch, in local time
+fn format_time(ms: u64) -> String {
+    Local
+        .timestamp_millis_opt(ms as i64)
+        .unwrap()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn format_outputs(flags: StatusFlags) -> String {
+    let mut outputs = Vec::new();
+    if flags.contains(StatusFlags::PUMP_ON) {
+        outputs.push("pump");
+    }
+    if flags.contains(StatusFlags::VALVE_OPEN) {
+        outputs.push("valve");
+    }
+
+    if outputs.is_empty() {
+        "nothing".to_owned()
+    } else {
+        outputs.join(", ")
+    }
+}
+
+/// How to describe an output being on or off
+fn output_state(flag: StatusFlags, on: bool) -> &'static str {
+    match (flag, on) {
+        (StatusFlags::VALVE_OPEN, true) => "open",
+        (StatusFlags::VALVE_OPEN, false) => "closed",
+        (_, true) => "on",
+        (_, false) => "off",
+    }
+}
+
+fn on_off(flags: StatusFlags, flag: StatusFlags) -> &'static str {
+    output_state(flag, flags.contains(flag))
+}
+
+fn print_status(client: &Client) {
+    let state = &client.state;
+
+    match state.status {
+        Some(status) => {
+            println!("Pump:      {}", on_off(status.flags, StatusFlags::PUMP_ON));
+            println!(
+                "Valve:     {}",
+                on_off(status.flags, StatusFlags::VALVE_OPEN)
+            );
+            if status.flags.contains(StatusFlags::AUTO_WATERING) {
+                println!("Watering:  automatic run in progress");
+            }
+        }
+        None => println!("No status from the device yet"),
+    }
+
+    if let Some(desired) = state.desired {
+        println!("Wanted:    {}", format_outputs(desired));
+    }
+
+    if let Some((at, report)) = &state.moisture {
+        let levels = report
+            .moisture
+            .iter()
+            .map(|r| format!("{:.0}", r.per_second()))
+            .collect::<Vec<_>>();
+        println!("Moisture:  {} Hz ({})", levels.join(", "), format_time(*at));
+    }
+
+    if let Some((at, report)) = &state.bme {
+        println!(
+            "Climate:   {:.1}°C, {:.0}% humidity, {:.0} hPa ({})",
+            report.temp.get::<degree_celsius>(),
+            report.humidity.get::<percent>(),
+            report.pressure.get::<hectopascal>(),
+            format_time(*at)
+        );
+    }
+
+    if let Some(health) = state.radio {
+        println!("Radio:     {}", format_radio(health));
+    }
+
+    if let Some(stats) = &state.channel {
+        println!("Air:       {}", format_channel(stats));
+    }
+
+    if let Some(link) = state.link {
+        println!(
+            "Uplink:    {}\nDownlink:  {}",
+            format_link(link.uplink),
+            format_link(link.downlink)
+        );
+    }
+
+    if let Some(diagnostics) = &state.diagnostics {
+        println!(
+            "Firmware:  {} ({}), up {} since {:?} reset",
+            diagnostics.version,
+            diagnostics.git_hash,
+            format_duration(diagnostics.uptime),
+            diagnostics.reset_cause
+        );
+        println!(
+            "Errors:    {} BME failures, {} dropped messages, {} radio errors, {} link fallbacks",
+            diagnostics.bme_failures,
+            diagnostics.dropped_messages,
+            diagnostics.radio_errors,
+            diagnostics.link_fallbacks
+        );
+        println!(
+            "Airtime:   {:.1}s in the last hour, {} messages held back",
+            diagnostics.airtime.as_secs_f32(),
+            diagnostics.airtime_deferred
+        );
+        println!(
+            "Channel:   {} waited for a busy channel, {} likely collided",
+            diagnostics.channel_deferred, diagnostics.channel_collisions
+        );
+    }
+
+    if let Some(progress) = &state.firmware {
+        println!("Update:    {}", format_firmware(progress));
+    }
+
+    if let Some((at, report)) = state.crashes.last() {
+        println!(
+            "Crashed:   {} at {}:{} ({})",
+            report.message,
+            report.location.file,
+            report.location.line,
+            format_time(*at)
+        );
+    }
+}
+
+fn format_link(quality: Option<LinkQuality>) -> String {
+    match quality {
+        Some(q) => format!("{} dBm, SNR {} dB", q.rssi, q.snr),
+        None => "unknown".to_owned(),
+    }
+}
+
+fn format_radio(health: RadioHealth) -> String {
+    let state = match health.state {
+        RadioState::Starting => "starting",
+        RadioState::Up => "up",
+        RadioState::Recovering => "recovering",
+        RadioState::Failed => "failed",
+    };
+
+    format!(
+        "{}, {} restarts, {} errors",
+        state, health.restarts, health.errors
+    )
+}
+
+fn format_channel(stats: &ChannelStats) -> String {
+    let mut neighbours = stats
+        .neighbours
+        .iter()
+        .map(|n| format!("{:#x}", n.src.0))
+        .collect::<Vec<_>>();
+    if neighbours.is_empty() {
+        neighbours.push("none".to_owned());
+    }
+
+    format!(
+        "{:.2}% busy over the last {}, {} foreign and {} undecodable frames, neighbours: {}",
+        stats.utilisation(),
+        format_duration(stats.window),
+        stats.foreign.frames,
+        stats.undecodable.frames,
+        neighbours.join(", ")
+    )
+}
+
+/// Firmware that wasn't sent over the air has no image id
+fn format_image(image: u32) -> String {
+    match image {
+        0 => "flashed firmware".to_owned(),
+        image => format!("{:08x}", image),
+    }
+}
+
+fn format_firmware(progress: &FirmwareProgress) -> String {
+    if let Some(upload) = progress.upload {
+        return format!(
+            "sending {:08x}, {} of {} bytes",
+            upload.image,
+            progress.received().unwrap_or(0),
+            upload.size
+        );
+    }
+
+    let status = match progress.device {
+        Some(status) => status,
+        None => return "nothing sent".to_owned(),
+    };
+    let image = format_image(status.image);
+
+    match status.state {
+        FirmwareState::Idle => format!("running {}", image),
+        FirmwareState::Receiving { next } => {
+            format!("stopped sending {} after {} pieces", image, next)
+        }
+        FirmwareState::Verified => format!("{} arrived, the device is resetting into it", image),
+        FirmwareState::Trial => format!(
+            "trying out {}, it's kept once it gets through to the base station",
+            image
+        ),
+        FirmwareState::RolledBack => format!("new firmware failed, rolled back to {}", image),
+        FirmwareState::Failed(error) => format!("the device gave up on {}: {:?}", image, error),
+    }
+}
+
+fn summarise_radio_report(report: &RadioReport) -> String {
+    match report {
+        RadioReport::Registers(registers) => format!("read {} registers", registers.len()),
+        RadioReport::SelfTest(test) if test.passed() => "self-test passed".to_owned(),
+        RadioReport::SelfTest(_) => "self-test FAILED".to_owned(),
+        RadioReport::NoiseFloor { floor, .. } => format!("noise floor {} dBm", floor),
+        RadioReport::Failed { check, reason } => format!("{:?} failed: {}", check, reason),
+    }
+}
+
+fn print_radio_report(report: &RadioReport) {
+    let pass = |ok: bool| if ok { "pass" } else { "FAIL" };
+
+    match report {
+        RadioReport::Registers(registers) => {
+            for r in registers {
+                println!(
+                    "{:#04x} {:<24} {:#04x} {:08b}",
+                    r.addr,
+                    sx127x_register_name(r.addr).unwrap_or("?"),
+                    r.value,
+                    r.value
+                );
+            }
+        }
+        RadioReport::SelfTest(test) => {
+            println!(
+                "Chip version:       {:#04x} (expected {:#04x})",
+                test.version, SX127X_VERSION
+            );
+            println!("Register read back: {}", pass(test.spi));
+            println!("Mode changes:       {}", pass(test.modes));
+            println!("FIFO loopback:      {}", pass(test.fifo));
+        }
+        RadioReport::NoiseFloor { floor, samples } => {
+            for s in samples {
+                println!(
+                    "{:>9.3} MHz  {:>4} dBm  peak {:>4} dBm",
+                    s.frequency as f32 / 1_000_000.0,
+                    s.rssi,
+                    s.peak
+                );
+            }
+            println!("Noise floor:        {} dBm", floor);
+        }
+        RadioReport::Failed { .. } => {}
+    }
+}
+
+fn print_config(config: &DeviceConfig) {
+    println!("Address:              {}", config.address.0);
+    println!("Role:                 {:?}", config.role);
+    println!(
+        "Measurement interval: {}",
+        format_duration(config.measurement_interval)
+    );
+    println!(
+        "BME interval:         {}",
+        format_duration(config.bme_interval)
+    );
+    println!(
+        "Status interval:      {}",
+        format_duration(config.status_interval)
+    );
+    println!("Temperature offset:   {}°C", config.temperature_offset);
+    println!(
+        "Reset interval:       {}",
+        config
+            .reset_interval
+            .map(format_duration)
+            .unwrap_or_else(|| "never".to_owned())
+    );
+    println!(
+        "Radio:                {} Hz, SF{}, {:?}, {:?}, {} dBm",
+        config.radio.frequency,
+        config.radio.spreading_factor,
+        config.radio.bandwidth,
+        config.radio.coding_rate,
+        config.radio.tx_power
+    );
+    println!();
+    print_watering(&config.watering);
+}
+
+fn print_watering(watering: &WateringConfig) {
+    let sensors = (0..8)
+        .filter(|n| watering.sensors & (1 << n) != 0)
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>();
+
+    println!(
+        "Automatic watering:   {}",
+        if watering.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    println!(
+        "Starts when drier than {:.0} Hz and stops below {:.0} Hz, averaging sensors {}",
+        watering.dry_threshold,
+        watering.dry_threshold - watering.hysteresis,
+        sensors.join(", ")
+    );
+    println!(
+        "Runs {} for at most {}, at least {} apart",
+        format_outputs(watering.outputs),
+        format_duration(watering.max_run_time),
+        format_duration(watering.min_interval)
+    );
+}
+
+fn print_event(msg: &PanelMessage) {
+    let now = Local::now().format("%H:%M:%S");
+
+    match msg {
+        PanelMessage::Hello | PanelMessage::DesiredStatus(_) => {}
+        PanelMessage::Status(status) => println!(
+            "{} status: pump {}, valve {}{}",
+            now,
+            on_off(status.flags, StatusFlags::PUMP_ON),
+            on_off(status.flags, StatusFlags::VALVE_OPEN),
+            if status.flags.contains(StatusFlags::AUTO_WATERING) {
+                " (watering)"
+            } else {
+                ""
+            }
+        ),
+        PanelMessage::Watering(WateringEvent::Started { level }) => {
+            println!("{} watering started at {:.0} Hz", now, level)
+        }
+        PanelMessage::Watering(WateringEvent::Stopped { reason, .. }) => {
+            println!("{} watering stopped: {:?}", now, reason)
+        }
+        PanelMessage::Config(_) => println!("{} config updated", now),
+        PanelMessage::Diagnostics(d) => println!(
+            "{} diagnostics: up {}, {:?} reset",
+            now,
+            format_duration(d.uptime),
+            d.reset_cause
+        ),
+        PanelMessage::CrashReport { report, .. } => println!(
+            "{} crashed: {} at {}:{}",
+            now, report.message, report.location.file, report.location.line
+        ),
+        PanelMessage::Moisture { report, .. } => {
+            let levels = report
+                .moisture
+                .iter()
+                .map(|r| format!("{:.0}", r.per_second()))
+                .collect::<Vec<_>>();
+            println!("{} moisture: {} Hz", now, levels.join(", "))
+        }
+        PanelMessage::Link(link) => println!(
+            "{} link: uplink {}, downlink {}",
+            now,
+            format_link(link.uplink),
+            format_link(link.downlink)
+        ),
+        PanelMessage::Radio(health) => println!("{} radio: {}", now, format_radio(*health)),
+        PanelMessage::Channel(stats) => println!("{} channel: {}", now, format_channel(stats)),
+        PanelMessage::RadioReport { report, .. } => {
+            println!("{} radio check: {}", now, summarise_radio_report(report))
+        }
+        PanelMessage::Firmware(progress) => {
+            println!("{} firmware: {}", now, format_firmware(progress))
+        }
+        PanelMessage::Bme { report, .. } => println!(
+            "{} climate: {:.1}°C, {:.0}%, {:.0} hPa",
+            now,
+            report.temp.get::<degree_celsius>(),
+            report.humidity.get::<percent>(),
+            report.pressure.get::<hectopascal>()
+        ),
+    }
+}
+
+/// Switch an output and wait for the device to report it switched
+async fn set_output(
+    client: &mut Client,
+    timeout: Duration,
+    flag: StatusFlags,
+    on: bool,
+    what: &str,
+) -> Result<()> {
+    let cmd = match (flag, on) {
+        (StatusFlags::PUMP_ON, true) => UiCommand::PumpOn,
+        (StatusFlags::PUMP_ON, false) => UiCommand::PumpOff,
+        (_, true) => UiCommand::ValveOpen,
+        (_, false) => UiCommand::ValveClose,
+    };
+    client.send(cmd).await?;
+
+    let auto_watering = client
+        .state
+        .status
+        .is_some_and(|s| s.flags.contains(StatusFlags::AUTO_WATERING));
+    if auto_watering {
+        println!("The device is watering on its own, it'll follow once it's done");
+    }
+
+    println!("Waiting for the {} to be {}", what, output_state(flag, on));
+
+    let confirmed = client
+        .wait_for(timeout, |msg| match msg {
+            PanelMessage::Status(s) if s.flags.contains(flag) == on => Some(()),
+            _ => None,
+        })
+        .await?;
+
+    match confirmed {
+        Some(()) => {
+            println!("The {} is {}", what, output_state(flag, on));
+            Ok(())
+        }
+        None => bail!(
+            "The device hasn't confirmed yet, it'll switch the {} when it next gets in touch",
+            what
+        ),
+    }
+}
+
+async fn reset(client: &mut Client, timeout: Duration) -> Result<()> {
+    // uptime only goes backwards if the device rebooted, diagnostics are sent
+    // at the same point after every boot
+    let uptime = client.state.diagnostics.as_ref().map(|d| d.uptime);
+
+    client.send(UiCommand::Reset).await?;
+    println!("Waiting for the device to reset");
+
+    let back = client
+        .wait_for(timeout, |msg| match msg {
+            PanelMessage::Diagnostics(d) if uptime.is_none_or(|u| d.uptime <= u) => {
+                Some(d.reset_cause)
+            }
+            _ => None,
+        })
+        .await?;
+
+    match back {
+        Some(cause) => {
+            println!("The device is back ({:?} reset)", cause);
+            Ok(())
+        }
+        None => bail!("The device hasn't come back yet, it'll reset when it next gets in touch"),
+    }
+}
+
+/// The device's config, asking for it if the base station doesn't know it
+async fn device_config(client: &mut Client, timeout: Duration) -> Result<DeviceConfig> {
+    if let Some(config) = client.state.config {
+        return Ok(config);
+    }
+
+    client.send(UiCommand::RequestConfig).await?;
+    println!("Asking the device for its config");
+
+    client
+        .wait_for(timeout, |msg| match msg {
+            PanelMessage::Config(config) => Some(*config),
+            _ => None,
+        })
+        .await?
+        .ok_or_else(|| eyre!("The device didn't send its config"))
+}
+
+fn update_watering(mut watering: WateringConfig, pairs: &[String]) -> Result<WateringConfig> {
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        bail!("watering set needs pairs of <key> <value>\n\n{}", USAGE);
+    }
+
+    for pair in pairs.chunks(2) {
+        let (key, value) = (pair[0].as_str(), pair[1].as_str());
+        let duration = || parse_duration(value).ok_or_else(|| eyre!("Bad duration: {}", value));
+
+        match key {
+            "threshold" => watering.dry_threshold = value.parse()?,
+            "hysteresis" => watering.hysteresis = value.parse()?,
+            "max-run" => watering.max_run_time = duration()?,
+            "min-interval" => watering.min_interval = duration()?,
+            "sensors" => {
+                watering.sensors = 0;
+                for n in value.split(',') {
+                    let n: u8 = n.trim().parse()?;
+                    if n >= 8 {
+                        bail!("There are only sensors 0 to 7");
+                    }
+                    watering.sensors |= 1 << n;
+                }
+            }
+            "outputs" => {
+                watering.outputs = StatusFlags::empty();
+                for output in value.split(',') {
+                    watering.outputs |= match output.trim() {
+                        "pump" => StatusFlags::PUMP_ON,
+                        "valve" => StatusFlags::VALVE_OPEN,
+                        _ => bail!("Unknown output {}, expected pump or valve", output),
+                    };
+                }
+            }
+            _ => bail!("Unknown watering setting {}\n\n{}", key, USAGE),
+        }
+    }
+
+    Ok(watering)
+}
+
+async fn watering(client: &mut Client, timeout: Duration, args: &[String]) -> Result<()> {
+    let current = device_config(client, timeout).await?.watering;
+
+    let wanted = match args.first().map(String::as_str) {
+        None | Some("show") => {
+            print_watering(&current);
+            return Ok(());
+        }
+        Some("enable") => WateringConfig {
+            enabled: true,
+            ..current
+        },
+        Some("disable") => WateringConfig {
+            enabled: false,
+            ..current
+        },
+        Some("set") => update_watering(current, &args[1..])?,
+        Some(other) => bail!("Unknown watering command {}\n\n{}", other, USAGE),
+    };
+
+    if wanted == current {
+        println!("Nothing to change");
+        return Ok(());
+    }
+
+    client
+        .send(UiCommand::SetConfig(ConfigField::Watering(wanted)))
+        .await?;
+    println!("Waiting for the device to take the new rules");
+
+    // the device answers with its whole config, which won't match if it
+    // turned the change down
+    let confirmed = client
+        .wait_for(timeout, |msg| match msg {
+            PanelMessage::Config(config) => Some(config.watering),
+            _ => None,
+        })
+        .await?;
+
+    match confirmed {
+        Some(watering) if watering == wanted => {
+            print_watering(&watering);
+            Ok(())
+        }
+        Some(_) => bail!("The device turned the change down"),
+        None => {
+            bail!("The device hasn't confirmed yet, it'll be updated when it next gets in touch")
+        }
+    }
+}
+
+/// Run a check on the base station's radio and print what it found
+async fn check_radio(client: &mut Client, timeout: Duration, check: RadioCheck) -> Result<()> {
+    client.send(UiCommand::CheckRadio(check)).await?;
+
+    let report = client
+        .wait_for(timeout, |msg| match msg {
+            PanelMessage::RadioReport { report, .. } if report.check() == check => {
+                Some(report.clone())
+            }
+            _ => None,
+        })
+        .await?
+        .ok_or_else(|| eyre!("The base station didn't get round to checking its radio"))?;
+
+    print_radio_report(&report);
+    match report {
+        RadioReport::Failed { reason, .. } => bail!("{}", reason),
+        RadioReport::SelfTest(test) if !test.passed() => bail!("The radio failed its self-test"),
+        _ => Ok(()),
+    }
+}
+
+async fn firmware(client: &mut Client, timeout: Duration, args: &[String]) -> Result<()> {
+    match args {
+        [] => {}
+        [cmd] if cmd == "status" => {}
+        [cmd] if cmd == "cancel" => {
+            client.send(UiCommand::CancelFirmware).await?;
+            client
+                .wait_for(timeout, |msg| {
+                    matches!(msg, PanelMessage::Firmware(p) if p.upload.is_none()).then(|| ())
+                })
+                .await?
+                .ok_or_else(|| eyre!("The base station didn't stop sending"))?;
+        }
+        [cmd, path] if cmd == "upload" => {
+            let image = std::fs::read(path).map_err(|e| eyre!("Failed to read {}: {}", path, e))?;
+            let size = image.len() as u32;
+            client.upload(image).await?;
+
+            // the base station answers either way, without an upload if it
+            // turned the image down
+            let accepted = client
+                .wait_for(timeout, |msg| match msg {
+                    PanelMessage::Firmware(p) => Some(p.upload.filter(|u| u.size == size)),
+                    _ => None,
+                })
+                .await?
+                .ok_or_else(|| eyre!("The base station didn't answer"))?;
+
+            match accepted {
+                Some(upload) => println!(
+                    "Sending {:08x} to the device, a piece each time it gets in touch",
+                    upload.image
+                ),
+                None => bail!(
+                    "The base station turned {} down, is it firmware for the device?",
+                    path
+                ),
+            }
+        }
+        _ => bail!("Unknown firmware command {}\n\n{}", args.join(" "), USAGE),
+    }
+
+    match &client.state.firmware {
+        Some(progress) => println!("{}", format_firmware(progress)),
+        None => println!("Nothing's been heard about the device's firmware yet"),
+    }
+    Ok(())
+}
+
+fn decode(args: &[String]) -> Result<()> {
+    let (command, frame) = match args {
+        [flag, frame] if flag == "--command" => (true, frame),
+        [frame] => (false, frame),
+        _ => bail!("decode needs a frame\n\n{}", USAGE),
+    };
+
+    let frame = decode::parse_frame(frame)?;
+    match decode::decode(&frame, command)? {
+        Decoded::Message(msg) => println!("Transmission<Message> {:#?}", msg),
+        Decoded::Command(cmd) => println!("Transmission<Command> {:#?}", cmd),
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let args = parse_args()?;
+    let (command, rest) = args
+        .command
+        .split_first()
+        .ok_or_else(|| eyre!("No command given\n\n{}", USAGE))?;
+
+    // doesn't need the base station
+    if command == "decode" {
+        return decode(rest);
+    }
+
+    let url = if args.base.contains("://") {
+        args.base.clone()
+    } else {
+        format!("ws://{}/ws", args.base)
+    };
+    let mut client = Client::connect(&url).await?;
+
+    let result = match (command.as_str(), rest) {
+        ("status", []) => {
+            print_status(&client);
+            Ok(())
+        }
+        ("tail", []) => loop {
+            print_event(&client.next().await?);
+        },
+        ("pump", [state]) if state == "on" || state == "off" => {
+            let on = state == "on";
+            set_output(&mut client, args.timeout, StatusFlags::PUMP_ON, on, "pump").await
+        }
+        ("valve", [state]) if state == "open" || state == "close" => {
+            let open = state == "open";
+            set_output(
+                &mut client,
+                args.timeout,
+                StatusFlags::VALVE_OPEN,
+                open,
+                "valve",
+            )
+            .await
+        }
+        ("reset", []) => reset(&mut client, args.timeout).await,
+        ("config", []) => device_config(&mut client, args.timeout)
+            .await
+            .map(|config| print_config(&config)),
+        ("watering", rest) => watering(&mut client, args.timeout, rest).await,
+        ("firmware", rest) => firmware(&mut client, args.timeout, rest).await,
+        ("radio", [check]) => match check.as_str() {
+            "self-test" => check_radio(&mut client, args.timeout, RadioCheck::SelfTest).await,
+            "registers" => check_radio(&mut client, args.timeout, RadioCheck::Registers).await,
+            "noise" => check_radio(&mut client, args.timeout, RadioCheck::NoiseFloor).await,
+            _ => Err(eyre!("Unknown radio check {}\n\n{}", check, USAGE)),
+        },
+        _ => Err(eyre!(
+            "Unknown command {}\n\n{}",
+            args.command.join(" "),
+            USAGE
+        )),
+    };
+
+    client.close().await?;
+    result
+}
@@ -0,0 +1,238 @@
+use core::time::Duration;
+
+use garden_shared::{
+    Command, LoRaBandwidth, LoRaCodingRate, Message, RadioConfig, PAYLOAD_CRC, PREAMBLE_LEN,
+};
+
+/// The window airtime is counted over, split into minutes
+const MINUTES: usize = 60;
+const MINUTE_MS: u64 = 60 * 1000;
+/// Percentage of the budget low priority traffic can use, the rest is kept
+/// for traffic that can't wait
+const LOW_PRIORITY_SHARE: u64 = 80;
+
+/// How long a LoRa frame with `payload_len` bytes spends on the air, from the
+/// formula in the SX127x datasheet. Frames always have an explicit header.
+pub fn time_on_air(radio: &RadioConfig, payload_len: usize) -> Duration {
+    let sf = radio.spreading_factor as i64;
+    let bw: i64 = match radio.bandwidth {
+        LoRaBandwidth::Bw125kHz => 125_000,
+        LoRaBandwidth::Bw250kHz => 250_000,
+        LoRaBandwidth::Bw500kHz => 500_000,
+    };
+    let cr: i64 = match radio.coding_rate {
+        LoRaCodingRate::Cr4_5 => 1,
+        LoRaCodingRate::Cr4_6 => 2,
+        LoRaCodingRate::Cr4_7 => 3,
+        LoRaCodingRate::Cr4_8 => 4,
+    };
+
+    // exact in microseconds for all the bandwidths we support
+    let symbol_us = (1i64 << sf) * 1_000_000 / bw;
+    // the radio turns on low data rate optimisation past 16ms symbols
+    let de = (symbol_us > 16_000) as i64;
+    let crc = PAYLOAD_CRC as i64;
+
+    let preamble_us = (PREAMBLE_LEN as i64 * 4 + 17) * symbol_us / 4;
+
+    let bits = 8 * payload_len as i64 - 4 * sf + 28 + 16 * crc;
+    let per_block = 4 * (sf - 2 * de);
+    let blocks = (bits.max(0) + per_block - 1) / per_block;
+    let payload_symbols = 8 + blocks * (cr + 4);
+
+    Duration::from_micros((preamble_us + payload_symbols * symbol_us) as u64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Can be put off or lost without anyone minding much
+    Low,
+    /// Someone is waiting on it, or it's the last word before trouble
+    High,
+}
+
+/// How much a message from the device matters when airtime runs short
+pub fn message_priority(msg: &Message) -> Priority {
+    match msg {
+        Message::Watering(_) | Message::Config(_) | Message::CrashReport(_) => Priority::High,
+        Message::MoistureReport(_)
+        | Message::BME688Report(_)
+        | Message::StatusUpdate(_)
+        | Message::Diagnostics(_)
+        | Message::Firmware(_) => Priority::Low,
+    }
+}
+
+/// How much a command from the base station matters when airtime runs short.
+/// Acks are high priority, a missed one costs a replay from the device.
+pub fn command_priority(cmd: &Command) -> Priority {
+    match cmd {
+        Command::Ack(_)
+        | Command::SyncFlags(_)
+        | Command::Reset
+        | Command::SetConfig(_)
+        | Command::GetConfig
+        | Command::FirmwareCancel => Priority::High,
+        // firmware takes hours to send at the best of times, it's not going
+        // to crowd out anything else
+        Command::SetTime(_)
+        | Command::SetLink(_)
+        | Command::FirmwareManifest(_)
+        | Command::FirmwareChunk(_) => Priority::Low,
+    }
+}
+
+/// Keeps count of the airtime used over the last hour, to stay within the
+/// duty cycle of the band. Time is in milliseconds on whatever clock the
+/// caller likes, as long as it only goes forwards.
+pub struct Airtime {
+    /// Airtime in microseconds used in each minute, indexed by minute
+    minutes: [u32; MINUTES],
+    /// The minute last recorded in
+    current: u64,
+    /// Allowed airtime per hour, if the band limits it
+    budget: Option<Duration>,
+}
+
+impl Airtime {
+    pub const fn new(budget: Option<Duration>) -> Self {
+        Self {
+            minutes: [0; MINUTES],
+            current: 0,
+            budget,
+        }
+    }
+
+    pub fn budget(&self) -> Option<Duration> {
+        self.budget
+    }
+
+    /// Forget the minutes that have fallen out of the window
+    fn advance(&mut self, now_ms: u64) {
+        let minute = now_ms / MINUTE_MS;
+        if minute <= self.current {
+            return;
+        }
+
+        let stale = (minute - self.current).min(MINUTES as u64);
+        for m in 1..=stale {
+            self.minutes[((self.current + m) % MINUTES as u64) as usize] = 0;
+        }
+        self.current = minute;
+    }
+
+    pub fn record(&mut self, now_ms: u64, airtime: Duration) {
+        self.advance(now_ms);
+
+        let slot = &mut self.minutes[(self.current % MINUTES as u64) as usize];
+        *slot = slot.saturating_add(airtime.as_micros() as u32);
+    }
+
+    /// Airtime used over the last hour
+    pub fn used(&mut self, now_ms: u64) -> Duration {
+        self.advance(now_ms);
+
+        Duration::from_micros(self.minutes.iter().map(|&us| us as u64).sum())
+    }
+
+    /// Whether a frame taking `airtime` fits in what's left of the budget.
+    /// Low priority frames only get a share of it.
+    pub fn allows(&mut self, now_ms: u64, airtime: Duration, priority: Priority) -> bool {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return true,
+        };
+
+        let budget = match priority {
+            Priority::High => budget,
+            Priority::Low => budget * LOW_PRIORITY_SHARE as u32 / 100,
+        };
+
+        self.used(now_ms) + airtime <= budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use garden_shared::EU868;
+
+    use super::*;
+
+    fn radio(spreading_factor: u8) -> RadioConfig {
+        RadioConfig {
+            spreading_factor,
+            ..EU868.radio
+        }
+    }
+
+    #[test]
+    fn time_on_air_matches_the_datasheet() {
+        // the usual figure for 10 bytes at SF7, 125kHz and CR4/5
+        let fast = RadioConfig {
+            coding_rate: LoRaCodingRate::Cr4_5,
+            ..radio(7)
+        };
+        assert_eq!(time_on_air(&fast, 10).as_micros(), 41_216);
+
+        assert_eq!(time_on_air(&radio(7), 10).as_micros(), 53_504);
+        assert_eq!(time_on_air(&radio(7), 50).as_micros(), 143_616);
+        // low data rate optimisation kicks in
+        assert_eq!(time_on_air(&radio(12), 10).as_micros(), 1_187_840);
+    }
+
+    #[test]
+    fn more_bandwidth_is_quicker() {
+        let wide = RadioConfig {
+            bandwidth: LoRaBandwidth::Bw500kHz,
+            ..radio(7)
+        };
+
+        assert_eq!(time_on_air(&wide, 10) * 4, time_on_air(&radio(7), 10));
+    }
+
+    #[test]
+    fn counts_the_last_hour() {
+        let mut airtime = Airtime::new(None);
+        let frame = Duration::from_millis(100);
+
+        airtime.record(0, frame);
+        airtime.record(30 * MINUTE_MS, frame);
+        assert_eq!(airtime.used(30 * MINUTE_MS), frame * 2);
+
+        // the first frame drops out once it's an hour old
+        assert_eq!(airtime.used(60 * MINUTE_MS), frame);
+        assert_eq!(airtime.used(91 * MINUTE_MS), Duration::ZERO);
+
+        // and a long gap doesn't leave anything behind
+        airtime.record(91 * MINUTE_MS, frame);
+        assert_eq!(airtime.used(10 * 60 * MINUTE_MS), Duration::ZERO);
+    }
+
+    #[test]
+    fn keeps_some_budget_back_for_high_priority() {
+        let mut airtime = Airtime::new(Some(Duration::from_secs(10)));
+        let frame = Duration::from_secs(1);
+
+        for _ in 0..8 {
+            assert!(airtime.allows(0, frame, Priority::Low));
+            airtime.record(0, frame);
+        }
+
+        assert!(!airtime.allows(0, frame, Priority::Low));
+        assert!(airtime.allows(0, frame, Priority::High));
+        airtime.record(0, frame);
+        airtime.record(0, frame);
+        assert!(!airtime.allows(0, frame, Priority::High));
+
+        // room again an hour later
+        assert!(airtime.allows(60 * MINUTE_MS, frame, Priority::Low));
+    }
+
+    #[test]
+    fn no_budget_no_limit() {
+        let mut airtime = Airtime::new(None);
+        airtime.record(0, Duration::from_secs(3600));
+
+        assert!(airtime.allows(0, Duration::from_secs(1), Priority::Low));
+    }
+}
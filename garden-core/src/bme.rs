@@ -0,0 +1,217 @@
+use drogue_bme680::{
+    Bme680Controller, Bme680Sensor, Configuration, DelayMsWrapper, StaticProvider,
+};
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use garden_shared::BME688SensorReport;
+use uom::si::electrical_resistance::ohm;
+use uom::si::f32::ElectricalResistance;
+use uom::si::f32::{Pressure, Ratio, ThermodynamicTemperature};
+use uom::si::pressure::pascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+/// How many failed readings in a row before we give up and reset
+const MAX_CONSECUTIVE_FAILURES: u8 = 4;
+
+#[derive(Debug)]
+pub struct InitError;
+
+pub struct Bme688<I2C, D>
+where
+    I2C: WriteRead + Write,
+    <I2C as WriteRead>::Error: core::fmt::Debug,
+    <I2C as Write>::Error: core::fmt::Debug,
+    D: DelayMs<u16>,
+{
+    bme: Bme680Controller<I2C, DelayMsWrapper<D>, StaticProvider>,
+}
+
+impl<I2C, D> Bme688<I2C, D>
+where
+    I2C: WriteRead + Write,
+    <I2C as WriteRead>::Error: core::fmt::Debug,
+    <I2C as Write>::Error: core::fmt::Debug,
+    D: DelayMs<u16>,
+{
+    pub fn new(i2c: I2C, delay: D) -> Result<Self, InitError> {
+        let bme =
+            Bme680Sensor::from(i2c, drogue_bme680::Address::Primary).map_err(|_| InitError)?;
+
+        let mut config = Configuration::standard();
+        config.run_gas = false;
+
+        let bme =
+            Bme680Controller::new(bme, DelayMsWrapper::new(delay), config, StaticProvider(14))
+                .map_err(|_| InitError)?;
+
+        Ok(Self { bme })
+    }
+
+    /// Take a reading, this doesn't check that it makes any sense
+    pub fn measure(&mut self, temperature_offset: f32) -> Option<BME688SensorReport> {
+        let result = self.bme.measure_default().ok()??;
+
+        Some(BME688SensorReport {
+            temp: ThermodynamicTemperature::new::<degree_celsius>(
+                result.temperature + temperature_offset,
+            ),
+            pressure: Pressure::new::<pascal>(result.pressure.unwrap_or(0.0)),
+            humidity: Ratio::new::<percent>(result.humidity),
+            gas_resistance: ElectricalResistance::new::<ohm>(result.gas_resistance),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Reading(BME688SensorReport),
+    /// The reading failed or didn't look right
+    Failed,
+    /// Too many readings in a row have failed, the sensor probably needs a
+    /// reset to come back
+    Reset,
+}
+
+/// Checks readings against the last good one and keeps track of failures
+pub struct BmeMonitor {
+    last: Option<BME688SensorReport>,
+    consecutive_failures: u8,
+}
+
+impl Default for BmeMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BmeMonitor {
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            consecutive_failures: 0,
+        }
+    }
+
+    pub fn on_reading(&mut self, reading: Option<BME688SensorReport>) -> Outcome {
+        let checked = reading.and_then(|r| r.sanity_check(self.last.as_ref()).ok());
+
+        match checked {
+            Some(report) => {
+                self.consecutive_failures = 0;
+                self.last = Some(report.clone());
+                Outcome::Reading(report)
+            }
+            None => {
+                self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+                if self.consecutive_failures > MAX_CONSECUTIVE_FAILURES {
+                    Outcome::Reset
+                } else {
+                    Outcome::Failed
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{FakeBme, NoDelay};
+
+    fn report(temp: f32) -> BME688SensorReport {
+        BME688SensorReport {
+            temp: ThermodynamicTemperature::new::<degree_celsius>(temp),
+            pressure: Pressure::new::<pascal>(101_325.0),
+            humidity: Ratio::new::<percent>(50.0),
+            gas_resistance: ElectricalResistance::new::<ohm>(0.0),
+        }
+    }
+
+    #[test]
+    fn reads_through_i2c() {
+        let fake = FakeBme::new();
+        let mut bme = Bme688::new(fake.clone(), NoDelay).unwrap();
+
+        // the fake's calibration is all zeroes, so only the offset survives
+        let reading = bme.measure(-5.0).unwrap();
+        assert_eq!(reading.temp.get::<degree_celsius>(), -5.0);
+        assert!(fake.was_triggered());
+    }
+
+    #[test]
+    fn rejects_the_wrong_chip() {
+        let fake = FakeBme::new();
+        fake.set_register(0xD0, 0x60);
+
+        assert!(Bme688::new(fake, NoDelay).is_err());
+    }
+
+    #[test]
+    fn bus_errors_fail_the_reading() {
+        let fake = FakeBme::new();
+        let mut bme = Bme688::new(fake.clone(), NoDelay).unwrap();
+
+        fake.set_failing(true);
+        assert!(bme.measure(0.0).is_none());
+
+        fake.set_failing(false);
+        assert!(bme.measure(0.0).is_some());
+    }
+
+    #[test]
+    fn no_new_data_fails_the_reading() {
+        let fake = FakeBme::new();
+        let mut bme = Bme688::new(fake.clone(), NoDelay).unwrap();
+
+        fake.set_register(0x1D, 0);
+        assert!(bme.measure(0.0).is_none());
+    }
+
+    #[test]
+    fn rejects_implausible_readings() {
+        let mut monitor = BmeMonitor::new();
+
+        assert!(matches!(
+            monitor.on_reading(Some(report(20.0))),
+            Outcome::Reading(_)
+        ));
+        assert!(matches!(
+            monitor.on_reading(Some(report(90.0))),
+            Outcome::Failed
+        ));
+        // too big a jump from the last good reading
+        assert!(matches!(
+            monitor.on_reading(Some(report(45.0))),
+            Outcome::Failed
+        ));
+        assert!(matches!(
+            monitor.on_reading(Some(report(22.0))),
+            Outcome::Reading(_)
+        ));
+    }
+
+    #[test]
+    fn resets_after_repeated_failures() {
+        let mut monitor = BmeMonitor::new();
+
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            assert!(matches!(monitor.on_reading(None), Outcome::Failed));
+        }
+        assert!(matches!(monitor.on_reading(None), Outcome::Reset));
+    }
+
+    #[test]
+    fn good_readings_clear_the_failures() {
+        let mut monitor = BmeMonitor::new();
+
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            monitor.on_reading(None);
+        }
+        monitor.on_reading(Some(report(20.0)));
+
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            assert!(matches!(monitor.on_reading(None), Outcome::Failed));
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::time::Instant;
+
+/// Ticks per second of the RTC monotonic
+const TICK_HZ: u64 = 32_768;
+
+/// Keeps track of uptime past the point where the 32 bit RTC wraps (a bit
+/// over 36 hours), and of the wall clock once the base station has told us
+/// the time.
+pub struct Clock {
+    last_ticks: u32,
+    uptime_ticks: u64,
+    /// Unix time in milliseconds at boot
+    boot_unix_ms: Option<u64>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock {
+    pub const fn new() -> Self {
+        Self {
+            last_ticks: 0,
+            uptime_ticks: 0,
+            boot_unix_ms: None,
+        }
+    }
+
+    /// Milliseconds since boot. This needs calling at least once per wrap
+    /// of the RTC to stay correct.
+    pub fn uptime_ms(&mut self, now: Instant) -> u64 {
+        let ticks = now.ticks();
+        self.uptime_ticks += ticks.wrapping_sub(self.last_ticks) as u64;
+        self.last_ticks = ticks;
+
+        self.uptime_ticks * 1000 / TICK_HZ
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.boot_unix_ms.is_some()
+    }
+
+    /// Convert a point in uptime into the device's clock, see
+    /// [`garden_shared::FrameTime`]
+    pub fn stamp(&self, uptime_ms: u64) -> u64 {
+        self.boot_unix_ms.unwrap_or(0) + uptime_ms
+    }
+
+    pub fn set_time(&mut self, now: Instant, unix_ms: u64) {
+        let uptime = self.uptime_ms(now);
+        self.boot_unix_ms = Some(unix_ms.saturating_sub(uptime));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::Duration;
+
+    #[test]
+    fn counts_uptime() {
+        let mut clock = Clock::new();
+
+        assert_eq!(clock.uptime_ms(Instant::from_ticks(0)), 0);
+        assert_eq!(clock.uptime_ms(Instant::from_ticks(32_768)), 1000);
+        assert_eq!(clock.uptime_ms(Instant::from_ticks(16_384 * 5)), 2500);
+    }
+
+    #[test]
+    fn survives_the_rtc_wrapping() {
+        let mut clock = Clock::new();
+        let before = Instant::from_ticks(u32::MAX - 32_767);
+
+        let uptime = clock.uptime_ms(before);
+        let after = clock.uptime_ms(before + Duration::secs(2));
+
+        assert_eq!(after - uptime, 2000);
+    }
+
+    #[test]
+    fn stamps_follow_the_set_time() {
+        let mut clock = Clock::new();
+        let now = Instant::from_ticks(32_768 * 10);
+
+        assert!(!clock.is_synced());
+        let uptime = clock.uptime_ms(now);
+        assert_eq!(clock.stamp(uptime), 10_000);
+
+        clock.set_time(now, 1_600_000_000_000);
+        assert!(clock.is_synced());
+        assert_eq!(clock.stamp(10_000), 1_600_000_000_000);
+        // readings taken before the clock was set line up too
+        assert_eq!(clock.stamp(4_000), 1_599_999_994_000);
+    }
+}
@@ -0,0 +1,117 @@
+//! Keeping the device config in flash across resets.
+
+use garden_shared::DeviceConfig;
+
+use crate::nvm::{Log, Nvm, BODY_LEN};
+
+const MAGIC: u16 = 0x6A7D;
+/// Bump this whenever the layout of [`DeviceConfig`] changes, old records are
+/// then ignored and the defaults used instead
+const VERSION: u8 = 2;
+
+#[derive(Debug)]
+pub enum ConfigStoreError<E> {
+    TooLarge,
+    Flash(E),
+}
+
+/// Stores the device config in a [`Log`] in a reserved area of flash.
+///
+/// The flash is passed in, it's shared with firmware updates.
+pub struct ConfigStore {
+    log: Log,
+    last: Option<DeviceConfig>,
+}
+
+impl ConfigStore {
+    /// The store in the row aligned area `len` bytes long at `start`
+    pub const fn new(start: u32, len: u32) -> Self {
+        Self {
+            log: Log::new(start, len, MAGIC, VERSION),
+            last: None,
+        }
+    }
+
+    /// Find the newest valid config, if there is one
+    pub fn load<N: Nvm>(&mut self, nvm: &N) -> Option<DeviceConfig> {
+        let config = self.log.load(nvm, |body| postcard::from_bytes(body).ok())?;
+        self.last = Some(config);
+
+        Some(config)
+    }
+
+    pub fn save<N: Nvm>(
+        &mut self,
+        nvm: &mut N,
+        config: &DeviceConfig,
+    ) -> Result<(), ConfigStoreError<N::Error>> {
+        if self.last.as_ref() == Some(config) {
+            return Ok(());
+        }
+
+        let mut buf = [0u8; BODY_LEN];
+        let body = postcard::to_slice(config, &mut buf).map_err(|_| ConfigStoreError::TooLarge)?;
+        self.log
+            .append(nvm, body)
+            .map_err(ConfigStoreError::Flash)?;
+        self.last = Some(*config);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::mock::MockFlash;
+    use crate::nvm::ROW_SIZE;
+
+    const LEN: u32 = 4 * ROW_SIZE as u32;
+
+    fn store() -> ConfigStore {
+        ConfigStore::new(0, LEN)
+    }
+
+    fn config(secs: u64) -> DeviceConfig {
+        DeviceConfig {
+            status_interval: Duration::from_secs(secs),
+            ..DeviceConfig::default()
+        }
+    }
+
+    #[test]
+    fn loads_the_last_config_saved() {
+        let mut flash = MockFlash::new(LEN as usize);
+        let mut store = store();
+        assert_eq!(store.load(&flash), None);
+
+        for secs in 1..40 {
+            store.save(&mut flash, &config(secs)).unwrap();
+        }
+        assert_eq!(self::store().load(&flash), Some(config(39)));
+    }
+
+    #[test]
+    fn only_writes_when_the_config_changes() {
+        let mut flash = MockFlash::new(LEN as usize);
+        let mut store = store();
+        store.save(&mut flash, &config(5)).unwrap();
+
+        // any write would fail
+        flash.cut_power_after(0);
+        store.save(&mut flash, &config(5)).unwrap();
+        assert!(matches!(
+            store.save(&mut flash, &config(6)),
+            Err(ConfigStoreError::Flash(_))
+        ));
+        flash.restore_power();
+
+        // the one cut off never made it
+        let mut store = self::store();
+        assert_eq!(store.load(&flash), Some(config(5)));
+        store.save(&mut flash, &config(6)).unwrap();
+        assert_eq!(self::store().load(&flash), Some(config(6)));
+    }
+}
@@ -0,0 +1,347 @@
+use embedded_hal::digital::v2::OutputPin;
+use garden_shared::{
+    Command, DeviceConfig, DeviceStatus, FrequencyPlan, StatusFlags, WateringEvent,
+};
+
+use crate::time::Instant;
+use crate::watering::Watering;
+
+/// The valve and pump, along with the flags they were last set from
+pub struct Outputs<V, P> {
+    flags: StatusFlags,
+    valve: V,
+    pump: P,
+}
+
+fn set(pin: &mut impl OutputPin, high: bool) {
+    // the pins on the boards we use can't fail
+    let _ = if high { pin.set_high() } else { pin.set_low() };
+}
+
+impl<V: OutputPin, P: OutputPin> Outputs<V, P> {
+    /// Takes over the pins, switching everything off
+    pub fn new(valve: V, pump: P) -> Self {
+        let mut outputs = Self {
+            flags: StatusFlags::empty(),
+            valve,
+            pump,
+        };
+        outputs.set_flags(StatusFlags::empty());
+        outputs
+    }
+
+    pub fn flags(&self) -> StatusFlags {
+        self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: StatusFlags) {
+        set(&mut self.pump, flags.contains(StatusFlags::PUMP_ON));
+        set(&mut self.valve, flags.contains(StatusFlags::VALVE_OPEN));
+        self.flags = flags;
+    }
+
+    /// Switch the outputs the watering controller drives for an event
+    pub fn apply_watering_event(&mut self, outputs: StatusFlags, event: &WateringEvent) {
+        let flags = match event {
+            WateringEvent::Started { .. } => self.flags | outputs,
+            WateringEvent::Stopped { .. } => self.flags - outputs,
+        };
+        self.set_flags(flags);
+    }
+
+    /// The status update to send to the base station
+    pub fn status(&self) -> DeviceStatus {
+        DeviceStatus { flags: self.flags }
+    }
+}
+
+/// What needs doing after handling a command that can't be done from here
+#[derive(Debug, Default, PartialEq)]
+pub struct Response {
+    /// The config to send back to the base station
+    pub config: Option<DeviceConfig>,
+    /// A watering run was stopped by the command, the outputs have already
+    /// been switched
+    pub watering: Option<WateringEvent>,
+    pub set_time: Option<u64>,
+    pub reset: bool,
+}
+
+/// Apply a command from the base station. New radio settings have to be in
+/// `plan`'s band. `save` persists a new config, returning whether it
+/// succeeded.
+pub fn handle_command<V: OutputPin, P: OutputPin>(
+    cmd: Command,
+    outputs: &mut Outputs<V, P>,
+    watering: &mut Watering,
+    config: &mut DeviceConfig,
+    plan: &FrequencyPlan,
+    now: Instant,
+    save: impl FnOnce(&DeviceConfig) -> bool,
+) -> Response {
+    let mut response = Response::default();
+
+    match cmd {
+        Command::SyncFlags(flags) => {
+            // don't let a stale sync cut off a watering run
+            let flags = if watering.is_running() {
+                flags | watering.outputs()
+            } else {
+                flags
+            };
+            outputs.set_flags(flags);
+        }
+        Command::Reset => {
+            response.reset = true;
+        }
+        Command::SetConfig(field) => {
+            let mut new_config = *config;
+            if new_config.set(field, plan).is_ok() && save(&new_config) {
+                let watering_outputs = watering.outputs();
+                if let Some(event) = watering.configure(new_config.watering, now) {
+                    outputs.apply_watering_event(watering_outputs, &event);
+                    response.watering = Some(event);
+                }
+
+                // the role decides what runs from boot
+                response.reset = new_config.role != config.role;
+                *config = new_config;
+            }
+            response.config = Some(*config);
+        }
+        Command::GetConfig => {
+            response.config = Some(*config);
+        }
+        // acks and link settings are dealt with as they're received
+        Command::Ack(_) | Command::SetLink(_) => {}
+        // and firmware by the updater, which has the flash
+        Command::FirmwareManifest(_) | Command::FirmwareChunk(_) | Command::FirmwareCancel => {}
+        Command::SetTime(unix_ms) => {
+            response.set_time = Some(unix_ms);
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use garden_shared::{ConfigField, Role, WateringConfig, WateringStopReason, EU868};
+
+    use super::*;
+    use crate::mock::{at, MockPin};
+
+    struct Device {
+        outputs: Outputs<MockPin, MockPin>,
+        valve: MockPin,
+        pump: MockPin,
+        watering: Watering,
+        config: DeviceConfig,
+    }
+
+    impl Device {
+        fn new() -> Self {
+            let config = DeviceConfig::default();
+            let valve = MockPin::default();
+            let pump = MockPin::default();
+
+            Self {
+                outputs: Outputs::new(valve.clone(), pump.clone()),
+                valve,
+                pump,
+                watering: Watering::new(config.watering),
+                config,
+            }
+        }
+
+        fn handle(&mut self, cmd: Command) -> Response {
+            self.handle_saving(cmd, true)
+        }
+
+        fn handle_saving(&mut self, cmd: Command, saved: bool) -> Response {
+            handle_command(
+                cmd,
+                &mut self.outputs,
+                &mut self.watering,
+                &mut self.config,
+                &EU868,
+                at(0),
+                |_| saved,
+            )
+        }
+    }
+
+    #[test]
+    fn sync_flags_drives_the_pins() {
+        let mut device = Device::new();
+
+        device.handle(Command::SyncFlags(StatusFlags::PUMP_ON));
+        assert!(device.pump.is_high());
+        assert!(!device.valve.is_high());
+
+        device.handle(Command::SyncFlags(StatusFlags::VALVE_OPEN));
+        assert!(!device.pump.is_high());
+        assert!(device.valve.is_high());
+        assert_eq!(device.outputs.status().flags, StatusFlags::VALVE_OPEN);
+    }
+
+    #[test]
+    fn sync_flags_keeps_watering_running() {
+        let mut device = Device::new();
+        device.handle(Command::SetConfig(ConfigField::Watering(WateringConfig {
+            enabled: true,
+            ..Default::default()
+        })));
+
+        let dry = crate::mock::moisture_report(&[500.0, 500.0, 500.0]);
+        let event = device.watering.on_reading(&dry, at(1)).unwrap();
+        device
+            .outputs
+            .apply_watering_event(device.watering.outputs(), &event);
+
+        device.handle(Command::SyncFlags(StatusFlags::empty()));
+        assert!(device.pump.is_high());
+        assert!(device.outputs.flags().contains(StatusFlags::AUTO_WATERING));
+    }
+
+    #[test]
+    fn set_config_replies_with_the_result() {
+        let mut device = Device::new();
+
+        let response = device.handle(Command::SetConfig(ConfigField::BmeInterval(
+            Duration::from_secs(120),
+        )));
+        assert_eq!(device.config.bme_interval, Duration::from_secs(120));
+        assert_eq!(response.config, Some(device.config));
+    }
+
+    #[test]
+    fn invalid_config_is_not_applied() {
+        let mut device = Device::new();
+        let before = device.config;
+
+        let response = device.handle(Command::SetConfig(ConfigField::BmeInterval(
+            Duration::from_millis(10),
+        )));
+        assert_eq!(device.config, before);
+        assert_eq!(response.config, Some(before));
+    }
+
+    #[test]
+    fn intervals_are_capped_at_what_the_rtc_can_count() {
+        let mut device = Device::new();
+
+        device.handle(Command::SetConfig(ConfigField::StatusInterval(
+            garden_shared::MAX_INTERVAL,
+        )));
+        assert_eq!(device.config.status_interval, garden_shared::MAX_INTERVAL);
+
+        let too_long = garden_shared::MAX_INTERVAL + Duration::from_secs(1);
+        device.handle(Command::SetConfig(ConfigField::StatusInterval(too_long)));
+        device.handle(Command::SetConfig(ConfigField::ResetInterval(Some(
+            too_long,
+        ))));
+        assert_eq!(device.config.status_interval, garden_shared::MAX_INTERVAL);
+        assert_eq!(
+            device.config.reset_interval,
+            DeviceConfig::default().reset_interval
+        );
+    }
+
+    #[test]
+    fn watering_times_are_capped_at_what_the_rtc_can_count() {
+        let mut device = Device::new();
+        let longest = WateringConfig {
+            max_run_time: garden_shared::MAX_INTERVAL,
+            min_interval: garden_shared::MAX_INTERVAL,
+            ..Default::default()
+        };
+
+        device.handle(Command::SetConfig(ConfigField::Watering(longest)));
+        assert_eq!(device.config.watering, longest);
+
+        let too_long = garden_shared::MAX_INTERVAL + Duration::from_secs(1);
+        for watering in [
+            WateringConfig {
+                max_run_time: too_long,
+                ..longest
+            },
+            WateringConfig {
+                min_interval: too_long,
+                ..longest
+            },
+        ] {
+            device.handle(Command::SetConfig(ConfigField::Watering(watering)));
+            assert_eq!(device.config.watering, longest);
+        }
+    }
+
+    #[test]
+    fn config_is_not_applied_unless_saved() {
+        let mut device = Device::new();
+        let before = device.config;
+
+        device.handle_saving(
+            Command::SetConfig(ConfigField::TemperatureOffset(1.0)),
+            false,
+        );
+        assert_eq!(device.config, before);
+    }
+
+    #[test]
+    fn changing_role_resets() {
+        let mut device = Device::new();
+
+        assert!(
+            !device
+                .handle(Command::SetConfig(ConfigField::Role(Role::EndNode)))
+                .reset
+        );
+        assert!(
+            device
+                .handle(Command::SetConfig(ConfigField::Role(Role::Repeater)))
+                .reset
+        );
+        assert_eq!(device.config.role, Role::Repeater);
+    }
+
+    #[test]
+    fn disabling_watering_stops_a_run() {
+        let mut device = Device::new();
+        device.handle(Command::SetConfig(ConfigField::Watering(WateringConfig {
+            enabled: true,
+            ..Default::default()
+        })));
+
+        let dry = crate::mock::moisture_report(&[500.0, 500.0, 500.0]);
+        let event = device.watering.on_reading(&dry, at(1)).unwrap();
+        device
+            .outputs
+            .apply_watering_event(device.watering.outputs(), &event);
+        assert!(device.pump.is_high());
+
+        let response = device.handle(Command::SetConfig(ConfigField::Watering(
+            WateringConfig::default(),
+        )));
+        assert!(matches!(
+            response.watering,
+            Some(WateringEvent::Stopped {
+                reason: WateringStopReason::Disabled,
+                ..
+            })
+        ));
+        assert!(!device.pump.is_high());
+        assert!(!device.outputs.flags().contains(StatusFlags::AUTO_WATERING));
+    }
+
+    #[test]
+    fn reset_and_time_are_left_to_the_caller() {
+        let mut device = Device::new();
+
+        assert!(device.handle(Command::Reset).reset);
+        assert_eq!(device.handle(Command::SetTime(1234)).set_time, Some(1234));
+        assert_eq!(device.handle(Command::Ack(3)), Response::default());
+    }
+}
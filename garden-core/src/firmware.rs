@@ -0,0 +1,948 @@
+//! Firmware updates over the air: writing new firmware to a staging slot as
+//! it arrives, and the swap the bootloader does to boot it and to roll it
+//! back if it doesn't work out.
+//!
+//! Flash is split into the app slot the firmware runs from, a staging slot
+//! of the same size and a state area. The bootloader swaps the slots a row at
+//! a time through scratch rows in the state area, noting down each step in
+//! a journal so it carries on where it left off if the power goes partway
+//! through. The old firmware ends up in the staging slot, so rolling back is
+//! the same swap done again.
+
+// `div_ceil` and `is_multiple_of` are newer than the firmware's toolchain
+#![allow(clippy::manual_div_ceil, clippy::manual_is_multiple_of)]
+
+use garden_shared::{
+    crc32, image_id, FirmwareChunk, FirmwareError, FirmwareManifest, FirmwareState, FirmwareStatus,
+    FIRMWARE_CHUNK,
+};
+use sha2::{Digest, Sha256};
+
+use crate::nvm::{Log, Nvm, PAGE_SIZE, ROW_SIZE};
+
+/// Boots new firmware gets to reach the base station in before it's rolled
+/// back
+pub const TRIAL_BOOTS: u8 = 3;
+
+/// How much of new firmware is written between noting down how far it got,
+/// a transfer cut short resumes from the last note
+const PROGRESS_EVERY: u32 = 2 * ROW_SIZE as u32;
+
+/// Where the slots and the state area are, all row aligned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub app: u32,
+    pub staging: u32,
+    /// Length of each slot
+    pub slot_len: u32,
+    /// The scratch rows for swapping, followed by the journal
+    pub state: u32,
+    pub state_len: u32,
+    /// Each row swapped goes through the next of these in turn, so a swap
+    /// doesn't wear out any one of them
+    pub scratch_rows: u32,
+}
+
+impl Layout {
+    /// The Feather M0: 8k for its own bootloader and 8k for ours, then the
+    /// slots and the state area, leaving the last 4k for the device config
+    pub const SAMD21: Layout = Layout {
+        app: 0x0000_4000,
+        staging: 0x0002_1000,
+        slot_len: 0x0001_D000,
+        state: 0x0003_E000,
+        state_len: 0x1000,
+        // a full swap erases each of these 58 times and each row of the
+        // journal 87 times, out of the 25k erases flash is good for
+        scratch_rows: 8,
+    };
+
+    fn scratch(&self, row: u16) -> u32 {
+        self.state + (row as u32 % self.scratch_rows) * ROW_SIZE as u32
+    }
+
+    fn journal(&self) -> Log {
+        let scratch_len = self.scratch_rows * ROW_SIZE as u32;
+        Log::new(
+            self.state + scratch_len,
+            self.state_len - scratch_len,
+            MAGIC,
+            VERSION,
+        )
+    }
+}
+
+/// Whether `image` starts with a vector table for firmware linked to run from
+/// the app slot, to catch images built for somewhere else
+pub fn is_app_image(layout: &Layout, image: &[u8]) -> bool {
+    if image.len() < 8 {
+        return false;
+    }
+
+    let reset = u32::from_le_bytes([image[4], image[5], image[6], image[7]]);
+    // thumb code, so the bottom bit is set
+    reset & 1 == 1 && reset > layout.app && reset < layout.app + layout.slot_len
+}
+
+/// What the bootloader has to do on the next boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boot {
+    /// Run the firmware in the app slot, it's been kept
+    Confirmed,
+    /// Swap in the verified firmware in the staging slot
+    Pending,
+    /// Carry on swapping the slots from `step` of `row`
+    Swapping { row: u16, step: u8, reverting: bool },
+    /// Run new firmware that hasn't been kept yet, it's been booted
+    /// `attempts` times
+    Trial { attempts: u8 },
+}
+
+/// Firmware in one of the slots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Image {
+    /// Length in bytes
+    pub len: u32,
+    /// All zeroes if it isn't known
+    pub sha256: [u8; 32],
+}
+
+impl Image {
+    pub fn id(&self) -> u32 {
+        image_id(&self.sha256)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct State {
+    pub boot: Boot,
+    /// The last update was rolled back
+    pub rolled_back: bool,
+    pub app: Image,
+    pub staged: Image,
+    /// How much of the staged image has been written
+    pub received: u32,
+}
+
+impl State {
+    /// Before any update, the app slot holds whatever was flashed with a
+    /// probe
+    fn initial(layout: &Layout) -> Self {
+        Self {
+            boot: Boot::Confirmed,
+            rolled_back: false,
+            app: Image {
+                len: layout.slot_len,
+                sha256: [0; 32],
+            },
+            staged: Image {
+                len: 0,
+                sha256: [0; 32],
+            },
+            received: 0,
+        }
+    }
+}
+
+const MAGIC: u16 = 0xF1A5;
+/// Bump this whenever the layout of a record changes
+const VERSION: u8 = 2;
+const STATE_LEN: usize = 84;
+
+fn encode(state: &State) -> [u8; STATE_LEN] {
+    let mut buf = [0; STATE_LEN];
+
+    let (kind, row, step, reverting, attempts) = match state.boot {
+        Boot::Confirmed => (0, 0, 0, false, 0),
+        Boot::Pending => (1, 0, 0, false, 0),
+        Boot::Swapping {
+            row,
+            step,
+            reverting,
+        } => (2, row, step, reverting, 0),
+        Boot::Trial { attempts } => (3, 0, 0, false, attempts),
+    };
+    buf[0] = kind;
+    buf[1..3].copy_from_slice(&row.to_le_bytes());
+    buf[3] = step;
+    buf[4] = reverting as u8 | (state.rolled_back as u8) << 1;
+    buf[5] = attempts;
+    buf[8..12].copy_from_slice(&state.app.len.to_le_bytes());
+    buf[12..16].copy_from_slice(&state.staged.len.to_le_bytes());
+    buf[16..20].copy_from_slice(&state.received.to_le_bytes());
+    buf[20..52].copy_from_slice(&state.app.sha256);
+    buf[52..84].copy_from_slice(&state.staged.sha256);
+
+    buf
+}
+
+fn decode(buf: &[u8]) -> Option<State> {
+    if buf.len() != STATE_LEN {
+        return None;
+    }
+
+    let word = |at: usize| u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+
+    let boot = match buf[0] {
+        0 => Boot::Confirmed,
+        1 => Boot::Pending,
+        2 => Boot::Swapping {
+            row: u16::from_le_bytes([buf[1], buf[2]]),
+            step: buf[3],
+            reverting: buf[4] & 1 != 0,
+        },
+        3 => Boot::Trial { attempts: buf[5] },
+        _ => return None,
+    };
+
+    let mut app = [0; 32];
+    app.copy_from_slice(&buf[20..52]);
+    let mut staged = [0; 32];
+    staged.copy_from_slice(&buf[52..84]);
+
+    Some(State {
+        boot,
+        rolled_back: buf[4] & 2 != 0,
+        app: Image {
+            len: word(8),
+            sha256: app,
+        },
+        staged: Image {
+            len: word(12),
+            sha256: staged,
+        },
+        received: word(16),
+    })
+}
+
+/// The state of updates, kept in a [`Log`] in the state area after the
+/// scratch rows
+pub struct Journal {
+    layout: Layout,
+    state: State,
+    log: Log,
+}
+
+impl Journal {
+    /// Find the newest state, or the state before any update if there isn't
+    /// one
+    pub fn open<N: Nvm>(nvm: &N, layout: Layout) -> Self {
+        let mut log = layout.journal();
+        let state = log
+            .load(nvm, decode)
+            .unwrap_or_else(|| State::initial(&layout));
+
+        Self { layout, state, log }
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn save<N: Nvm>(&mut self, nvm: &mut N, state: State) -> Result<(), N::Error> {
+        self.log.append(nvm, &encode(&state))?;
+        self.state = state;
+
+        Ok(())
+    }
+}
+
+/// How many rows a swap goes through, enough for whichever image is longer
+fn rows_to_swap(layout: &Layout, state: &State) -> u16 {
+    let len = state.app.len.max(state.staged.len).min(layout.slot_len) as usize;
+    ((len + ROW_SIZE - 1) / ROW_SIZE) as u16
+}
+
+fn copy_row<N: Nvm>(nvm: &mut N, from: u32, to: u32) -> Result<(), N::Error> {
+    let mut row = [0; ROW_SIZE];
+    nvm.read(from, &mut row);
+    nvm.erase_row(to)?;
+    nvm.write(to, &row)
+}
+
+/// One of the three steps of swapping a row. Each one only reads from a row
+/// the next one hasn't touched yet, so a step cut short can be done again.
+fn swap_step<N: Nvm>(nvm: &mut N, layout: &Layout, row: u16, step: u8) -> Result<(), N::Error> {
+    let offset = row as u32 * ROW_SIZE as u32;
+    let (app, staging) = (layout.app + offset, layout.staging + offset);
+    let scratch = layout.scratch(row);
+
+    match step {
+        0 => copy_row(nvm, app, scratch),
+        1 => copy_row(nvm, staging, app),
+        _ => copy_row(nvm, scratch, staging),
+    }
+}
+
+/// Get the app slot ready to boot, starting or carrying on with a swap if
+/// there's one to do. Run by the bootloader on every boot.
+pub fn boot<N: Nvm>(nvm: &mut N, journal: &mut Journal) -> Result<(), N::Error> {
+    loop {
+        let layout = *journal.layout();
+        let mut state = *journal.state();
+
+        state.boot = match state.boot {
+            Boot::Confirmed => return Ok(()),
+            Boot::Trial { attempts } if attempts < TRIAL_BOOTS => {
+                state.boot = Boot::Trial {
+                    attempts: attempts + 1,
+                };
+                return journal.save(nvm, state);
+            }
+            // it never got through to the base station, put the old firmware
+            // back
+            Boot::Trial { .. } => Boot::Swapping {
+                row: 0,
+                step: 0,
+                reverting: true,
+            },
+            Boot::Pending => Boot::Swapping {
+                row: 0,
+                step: 0,
+                reverting: false,
+            },
+            Boot::Swapping {
+                row,
+                step,
+                reverting,
+            } if row < rows_to_swap(&layout, &state) => {
+                swap_step(nvm, &layout, row, step)?;
+                if step < 2 {
+                    Boot::Swapping {
+                        row,
+                        step: step + 1,
+                        reverting,
+                    }
+                } else {
+                    Boot::Swapping {
+                        row: row + 1,
+                        step: 0,
+                        reverting,
+                    }
+                }
+            }
+            Boot::Swapping { reverting, .. } => {
+                core::mem::swap(&mut state.app, &mut state.staged);
+                state.received = state.staged.len;
+                state.rolled_back = reverting;
+
+                if reverting {
+                    Boot::Confirmed
+                } else {
+                    Boot::Trial { attempts: 0 }
+                }
+            }
+        };
+
+        journal.save(nvm, state)?;
+    }
+}
+
+/// The firmware's end of an update, writing new firmware to the staging slot
+/// as it arrives and leaving it for the bootloader once it's all there.
+///
+/// Chunks are taken in order and only after a manifest since the last boot,
+/// so the base station always knows where to carry on from.
+pub struct Updater {
+    journal: Journal,
+    /// How much of the staged image has been written, ahead of the journal
+    received: u32,
+    /// A manifest came in since boot and the transfer hasn't ended
+    receiving: bool,
+}
+
+impl Updater {
+    pub fn open<N: Nvm>(nvm: &N, layout: Layout) -> Self {
+        let journal = Journal::open(nvm, layout);
+
+        Self {
+            received: journal.state().received,
+            journal,
+            receiving: false,
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        self.journal.state()
+    }
+
+    /// Where we are with our firmware, sent after booting and in answer to
+    /// every firmware command
+    pub fn status(&self) -> FirmwareStatus {
+        let state = self.journal.state();
+
+        let (image, state) = match state.boot {
+            Boot::Pending => (state.staged.id(), FirmwareState::Verified),
+            _ if self.receiving => (
+                state.staged.id(),
+                FirmwareState::Receiving {
+                    next: (self.received / FIRMWARE_CHUNK as u32) as u16,
+                },
+            ),
+            Boot::Trial { .. } => (state.app.id(), FirmwareState::Trial),
+            _ if state.rolled_back => (state.app.id(), FirmwareState::RolledBack),
+            _ => (state.app.id(), FirmwareState::Idle),
+        };
+
+        FirmwareStatus { image, state }
+    }
+
+    fn failed(&mut self, image: u32, error: FirmwareError) -> FirmwareStatus {
+        self.receiving = false;
+
+        FirmwareStatus {
+            image,
+            state: FirmwareState::Failed(error),
+        }
+    }
+
+    /// Start receiving new firmware, or carry on with it if it's the firmware
+    /// we were already receiving
+    pub fn manifest<N: Nvm>(&mut self, nvm: &mut N, manifest: &FirmwareManifest) -> FirmwareStatus {
+        let layout = *self.journal.layout();
+        let mut state = *self.journal.state();
+        let staged = Image {
+            len: manifest.size,
+            sha256: manifest.sha256,
+        };
+
+        // the old firmware in the staging slot is what we'd roll back to
+        if let Boot::Trial { .. } = state.boot {
+            return self.failed(manifest.image(), FirmwareError::OnTrial);
+        }
+        if manifest.size == 0 || manifest.size > layout.slot_len {
+            return self.failed(manifest.image(), FirmwareError::TooLarge);
+        }
+
+        if state.staged == staged {
+            // a manifest sent again, or the base station starting over after
+            // one of us reset
+            if !self.receiving {
+                self.received = state.received;
+            }
+            self.receiving = true;
+
+            if self.received == staged.len && state.boot != Boot::Pending {
+                return self.verify(nvm);
+            }
+            return self.status();
+        }
+
+        state.boot = Boot::Confirmed;
+        state.rolled_back = false;
+        state.staged = staged;
+        state.received = 0;
+        if self.journal.save(nvm, state).is_err() {
+            return self.failed(manifest.image(), FirmwareError::Flash);
+        }
+
+        self.received = 0;
+        self.receiving = true;
+        self.status()
+    }
+
+    /// Write a chunk of the firmware being received if it's the one we want
+    /// next, answering with the one we want after that
+    pub fn chunk<N: Nvm>(&mut self, nvm: &mut N, chunk: &FirmwareChunk) -> FirmwareStatus {
+        let layout = *self.journal.layout();
+        let state = *self.journal.state();
+
+        let offset = chunk.index as u32 * FIRMWARE_CHUNK as u32;
+        let end = offset + chunk.data.len() as u32;
+        let wanted = self.receiving
+            && state.boot != Boot::Pending
+            && offset == self.received
+            && crc32(&chunk.data) == chunk.crc
+            // only the last chunk can be short
+            && (chunk.data.len() == FIRMWARE_CHUNK || end == state.staged.len)
+            && end <= state.staged.len;
+        if !wanted {
+            return self.status();
+        }
+
+        let addr = layout.staging + offset;
+        let mut page = [0xff; FIRMWARE_CHUNK];
+        page[..chunk.data.len()].copy_from_slice(&chunk.data);
+        let len = (chunk.data.len() + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+
+        let written = if offset % ROW_SIZE as u32 == 0 {
+            nvm.erase_row(addr)
+                .and_then(|_| nvm.write(addr, &page[..len]))
+        } else {
+            nvm.write(addr, &page[..len])
+        };
+        if written.is_err() {
+            return self.failed(state.staged.id(), FirmwareError::Flash);
+        }
+        self.received = end;
+
+        if end == state.staged.len {
+            return self.verify(nvm);
+        }
+
+        if end % PROGRESS_EVERY == 0 {
+            let mut state = state;
+            state.received = end;
+            if self.journal.save(nvm, state).is_err() {
+                return self.failed(state.staged.id(), FirmwareError::Flash);
+            }
+        }
+
+        self.status()
+    }
+
+    /// Check all of the staged firmware against its manifest, leaving it for
+    /// the bootloader if it matches
+    fn verify<N: Nvm>(&mut self, nvm: &mut N) -> FirmwareStatus {
+        let layout = *self.journal.layout();
+        let mut state = *self.journal.state();
+
+        let mut hasher = Sha256::new();
+        let mut buf = [0; ROW_SIZE];
+        let mut first = [0; 8];
+        let mut at = 0;
+        while at < state.staged.len {
+            let len = (state.staged.len - at).min(ROW_SIZE as u32) as usize;
+            nvm.read(layout.staging + at, &mut buf[..len]);
+            if at == 0 {
+                let n = len.min(first.len());
+                first[..n].copy_from_slice(&buf[..n]);
+            }
+            hasher.update(&buf[..len]);
+            at += len as u32;
+        }
+
+        let error = if hasher.finalize()[..] != state.staged.sha256[..] {
+            Some(FirmwareError::HashMismatch)
+        } else if !is_app_image(&layout, &first) {
+            Some(FirmwareError::NotAnImage)
+        } else {
+            None
+        };
+
+        let image = state.staged.id();
+        if let Some(error) = error {
+            // start over if it's sent again
+            state.received = 0;
+            state.staged.sha256 = [0; 32];
+            self.received = 0;
+            let _ = self.journal.save(nvm, state);
+            return self.failed(image, error);
+        }
+
+        state.received = state.staged.len;
+        state.boot = Boot::Pending;
+        if self.journal.save(nvm, state).is_err() {
+            return self.failed(image, FirmwareError::Flash);
+        }
+
+        self.receiving = false;
+        self.status()
+    }
+
+    /// Give up on the firmware being received, or on booting into firmware
+    /// that's been verified but not booted yet
+    pub fn cancel<N: Nvm>(&mut self, nvm: &mut N) -> FirmwareStatus {
+        let mut state = *self.journal.state();
+        self.receiving = false;
+
+        if let Boot::Confirmed | Boot::Pending = state.boot {
+            state.boot = Boot::Confirmed;
+            state.staged = Image {
+                len: 0,
+                sha256: [0; 32],
+            };
+            state.received = 0;
+            self.received = 0;
+            let _ = self.journal.save(nvm, state);
+        }
+
+        self.status()
+    }
+
+    /// Keep the firmware we're running if it's on trial, returning whether it
+    /// was
+    pub fn confirm<N: Nvm>(&mut self, nvm: &mut N) -> Result<bool, N::Error> {
+        let mut state = *self.journal.state();
+        if let Boot::Trial { .. } = state.boot {
+            state.boot = Boot::Confirmed;
+            state.rolled_back = false;
+            self.journal.save(nvm, state)?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockFlash;
+
+    /// Four rows per slot, and two scratch rows and three rows of journal
+    const LAYOUT: Layout = Layout {
+        app: 0x1000,
+        staging: 0x1400,
+        slot_len: 0x400,
+        state: 0x1800,
+        state_len: 0x500,
+        scratch_rows: 2,
+    };
+
+    fn flash() -> MockFlash {
+        MockFlash::new(0x1d00)
+    }
+
+    /// Made up firmware of `len` bytes with a vector table for the app slot
+    fn image(len: usize, seed: u8) -> Vec<u8> {
+        let mut image: Vec<u8> = (0..len)
+            .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+            .collect();
+        image[..4].copy_from_slice(&0x2000_8000u32.to_le_bytes());
+        image[4..8].copy_from_slice(&(LAYOUT.app + 0x101).to_le_bytes());
+        image
+    }
+
+    fn manifest(image: &[u8]) -> FirmwareManifest {
+        FirmwareManifest {
+            size: image.len() as u32,
+            sha256: Sha256::digest(image).into(),
+        }
+    }
+
+    fn chunk(image: &[u8], index: u16) -> FirmwareChunk {
+        let data: heapless::Vec<u8, FIRMWARE_CHUNK> = image
+            .chunks(FIRMWARE_CHUNK)
+            .nth(index as usize)
+            .unwrap()
+            .iter()
+            .copied()
+            .collect();
+
+        FirmwareChunk {
+            index,
+            crc: crc32(&data),
+            data,
+        }
+    }
+
+    /// Flash `image` into the app slot the way a probe would
+    fn flashed(image: &[u8]) -> MockFlash {
+        let mut flash = flash();
+        let app = LAYOUT.app as usize;
+        flash.data[app..app + image.len()].copy_from_slice(image);
+        flash
+    }
+
+    fn slot(flash: &MockFlash, at: u32, len: usize) -> &[u8] {
+        &flash.data[at as usize..at as usize + len]
+    }
+
+    fn receiving(next: u16) -> FirmwareState {
+        FirmwareState::Receiving { next }
+    }
+
+    /// Send all of `image` over, returning the last status
+    fn send(flash: &mut MockFlash, updater: &mut Updater, image: &[u8]) -> FirmwareStatus {
+        let manifest = manifest(image);
+        let mut status = updater.manifest(flash, &manifest);
+        while let FirmwareState::Receiving { next } = status.state {
+            status = updater.chunk(flash, &chunk(image, next));
+        }
+        status
+    }
+
+    fn reboot(flash: &mut MockFlash) -> Updater {
+        let mut journal = Journal::open(flash, LAYOUT);
+        boot(flash, &mut journal).unwrap();
+        Updater::open(flash, LAYOUT)
+    }
+
+    #[test]
+    fn receives_and_verifies_firmware() {
+        let old = image(1000, 1);
+        let new = image(700, 2);
+        let mut flash = flashed(&old);
+        let mut updater = Updater::open(&flash, LAYOUT);
+        assert_eq!(updater.status().state, FirmwareState::Idle);
+
+        let manifest = manifest(&new);
+        let status = updater.manifest(&mut flash, &manifest);
+        assert_eq!(status.image, manifest.image());
+        assert_eq!(status.state, receiving(0));
+        assert_eq!(manifest.chunks(), 6);
+
+        for index in 0..5 {
+            let status = updater.chunk(&mut flash, &chunk(&new, index));
+            assert_eq!(status.state, receiving(index + 1));
+        }
+        let status = updater.chunk(&mut flash, &chunk(&new, 5));
+        assert_eq!(status.state, FirmwareState::Verified);
+        assert_eq!(updater.state().boot, Boot::Pending);
+
+        assert_eq!(slot(&flash, LAYOUT.staging, new.len()), &new[..]);
+        // nothing's been touched until the bootloader swaps them
+        assert_eq!(slot(&flash, LAYOUT.app, old.len()), &old[..]);
+    }
+
+    #[test]
+    fn only_takes_the_chunk_it_wants() {
+        let new = image(700, 2);
+        let mut flash = flash();
+        let mut updater = Updater::open(&flash, LAYOUT);
+
+        // not without a manifest
+        let status = updater.chunk(&mut flash, &chunk(&new, 0));
+        assert_eq!(status.state, FirmwareState::Idle);
+
+        updater.manifest(&mut flash, &manifest(&new));
+        updater.chunk(&mut flash, &chunk(&new, 0));
+
+        let skipped = updater.chunk(&mut flash, &chunk(&new, 2));
+        assert_eq!(skipped.state, receiving(1));
+        let again = updater.chunk(&mut flash, &chunk(&new, 0));
+        assert_eq!(again.state, receiving(1));
+
+        let mut corrupt = chunk(&new, 1);
+        corrupt.data[10] ^= 1;
+        assert_eq!(updater.chunk(&mut flash, &corrupt).state, receiving(1));
+
+        assert_eq!(
+            updater.chunk(&mut flash, &chunk(&new, 1)).state,
+            receiving(2)
+        );
+    }
+
+    #[test]
+    fn resumes_after_a_reset() {
+        let new = image(1000, 2);
+        let mut flash = flash();
+        let mut updater = Updater::open(&flash, LAYOUT);
+
+        updater.manifest(&mut flash, &manifest(&new));
+        for index in 0..7 {
+            updater.chunk(&mut flash, &chunk(&new, index));
+        }
+
+        // carries on from the last progress it noted down
+        let mut updater = reboot(&mut flash);
+        assert_eq!(updater.status().state, FirmwareState::Idle);
+        let status = updater.manifest(&mut flash, &manifest(&new));
+        assert_eq!(status.state, receiving(4));
+
+        assert_eq!(
+            send(&mut flash, &mut updater, &new).state,
+            FirmwareState::Verified
+        );
+        assert_eq!(slot(&flash, LAYOUT.staging, new.len()), &new[..]);
+    }
+
+    #[test]
+    fn new_firmware_starts_over() {
+        let first = image(1000, 2);
+        let second = image(1000, 3);
+        let mut flash = flash();
+        let mut updater = Updater::open(&flash, LAYOUT);
+
+        updater.manifest(&mut flash, &manifest(&first));
+        updater.chunk(&mut flash, &chunk(&first, 0));
+
+        let status = updater.manifest(&mut flash, &manifest(&second));
+        assert_eq!(status.image, manifest(&second).image());
+        assert_eq!(status.state, receiving(0));
+    }
+
+    #[test]
+    fn rejects_firmware_that_does_not_match() {
+        let new = image(700, 2);
+        let mut flash = flash();
+        let mut updater = Updater::open(&flash, LAYOUT);
+
+        let mut wrong = manifest(&new);
+        wrong.sha256[0] ^= 1;
+        let mut status = updater.manifest(&mut flash, &wrong);
+        while let FirmwareState::Receiving { next } = status.state {
+            status = updater.chunk(&mut flash, &chunk(&new, next));
+        }
+        assert_eq!(
+            status.state,
+            FirmwareState::Failed(FirmwareError::HashMismatch)
+        );
+        assert_eq!(updater.state().boot, Boot::Confirmed);
+
+        // built for where the old bootloader put the app
+        let mut elsewhere = image(700, 2);
+        elsewhere[4..8].copy_from_slice(&0x2101u32.to_le_bytes());
+        assert_eq!(
+            send(&mut flash, &mut updater, &elsewhere).state,
+            FirmwareState::Failed(FirmwareError::NotAnImage)
+        );
+
+        let huge = manifest(&image(LAYOUT.slot_len as usize + 1, 2));
+        assert_eq!(
+            updater.manifest(&mut flash, &huge).state,
+            FirmwareState::Failed(FirmwareError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn boots_new_firmware_and_keeps_it() {
+        let old = image(1000, 1);
+        let new = image(700, 2);
+        let mut flash = flashed(&old);
+        let mut updater = Updater::open(&flash, LAYOUT);
+        send(&mut flash, &mut updater, &new);
+
+        let mut updater = reboot(&mut flash);
+        assert_eq!(slot(&flash, LAYOUT.app, new.len()), &new[..]);
+        assert_eq!(slot(&flash, LAYOUT.staging, old.len()), &old[..]);
+        assert_eq!(
+            updater.status(),
+            FirmwareStatus {
+                image: manifest(&new).image(),
+                state: FirmwareState::Trial,
+            }
+        );
+
+        // the old firmware is kept around until the new one has proven itself
+        assert_eq!(
+            updater
+                .manifest(&mut flash, &manifest(&image(700, 3)))
+                .state,
+            FirmwareState::Failed(FirmwareError::OnTrial)
+        );
+
+        assert!(updater.confirm(&mut flash).unwrap());
+        assert!(!updater.confirm(&mut flash).unwrap());
+        assert_eq!(updater.status().state, FirmwareState::Idle);
+
+        // and stays
+        for _ in 0..TRIAL_BOOTS + 1 {
+            reboot(&mut flash);
+        }
+        assert_eq!(slot(&flash, LAYOUT.app, new.len()), &new[..]);
+    }
+
+    #[test]
+    fn rolls_back_firmware_that_is_not_kept() {
+        let old = image(1000, 1);
+        let new = image(700, 2);
+        let mut flash = flashed(&old);
+        let mut updater = Updater::open(&flash, LAYOUT);
+        send(&mut flash, &mut updater, &new);
+
+        for _ in 0..TRIAL_BOOTS {
+            let updater = reboot(&mut flash);
+            assert_eq!(updater.status().state, FirmwareState::Trial);
+            assert_eq!(slot(&flash, LAYOUT.app, new.len()), &new[..]);
+        }
+
+        let mut updater = reboot(&mut flash);
+        assert_eq!(slot(&flash, LAYOUT.app, old.len()), &old[..]);
+        assert_eq!(updater.status().state, FirmwareState::RolledBack);
+        assert!(!updater.confirm(&mut flash).unwrap());
+
+        // the next update clears it
+        let newer = image(700, 3);
+        assert_eq!(
+            send(&mut flash, &mut updater, &newer).state,
+            FirmwareState::Verified
+        );
+        let updater = reboot(&mut flash);
+        assert_eq!(slot(&flash, LAYOUT.app, newer.len()), &newer[..]);
+        assert_eq!(updater.status().state, FirmwareState::Trial);
+    }
+
+    #[test]
+    fn swapping_survives_the_power_going() {
+        let old = image(1000, 1);
+        let new = image(700, 2);
+
+        let mut cut = 0;
+        loop {
+            let mut flash = flashed(&old);
+            let mut updater = Updater::open(&flash, LAYOUT);
+            send(&mut flash, &mut updater, &new);
+
+            // the power goes `cut` operations in, then comes back
+            flash.cut_power_after(cut);
+            let mut journal = Journal::open(&flash, LAYOUT);
+            let finished = boot(&mut flash, &mut journal).is_ok();
+            flash.restore_power();
+
+            let updater = reboot(&mut flash);
+            assert_eq!(slot(&flash, LAYOUT.app, new.len()), &new[..], "{}", cut);
+            assert_eq!(slot(&flash, LAYOUT.staging, old.len()), &old[..], "{}", cut);
+            assert_eq!(updater.status().state, FirmwareState::Trial);
+
+            if finished {
+                break;
+            }
+            cut += 1;
+        }
+
+        // every row's three steps took an erase, a write and a note each
+        assert!(cut > 4 * 3 * 3, "{}", cut);
+    }
+
+    #[test]
+    fn swapping_shares_the_wear_between_scratch_rows() {
+        let old = image(1000, 1);
+        let new = image(1000, 2);
+        let mut flash = flashed(&old);
+        let mut updater = Updater::open(&flash, LAYOUT);
+        send(&mut flash, &mut updater, &new);
+
+        flash.erases.clear();
+        reboot(&mut flash);
+        assert_eq!(slot(&flash, LAYOUT.app, new.len()), &new[..]);
+
+        for scratch in 0..LAYOUT.scratch_rows {
+            let row = LAYOUT.state + scratch * ROW_SIZE as u32;
+            assert_eq!(flash.erases.get(&row), Some(&2), "{:#x}", row);
+        }
+    }
+
+    #[test]
+    fn cancelling_forgets_the_firmware() {
+        let new = image(700, 2);
+        let mut flash = flash();
+        let mut updater = Updater::open(&flash, LAYOUT);
+        send(&mut flash, &mut updater, &new);
+
+        assert_eq!(updater.cancel(&mut flash).state, FirmwareState::Idle);
+        let updater = reboot(&mut flash);
+        assert_eq!(updater.state().boot, Boot::Confirmed);
+        assert_eq!(updater.status().state, FirmwareState::Idle);
+    }
+
+    #[test]
+    fn journal_wraps_around() {
+        let mut flash = flash();
+        let mut journal = Journal::open(&flash, LAYOUT);
+
+        let mut state = *journal.state();
+        for received in 0..20 {
+            state.received = received;
+            journal.save(&mut flash, state).unwrap();
+        }
+        assert_eq!(Journal::open(&flash, LAYOUT).state().received, 19);
+
+        // a note cut off partway through leaves the one before
+        state.received = 20;
+        flash.cut_power_after(1);
+        assert!(journal.save(&mut flash, state).is_err());
+        flash.restore_power();
+        let mut journal = Journal::open(&flash, LAYOUT);
+        assert_eq!(journal.state().received, 19);
+
+        state.received = 21;
+        journal.save(&mut flash, state).unwrap();
+        assert_eq!(Journal::open(&flash, LAYOUT).state().received, 21);
+    }
+}
@@ -0,0 +1,161 @@
+/// How many times the channel is checked before transmitting anyway
+pub const MAX_ATTEMPTS: u8 = 5;
+/// The unit of backoff, about as long as one of our frames at SF7
+pub const SLOT_MS: u32 = 100;
+/// The most slots a single backoff can last
+const MAX_SLOTS: u32 = 16;
+
+/// How the channel looked before a transmission
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clearance {
+    /// Nobody else was on the air
+    Clear,
+    /// Somebody was, but they finished while we backed off
+    Deferred,
+    /// Somebody was on the air every time we checked, we're going anyway
+    /// and will likely collide
+    Busy,
+}
+
+/// Randomised exponential backoff for listen before talk, so two devices
+/// that found the channel busy at the same time don't both try again at the
+/// same time.
+pub struct Backoff {
+    state: u32,
+}
+
+impl Backoff {
+    /// `seed` should differ between devices, anything from noise on the
+    /// radio to the device address does
+    pub const fn new(seed: u32) -> Self {
+        Self {
+            // xorshift gets stuck on zero
+            state: if seed == 0 { 0x9e37_79b9 } else { seed },
+        }
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// How long to wait after finding the channel busy `busy` times in a row,
+    /// a random number of slots from a window that doubles each time
+    pub fn delay_ms(&mut self, busy: u8) -> u32 {
+        let window = (1u32 << busy.min(31)).min(MAX_SLOTS);
+        (1 + self.next() % window) * SLOT_MS
+    }
+
+    /// Wait for the channel to clear, checking it with `busy` and backing off
+    /// with `wait` for up to [`MAX_ATTEMPTS`] checks. Both are handed `radio`,
+    /// which usually needs to do the waiting as well as the checking.
+    pub fn wait_for_clear<R>(
+        &mut self,
+        radio: &mut R,
+        mut busy: impl FnMut(&mut R) -> bool,
+        mut wait: impl FnMut(&mut R, u32),
+    ) -> Clearance {
+        for attempt in 0..MAX_ATTEMPTS {
+            if !busy(radio) {
+                return if attempt == 0 {
+                    Clearance::Clear
+                } else {
+                    Clearance::Deferred
+                };
+            }
+
+            if attempt + 1 < MAX_ATTEMPTS {
+                wait(radio, self.delay_ms(attempt + 1));
+            }
+        }
+
+        Clearance::Busy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clear_channel_goes_straight_away() {
+        let mut backoff = Backoff::new(1);
+        let mut waited = 0;
+
+        let clearance = backoff.wait_for_clear(&mut waited, |_| false, |w, ms| *w += ms);
+        assert_eq!(clearance, Clearance::Clear);
+        assert_eq!(waited, 0);
+    }
+
+    #[test]
+    fn backs_off_until_the_channel_clears() {
+        let mut backoff = Backoff::new(1);
+        let mut checks = 0;
+        let mut waits = Vec::new();
+
+        let clearance = backoff.wait_for_clear(
+            &mut waits,
+            |_| {
+                checks += 1;
+                checks < 3
+            },
+            |w, ms| w.push(ms),
+        );
+        assert_eq!(clearance, Clearance::Deferred);
+        assert_eq!(checks, 3);
+        assert_eq!(waits.len(), 2);
+        // the first backoff is one or two slots
+        assert!(waits[0] == SLOT_MS || waits[0] == 2 * SLOT_MS);
+    }
+
+    #[test]
+    fn gives_up_on_a_busy_channel() {
+        let mut backoff = Backoff::new(1);
+        let mut checks = 0;
+        let mut waits = 0;
+
+        let clearance = backoff.wait_for_clear(
+            &mut waits,
+            |_| {
+                checks += 1;
+                true
+            },
+            |w, _| *w += 1,
+        );
+        assert_eq!(clearance, Clearance::Busy);
+        assert_eq!(checks, MAX_ATTEMPTS);
+        // no point waiting after the last check
+        assert_eq!(waits, MAX_ATTEMPTS - 1);
+    }
+
+    #[test]
+    fn backoff_window_grows_and_is_capped() {
+        let mut backoff = Backoff::new(0xdead_beef);
+
+        for busy in 1..10u8 {
+            let window = (1u32 << busy).min(MAX_SLOTS);
+            let delays = (0..200)
+                .map(|_| backoff.delay_ms(busy) / SLOT_MS)
+                .collect::<Vec<_>>();
+
+            assert!(delays.iter().all(|&slots| (1..=window).contains(&slots)));
+            // and it's actually random across the window
+            assert!(delays.contains(&1));
+            assert!(delays.contains(&window));
+        }
+    }
+
+    #[test]
+    fn devices_back_off_differently() {
+        let mut a = Backoff::new(1);
+        let mut b = Backoff::new(2);
+
+        let a = (0..8).map(|_| a.delay_ms(4)).collect::<Vec<_>>();
+        let b = (0..8).map(|_| b.delay_ms(4)).collect::<Vec<_>>();
+        assert_ne!(a, b);
+    }
+}
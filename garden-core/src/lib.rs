@@ -0,0 +1,22 @@
+//! The parts of the transmitter's firmware that don't care which board they
+//! run on. Everything here is generic over embedded-hal traits so it can be
+//! tested on the host.
+#![cfg_attr(not(test), no_std)]
+
+pub mod airtime;
+pub mod bme;
+pub mod clock;
+pub mod config;
+pub mod control;
+pub mod firmware;
+pub mod lbt;
+pub mod link;
+pub mod moisture;
+pub mod nvm;
+pub mod outbox;
+pub mod repeater;
+pub mod time;
+pub mod watering;
+
+#[cfg(test)]
+mod mock;
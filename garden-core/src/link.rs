@@ -0,0 +1,225 @@
+use garden_shared::{LinkParams, LinkQuality, RadioConfig};
+
+/// Exchanges in a row the base station can miss on adapted settings before
+/// we go back to the configured ones
+pub const FALLBACK_AFTER: u8 = 3;
+
+/// What's kept of the link across a reset: the configured radio settings and
+/// the settings the base station asked for on top of them
+pub type Kept = (RadioConfig, LinkParams);
+
+/// The device's end of link adaptation. The base station picks a spreading
+/// factor and power from how well it hears us, which we use until it stops
+/// answering. Then we fall back to the configured radio settings, where the
+/// base station will come looking for us.
+///
+/// The base station only notices the device is gone after a while, so the
+/// settings are kept across a reset rather than going back to the configured
+/// ones it isn't listening on.
+pub struct Link {
+    params: Option<LinkParams>,
+    /// Unacked exchanges in a row on `params`
+    missed: u8,
+    /// How the last frame from the base station came through, until it's
+    /// reported back
+    downlink: Option<LinkQuality>,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Link {
+    pub const fn new() -> Self {
+        Self {
+            params: None,
+            missed: 0,
+            downlink: None,
+        }
+    }
+
+    /// Carry on with the settings kept from before a reset, unless the
+    /// configured settings have changed since
+    pub fn resume(kept: Option<Kept>, config: &RadioConfig) -> Self {
+        let mut link = Self::new();
+        if let Some((radio, params)) = kept {
+            if radio == *config {
+                link.set(Some(params), config);
+            }
+        }
+        link
+    }
+
+    /// What to keep across a reset, nothing if we're on the configured
+    /// settings
+    pub fn keep(&self, config: &RadioConfig) -> Option<Kept> {
+        self.params.map(|params| (*config, params))
+    }
+
+    pub fn params(&self) -> Option<LinkParams> {
+        self.params
+    }
+
+    /// The radio settings to use, given the configured ones
+    pub fn radio(&self, config: &RadioConfig) -> RadioConfig {
+        match self.params {
+            Some(params) => params.apply(config),
+            None => *config,
+        }
+    }
+
+    /// Take on the settings the base station asked for, ignoring them if the
+    /// radio can't do them. Returns whether they were taken.
+    pub fn set(&mut self, params: Option<LinkParams>, config: &RadioConfig) -> bool {
+        if let Some(params) = params {
+            if params.apply(config).validate().is_err() {
+                return false;
+            }
+        }
+
+        self.params = params;
+        self.missed = 0;
+        true
+    }
+
+    /// A frame from the base station came through
+    pub fn heard(&mut self, quality: LinkQuality) {
+        self.downlink = Some(quality);
+    }
+
+    /// How the last frame from the base station came through, only handed
+    /// out once so the base station doesn't store it twice
+    pub fn take_downlink(&mut self) -> Option<LinkQuality> {
+        self.downlink.take()
+    }
+
+    /// Note whether the base station acked an exchange, returning true if
+    /// that was the last straw and we've fallen back to the configured
+    /// settings
+    pub fn on_exchange(&mut self, acked: bool) -> bool {
+        if acked || self.params.is_none() {
+            self.missed = 0;
+            return false;
+        }
+
+        self.missed += 1;
+        if self.missed < FALLBACK_AFTER {
+            return false;
+        }
+
+        self.params = None;
+        self.missed = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLOW: LinkParams = LinkParams {
+        spreading_factor: 10,
+        tx_power: 5,
+    };
+
+    #[test]
+    fn params_override_the_config() {
+        let config = RadioConfig::default();
+        let mut link = Link::new();
+        assert_eq!(link.radio(&config), config);
+
+        assert!(link.set(Some(SLOW), &config));
+        let radio = link.radio(&config);
+        assert_eq!(radio.spreading_factor, 10);
+        assert_eq!(radio.tx_power, 5);
+        assert_eq!(radio.frequency, config.frequency);
+
+        assert!(link.set(None, &config));
+        assert_eq!(link.radio(&config), config);
+    }
+
+    #[test]
+    fn ignores_params_the_radio_cant_do() {
+        let config = RadioConfig::default();
+        let mut link = Link::new();
+        link.set(Some(SLOW), &config);
+
+        assert!(!link.set(
+            Some(LinkParams {
+                spreading_factor: 13,
+                tx_power: 5
+            }),
+            &config
+        ));
+        assert!(!link.set(
+            Some(LinkParams {
+                spreading_factor: 7,
+                tx_power: 30
+            }),
+            &config
+        ));
+        assert_eq!(link.params(), Some(SLOW));
+    }
+
+    #[test]
+    fn falls_back_once_the_base_station_goes_quiet() {
+        let config = RadioConfig::default();
+        let mut link = Link::new();
+        link.set(Some(SLOW), &config);
+
+        for _ in 1..FALLBACK_AFTER {
+            assert!(!link.on_exchange(false));
+        }
+        // an ack starts the count again
+        assert!(!link.on_exchange(true));
+        for _ in 1..FALLBACK_AFTER {
+            assert!(!link.on_exchange(false));
+        }
+        assert_eq!(link.params(), Some(SLOW));
+
+        assert!(link.on_exchange(false));
+        assert_eq!(link.params(), None);
+        assert_eq!(link.radio(&config), config);
+    }
+
+    #[test]
+    fn nothing_to_fall_back_from_on_the_config() {
+        let mut link = Link::new();
+
+        for _ in 0..FALLBACK_AFTER * 2 {
+            assert!(!link.on_exchange(false));
+        }
+    }
+
+    #[test]
+    fn adapted_settings_are_kept_across_a_reset() {
+        let config = RadioConfig::default();
+        let mut link = Link::new();
+        assert_eq!(link.keep(&config), None);
+
+        link.set(Some(SLOW), &config);
+        let link = Link::resume(link.keep(&config), &config);
+        assert_eq!(link.params(), Some(SLOW));
+
+        // unless the settings they were adapted from changed
+        let moved = RadioConfig {
+            frequency: config.frequency + 1_000_000,
+            ..config
+        };
+        let link = Link::resume(link.keep(&config), &moved);
+        assert_eq!(link.params(), None);
+        assert_eq!(link.radio(&moved), moved);
+    }
+
+    #[test]
+    fn downlink_is_reported_once() {
+        let mut link = Link::new();
+        let quality = LinkQuality { rssi: -90, snr: 3 };
+
+        link.heard(quality);
+        assert_eq!(link.take_downlink(), Some(quality));
+        assert_eq!(link.take_downlink(), None);
+    }
+}
@@ -0,0 +1,270 @@
+//! Stand-ins for the hardware, for testing on the host
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use embedded_hal::digital::v2::OutputPin;
+use garden_shared::{MoistureReading, MoistureSensorReport};
+
+use crate::moisture::PulseInput;
+use crate::time::{Duration, Instant};
+
+/// `secs` seconds after boot
+pub fn at(secs: u32) -> Instant {
+    Instant::from_ticks(0) + Duration::secs(secs)
+}
+
+/// A report where each sensor counted the given number of pulses in a second
+pub fn moisture_report(per_second: &[f32]) -> MoistureSensorReport {
+    MoistureSensorReport {
+        moisture: per_second
+            .iter()
+            .map(|&n| MoistureReading {
+                clocks: n as u16,
+                duration: core::time::Duration::from_secs(1),
+            })
+            .collect(),
+    }
+}
+
+/// An output pin that can be inspected through its clones
+#[derive(Clone, Default)]
+pub struct MockPin(Rc<Cell<bool>>);
+
+impl MockPin {
+    pub fn is_high(&self) -> bool {
+        self.0.get()
+    }
+}
+
+impl OutputPin for MockPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set(true);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Pulses {
+    enabled: Cell<bool>,
+    pending: Cell<bool>,
+}
+
+/// An interrupt line that only sees edges while it is enabled
+#[derive(Clone, Default)]
+pub struct MockPulses(Rc<Pulses>);
+
+impl MockPulses {
+    pub fn edge(&self) {
+        if self.0.enabled.get() {
+            self.0.pending.set(true);
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.enabled.get()
+    }
+}
+
+impl PulseInput for MockPulses {
+    type Controller = ();
+
+    fn enable(&mut self, _: &mut ()) {
+        self.0.enabled.set(true);
+    }
+
+    fn disable(&mut self, _: &mut ()) {
+        self.0.enabled.set(false);
+    }
+
+    fn take_edge(&mut self) -> bool {
+        self.0.pending.replace(false)
+    }
+}
+
+pub struct NoDelay;
+
+impl DelayMs<u16> for NoDelay {
+    fn delay_ms(&mut self, _: u16) {}
+}
+
+const CHIP_ID: u8 = 0x61;
+const CHIP_ID_ADDR: u8 = 0xD0;
+const CTRL_MEAS_ADDR: u8 = 0x74;
+const STATUS_ADDR: u8 = 0x1D;
+const NEW_DATA: u8 = 0x80;
+/// Forced mode in the bottom bits of ctrl_meas
+const FORCED: u8 = 0b01;
+
+#[derive(Debug)]
+pub struct BusError;
+
+struct Bme {
+    registers: RefCell<[u8; 256]>,
+    failing: Cell<bool>,
+    triggered: Cell<bool>,
+}
+
+/// A BME680 on an I2C bus, as a bank of registers with data always ready
+#[derive(Clone)]
+pub struct FakeBme(Rc<Bme>);
+
+impl FakeBme {
+    pub fn new() -> Self {
+        let mut registers = [0; 256];
+        registers[CHIP_ID_ADDR as usize] = CHIP_ID;
+        registers[STATUS_ADDR as usize] = NEW_DATA;
+
+        Self(Rc::new(Bme {
+            registers: RefCell::new(registers),
+            failing: Cell::new(false),
+            triggered: Cell::new(false),
+        }))
+    }
+
+    pub fn set_register(&self, register: u8, value: u8) {
+        self.0.registers.borrow_mut()[register as usize] = value;
+    }
+
+    /// Make every transfer fail
+    pub fn set_failing(&self, failing: bool) {
+        self.0.failing.set(failing);
+    }
+
+    /// Whether a measurement was started
+    pub fn was_triggered(&self) -> bool {
+        self.0.triggered.get()
+    }
+}
+
+impl Write for FakeBme {
+    type Error = BusError;
+
+    fn write(&mut self, _address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        if self.0.failing.get() {
+            return Err(BusError);
+        }
+
+        if let [register, values @ ..] = bytes {
+            for (n, &value) in values.iter().enumerate() {
+                let register = *register as usize + n;
+                if register == CTRL_MEAS_ADDR as usize && value & 0b11 == FORCED {
+                    self.0.triggered.set(true);
+                    // the measurement finishes straight away and the sensor
+                    // drops back to sleep
+                    self.0.registers.borrow_mut()[register] = value & !0b11;
+                } else {
+                    self.0.registers.borrow_mut()[register] = value;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl WriteRead for FakeBme {
+    type Error = BusError;
+
+    fn write_read(
+        &mut self,
+        _address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        if self.0.failing.get() {
+            return Err(BusError);
+        }
+
+        let start = bytes[0] as usize;
+        buffer.copy_from_slice(&self.0.registers.borrow()[start..start + buffer.len()]);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PowerCut;
+
+/// Flash held in memory, which like the real thing can only clear bits when
+/// written. The power can be cut partway through a run of operations.
+pub struct MockFlash {
+    pub data: Vec<u8>,
+    /// How many times each row has been erased
+    pub erases: HashMap<u32, u32>,
+    ops_left: Option<usize>,
+}
+
+impl MockFlash {
+    /// `len` bytes of erased flash
+    pub fn new(len: usize) -> Self {
+        Self {
+            data: vec![0xff; len],
+            erases: HashMap::new(),
+            ops_left: None,
+        }
+    }
+
+    /// Fail every erase and write after the next `ops`
+    pub fn cut_power_after(&mut self, ops: usize) {
+        self.ops_left = Some(ops);
+    }
+
+    pub fn restore_power(&mut self) {
+        self.ops_left = None;
+    }
+
+    fn operate(&mut self) -> Result<(), PowerCut> {
+        match &mut self.ops_left {
+            Some(0) => Err(PowerCut),
+            Some(n) => {
+                *n -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl crate::nvm::Nvm for MockFlash {
+    type Error = PowerCut;
+
+    fn read(&self, addr: u32, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.data[addr as usize..addr as usize + buf.len()]);
+    }
+
+    fn erase_row(&mut self, addr: u32) -> Result<(), PowerCut> {
+        self.operate()?;
+        *self.erases.entry(addr).or_default() += 1;
+        let addr = addr as usize;
+        assert_eq!(addr % crate::nvm::ROW_SIZE, 0);
+        self.data[addr..addr + crate::nvm::ROW_SIZE].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), PowerCut> {
+        let addr = addr as usize;
+        assert_eq!(addr % crate::nvm::PAGE_SIZE, 0);
+        assert_eq!(data.len() % crate::nvm::PAGE_SIZE, 0);
+
+        // a page at a time, so a cut can leave a write half done
+        for (n, page) in data.chunks(crate::nvm::PAGE_SIZE).enumerate() {
+            self.operate()?;
+            let start = addr + n * crate::nvm::PAGE_SIZE;
+            for (old, new) in self.data[start..start + page.len()].iter_mut().zip(page) {
+                *old &= new;
+            }
+        }
+        Ok(())
+    }
+}
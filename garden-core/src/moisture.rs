@@ -0,0 +1,258 @@
+use embedded_hal::digital::v2::OutputPin;
+use garden_shared::{MoistureReading, MoistureSensorReport};
+
+use crate::time::{Duration, Instant};
+
+/// The input the moisture sensors' oscillator is counted on
+pub trait PulseInput {
+    /// Whatever else is needed to switch the input on and off, the EIC on the
+    /// SAMD21
+    type Controller;
+
+    fn enable(&mut self, controller: &mut Self::Controller);
+    fn disable(&mut self, controller: &mut Self::Controller);
+
+    /// Whether there's an edge waiting to be counted, clearing it if so
+    fn take_edge(&mut self) -> bool;
+}
+
+/// Reads up to seven moisture sensors through a multiplexer, counting pulses
+/// from each one's oscillator for a second in turn
+pub struct Moisture<I, A1, A2, A3, const PINS: usize> {
+    readings: [Option<(u16, Duration)>; PINS],
+    input: I,
+    a1: A1,
+    a2: A2,
+    a3: A3,
+    state: State,
+    count: u16,
+}
+
+enum State {
+    Off,
+    Measuring(u8, Instant),
+}
+
+fn set(pin: &mut impl OutputPin, high: bool) {
+    // the pins on the boards we use can't fail
+    let _ = if high { pin.set_high() } else { pin.set_low() };
+}
+
+impl<I, A1, A2, A3, const PINS: usize> Moisture<I, A1, A2, A3, PINS>
+where
+    I: PulseInput,
+    A1: OutputPin,
+    A2: OutputPin,
+    A3: OutputPin,
+{
+    const PINS_FIT: () = assert!(PINS < 8, "the multiplexer has 3 select lines");
+
+    const BETWEEN_READINGS_DELAY: Duration = Duration::secs(1);
+
+    pub fn new(input: I, a1: A1, a2: A2, a3: A3) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::PINS_FIT;
+
+        Self {
+            readings: [None; PINS],
+            input,
+            a1,
+            a2,
+            a3,
+            state: State::Off,
+            count: 0,
+        }
+    }
+
+    fn set_on(&mut self, controller: &mut I::Controller) {
+        self.input.enable(controller);
+        self.count = 0;
+        self.readings.fill(None);
+    }
+
+    fn set_off(&mut self, controller: &mut I::Controller) {
+        self.input.disable(controller);
+    }
+
+    fn set_pins_for(&mut self, n: u8) {
+        set(&mut self.a1, (n & 0b1) == 0b1);
+        set(&mut self.a2, (n & 0b10) == 0b10);
+        set(&mut self.a3, (n & 0b100) == 0b100);
+    }
+
+    pub fn is_reading_ready(&self) -> bool {
+        matches!(self.state, State::Off)
+    }
+
+    pub fn format_message(&self) -> MoistureSensorReport {
+        let r = self
+            .readings
+            .iter()
+            .map(|r| {
+                let (clocks, duration) = r.unwrap();
+
+                MoistureReading {
+                    clocks,
+                    duration: core::time::Duration::from_millis(duration.to_millis() as u64),
+                }
+            })
+            .collect::<heapless::Vec<_, 8>>();
+
+        MoistureSensorReport { moisture: r }
+    }
+
+    /// Move on to the next sensor, returning how long to wait before calling
+    /// this again
+    pub fn step_state(
+        &mut self,
+        controller: &mut I::Controller,
+        now: Instant,
+        between_measurements_delay: Duration,
+    ) -> Duration {
+        let (new_state, next_step_delay) = match self.state {
+            State::Off => {
+                self.set_pins_for(0);
+                self.set_on(controller);
+                (State::Measuring(0, now), Self::BETWEEN_READINGS_DELAY)
+            }
+            State::Measuring(n, inst) => {
+                let reading = core::mem::replace(&mut self.count, 0);
+                let duration = now
+                    .checked_duration_since(inst)
+                    .unwrap_or(Duration::from_ticks(0));
+                self.readings[n as usize] = Some((reading, duration));
+
+                if (n + 1) as usize == PINS {
+                    self.set_off(controller);
+                    (State::Off, between_measurements_delay)
+                } else {
+                    self.set_pins_for(n + 1);
+                    (State::Measuring(n + 1, now), Self::BETWEEN_READINGS_DELAY)
+                }
+            }
+        };
+
+        self.state = new_state;
+
+        next_step_delay
+    }
+
+    pub fn tick_count(&mut self) {
+        if self.input.take_edge() {
+            self.count += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{at, MockPin, MockPulses};
+
+    fn moisture() -> (
+        Moisture<MockPulses, MockPin, MockPin, MockPin, 3>,
+        MockPulses,
+        [MockPin; 3],
+    ) {
+        let input = MockPulses::default();
+        let pins = [MockPin::default(), MockPin::default(), MockPin::default()];
+        let m = Moisture::new(
+            input.clone(),
+            pins[0].clone(),
+            pins[1].clone(),
+            pins[2].clone(),
+        );
+        (m, input, pins)
+    }
+
+    fn pulse(
+        m: &mut Moisture<MockPulses, MockPin, MockPin, MockPin, 3>,
+        input: &MockPulses,
+        n: u16,
+    ) {
+        for _ in 0..n {
+            input.edge();
+            m.tick_count();
+        }
+    }
+
+    #[test]
+    fn measures_each_sensor_in_turn() {
+        let (mut m, input, pins) = moisture();
+        let between = Duration::secs(60);
+
+        assert_eq!(m.step_state(&mut (), at(0), between), Duration::secs(1));
+        assert!(input.is_enabled());
+        assert!(!m.is_reading_ready());
+
+        for (n, count) in [100, 200, 300].into_iter().enumerate() {
+            let selected = pins.iter().map(|p| p.is_high()).collect::<Vec<_>>();
+            assert_eq!(selected, [n & 1 != 0, n & 2 != 0, n & 4 != 0]);
+
+            pulse(&mut m, &input, count);
+            m.step_state(&mut (), at(n as u32 + 1), between);
+        }
+
+        assert!(m.is_reading_ready());
+        assert!(!input.is_enabled());
+
+        let report = m.format_message();
+        let per_second = report
+            .moisture
+            .iter()
+            .map(|r| r.per_second())
+            .collect::<Vec<_>>();
+        assert_eq!(per_second, [100.0, 200.0, 300.0]);
+    }
+
+    #[test]
+    fn waits_between_measurements() {
+        let (mut m, _, _) = moisture();
+        let between = Duration::secs(60);
+
+        for n in 0..3 {
+            assert_eq!(m.step_state(&mut (), at(n), between), Duration::secs(1));
+        }
+        assert_eq!(m.step_state(&mut (), at(3), between), between);
+    }
+
+    #[test]
+    fn ignores_pulses_without_an_edge() {
+        let (mut m, input, _) = moisture();
+
+        m.step_state(&mut (), at(0), Duration::secs(60));
+        pulse(&mut m, &input, 5);
+        // spurious interrupts for other lines don't count
+        m.tick_count();
+        m.tick_count();
+
+        for n in 1..=3 {
+            m.step_state(&mut (), at(n), Duration::secs(60));
+        }
+
+        assert_eq!(m.format_message().moisture[0].clocks, 5);
+    }
+
+    #[test]
+    fn starts_afresh_each_measurement() {
+        let (mut m, input, _) = moisture();
+        let between = Duration::secs(60);
+
+        m.step_state(&mut (), at(0), between);
+        pulse(&mut m, &input, 50);
+        for n in 1..=3 {
+            m.step_state(&mut (), at(n), between);
+        }
+
+        // counted while the sensors were off
+        pulse(&mut m, &input, 1000);
+
+        m.step_state(&mut (), at(63), between);
+        pulse(&mut m, &input, 20);
+        for n in 64..=66 {
+            m.step_state(&mut (), at(n), between);
+        }
+
+        assert_eq!(m.format_message().moisture[0].clocks, 20);
+    }
+}
@@ -0,0 +1,215 @@
+//! The device's internal flash, and the log of records the device config and
+//! the firmware update journal are each kept in.
+
+// `is_multiple_of` is newer than the firmware's toolchain
+#![allow(clippy::manual_is_multiple_of)]
+
+use garden_shared::crc32;
+
+/// Size of a page, the unit of writes
+pub const PAGE_SIZE: usize = 64;
+/// Size of a row, the unit of erases
+pub const ROW_SIZE: usize = PAGE_SIZE * 4;
+
+/// Flash that's written a page and erased a row at a time
+pub trait Nvm {
+    type Error;
+
+    fn read(&self, addr: u32, buf: &mut [u8]);
+    fn erase_row(&mut self, addr: u32) -> Result<(), Self::Error>;
+    /// Write whole pages, the target must have been erased beforehand
+    fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Every record is two pages, so two fit in a row
+const RECORD_LEN: usize = 2 * PAGE_SIZE;
+
+// magic, version, body length, sequence number
+const HEADER_LEN: usize = 2 + 1 + 1 + 4;
+const CRC_LEN: usize = 4;
+
+/// The most a record can hold
+pub const BODY_LEN: usize = RECORD_LEN - HEADER_LEN - CRC_LEN;
+
+const SLOTS_PER_ROW: usize = ROW_SIZE / RECORD_LEN;
+
+/// A log of records in a reserved, row aligned area of flash.
+///
+/// Each record goes into the next slot and a row is only erased when the log
+/// wraps back around onto it, spreading the wear over the whole area. The
+/// record with the highest sequence number and a valid CRC wins, so a write
+/// interrupted by a reset just leaves the previous record in place.
+pub struct Log {
+    start: u32,
+    slots: usize,
+    magic: u16,
+    version: u8,
+    next_slot: usize,
+    next_seq: u32,
+}
+
+impl Log {
+    /// Records are tagged with `magic` and `version`, bump the version
+    /// whenever the layout of what's in them changes and older ones are then
+    /// ignored
+    pub const fn new(start: u32, len: u32, magic: u16, version: u8) -> Self {
+        Self {
+            start,
+            slots: len as usize / RECORD_LEN,
+            magic,
+            version,
+            next_slot: 0,
+            next_seq: 0,
+        }
+    }
+
+    fn slot_addr(&self, slot: usize) -> u32 {
+        self.start + (slot * RECORD_LEN) as u32
+    }
+
+    fn read_slot<N: Nvm, T>(
+        &self,
+        nvm: &N,
+        slot: usize,
+        decode: &impl Fn(&[u8]) -> Option<T>,
+    ) -> Option<(u32, T)> {
+        let mut buf = [0u8; RECORD_LEN];
+        nvm.read(self.slot_addr(slot), &mut buf);
+
+        let magic = u16::from_le_bytes([buf[0], buf[1]]);
+        let version = buf[2];
+        let len = buf[3] as usize;
+
+        if magic != self.magic || version != self.version || len > BODY_LEN {
+            return None;
+        }
+
+        let seq = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let crc = &buf[HEADER_LEN + len..][..CRC_LEN];
+        let crc = u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]);
+
+        if crc32(&buf[..HEADER_LEN + len]) != crc {
+            return None;
+        }
+
+        Some((seq, decode(&buf[HEADER_LEN..HEADER_LEN + len])?))
+    }
+
+    fn slot_is_blank<N: Nvm>(&self, nvm: &N, slot: usize) -> bool {
+        let mut buf = [0u8; RECORD_LEN];
+        nvm.read(self.slot_addr(slot), &mut buf);
+        buf.iter().all(|&b| b == 0xff)
+    }
+
+    /// Find the newest record `decode` makes sense of, new records then go
+    /// after it
+    pub fn load<N: Nvm, T>(&mut self, nvm: &N, decode: impl Fn(&[u8]) -> Option<T>) -> Option<T> {
+        let (slot, seq, value) = (0..self.slots)
+            .filter_map(|slot| {
+                self.read_slot(nvm, slot, &decode)
+                    .map(|(seq, value)| (slot, seq, value))
+            })
+            .max_by_key(|(_, seq, _)| *seq)?;
+
+        self.next_slot = (slot + 1) % self.slots;
+        self.next_seq = seq.wrapping_add(1);
+
+        Some(value)
+    }
+
+    /// Add a record holding `body`, which is at most [`BODY_LEN`] long
+    pub fn append<N: Nvm>(&mut self, nvm: &mut N, body: &[u8]) -> Result<(), N::Error> {
+        assert!(body.len() <= BODY_LEN);
+
+        let mut buf = [0xffu8; RECORD_LEN];
+        buf[0..2].copy_from_slice(&self.magic.to_le_bytes());
+        buf[2] = self.version;
+        buf[3] = body.len() as u8;
+        buf[4..8].copy_from_slice(&self.next_seq.to_le_bytes());
+        buf[HEADER_LEN..HEADER_LEN + body.len()].copy_from_slice(body);
+        let crc = crc32(&buf[..HEADER_LEN + body.len()]);
+        buf[HEADER_LEN + body.len()..][..CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        // a slot that isn't blank midway through a row means an earlier write
+        // was interrupted, skip ahead to a fresh row rather than write over it
+        if self.next_slot % SLOTS_PER_ROW != 0 && !self.slot_is_blank(nvm, self.next_slot) {
+            self.next_slot = (self.next_slot / SLOTS_PER_ROW + 1) * SLOTS_PER_ROW % self.slots;
+        }
+
+        let addr = self.slot_addr(self.next_slot);
+        if self.next_slot % SLOTS_PER_ROW == 0 {
+            nvm.erase_row(addr)?;
+        }
+        nvm.write(addr, &buf)?;
+
+        self.next_slot = (self.next_slot + 1) % self.slots;
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockFlash;
+
+    const LEN: u32 = 2 * ROW_SIZE as u32;
+
+    fn log() -> Log {
+        Log::new(ROW_SIZE as u32, LEN, 0x1234, 1)
+    }
+
+    fn byte(body: &[u8]) -> Option<u8> {
+        body.first().copied()
+    }
+
+    #[test]
+    fn newest_record_wins_as_it_wraps_around() {
+        let mut flash = MockFlash::new(4 * ROW_SIZE);
+        let mut log = log();
+        assert_eq!(log.load(&flash, byte), None);
+
+        for n in 0..20 {
+            log.append(&mut flash, &[n]).unwrap();
+            assert_eq!(self::log().load(&flash, byte), Some(n));
+        }
+
+        // nothing outside of the area was touched
+        assert!(flash.data[..ROW_SIZE].iter().all(|&b| b == 0xff));
+        assert!(flash.data[3 * ROW_SIZE..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn interrupted_writes_leave_the_one_before() {
+        let mut flash = MockFlash::new(4 * ROW_SIZE);
+        let mut log = log();
+        log.append(&mut flash, &[1]).unwrap();
+        log.append(&mut flash, &[2]).unwrap();
+
+        // half a record
+        flash.cut_power_after(1);
+        assert!(log.append(&mut flash, &[3]).is_err());
+        flash.restore_power();
+
+        let mut log = self::log();
+        assert_eq!(log.load(&flash, byte), Some(2));
+
+        // and the next one steps around what's left of it
+        log.append(&mut flash, &[4]).unwrap();
+        assert_eq!(self::log().load(&flash, byte), Some(4));
+        log.append(&mut flash, &[5]).unwrap();
+        assert_eq!(self::log().load(&flash, byte), Some(5));
+    }
+
+    #[test]
+    fn ignores_records_of_other_versions() {
+        let mut flash = MockFlash::new(4 * ROW_SIZE);
+        log().append(&mut flash, &[1]).unwrap();
+
+        let mut newer = Log::new(ROW_SIZE as u32, LEN, 0x1234, 2);
+        assert_eq!(newer.load(&flash, byte), None);
+        let mut other = Log::new(ROW_SIZE as u32, LEN, 0x4321, 1);
+        assert_eq!(other.load(&flash, byte), None);
+    }
+}
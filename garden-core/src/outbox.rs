@@ -0,0 +1,171 @@
+use garden_shared::Message;
+
+pub struct Entry {
+    pub seq: u16,
+    /// Uptime in milliseconds when the message was produced
+    pub captured: u64,
+    pub msg: Message,
+}
+
+/// Ring buffer of messages that haven't been acknowledged by the base station
+/// yet. Once full the oldest entries are dropped to make room.
+pub struct Outbox<const N: usize> {
+    entries: heapless::Deque<Entry, N>,
+    next_seq: u16,
+}
+
+/// Whether a message is worth replaying if the base station missed it, status
+/// updates and the like are superseded by the next one anyway
+pub fn is_durable(msg: &Message) -> bool {
+    matches!(
+        msg,
+        Message::MoistureReport(_)
+            | Message::BME688Report(_)
+            | Message::Watering(_)
+            | Message::CrashReport(_)
+    )
+}
+
+impl<const N: usize> Default for Outbox<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Outbox<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: heapless::Deque::new(),
+            next_seq: 0,
+        }
+    }
+
+    /// Allocate a sequence number for a message that won't be kept
+    pub fn next_seq(&mut self) -> u16 {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        seq
+    }
+
+    pub fn push(&mut self, msg: Message, uptime_ms: u64) -> u16 {
+        let seq = self.next_seq();
+
+        if self.entries.is_full() {
+            self.entries.pop_front();
+        }
+
+        let _ = self.entries.push_back(Entry {
+            seq,
+            captured: uptime_ms,
+            msg,
+        });
+
+        seq
+    }
+
+    /// Drop the entry with the given sequence number, returning whether it was
+    /// still waiting
+    pub fn ack(&mut self, seq: u16) -> bool {
+        let before = self.entries.len();
+
+        // heapless' deque can't remove from the middle, so rotate through it
+        for _ in 0..before {
+            if let Some(entry) = self.entries.pop_front() {
+                if entry.seq != seq {
+                    let _ = self.entries.push_back(entry);
+                }
+            }
+        }
+
+        self.entries.len() != before
+    }
+
+    pub fn oldest(&self) -> Option<&Entry> {
+        self.entries.front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use garden_shared::{DeviceStatus, StatusFlags, WateringEvent};
+
+    fn event(level: f32) -> Message {
+        Message::Watering(WateringEvent::Started { level })
+    }
+
+    fn level(entry: &Entry) -> f32 {
+        match entry.msg {
+            Message::Watering(WateringEvent::Started { level }) => level,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn acked_messages_are_dropped() {
+        let mut outbox = Outbox::<4>::new();
+
+        let a = outbox.push(event(1.0), 0);
+        let b = outbox.push(event(2.0), 10);
+        assert_eq!(outbox.len(), 2);
+
+        assert!(outbox.ack(a));
+        assert!(!outbox.ack(a));
+        assert_eq!(outbox.oldest().map(|e| e.seq), Some(b));
+
+        assert!(outbox.ack(b));
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn acks_out_of_order_keep_the_rest_in_order() {
+        let mut outbox = Outbox::<4>::new();
+
+        outbox.push(event(1.0), 0);
+        let b = outbox.push(event(2.0), 10);
+        outbox.push(event(3.0), 20);
+
+        outbox.ack(b);
+        assert_eq!(level(outbox.oldest().unwrap()), 1.0);
+        assert_eq!(outbox.len(), 2);
+    }
+
+    #[test]
+    fn drops_the_oldest_when_full() {
+        let mut outbox = Outbox::<2>::new();
+
+        outbox.push(event(1.0), 0);
+        outbox.push(event(2.0), 10);
+        outbox.push(event(3.0), 20);
+
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(level(outbox.oldest().unwrap()), 2.0);
+        assert_eq!(outbox.oldest().unwrap().captured, 10);
+    }
+
+    #[test]
+    fn sequence_numbers_are_shared_and_wrap() {
+        let mut outbox = Outbox::<2>::new();
+        outbox.next_seq = u16::MAX;
+
+        assert_eq!(outbox.next_seq(), u16::MAX);
+        assert_eq!(outbox.push(event(1.0), 0), 0);
+        assert_eq!(outbox.next_seq(), 1);
+    }
+
+    #[test]
+    fn only_readings_are_durable() {
+        assert!(is_durable(&event(1.0)));
+        assert!(!is_durable(&Message::StatusUpdate(DeviceStatus {
+            flags: StatusFlags::empty()
+        })));
+    }
+}
@@ -0,0 +1,369 @@
+//! Passing frames on between end nodes and the base station, for devices
+//! further out than a single hop reaches.
+//!
+//! Frames from a device are for the base station, a repeater passes on any
+//! it hears with itself as the `via`, so the base station knows which way to
+//! answer. Answers name the repeater they should go through, which passes
+//! them on towards the device the way its frames came in.
+
+use core::time::Duration;
+
+use garden_shared::{crc32, DevAddr, Header, Route, BASE_ADDR};
+
+/// The most repeaters a frame is passed on by
+pub const MAX_HOPS: u8 = 3;
+/// How long an end node listens for the base station's replies when they
+/// come straight back
+pub const REPLY_WINDOW: Duration = Duration::from_millis(500);
+/// On top of a frame's time on the air, how long a repeater can take to
+/// pass it on: turning around and listening before it talks
+pub const HOP_MARGIN: Duration = Duration::from_millis(200);
+
+/// How many frames are remembered for spotting them again
+const RECENT: usize = 16;
+/// How many devices a repeater remembers the way to
+const ROUTES: usize = 8;
+
+/// Split a frame into its [`Header`] and the rest, which a repeater passes
+/// on as it is
+pub fn split(frame: &[u8]) -> Option<(Header, &[u8])> {
+    postcard::take_from_bytes(frame).ok()
+}
+
+/// Frames heard lately, to spot the same one coming in again another way.
+/// The same frame through another repeater only differs in its route, so
+/// they're told apart by who sent them and what comes after the header.
+pub struct Seen<const N: usize> {
+    frames: heapless::Deque<(DevAddr, u32), N>,
+}
+
+impl<const N: usize> Default for Seen<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Seen<N> {
+    pub const fn new() -> Self {
+        Self {
+            frames: heapless::Deque::new(),
+        }
+    }
+
+    /// Note down a frame from `src`, returning whether it had been seen
+    /// already
+    pub fn check(&mut self, src: DevAddr, body: &[u8]) -> bool {
+        let key = (src, crc32(body));
+        if self.frames.iter().any(|seen| *seen == key) {
+            return true;
+        }
+
+        if self.frames.is_full() {
+            self.frames.pop_front();
+        }
+        let _ = self.frames.push_back(key);
+
+        false
+    }
+}
+
+/// A repeater's end of passing frames on
+pub struct Repeater {
+    seen: Seen<RECENT>,
+    /// The repeater each device's frames came in through, `None` for devices
+    /// in earshot
+    routes: heapless::Deque<(DevAddr, Option<DevAddr>), ROUTES>,
+}
+
+impl Default for Repeater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repeater {
+    pub const fn new() -> Self {
+        Self {
+            seen: Seen::new(),
+            routes: heapless::Deque::new(),
+        }
+    }
+
+    /// Which way frames for `dst` should go next, if we know
+    pub fn next_hop(&self, dst: DevAddr) -> Option<DevAddr> {
+        self.routes
+            .iter()
+            .find(|(addr, _)| *addr == dst)
+            .and_then(|(_, via)| *via)
+    }
+
+    fn learn(&mut self, src: DevAddr, via: Option<DevAddr>) {
+        if let Some(route) = self.routes.iter_mut().find(|(addr, _)| *addr == src) {
+            route.1 = via;
+            return;
+        }
+
+        if self.routes.is_full() {
+            self.routes.pop_front();
+        }
+        let _ = self.routes.push_back((src, via));
+    }
+
+    /// Work out whether `frame` is for us, at `addr`, to pass on. If it is
+    /// what to send is written to `out` and its length returned.
+    pub fn forward(&mut self, addr: DevAddr, frame: &[u8], out: &mut [u8]) -> Option<usize> {
+        let (Header { src, route }, body) = split(frame)?;
+
+        let uplink = route.dst == BASE_ADDR;
+        if src == addr || route.hops >= MAX_HOPS || !(uplink || route.via == Some(addr)) {
+            return None;
+        }
+
+        // the first copy of an uplink is the one that came the shortest way
+        if self.seen.check(src, body) {
+            return None;
+        }
+
+        let via = if uplink {
+            self.learn(src, route.via);
+            Some(addr)
+        } else {
+            // a device we haven't heard from is most likely in earshot
+            self.next_hop(route.dst)
+        };
+
+        let header = Header {
+            src,
+            route: Route {
+                dst: route.dst,
+                hops: route.hops + 1,
+                via,
+            },
+        };
+        let len = postcard::to_slice(&header, out).ok()?.len();
+        out.get_mut(len..len + body.len())?.copy_from_slice(body);
+
+        Some(len + body.len())
+    }
+}
+
+/// How many repeaters away the base station is, as far as an end node can
+/// tell from the replies that reach it. Until one does it could be as far as
+/// a frame can go, so it listens long enough to hear back from there.
+pub struct Distance {
+    hops: u8,
+}
+
+impl Default for Distance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Distance {
+    pub const fn new() -> Self {
+        Self { hops: MAX_HOPS }
+    }
+
+    pub fn hops(&self) -> u8 {
+        self.hops
+    }
+
+    /// A reply came through `hops` repeaters
+    pub fn heard(&mut self, hops: u8) {
+        self.hops = hops.min(MAX_HOPS);
+    }
+
+    /// Nothing came back from an exchange, the way there may have changed
+    pub fn missed(&mut self) {
+        self.hops = MAX_HOPS;
+    }
+
+    /// How long the repeaters take to pass a frame there and its reply back,
+    /// for frames around `on_air` long
+    pub fn round_trip(&self, on_air: Duration) -> Duration {
+        (2 * on_air + HOP_MARGIN) * self.hops as u32
+    }
+
+    /// How long to listen for replies to a frame that was `on_air` long
+    pub fn reply_window(&self, on_air: Duration) -> Duration {
+        REPLY_WINDOW + self.round_trip(on_air)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use garden_shared::{FrameTime, Transmission};
+
+    const DEVICE: DevAddr = DevAddr(0x69);
+    const REPEATER: DevAddr = DevAddr(0x70);
+    const FAR_REPEATER: DevAddr = DevAddr(0x71);
+
+    fn frame(src: DevAddr, route: Route, seq: u16) -> ([u8; 64], usize) {
+        let mut buffer = [0; 64];
+        let len = postcard::to_slice(
+            &Transmission {
+                src,
+                route,
+                seq,
+                time: FrameTime::now(1000, false),
+                link: None,
+                msg: seq as u32,
+            },
+            &mut buffer,
+        )
+        .unwrap()
+        .len();
+
+        (buffer, len)
+    }
+
+    fn forward(repeater: &mut Repeater, addr: DevAddr, frame: &[u8]) -> Option<Transmission<u32>> {
+        let mut out = [0; 64];
+        let len = repeater.forward(addr, frame, &mut out)?;
+        Some(postcard::from_bytes(&out[..len]).unwrap())
+    }
+
+    #[test]
+    fn passes_uplinks_on_through_itself() {
+        let mut repeater = Repeater::new();
+        let (uplink, len) = frame(DEVICE, Route::to(BASE_ADDR), 1);
+
+        let passed = forward(&mut repeater, REPEATER, &uplink[..len]).unwrap();
+        assert_eq!(passed.src, DEVICE);
+        assert_eq!(passed.route.dst, BASE_ADDR);
+        assert_eq!(passed.route.hops, 1);
+        assert_eq!(passed.route.via, Some(REPEATER));
+        assert_eq!(passed.seq, 1);
+        assert_eq!(passed.msg, 1);
+    }
+
+    #[test]
+    fn passes_each_frame_on_once() {
+        let mut repeater = Repeater::new();
+        let (uplink, len) = frame(DEVICE, Route::to(BASE_ADDR), 1);
+
+        assert!(forward(&mut repeater, REPEATER, &uplink[..len]).is_some());
+        assert!(forward(&mut repeater, REPEATER, &uplink[..len]).is_none());
+
+        // the same frame passed on by another repeater is still the same frame
+        let (relayed, len) = frame(
+            DEVICE,
+            Route {
+                dst: BASE_ADDR,
+                hops: 1,
+                via: Some(FAR_REPEATER),
+            },
+            1,
+        );
+        assert!(forward(&mut repeater, REPEATER, &relayed[..len]).is_none());
+
+        let (next, len) = frame(DEVICE, Route::to(BASE_ADDR), 2);
+        assert!(forward(&mut repeater, REPEATER, &next[..len]).is_some());
+    }
+
+    #[test]
+    fn stops_after_too_many_hops() {
+        let mut repeater = Repeater::new();
+        let (uplink, len) = frame(
+            DEVICE,
+            Route {
+                dst: BASE_ADDR,
+                hops: MAX_HOPS,
+                via: Some(FAR_REPEATER),
+            },
+            1,
+        );
+
+        assert!(forward(&mut repeater, REPEATER, &uplink[..len]).is_none());
+    }
+
+    #[test]
+    fn only_passes_on_downlinks_sent_through_it() {
+        let mut repeater = Repeater::new();
+
+        let (direct, len) = frame(BASE_ADDR, Route::to(DEVICE), 1);
+        assert!(forward(&mut repeater, REPEATER, &direct[..len]).is_none());
+
+        let (other, len) = frame(
+            BASE_ADDR,
+            Route {
+                dst: DEVICE,
+                hops: 0,
+                via: Some(FAR_REPEATER),
+            },
+            2,
+        );
+        assert!(forward(&mut repeater, REPEATER, &other[..len]).is_none());
+
+        let (ours, len) = frame(
+            BASE_ADDR,
+            Route {
+                dst: DEVICE,
+                hops: 0,
+                via: Some(REPEATER),
+            },
+            3,
+        );
+        let passed = forward(&mut repeater, REPEATER, &ours[..len]).unwrap();
+        assert_eq!(passed.route.dst, DEVICE);
+        assert_eq!(passed.route.hops, 1);
+        // a device we haven't heard from is tried in earshot
+        assert_eq!(passed.route.via, None);
+    }
+
+    #[test]
+    fn answers_go_back_the_way_frames_came() {
+        let mut repeater = Repeater::new();
+
+        // the device is further out than us
+        let (uplink, len) = frame(
+            DEVICE,
+            Route {
+                dst: BASE_ADDR,
+                hops: 1,
+                via: Some(FAR_REPEATER),
+            },
+            1,
+        );
+        forward(&mut repeater, REPEATER, &uplink[..len]).unwrap();
+        assert_eq!(repeater.next_hop(DEVICE), Some(FAR_REPEATER));
+
+        let (downlink, len) = frame(
+            BASE_ADDR,
+            Route {
+                dst: DEVICE,
+                hops: 0,
+                via: Some(REPEATER),
+            },
+            7,
+        );
+        let passed = forward(&mut repeater, REPEATER, &downlink[..len]).unwrap();
+        assert_eq!(passed.route.via, Some(FAR_REPEATER));
+
+        // and then it moved into earshot
+        let (uplink, len) = frame(DEVICE, Route::to(BASE_ADDR), 2);
+        forward(&mut repeater, REPEATER, &uplink[..len]).unwrap();
+        assert_eq!(repeater.next_hop(DEVICE), None);
+    }
+
+    #[test]
+    fn listens_longer_for_replies_from_further_away() {
+        let on_air = Duration::from_millis(100);
+        let mut distance = Distance::new();
+        let unknown = distance.reply_window(on_air);
+
+        distance.heard(0);
+        assert_eq!(distance.reply_window(on_air), REPLY_WINDOW);
+        distance.heard(1);
+        assert_eq!(
+            distance.reply_window(on_air),
+            REPLY_WINDOW + Duration::from_millis(400)
+        );
+
+        distance.missed();
+        assert_eq!(distance.hops(), MAX_HOPS);
+        assert_eq!(distance.reply_window(on_air), unknown);
+    }
+}
@@ -0,0 +1,33 @@
+/// A point in time on the device's RTC, which ticks at 32.768kHz
+pub type Instant = fugit::TimerInstantU32<32_768>;
+pub type Duration = fugit::TimerDurationU32<32_768>;
+
+/// `d` in RTC ticks, anything longer than the ticks can count comes out as
+/// the longest they can
+pub fn secs(d: core::time::Duration) -> Duration {
+    let ticks = d.as_secs().saturating_mul(32_768);
+    Duration::from_ticks(ticks.min(u32::MAX as u64) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use garden_shared::MAX_INTERVAL;
+
+    use super::*;
+
+    #[test]
+    fn secs_saturates_past_the_longest_interval() {
+        assert_eq!(
+            secs(MAX_INTERVAL),
+            Duration::secs(MAX_INTERVAL.as_secs() as u32)
+        );
+        assert_eq!(
+            secs(MAX_INTERVAL + core::time::Duration::from_secs(1)),
+            Duration::from_ticks(u32::MAX)
+        );
+        assert_eq!(
+            secs(core::time::Duration::from_secs(u64::MAX)),
+            Duration::from_ticks(u32::MAX)
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use garden_shared::{
+    MoistureSensorReport, StatusFlags, WateringConfig, WateringEvent, WateringStopReason,
+};
+
+use crate::time::{secs, Duration, Instant};
+
+/// Waters the greenhouse based on the moisture readings alone, so that it keeps
+/// going when the base station is unreachable.
+pub struct Watering {
+    config: WateringConfig,
+    state: State,
+}
+
+enum State {
+    Idle { last_stop: Option<Instant> },
+    Running { since: Instant },
+}
+
+impl Watering {
+    pub fn new(config: WateringConfig) -> Self {
+        Self {
+            config,
+            state: State::Idle { last_stop: None },
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, State::Running { .. })
+    }
+
+    /// The flags that should be set on the device while the controller is running
+    pub fn outputs(&self) -> StatusFlags {
+        self.config.outputs | StatusFlags::AUTO_WATERING
+    }
+
+    pub fn max_run_time(&self) -> Duration {
+        secs(self.config.max_run_time)
+    }
+
+    fn stop(
+        &mut self,
+        now: Instant,
+        level: Option<f32>,
+        reason: WateringStopReason,
+    ) -> WateringEvent {
+        self.state = State::Idle {
+            last_stop: Some(now),
+        };
+
+        WateringEvent::Stopped { level, reason }
+    }
+
+    /// Replace the configuration, stopping a run in progress if the
+    /// controller was disabled or its outputs changed.
+    ///
+    /// Grab [`Self::outputs`] before calling this, if a stop event is produced
+    /// those are the outputs that need switching off.
+    pub fn configure(&mut self, config: WateringConfig, now: Instant) -> Option<WateringEvent> {
+        let event = if !self.is_running() {
+            None
+        } else if !config.enabled {
+            Some(self.stop(now, None, WateringStopReason::Disabled))
+        } else if config.outputs != self.config.outputs {
+            Some(self.stop(now, None, WateringStopReason::Reconfigured))
+        } else {
+            None
+        };
+
+        self.config = config;
+
+        event
+    }
+
+    pub fn on_reading(
+        &mut self,
+        report: &MoistureSensorReport,
+        now: Instant,
+    ) -> Option<WateringEvent> {
+        let level = self.config.level(report)?;
+
+        match self.state {
+            State::Idle { last_stop } => {
+                if !self.config.enabled || level < self.config.dry_threshold {
+                    return None;
+                }
+
+                if let Some(last_stop) = last_stop {
+                    let since_last = now
+                        .checked_duration_since(last_stop)
+                        .unwrap_or(Duration::from_ticks(0));
+
+                    if since_last < secs(self.config.min_interval) {
+                        return None;
+                    }
+                }
+
+                self.state = State::Running { since: now };
+
+                Some(WateringEvent::Started { level })
+            }
+            State::Running { .. } => {
+                if level <= self.config.dry_threshold - self.config.hysteresis {
+                    Some(self.stop(now, Some(level), WateringStopReason::Wet))
+                } else {
+                    self.check_timeout(now)
+                }
+            }
+        }
+    }
+
+    /// Stop the current run if it has exceeded the maximum run time
+    pub fn check_timeout(&mut self, now: Instant) -> Option<WateringEvent> {
+        if let State::Running { since } = self.state {
+            let running_for = now
+                .checked_duration_since(since)
+                .unwrap_or(Duration::from_ticks(0));
+
+            if running_for >= self.max_run_time() {
+                return Some(self.stop(now, None, WateringStopReason::MaxRunTime));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{at, moisture_report};
+
+    fn enabled() -> WateringConfig {
+        WateringConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    fn dry() -> MoistureSensorReport {
+        moisture_report(&[400.0, 400.0, 400.0])
+    }
+
+    fn wet() -> MoistureSensorReport {
+        moisture_report(&[200.0, 200.0, 200.0])
+    }
+
+    #[test]
+    fn waters_when_dry() {
+        let mut w = Watering::new(enabled());
+
+        assert_eq!(w.on_reading(&wet(), at(0)), None);
+        assert_eq!(
+            w.on_reading(&dry(), at(60)),
+            Some(WateringEvent::Started { level: 400.0 })
+        );
+        assert!(w.is_running());
+
+        assert_eq!(
+            w.on_reading(&wet(), at(120)),
+            Some(WateringEvent::Stopped {
+                level: Some(200.0),
+                reason: WateringStopReason::Wet
+            })
+        );
+        assert!(!w.is_running());
+    }
+
+    #[test]
+    fn does_nothing_when_disabled() {
+        let mut w = Watering::new(WateringConfig::default());
+
+        assert_eq!(w.on_reading(&dry(), at(0)), None);
+    }
+
+    #[test]
+    fn keeps_going_within_the_hysteresis() {
+        let mut w = Watering::new(enabled());
+        w.on_reading(&dry(), at(0));
+
+        // below the threshold, but not by enough to stop
+        let damp = moisture_report(&[290.0, 290.0, 290.0]);
+        assert_eq!(w.on_reading(&damp, at(60)), None);
+        assert!(w.is_running());
+    }
+
+    #[test]
+    fn only_averages_selected_sensors() {
+        let mut w = Watering::new(WateringConfig {
+            sensors: 0b001,
+            ..enabled()
+        });
+
+        let mixed = moisture_report(&[200.0, 900.0, 900.0]);
+        assert_eq!(w.on_reading(&mixed, at(0)), None);
+    }
+
+    #[test]
+    fn stops_after_max_run_time() {
+        let mut w = Watering::new(enabled());
+        w.on_reading(&dry(), at(0));
+
+        assert_eq!(w.check_timeout(at(60)), None);
+        assert_eq!(
+            w.check_timeout(at(5 * 60)),
+            Some(WateringEvent::Stopped {
+                level: None,
+                reason: WateringStopReason::MaxRunTime
+            })
+        );
+    }
+
+    #[test]
+    fn waits_between_runs() {
+        let mut w = Watering::new(enabled());
+        w.on_reading(&dry(), at(0));
+        w.check_timeout(at(5 * 60));
+
+        assert_eq!(w.on_reading(&dry(), at(30 * 60)), None);
+        assert!(w.on_reading(&dry(), at(5 * 60 + 60 * 60)).is_some());
+    }
+
+    #[test]
+    fn disabling_stops_a_run() {
+        let mut w = Watering::new(enabled());
+        w.on_reading(&dry(), at(0));
+
+        assert_eq!(
+            w.configure(WateringConfig::default(), at(10)),
+            Some(WateringEvent::Stopped {
+                level: None,
+                reason: WateringStopReason::Disabled
+            })
+        );
+        assert_eq!(w.configure(enabled(), at(20)), None);
+    }
+
+    #[test]
+    fn changing_outputs_stops_a_run() {
+        let mut w = Watering::new(enabled());
+        w.on_reading(&dry(), at(0));
+
+        // the old outputs are the ones left to switch off
+        let before = w.outputs();
+        let valve = WateringConfig {
+            outputs: StatusFlags::VALVE_OPEN,
+            ..enabled()
+        };
+        assert_eq!(
+            w.configure(valve, at(10)),
+            Some(WateringEvent::Stopped {
+                level: None,
+                reason: WateringStopReason::Reconfigured
+            })
+        );
+        assert!(!w.is_running());
+        assert_ne!(w.outputs(), before);
+
+        // anything else carries on
+        w.on_reading(&dry(), at(2 * 60 * 60));
+        let slower = WateringConfig {
+            max_run_time: core::time::Duration::from_secs(10 * 60),
+            ..valve
+        };
+        assert_eq!(w.configure(slower, at(2 * 60 * 60 + 10)), None);
+        assert!(w.is_running());
+    }
+}
@@ -0,0 +1,117 @@
+//! Minimal driver for writing to the SAMD21's internal flash, shared by the
+//! firmware and garden-boot. It only uses the PAC so the bootloader stays
+//! small.
+#![no_std]
+
+use atsamd21g::NVMCTRL;
+use garden_core::nvm::{Nvm, PAGE_SIZE, ROW_SIZE};
+
+#[derive(Debug)]
+pub enum FlashError {
+    /// The address or length isn't aligned to a page/ row
+    Unaligned,
+    /// The controller reported a programming, lock or NVM error
+    Controller,
+}
+
+pub struct Flash {
+    nvm: NVMCTRL,
+}
+
+impl Flash {
+    pub fn new(nvm: NVMCTRL) -> Self {
+        // use manual page writes so a partially filled page buffer never gets
+        // committed behind our back
+        nvm.ctrlb.modify(|_, w| w.manw().set_bit());
+
+        Self { nvm }
+    }
+
+    fn wait_ready(&self) {
+        while self.nvm.intflag.read().ready().bit_is_clear() {}
+    }
+
+    fn check_errors(&self) -> Result<(), FlashError> {
+        let status = self.nvm.status.read();
+        if status.proge().bit_is_set() || status.locke().bit_is_set() || status.nvme().bit_is_set()
+        {
+            Err(FlashError::Controller)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn clear_errors(&mut self) {
+        self.nvm
+            .status
+            .modify(|_, w| w.proge().set_bit().locke().set_bit().nvme().set_bit());
+    }
+
+    /// The read cache can still hold what was there before an erase or write
+    fn invalidate_cache(&mut self) {
+        self.nvm.ctrla.write(|w| w.cmdex().key().cmd().invall());
+        self.wait_ready();
+    }
+}
+
+impl Nvm for Flash {
+    type Error = FlashError;
+
+    /// Read straight out of the memory mapped flash
+    fn read(&self, addr: u32, buf: &mut [u8]) {
+        let src = unsafe { core::slice::from_raw_parts(addr as *const u8, buf.len()) };
+        buf.copy_from_slice(src);
+    }
+
+    fn erase_row(&mut self, addr: u32) -> Result<(), FlashError> {
+        if addr as usize % ROW_SIZE != 0 {
+            return Err(FlashError::Unaligned);
+        }
+
+        self.wait_ready();
+        self.clear_errors();
+
+        // the address register is in 16 bit words
+        self.nvm.addr.write(|w| unsafe { w.addr().bits(addr >> 1) });
+        self.nvm.ctrla.write(|w| w.cmdex().key().cmd().er());
+
+        self.wait_ready();
+        self.check_errors()?;
+        self.invalidate_cache();
+
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+        if addr as usize % PAGE_SIZE != 0 || data.len() % PAGE_SIZE != 0 {
+            return Err(FlashError::Unaligned);
+        }
+
+        for (n, page) in data.chunks_exact(PAGE_SIZE).enumerate() {
+            let page_addr = addr + (n * PAGE_SIZE) as u32;
+
+            self.wait_ready();
+            self.clear_errors();
+            self.nvm.ctrla.write(|w| w.cmdex().key().cmd().pbc());
+            self.wait_ready();
+
+            // the page buffer only accepts 16/ 32 bit writes
+            let dst = page_addr as *mut u32;
+            for (i, word) in page.chunks_exact(4).enumerate() {
+                let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                unsafe { core::ptr::write_volatile(dst.add(i), word) };
+            }
+
+            self.nvm
+                .addr
+                .write(|w| unsafe { w.addr().bits(page_addr >> 1) });
+            self.nvm.ctrla.write(|w| w.cmdex().key().cmd().wp());
+
+            self.wait_ready();
+            self.check_errors()?;
+        }
+        self.invalidate_cache();
+
+        Ok(())
+    }
+}
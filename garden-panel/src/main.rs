@@ -2,12 +2,17 @@
 
 use std::collections::VecDeque;
 use std::rc::Rc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, TimeZone};
 use dioxus::prelude::*;
 use fermi::{use_atom_state, use_init_atom_root, use_read, use_set, Atom};
-use garden_shared::{DeviceStatus, PanelMessage, StatusFlags, UiCommand};
+use garden_shared::{
+    sx127x_register_name, ChannelStats, ConfigField, CrashReport, DeviceConfig, DeviceStatus,
+    Diagnostics, FirmwareProgress, FirmwareState, LinkQuality, LinkStatus, PanelMessage,
+    RadioCheck, RadioHealth, RadioReport, RadioState, StatusFlags, Traffic, UiCommand,
+    WateringConfig, WateringEvent, SX127X_VERSION,
+};
 use serde::{Deserialize, Serialize};
 use websocket_hook::{use_ws_context, use_ws_context_provider_json, DioxusWs};
 
@@ -42,6 +47,14 @@ fn app(cx: Scope) -> Element {
     let valve_status = use_ref(&cx, || None::<bool>);
     let desired_pump_status = use_ref(&cx, || None::<bool>);
     let desired_valve_status = use_ref(&cx, || None::<bool>);
+    let device_config = use_ref(&cx, || None::<DeviceConfig>);
+    let device_diagnostics = use_ref(&cx, || None::<Diagnostics>);
+    let crashes = use_ref(&cx, Vec::<(DateTime<Local>, CrashReport)>::new);
+    let link_status = use_ref(&cx, || None::<LinkStatus>);
+    let radio_health = use_ref(&cx, || None::<RadioHealth>);
+    let radio_reports = use_ref(&cx, Vec::<(DateTime<Local>, RadioReport)>::new);
+    let channel_stats = use_ref(&cx, || None::<ChannelStats>);
+    let firmware = use_ref(&cx, || None::<FirmwareProgress>);
     let log = use_ref(&cx, || vec![]);
 
     let url = web_sys::window().unwrap().location().origin().unwrap();
@@ -56,6 +69,14 @@ fn app(cx: Scope) -> Element {
         let valve_status = valve_status.clone();
         let desired_pump_status = desired_pump_status.clone();
         let desired_valve_status = desired_valve_status.clone();
+        let device_config = device_config.clone();
+        let device_diagnostics = device_diagnostics.clone();
+        let crashes = crashes.clone();
+        let link_status = link_status.clone();
+        let radio_health = radio_health.clone();
+        let radio_reports = radio_reports.clone();
+        let channel_stats = channel_stats.clone();
+        let firmware = firmware.clone();
         let log = log.clone();
         move |msg: PanelMessage| {
             log::info!("Got message: {:?}", msg);
@@ -96,6 +117,88 @@ fn app(cx: Scope) -> Element {
                         desired_valve_status.set(Some(valve_on));
                     }
                 }
+                PanelMessage::Watering(event) => {
+                    let msg = match event {
+                        WateringEvent::Started { level } => {
+                            format!("Automatic watering STARTED (moisture level {level:.0})")
+                        }
+                        WateringEvent::Stopped { reason, .. } => {
+                            format!("Automatic watering STOPPED ({reason:?})")
+                        }
+                    };
+                    log.with_mut(|x| x.push(LogEntry::new(&msg)));
+                }
+                PanelMessage::Config(config) => {
+                    device_config.set(Some(config));
+                }
+                PanelMessage::Diagnostics(diagnostics) => {
+                    if let Some(panic) = &diagnostics.last_panic {
+                        let msg = format!(
+                            "Device recovered from a panic at {}:{}:{}",
+                            panic.file, panic.line, panic.column
+                        );
+                        log.with_mut(|x| x.push(LogEntry::new(&msg)));
+                    }
+                    device_diagnostics.set(Some(diagnostics));
+                }
+                PanelMessage::CrashReport { at, report } => {
+                    let at = Local.timestamp_millis(at as i64);
+                    crashes.with_mut(|x| {
+                        // the receiver resends what it has when we reconnect
+                        if !x.iter().any(|(when, _)| *when == at) {
+                            x.push((at, report));
+                        }
+                    });
+                }
+                PanelMessage::Link(link) => {
+                    link_status.set(Some(link));
+                }
+                PanelMessage::Radio(health) => {
+                    let changed = radio_health.read().map(|h| h.state) != Some(health.state);
+                    if changed && health.state != RadioState::Starting {
+                        let msg = format!("Base station radio {}", radio_state(health.state));
+                        log.with_mut(|x| x.push(LogEntry::new(&msg)));
+                    }
+                    radio_health.set(Some(health));
+                }
+                PanelMessage::RadioReport { at, report } => {
+                    if let RadioReport::Failed { check, reason } = &report {
+                        let msg = format!("Radio check {check:?} failed: {reason}");
+                        log.with_mut(|x| x.push(LogEntry::new(&msg)));
+                    }
+
+                    // only the latest of each check is kept
+                    let at = Local.timestamp_millis(at as i64);
+                    radio_reports.with_mut(|x| {
+                        x.retain(|(_, r)| r.check() != report.check());
+                        x.push((at, report));
+                    });
+                }
+                PanelMessage::Channel(stats) => {
+                    channel_stats.set(Some(stats));
+                }
+                PanelMessage::Firmware(progress) => {
+                    let before = firmware.read().and_then(|p| p.device).map(|d| d.state);
+                    let device = progress.device.filter(|d| Some(d.state) != before);
+                    let msg = device.and_then(|d| match d.state {
+                        FirmwareState::Trial => {
+                            Some(format!("Device trying out firmware {:08x}", d.image))
+                        }
+                        FirmwareState::RolledBack => {
+                            Some("Device ROLLED BACK new firmware".to_owned())
+                        }
+                        FirmwareState::Failed(error) => {
+                            Some(format!("Device gave up on firmware: {error:?}"))
+                        }
+                        _ => None,
+                    });
+                    if let Some(msg) = msg {
+                        log.with_mut(|x| x.push(LogEntry::new(&msg)));
+                    }
+                    firmware.set(Some(progress));
+                }
+                // readings are graphed from influxdb instead
+                PanelMessage::Moisture { .. } | PanelMessage::Bme { .. } => {}
                 PanelMessage::Hello => {}
             }
         }
@@ -106,10 +209,25 @@ fn app(cx: Scope) -> Element {
         valve_status: valve_status.clone(),
         desired_pump_status: desired_pump_status.clone(),
         desired_valve_status: desired_valve_status.clone(),
+        device_config: device_config.clone(),
+        device_diagnostics: device_diagnostics.clone(),
+        crashes: crashes.clone(),
+        link_status: link_status.clone(),
+        radio_health: radio_health.clone(),
+        radio_reports: radio_reports.clone(),
+        channel_stats: channel_stats.clone(),
+        firmware: firmware.clone(),
         log: log.clone(),
     }))
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum Page {
+    Controls,
+    Device,
+    Radio,
+}
+
 #[inline_props]
 fn ResponseDisplay(
     cx: Scope,
@@ -117,9 +235,18 @@ fn ResponseDisplay(
     valve_status: UseRef<Option<bool>>,
     desired_pump_status: UseRef<Option<bool>>,
     desired_valve_status: UseRef<Option<bool>>,
+    device_config: UseRef<Option<DeviceConfig>>,
+    device_diagnostics: UseRef<Option<Diagnostics>>,
+    crashes: UseRef<Vec<(DateTime<Local>, CrashReport)>>,
+    link_status: UseRef<Option<LinkStatus>>,
+    radio_health: UseRef<Option<RadioHealth>>,
+    radio_reports: UseRef<Vec<(DateTime<Local>, RadioReport)>>,
+    channel_stats: UseRef<Option<ChannelStats>>,
+    firmware: UseRef<Option<FirmwareProgress>>,
     log: UseRef<Vec<LogEntry>>,
 ) -> Element {
     let ws = use_ws_context(&cx);
+    let page = use_state(&cx, || Page::Controls);
     let pump_on = (|ws: DioxusWs| {
         move |_| {
             log.with_mut(|x| {
@@ -174,10 +301,46 @@ fn ResponseDisplay(
                             "Garden Control Panel"
                         }
                     }
+                    div {
+                        class: "flex items-center space-x-4",
+                        RadioIndicator { radio_health: radio_health.clone() }
+                        LinkIndicator { link_status: link_status.clone() }
+                        a {
+                            class: "cursor-pointer hover:text-gray-700",
+                            onclick: move |_| page.set(Page::Controls),
+                            "Controls"
+                        }
+                        a {
+                            class: "cursor-pointer hover:text-gray-700",
+                            onclick: move |_| page.set(Page::Device),
+                            "Device"
+                        }
+                        a {
+                            class: "cursor-pointer hover:text-gray-700",
+                            onclick: move |_| page.set(Page::Radio),
+                            "Radio"
+                        }
+                    }
                 }
             }
         }
-        main {
+        (*page.get() == Page::Device).then(|| rsx!(
+            main {
+                DeviceSettings { device_config: device_config.clone(), log: log.clone() }
+                DeviceDiagnostics { device_diagnostics: device_diagnostics.clone() }
+                FirmwareUpdate { firmware: firmware.clone(), log: log.clone() }
+                CrashReports { crashes: crashes.clone() }
+                CommandLog { log: log.clone() }
+            }
+        ))
+        (*page.get() == Page::Radio).then(|| rsx!(
+            main {
+                ChannelUsage { channel_stats: channel_stats.clone() }
+                RadioChecks { radio_reports: radio_reports.clone(), log: log.clone() }
+                CommandLog { log: log.clone() }
+            }
+        ))
+        (*page.get() == Page::Controls).then(|| rsx!(main {
             div {
                 class: "justify-center flex space-x-2 bg-gray-50 text-gray-800 py-6 px-6",
                 button {
@@ -220,11 +383,536 @@ fn ResponseDisplay(
                     "Reboot MCU"
                 }
             }
+            WateringSettings { device_config: device_config.clone(), log: log.clone() }
             CommandLog { log: log.clone() }
+        }))
+    ))
+}
+
+#[inline_props]
+fn WateringSettings(
+    cx: Scope,
+    device_config: UseRef<Option<DeviceConfig>>,
+    log: UseRef<Vec<LogEntry>>,
+) -> Element {
+    let ws = use_ws_context(&cx);
+    let defaults = WateringConfig::default();
+
+    let enabled = use_state(&cx, || defaults.enabled);
+    let dry_threshold = use_state(&cx, || defaults.dry_threshold.to_string());
+    let hysteresis = use_state(&cx, || defaults.hysteresis.to_string());
+    let max_run_mins = use_state(&cx, || (defaults.max_run_time.as_secs() / 60).to_string());
+    let min_interval_mins = use_state(&cx, || (defaults.min_interval.as_secs() / 60).to_string());
+
+    let save = (|ws: DioxusWs| {
+        move |_| {
+            let current = device_config.read().map(|c| c.watering).unwrap_or_default();
+            let config = (|| {
+                Some(WateringConfig {
+                    enabled: *enabled.get(),
+                    dry_threshold: dry_threshold.get().parse().ok()?,
+                    hysteresis: hysteresis.get().parse().ok()?,
+                    max_run_time: Duration::from_secs(max_run_mins.get().parse::<u64>().ok()? * 60),
+                    min_interval: Duration::from_secs(
+                        min_interval_mins.get().parse::<u64>().ok()? * 60,
+                    ),
+                    ..current
+                })
+            })();
+
+            match config {
+                Some(config) => {
+                    log.with_mut(|x| {
+                        x.push(LogEntry::new("Enqueued watering settings"));
+                    });
+                    ws.send_json(&UiCommand::SetConfig(ConfigField::Watering(config)))
+                }
+                None => log.with_mut(|x| {
+                    x.push(LogEntry::new("Watering settings are not valid numbers"));
+                }),
+            }
+        }
+    })(ws);
+
+    let input_class = "w-20 px-2 py-1 border border-gray-300 rounded";
+
+    cx.render(rsx!(
+        div {
+            class: "justify-center flex flex-wrap items-center space-x-2 bg-gray-50 text-gray-800 py-6 px-6",
+            label {
+                "Automatic watering "
+                input {
+                    r#type: "checkbox",
+                    checked: "{enabled}",
+                    oninput: move |evt| enabled.set(evt.value == "true"),
+                }
+            }
+            label {
+                "Dry level "
+                input {
+                    class: "{input_class}",
+                    r#type: "number",
+                    value: "{dry_threshold}",
+                    oninput: move |evt| dry_threshold.set(evt.value.clone()),
+                }
+            }
+            label {
+                "Hysteresis "
+                input {
+                    class: "{input_class}",
+                    r#type: "number",
+                    value: "{hysteresis}",
+                    oninput: move |evt| hysteresis.set(evt.value.clone()),
+                }
+            }
+            label {
+                "Max run (mins) "
+                input {
+                    class: "{input_class}",
+                    r#type: "number",
+                    value: "{max_run_mins}",
+                    oninput: move |evt| max_run_mins.set(evt.value.clone()),
+                }
+            }
+            label {
+                "Min interval (mins) "
+                input {
+                    class: "{input_class}",
+                    r#type: "number",
+                    value: "{min_interval_mins}",
+                    oninput: move |evt| min_interval_mins.set(evt.value.clone()),
+                }
+            }
+            button {
+                class: "inline-block px-6 py-2.5 bg-blue-600 text-white font-medium text-xs leading-tight uppercase rounded shadow-md hover:bg-blue-700 hover:shadow-lg focus:bg-blue-700 focus:shadow-lg focus:outline-none focus:ring-0 active:bg-blue-800 active:shadow-lg transition duration-150 ease-in-out",
+                onclick: save,
+                "Save watering settings"
+            }
         }
     ))
 }
 
+#[inline_props]
+fn DeviceSettings(
+    cx: Scope,
+    device_config: UseRef<Option<DeviceConfig>>,
+    log: UseRef<Vec<LogEntry>>,
+) -> Element {
+    let ws = use_ws_context(&cx);
+
+    let refresh = (|ws: DioxusWs| {
+        move |_| {
+            log.with_mut(|x| {
+                x.push(LogEntry::new("Enqueued config request"));
+            });
+            ws.send_json(&UiCommand::RequestConfig)
+        }
+    })(ws);
+
+    let summary = match *device_config.read() {
+        Some(c) => format!(
+            "Address {:#x}, measuring every {}s, BME every {}s, status every {}s, \
+             temperature offset {:.1}°C, reset every {}, {:.1} MHz SF{} {} dBm",
+            c.address.0,
+            c.measurement_interval.as_secs(),
+            c.bme_interval.as_secs(),
+            c.status_interval.as_secs(),
+            c.temperature_offset,
+            c.reset_interval
+                .map(|d| format!("{}s", d.as_secs()))
+                .unwrap_or_else(|| "never".to_owned()),
+            c.radio.frequency as f32 / 1_000_000.0,
+            c.radio.spreading_factor,
+            c.radio.tx_power,
+        ),
+        None => "Device config unknown".to_owned(),
+    };
+
+    cx.render(rsx!(
+        div {
+            class: "justify-center flex items-center space-x-2 bg-gray-50 text-gray-800 py-6 px-6",
+            span { "{summary}" }
+            button {
+                class: "inline-block px-6 py-2.5 bg-blue-600 text-white font-medium text-xs leading-tight uppercase rounded shadow-md hover:bg-blue-700 hover:shadow-lg focus:bg-blue-700 focus:shadow-lg focus:outline-none focus:ring-0 active:bg-blue-800 active:shadow-lg transition duration-150 ease-in-out",
+                onclick: refresh,
+                "Refresh config"
+            }
+        }
+    ))
+}
+
+#[inline_props]
+fn DeviceDiagnostics(cx: Scope, device_diagnostics: UseRef<Option<Diagnostics>>) -> Element {
+    let d = match &*device_diagnostics.read() {
+        Some(d) => d.clone(),
+        None => {
+            return cx.render(rsx!(
+                div {
+                    class: "justify-center flex bg-gray-50 text-gray-800 py-6 px-6",
+                    "No diagnostics from the device yet"
+                }
+            ))
+        }
+    };
+
+    let uptime = d.uptime.as_secs();
+    let uptime = format!(
+        "{}d {}h {}m",
+        uptime / 86400,
+        (uptime / 3600) % 24,
+        (uptime / 60) % 60
+    );
+    let reset_cause = format!("{:?}", d.reset_cause);
+    let firmware = format!("{} ({})", d.version, d.git_hash);
+    let last_panic = match &d.last_panic {
+        Some(p) => format!("{}:{}:{}", p.file, p.line, p.column),
+        None => "None".to_owned(),
+    };
+
+    let rows = [
+        ("Uptime", uptime),
+        ("Last reset cause", reset_cause),
+        ("Firmware", firmware),
+        ("BME688 failures", d.bme_failures.to_string()),
+        ("Dropped messages", d.dropped_messages.to_string()),
+        ("Radio errors", d.radio_errors.to_string()),
+        ("Link fallbacks", d.link_fallbacks.to_string()),
+        (
+            "Airtime (last hour)",
+            format!("{:.1}s", d.airtime.as_secs_f32()),
+        ),
+        ("Held back for airtime", d.airtime_deferred.to_string()),
+        ("Waited for the channel", d.channel_deferred.to_string()),
+        ("Sent into a busy channel", d.channel_collisions.to_string()),
+        ("Last panic", last_panic),
+    ];
+
+    cx.render(rsx!(
+        div {
+            class: "justify-center flex bg-gray-50 text-gray-800 py-6 px-6",
+            table {
+                class: "font-mono",
+                rows.iter().map(|(name, value)| rsx!(
+                    tr {
+                        key: "{name}",
+                        td { class: "pr-6 font-medium", "{name}" }
+                        td { "{value}" }
+                    }
+                ))
+            }
+        }
+    ))
+}
+
+#[inline_props]
+fn CrashReports(cx: Scope, crashes: UseRef<Vec<(DateTime<Local>, CrashReport)>>) -> Element {
+    if crashes.read().is_empty() {
+        return None;
+    }
+
+    cx.render(rsx!(
+        div {
+            class: "mx-auto drop-shadow-lg m-4 rounded-lg font-mono w-8/12",
+            h2 { class: "font-medium", "Crash reports" }
+            crashes.read().iter().rev().map(|(when, report)| {
+                let ts = when.format("%Y-%m-%d %H:%M:%S");
+                let k = when.timestamp_millis();
+                let location = format!(
+                    "{}:{}:{}",
+                    report.location.file, report.location.line, report.location.column
+                );
+                let msg = &report.message;
+                rsx!(
+                    div {
+                        class: "mt-4 flex",
+                        key: "{k}",
+                        span {
+                            class: "text-red-500",
+                            "[{ts}]"
+                        }
+                        p {
+                            class: "flex-1 items-center pl-2",
+                            "{location}: {msg}"
+                        }
+                    }
+                )
+            })
+        }
+    ))
+}
+
+/// New firmware on its way to the device, which is sent with
+/// `garden-cli firmware upload`
+#[inline_props]
+fn FirmwareUpdate(
+    cx: Scope,
+    firmware: UseRef<Option<FirmwareProgress>>,
+    log: UseRef<Vec<LogEntry>>,
+) -> Element {
+    let ws = use_ws_context(&cx);
+    let progress = (*firmware.read())?;
+
+    let cancel = move |_| {
+        log.with_mut(|x| x.push(LogEntry::new("Cancelled firmware upload")));
+        ws.send_json(&UiCommand::CancelFirmware)
+    };
+
+    let image = |image: u32| match image {
+        0 => "flashed firmware".to_owned(),
+        image => format!("{image:08x}"),
+    };
+    let device = progress.device.map(|d| {
+        let state = match d.state {
+            FirmwareState::Idle => "Running".to_owned(),
+            FirmwareState::Receiving { next } => format!("Received {next} pieces of"),
+            FirmwareState::Verified => "Resetting into".to_owned(),
+            FirmwareState::Trial => "Trying out".to_owned(),
+            FirmwareState::RolledBack => "Rolled back to".to_owned(),
+            FirmwareState::Failed(error) => format!("Gave up ({error:?}) on"),
+        };
+        format!("{state} {}", image(d.image))
+    });
+    let device = device.unwrap_or_else(|| "Not heard from yet".to_owned());
+
+    let button = "inline-block px-6 py-2.5 bg-red-600 text-white font-medium text-xs leading-tight uppercase rounded shadow-md hover:bg-red-700 hover:shadow-lg focus:bg-red-700 focus:shadow-lg focus:outline-none focus:ring-0 active:bg-red-800 active:shadow-lg transition duration-150 ease-in-out";
+
+    cx.render(rsx!(
+        div {
+            class: "mx-auto drop-shadow-lg m-4 rounded-lg font-mono w-8/12",
+            h2 { class: "font-medium", "Firmware" }
+            p { "Device: {device}" }
+            progress.upload.map(|upload| {
+                let received = progress.received().unwrap_or(0);
+                let percent = received as u64 * 100 / upload.size.max(1) as u64;
+                let id = image(upload.image);
+                let size = upload.size;
+                rsx!(
+                    p { "Sending {id}: {received} of {size} bytes ({percent}%)" }
+                    button { class: "{button}", onclick: cancel, "Cancel" }
+                )
+            })
+        }
+    ))
+}
+
+/// Who's been using the channel, only known while the base station is
+/// sniffing
+#[inline_props]
+fn ChannelUsage(cx: Scope, channel_stats: UseRef<Option<ChannelStats>>) -> Element {
+    let stats = channel_stats.read().clone()?;
+
+    let minutes = stats.window.as_secs() / 60;
+    let utilisation = format!("{:.2}%", stats.utilisation());
+    let row = |name: String, t: &Traffic| {
+        let rssi = t
+            .rssi
+            .map(|r| format!("{r} dBm"))
+            .unwrap_or_else(|| "-".to_owned());
+        (
+            name,
+            t.frames.to_string(),
+            format!("{:.1}s", t.airtime.as_secs_f32()),
+            rssi,
+        )
+    };
+
+    let mut rows = vec![
+        row("Our device".to_owned(), &stats.ours),
+        row("Base station".to_owned(), &stats.sent),
+        row("Other addresses".to_owned(), &stats.foreign),
+        row("Undecodable".to_owned(), &stats.undecodable),
+    ];
+    rows.extend(
+        stats
+            .neighbours
+            .iter()
+            .map(|n| row(format!("  {:#x}", n.src.0), &n.traffic)),
+    );
+
+    cx.render(rsx!(
+        div {
+            class: "mx-auto drop-shadow-lg m-4 rounded-lg font-mono w-8/12",
+            h2 { class: "font-medium", "Channel {utilisation} busy over the last {minutes} minutes" }
+            table {
+                tr {
+                    th { class: "pr-6 text-left", "" }
+                    th { class: "pr-6 text-left", "Frames" }
+                    th { class: "pr-6 text-left", "Airtime" }
+                    th { class: "text-left", "RSSI" }
+                }
+                rows.iter().map(|(name, frames, airtime, rssi)| rsx!(
+                    tr {
+                        key: "{name}",
+                        td { class: "pr-6 font-medium whitespace-pre", "{name}" }
+                        td { class: "pr-6", "{frames}" }
+                        td { class: "pr-6", "{airtime}" }
+                        td { "{rssi}" }
+                    }
+                ))
+            }
+        }
+    ))
+}
+
+/// Looking inside the base station's own radio, for when the link misbehaves
+#[inline_props]
+fn RadioChecks(
+    cx: Scope,
+    radio_reports: UseRef<Vec<(DateTime<Local>, RadioReport)>>,
+    log: UseRef<Vec<LogEntry>>,
+) -> Element {
+    let ws = use_ws_context(&cx);
+
+    let run = |check: RadioCheck| {
+        let ws = ws.clone();
+        move |_| {
+            log.with_mut(|x| {
+                x.push(LogEntry::new(&format!("Asked for radio check {check:?}")));
+            });
+            ws.send_json(&UiCommand::CheckRadio(check))
+        }
+    };
+    let self_test = run(RadioCheck::SelfTest);
+    let registers = run(RadioCheck::Registers);
+    let noise_floor = run(RadioCheck::NoiseFloor);
+
+    let button = "inline-block px-6 py-2.5 bg-blue-600 text-white font-medium text-xs leading-tight uppercase rounded shadow-md hover:bg-blue-700 hover:shadow-lg focus:bg-blue-700 focus:shadow-lg focus:outline-none focus:ring-0 active:bg-blue-800 active:shadow-lg transition duration-150 ease-in-out";
+
+    let reports = radio_reports.read();
+    let latest = |check: RadioCheck| {
+        reports
+            .iter()
+            .find(|(_, r)| r.check() == check)
+            .map(|(at, r)| (at.format("%Y-%m-%d %H:%M:%S").to_string(), r.clone()))
+    };
+
+    let self_test_report = latest(RadioCheck::SelfTest).map(|(at, report)| {
+        let rows = match report {
+            RadioReport::SelfTest(t) => {
+                let pass = |ok: bool| if ok { "pass" } else { "FAIL" }.to_owned();
+                vec![
+                    (
+                        "Chip version",
+                        format!("{:#04x} (expected {:#04x})", t.version, SX127X_VERSION),
+                    ),
+                    ("Register read back", pass(t.spi)),
+                    ("Mode changes", pass(t.modes)),
+                    ("FIFO loopback", pass(t.fifo)),
+                    ("Overall", pass(t.passed())),
+                ]
+            }
+            RadioReport::Failed { reason, .. } => vec![("Failed", reason.to_string())],
+            _ => vec![],
+        };
+
+        rsx!(
+            div {
+                class: "mx-auto drop-shadow-lg m-4 rounded-lg font-mono w-8/12",
+                h2 { class: "font-medium", "Self-test ({at})" }
+                table {
+                    rows.iter().map(|(name, value)| rsx!(
+                        tr {
+                            key: "{name}",
+                            td { class: "pr-6 font-medium", "{name}" }
+                            td { "{value}" }
+                        }
+                    ))
+                }
+            }
+        )
+    });
+
+    let registers_report = latest(RadioCheck::Registers).map(|(at, report)| {
+        let rows = match report {
+            RadioReport::Registers(registers) => registers
+                .iter()
+                .map(|r| {
+                    (
+                        format!("{:#04x}", r.addr),
+                        sx127x_register_name(r.addr).unwrap_or("?").to_owned(),
+                        format!("{:#04x} {:08b}", r.value, r.value),
+                    )
+                })
+                .collect(),
+            RadioReport::Failed { reason, .. } => {
+                vec![(String::new(), "Failed".to_owned(), reason.to_string())]
+            }
+            _ => vec![],
+        };
+
+        rsx!(
+            div {
+                class: "mx-auto drop-shadow-lg m-4 rounded-lg font-mono w-8/12",
+                h2 { class: "font-medium", "Registers ({at})" }
+                table {
+                    rows.iter().map(|(addr, name, value)| rsx!(
+                        tr {
+                            key: "{addr}{name}",
+                            td { class: "pr-6", "{addr}" }
+                            td { class: "pr-6 font-medium", "{name}" }
+                            td { "{value}" }
+                        }
+                    ))
+                }
+            }
+        )
+    });
+
+    let noise_report = latest(RadioCheck::NoiseFloor).map(|(at, report)| {
+        let (summary, samples) = match report {
+            RadioReport::NoiseFloor { floor, samples } => {
+                (format!("Noise floor {floor} dBm"), samples.to_vec())
+            }
+            RadioReport::Failed { reason, .. } => (format!("Failed: {reason}"), vec![]),
+            _ => (String::new(), vec![]),
+        };
+
+        rsx!(
+            div {
+                class: "mx-auto drop-shadow-lg m-4 rounded-lg font-mono w-8/12",
+                h2 { class: "font-medium", "{summary} ({at})" }
+                table {
+                    samples.iter().map(|s| {
+                        let mhz = format!("{:.3} MHz", s.frequency as f32 / 1_000_000.0);
+                        // -140dBm is about as quiet as it gets, -60dBm is
+                        // somebody transmitting close by
+                        let width = ((s.peak as i32 + 140) * 100 / 80).clamp(0, 100);
+                        let rssi = s.rssi;
+                        let peak = s.peak;
+                        rsx!(
+                            tr {
+                                key: "{mhz}",
+                                td { class: "pr-6", "{mhz}" }
+                                td { class: "pr-6", "{rssi} dBm" }
+                                td { class: "pr-6", "peak {peak} dBm" }
+                                td {
+                                    class: "w-48",
+                                    div {
+                                        class: "h-2 bg-blue-500",
+                                        style: "width: {width}%",
+                                    }
+                                }
+                            }
+                        )
+                    })
+                }
+            }
+        )
+    });
+
+    cx.render(rsx!(
+        div {
+            class: "justify-center flex space-x-2 bg-gray-50 text-gray-800 py-6 px-6",
+            button { class: "{button}", onclick: self_test, "Self-test" }
+            button { class: "{button}", onclick: registers, "Read registers" }
+            button { class: "{button}", onclick: noise_floor, "Scan noise floor" }
+        }
+        self_test_report
+        registers_report
+        noise_report
+    ))
+}
+
 #[inline_props]
 fn CommandLog(cx: Scope, log: UseRef<Vec<LogEntry>>) -> Element {
     cx.render(rsx!(
@@ -254,6 +942,96 @@ fn CommandLog(cx: Scope, log: UseRef<Vec<LogEntry>>) -> Element {
     ))
 }
 
+/// Signal bars out of 4 for a link, going by SNR since LoRa keeps working a
+/// little below the noise floor
+fn link_bars(quality: &LinkQuality) -> usize {
+    match quality.snr {
+        s if s >= 5 => 4,
+        s if s >= 0 => 3,
+        s if s >= -5 => 2,
+        s if s >= -10 => 1,
+        _ => 0,
+    }
+}
+
+fn radio_state(state: RadioState) -> &'static str {
+    match state {
+        RadioState::Starting => "starting",
+        RadioState::Up => "up",
+        RadioState::Recovering => "recovering",
+        RadioState::Failed => "failed",
+    }
+}
+
+/// Only shows up when the base station's radio isn't listening, since then
+/// nothing else on the panel is going to change
+#[inline_props]
+fn RadioIndicator(cx: Scope, radio_health: UseRef<Option<RadioHealth>>) -> Element {
+    let health = (*radio_health.read())?;
+    if health.state == RadioState::Up {
+        return None;
+    }
+
+    let colour = match health.state {
+        RadioState::Failed => "text-red-600",
+        _ => "text-amber-600",
+    };
+    let state = radio_state(health.state);
+    let title = format!(
+        "{} restarts, {} errors since the last",
+        health.restarts, health.errors
+    );
+
+    cx.render(rsx!(
+        span {
+            class: "text-sm {colour}",
+            title: "{title}",
+            "Radio {state}"
+        }
+    ))
+}
+
+#[inline_props]
+fn LinkIndicator(cx: Scope, link_status: UseRef<Option<LinkStatus>>) -> Element {
+    let link = (*link_status.read())?;
+
+    // the link is only as good as its worst direction
+    let bars = [link.uplink, link.downlink]
+        .iter()
+        .flatten()
+        .map(link_bars)
+        .min()
+        .unwrap_or(0);
+
+    let describe = |q: Option<LinkQuality>| match q {
+        Some(q) => format!("{} dBm, SNR {} dB", q.rssi, q.snr),
+        None => "unknown".to_owned(),
+    };
+    let title = format!(
+        "Uplink: {}\nDownlink: {}",
+        describe(link.uplink),
+        describe(link.downlink)
+    );
+
+    cx.render(rsx!(
+        span {
+            class: "flex items-end space-x-0.5 h-4",
+            title: "{title}",
+            (1..=4).map(|n| {
+                let colour = if n <= bars { "bg-green-500" } else { "bg-gray-300" };
+                let height = n * 25;
+                rsx!(
+                    span {
+                        key: "{n}",
+                        class: "w-1 {colour}",
+                        style: "height: {height}%",
+                    }
+                )
+            })
+        }
+    ))
+}
+
 #[inline_props]
 fn PumpStatus(
     cx: Scope,
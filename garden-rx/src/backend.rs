@@ -0,0 +1,442 @@
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Report};
+use color_eyre::Result;
+use embedded_radio::{EmbeddedRadio, RadioMode};
+use futures::StreamExt;
+use garden_shared::{
+    FrequencyPlan, LoRaBandwidth, LoRaCodingRate, RadioConfig, PAYLOAD_CRC, PREAMBLE_LEN, SYNC_WORD,
+};
+use gpio_cdev::{AsyncLineEventHandle, Chip, EventRequestFlags, LineRequestFlags};
+use linux_embedded_hal as hal;
+use tokio::runtime::Handle;
+use tokio::sync::{watch, Notify};
+
+use hal::spidev::{self, SpidevOptions};
+use hal::Delay;
+use hal::{CdevPin, Spidev};
+
+use crate::capture::{self, Record};
+use crate::config::Gpio;
+use crate::diagnostics::{Registers, REG_SYNC_WORD};
+
+/// What the radio's lines show up as in `gpioinfo`
+const CONSUMER: &str = "garden-rx";
+
+/// The radio's interrupt flags, as handed back when they're cleared
+const IRQ_RX_DONE: u8 = 0b0100_0000;
+const IRQ_CRC_ERROR: u8 = 0b0010_0000;
+
+/// A frame as it came off the air
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub data: Vec<u8>,
+    /// Signal strength in dBm
+    pub rssi: Option<i32>,
+    /// Signal to noise ratio in dB
+    pub snr: Option<f32>,
+    /// How far off our frequency the frame was, in Hz
+    pub freq_error: Option<i32>,
+    /// Whether the payload CRC checked out, if the radio says
+    pub crc_ok: Option<bool>,
+}
+
+/// Something frames to and from the device can be sent over
+pub trait Radio {
+    /// Wait up to `timeout` for a frame
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>>;
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<()>;
+
+    /// Switch to the channel and modulation of `radio`, keeping our own
+    /// transmit power. Radios that aren't on the air have nothing to switch.
+    fn configure(&mut self, _radio: &RadioConfig) -> Result<()> {
+        Ok(())
+    }
+
+    /// The radio's registers, for looking inside it when the link
+    /// misbehaves. Radios that aren't on the air don't have any.
+    fn registers(&mut self) -> Option<&mut dyn Registers> {
+        None
+    }
+}
+
+/// Which radio to talk to the device with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// The SX127x on the LoRa hat
+    Sx127x,
+    /// Frames are exchanged as UDP datagrams, with `garden-sim` for example
+    Udp(SocketAddr),
+    /// Received frames are read back from a capture
+    Replay(PathBuf),
+}
+
+impl FromStr for Backend {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            _ if s == "sx127x" => Ok(Self::Sx127x),
+            Some(("udp", addr)) => Ok(Self::Udp(addr.parse()?)),
+            Some(("replay", path)) => Ok(Self::Replay(path.into())),
+            _ => Err(eyre!(
+                "Unknown radio {:?}, expected sx127x, udp:<addr> or replay:<path>",
+                s
+            )),
+        }
+    }
+}
+
+impl Backend {
+    /// Open the radio, the LoRa hat is wired up as `gpio` says. `shutdown`
+    /// going true cuts a wait for a frame short, so the radio can be dropped
+    /// and let go of its lines, and so does `check_wanted` so the panel's
+    /// checks don't wait for the device.
+    pub fn open(
+        &self,
+        plan: &FrequencyPlan,
+        gpio: &Gpio,
+        shutdown: watch::Receiver<bool>,
+        check_wanted: Arc<Notify>,
+    ) -> Result<Box<dyn Radio>> {
+        Ok(match self {
+            Backend::Sx127x => Box::new(Sx127x::open(plan, gpio, shutdown, check_wanted)?),
+            Backend::Udp(addr) => Box::new(VirtualRadio::bind(*addr)?),
+            Backend::Replay(path) => Box::new(Replay::open(path)?),
+        })
+    }
+}
+
+pub struct Sx127x {
+    lora: embedded_radio::LoRa<Spidev, CdevPin, CdevPin>,
+    /// Rising edges on DIO0, which the radio raises on RxDone and TxDone
+    dio0: AsyncLineEventHandle,
+    runtime: Handle,
+    /// Whether the radio is in continuous receive, it drops out of it to
+    /// transmit
+    listening: bool,
+    shutdown: watch::Receiver<bool>,
+    check_wanted: Arc<Notify>,
+}
+
+impl Sx127x {
+    pub fn open(
+        plan: &FrequencyPlan,
+        gpio: &Gpio,
+        shutdown: watch::Receiver<bool>,
+        check_wanted: Arc<Notify>,
+    ) -> Result<Self> {
+        let mut spi = Spidev::open("/dev/spidev0.1")?;
+        let options = SpidevOptions::new()
+            .bits_per_word(8)
+            .max_speed_hz(20_000)
+            .mode(spidev::SpiModeFlags::SPI_MODE_0)
+            .build();
+
+        spi.configure(&options)?;
+
+        // the kernel takes the lines back when their handles are dropped, or
+        // the process dies, so there's nothing left over for the next run
+        let mut chip = Chip::new(&gpio.chip)
+            .map_err(|e| eyre!("Failed to open {}: {}", gpio.chip.display(), e))?;
+        let mut output = |offset: u32| -> Result<CdevPin> {
+            let handle = chip
+                .get_line(offset)?
+                .request(LineRequestFlags::OUTPUT, 1, CONSUMER)
+                .map_err(|e| eyre!("Failed to claim GPIO line {}: {}", offset, e))?;
+            Ok(CdevPin::new(handle)?)
+        };
+        let cs = output(gpio.cs)?;
+        let reset = output(gpio.reset)?;
+
+        let frequency = (plan.radio.frequency / 1_000_000) as i64;
+        let mut lora = embedded_radio::LoRa::new(spi, cs, reset, frequency, &mut Delay)
+            .map_err(|e| eyre!("Failed to communicate with radio module: {:?}", e))?;
+        lora.set_tx_power(plan.radio.tx_power as i32, 1)
+            .map_err(|e| eyre!("Failed to set tx power: {:?}", e))?;
+        lora.set_preamble_length(PREAMBLE_LEN as i64)
+            .and_then(|_| lora.set_crc(PAYLOAD_CRC))
+            .map_err(|e| eyre!("Failed to set up framing: {:?}", e))?;
+
+        // the edges are picked up by tokio's reactor, so waiting for a frame
+        // doesn't take any polling
+        let dio0 = chip
+            .get_line(gpio.dio0)?
+            .events(
+                LineRequestFlags::INPUT,
+                EventRequestFlags::RISING_EDGE,
+                CONSUMER,
+            )
+            .map_err(|e| eyre!("Failed to claim GPIO line {}: {}", gpio.dio0, e))?;
+        let dio0 = AsyncLineEventHandle::new(dio0)?;
+
+        let mut radio = Self {
+            lora,
+            dio0,
+            runtime: Handle::current(),
+            listening: false,
+            shutdown,
+            check_wanted,
+        };
+        radio.write(REG_SYNC_WORD, SYNC_WORD)?;
+        radio.configure(&plan.radio)?;
+
+        Ok(radio)
+    }
+}
+
+impl Sx127x {
+    /// Wait for the last transmission to finish going out
+    fn finish_transmitting(&mut self) -> Result<()> {
+        while self
+            .lora
+            .transmitting()
+            .map_err(|e| eyre!("Failed to check on the radio: {:?}", e))?
+        {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        Ok(())
+    }
+
+    /// Put the radio in continuous receive if it isn't already
+    fn listen(&mut self) -> Result<()> {
+        if self.listening {
+            return Ok(());
+        }
+
+        self.finish_transmitting()?;
+        self.lora
+            .clear_irq()
+            .and_then(|_| self.lora.set_mode(RadioMode::RxContinuous))
+            .map_err(|e| eyre!("Failed to start receiving: {:?}", e))?;
+        self.listening = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for Sx127x {
+    fn drop(&mut self) {
+        // stop it listening, the lines go back to the kernel after this
+        let _ = self.finish_transmitting();
+        let _ = self.lora.set_mode(RadioMode::Sleep);
+    }
+}
+
+impl Radio for Sx127x {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        self.listen()?;
+
+        let deadline = Instant::now() + timeout;
+        let irq = loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let (dio0, shutdown, check_wanted) =
+                (&mut self.dio0, &mut self.shutdown, &self.check_wanted);
+            let edge = self.runtime.block_on(async {
+                tokio::select! {
+                    edge = tokio::time::timeout(left, dio0.next()) => Some(edge),
+                    _ = shutdown.changed() => None,
+                    // the panel wants a look inside the radio
+                    _ = check_wanted.notified() => None,
+                }
+            });
+
+            match edge {
+                Some(Ok(Some(event))) => {
+                    event?;
+                }
+                Some(Ok(None)) => return Err(eyre!("DIO0 events stopped coming")),
+                Some(Err(_)) | None => return Ok(None),
+            }
+
+            let irq = self
+                .lora
+                .clear_irq()
+                .map_err(|e| eyre!("Failed to read the radio's interrupts: {:?}", e))?;
+            // the edge from our last TxDone can still be queued up
+            if irq & IRQ_RX_DONE != 0 {
+                break irq;
+            }
+        };
+
+        let data = self
+            .lora
+            .read_packet()
+            .map_err(|e| eyre!("Failed to read a frame off the radio: {:?}", e))?;
+
+        Ok(Some(Frame {
+            data,
+            rssi: self.lora.get_packet_rssi().ok(),
+            snr: self.lora.get_packet_snr().ok().map(|snr| snr as f32),
+            freq_error: self
+                .lora
+                .get_packet_frequency_error()
+                .ok()
+                .map(|e| e as i32),
+            crc_ok: Some(irq & IRQ_CRC_ERROR == 0),
+        }))
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        // the radio drops out of receive to transmit, and needs to be done
+        // with anything it was sending already
+        self.finish_transmitting()?;
+        self.listening = false;
+
+        self.lora
+            .transmit_payload(frame)
+            .map_err(|e| eyre!("Opps: {:?}", e))
+    }
+
+    fn configure(&mut self, radio: &RadioConfig) -> Result<()> {
+        // let the last reply finish going out on the old settings
+        self.finish_transmitting()?;
+        self.listening = false;
+
+        let bandwidth = match radio.bandwidth {
+            LoRaBandwidth::Bw125kHz => 125_000,
+            LoRaBandwidth::Bw250kHz => 250_000,
+            LoRaBandwidth::Bw500kHz => 500_000,
+        };
+        let coding_rate = match radio.coding_rate {
+            LoRaCodingRate::Cr4_5 => 5,
+            LoRaCodingRate::Cr4_6 => 6,
+            LoRaCodingRate::Cr4_7 => 7,
+            LoRaCodingRate::Cr4_8 => 8,
+        };
+
+        // the driver only does whole MHz
+        self.lora
+            .set_frequency(radio.frequency as u64 / 1_000_000)
+            .and_then(|_| self.lora.set_signal_bandwidth(bandwidth))
+            .and_then(|_| self.lora.set_coding_rate_4(coding_rate))
+            .and_then(|_| self.lora.set_spreading_factor(radio.spreading_factor))
+            .map_err(|e| eyre!("Failed to configure radio: {:?}", e))
+    }
+
+    fn registers(&mut self) -> Option<&mut dyn Registers> {
+        Some(self)
+    }
+}
+
+impl Registers for Sx127x {
+    fn read(&mut self, addr: u8) -> Result<u8> {
+        self.lora
+            .read_register(addr)
+            .map_err(|e| eyre!("Failed to read register {:#04x}: {:?}", addr, e))
+    }
+
+    fn write(&mut self, addr: u8, value: u8) -> Result<()> {
+        // whatever was written, it needs putting back into receive
+        self.listening = false;
+
+        self.lora
+            .write_register(addr, value)
+            .map_err(|e| eyre!("Failed to write register {:#04x}: {:?}", addr, e))
+    }
+}
+
+/// Stands in for the LoRa hat, replies go to whoever sent the last frame
+pub struct VirtualRadio {
+    socket: UdpSocket,
+    peer: Option<SocketAddr>,
+}
+
+impl VirtualRadio {
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr)?,
+            peer: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+}
+
+impl Radio for VirtualRadio {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        let mut buffer = [0; 255];
+
+        self.socket.set_read_timeout(Some(timeout))?;
+        match self.socket.recv_from(&mut buffer) {
+            Ok((n, from)) => {
+                self.peer = Some(from);
+                Ok(Some(Frame {
+                    data: buffer[..n].to_vec(),
+                    rssi: None,
+                    snr: None,
+                    freq_error: None,
+                    crc_ok: Some(true),
+                }))
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+            // left over from a reply to a device that has since gone away
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        let peer = self
+            .peer
+            .ok_or_else(|| eyre!("Nothing to transmit to, no frames received yet"))?;
+
+        match self.socket.send_to(frame, peer) {
+            Ok(_) => Ok(()),
+            // the device going away is the same as it being out of range
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Plays back the received frames of a capture, as fast as they're taken.
+/// Whatever is transmitted goes nowhere.
+pub struct Replay {
+    records: std::vec::IntoIter<Record>,
+    finished: bool,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self> {
+        let records = capture::read(path)?
+            .into_iter()
+            .filter(|r| r.direction == capture::Direction::Rx)
+            .collect::<Vec<_>>();
+
+        println!("Replaying {} frames from {}", records.len(), path.display());
+
+        Ok(Self {
+            records: records.into_iter(),
+            finished: false,
+        })
+    }
+}
+
+impl Radio for Replay {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        match self.records.next() {
+            Some(record) => Ok(Some(record.frame()?)),
+            None => {
+                if !std::mem::replace(&mut self.finished, true) {
+                    println!("Replay finished");
+                }
+                std::thread::sleep(timeout);
+                Ok(None)
+            }
+        }
+    }
+
+    fn transmit(&mut self, _frame: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
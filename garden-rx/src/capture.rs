@@ -0,0 +1,147 @@
+//! Every frame that goes over the radio, written down as it happened.
+//!
+//! Captures are json lines, one [`Record`] per frame, so they can be appended
+//! to while the base station is running and picked through with `jq`. A capture
+//! can be fed back through the base station with the `replay:<path>` radio.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::Frame;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Milliseconds since the unix epoch
+    pub at: i64,
+    pub direction: Direction,
+    /// The frame as hex
+    pub data: String,
+    pub rssi: Option<i32>,
+    pub snr: Option<f32>,
+    pub freq_error: Option<i32>,
+    pub crc_ok: Option<bool>,
+    /// What the frame decoded to
+    pub decoded: Option<String>,
+    /// Why the frame didn't decode
+    pub error: Option<String>,
+}
+
+impl Record {
+    pub fn new(
+        direction: Direction,
+        at: DateTime<Utc>,
+        frame: &Frame,
+        decoded: Result<String, String>,
+    ) -> Self {
+        let (decoded, error) = match decoded {
+            Ok(d) => (Some(d), None),
+            Err(e) => (None, Some(e)),
+        };
+
+        Self {
+            at: at.timestamp_millis(),
+            direction,
+            data: to_hex(&frame.data),
+            rssi: frame.rssi,
+            snr: frame.snr,
+            freq_error: frame.freq_error,
+            crc_ok: frame.crc_ok,
+            decoded,
+            error,
+        }
+    }
+
+    pub fn at(&self) -> DateTime<Utc> {
+        Utc.timestamp_millis(self.at)
+    }
+
+    pub fn frame(&self) -> Result<Frame> {
+        Ok(Frame {
+            data: from_hex(&self.data)?,
+            rssi: self.rssi,
+            snr: self.snr,
+            freq_error: self.freq_error,
+            crc_ok: self.crc_ok,
+        })
+    }
+}
+
+pub struct Capture {
+    file: File,
+}
+
+impl Capture {
+    /// Append to the capture at `path`, creating it if needed
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("Failed to open capture {}", path.display()))?;
+
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, record: &Record) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        // one write per record so a crash leaves at most a torn last line
+        self.file.write_all(&line)?;
+
+        Ok(())
+    }
+}
+
+/// Read back all the records in a capture
+pub fn read(path: &Path) -> Result<Vec<Record>> {
+    let file =
+        File::open(path).wrap_err_with(|| format!("Failed to open capture {}", path.display()))?;
+
+    let mut records = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = serde_json::from_str(&line)
+            .wrap_err_with(|| format!("Bad record on line {} of {}", n + 1, path.display()))?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(s: &str) -> Result<Vec<u8>> {
+    let s = s.trim();
+    if s.len() % 2 != 0 {
+        return Err(eyre!("Odd number of hex digits in {:?}", s));
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| eyre!("Bad hex in {:?}", s))
+        })
+        .collect()
+}
@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use garden_shared::FrameTime;
+
+/// How many receptions to fit the drift over
+const MAX_SAMPLES: usize = 64;
+/// How much device time the samples need to cover before the drift estimate
+/// is trusted, over shorter spans the jitter in reception times dominates
+const MIN_SPAN_MS: f64 = 10.0 * 60.0 * 1000.0;
+/// How far the device's clock may wander from ours before it gets set again
+const MAX_OFFSET_MS: i64 = 2_000;
+
+/// Tracks the device's clock against ours, so that timestamps from the device
+/// can be turned into wall clock times.
+///
+/// Every received frame gives a pair of (device time sent, our time received),
+/// fitting a line through those gives the rate the device's clock runs at
+/// relative to ours.
+pub struct ClockSync {
+    samples: VecDeque<(u64, i64)>,
+    synced: bool,
+    last_set: Option<DateTime<Utc>>,
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(MAX_SAMPLES),
+            synced: false,
+            last_set: None,
+        }
+    }
+
+    /// Record a frame that was received at `at`
+    pub fn observe(&mut self, time: &FrameTime, at: DateTime<Utc>) {
+        if let Some(&(last, _)) = self.samples.back() {
+            // the device rebooted or had its clock set, the old samples are
+            // on a different clock
+            if time.sent < last || time.synced != self.synced {
+                self.samples.clear();
+            }
+        }
+
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+
+        self.synced = time.synced;
+        self.samples.push_back((time.sent, at.timestamp_millis()));
+    }
+
+    /// How far the device's clock was behind ours at the last reception
+    pub fn offset_ms(&self) -> Option<i64> {
+        let &(device, local) = self.samples.back()?;
+        Some(local - device as i64)
+    }
+
+    /// How fast the device's clock runs compared to ours, in parts per million
+    pub fn drift_ppm(&self) -> Option<f64> {
+        let &(d0, l0) = self.samples.front()?;
+        let &(d1, _) = self.samples.back()?;
+
+        if ((d1 - d0) as f64) < MIN_SPAN_MS {
+            return None;
+        }
+
+        // least squares fit of our time against the device's, relative to the
+        // first sample to keep the numbers small
+        let n = self.samples.len() as f64;
+        let points = || {
+            self.samples
+                .iter()
+                .map(move |&(d, l)| ((d - d0) as f64, (l - l0) as f64))
+        };
+
+        let mean_d = points().map(|(d, _)| d).sum::<f64>() / n;
+        let mean_l = points().map(|(_, l)| l).sum::<f64>() / n;
+
+        let (cov, var) = points().fold((0.0, 0.0), |(cov, var), (d, l)| {
+            (
+                cov + (d - mean_d) * (l - mean_l),
+                var + (d - mean_d) * (d - mean_d),
+            )
+        });
+
+        Some((cov / var - 1.0) * 1e6)
+    }
+
+    /// Whether the device's clock should be set, either because it hasn't
+    /// been yet or because it has drifted too far. Sets are rate limited in
+    /// case they aren't getting through.
+    pub fn needs_set(&self, now: DateTime<Utc>) -> bool {
+        let off = match self.offset_ms() {
+            Some(offset) => !self.synced || offset.abs() > MAX_OFFSET_MS,
+            None => false,
+        };
+
+        let recently_set = self
+            .last_set
+            .is_some_and(|at| now - at < Duration::minutes(10));
+
+        off && !recently_set
+    }
+
+    /// Note that the device's clock has been set, which makes the samples so
+    /// far useless even if it happened to jump forwards
+    pub fn mark_set(&mut self, now: DateTime<Utc>) {
+        self.last_set = Some(now);
+        self.samples.clear();
+    }
+
+    /// Convert a time on the device's clock into ours, relative to the last
+    /// reception so that the device's clock being off doesn't matter, only
+    /// how fast it runs
+    pub fn to_local(&self, device_ms: u64) -> DateTime<Utc> {
+        let &(d, l) = match self.samples.back() {
+            Some(it) => it,
+            None => return Utc::now(),
+        };
+
+        let rate = 1.0 + self.drift_ppm().unwrap_or(0.0) / 1e6;
+        let local = l as f64 - (d as f64 - device_ms as f64) * rate;
+
+        Utc.timestamp_millis(local as i64)
+    }
+}
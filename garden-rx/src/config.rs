@@ -0,0 +1,113 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use garden_shared::Region;
+
+use crate::backend::Backend;
+
+/// Settings for the base station, taken from the environment:
+///
+/// - `GARDEN_RADIO`: `sx127x` (the default) for the LoRa hat, or
+///   `udp:<addr>` to listen for a simulated device on `addr`, or
+///   `replay:<path>` to feed a capture back through
+/// - `GARDEN_CAPTURE`: a file to append every frame sent or received to
+/// - `GARDEN_REGION`: the frequency plan to meet the device on, `eu868` (the
+///   default), `us915` or `as923`
+/// - `GARDEN_GPIO_CHIP`: the GPIO character device the LoRa hat is on,
+///   `/dev/gpiochip0` by default
+/// - `GARDEN_GPIO_LINES`: the line offsets of the radio's CS, reset and DIO0
+///   pins on that chip, `26,22,25` by default
+/// - `GARDEN_SNIFF`: `1` to keep track of every frame on the channel, not
+///   just the ones from our device
+/// - `GARDEN_STATE`: a file to keep what we know about the device in across
+///   restarts, like the address it was moved to
+pub struct Config {
+    pub radio: Backend,
+    pub capture: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    pub sniff: bool,
+    pub region: Region,
+    pub gpio: Gpio,
+}
+
+/// Where the LoRa hat's pins are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gpio {
+    pub chip: PathBuf,
+    pub cs: u32,
+    pub reset: u32,
+    pub dio0: u32,
+}
+
+impl Default for Gpio {
+    fn default() -> Self {
+        Self {
+            chip: PathBuf::from("/dev/gpiochip0"),
+            cs: 26,
+            reset: 22,
+            dio0: 25,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        let radio = match std::env::var("GARDEN_RADIO") {
+            Ok(radio) => radio.parse()?,
+            Err(_) => Backend::Sx127x,
+        };
+
+        let capture = std::env::var_os("GARDEN_CAPTURE").map(PathBuf::from);
+        let state = std::env::var_os("GARDEN_STATE").map(PathBuf::from);
+
+        let sniff = match std::env::var("GARDEN_SNIFF").as_deref() {
+            Ok("1") => true,
+            Ok("0") | Err(_) => false,
+            Ok(other) => return Err(eyre!("Bad GARDEN_SNIFF {:?}, expected 1 or 0", other)),
+        };
+
+        let region = match std::env::var("GARDEN_REGION") {
+            Ok(region) => region.parse().map_err(|_| {
+                eyre!(
+                    "Unknown region {:?}, expected eu868, us915 or as923",
+                    region
+                )
+            })?,
+            Err(_) => Region::Eu868,
+        };
+
+        let mut gpio = Gpio::default();
+        if let Some(chip) = std::env::var_os("GARDEN_GPIO_CHIP") {
+            gpio.chip = PathBuf::from(chip);
+        }
+        if let Ok(lines) = std::env::var("GARDEN_GPIO_LINES") {
+            let offsets = lines
+                .split(',')
+                .map(|l| l.trim().parse::<u32>())
+                .collect::<Result<Vec<_>, _>>();
+            match offsets.as_deref() {
+                Ok(&[cs, reset, dio0]) => {
+                    gpio.cs = cs;
+                    gpio.reset = reset;
+                    gpio.dio0 = dio0;
+                }
+                _ => {
+                    return Err(eyre!(
+                    "Bad GPIO lines {:?}, expected the CS, reset and DIO0 offsets like 26,22,25",
+                    lines
+                ))
+                }
+            }
+        }
+
+        Ok(Self {
+            radio,
+            capture,
+            state,
+            sniff,
+            region,
+            gpio,
+        })
+    }
+}
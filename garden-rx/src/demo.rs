@@ -0,0 +1,273 @@
+//! Makes up a greenhouse for working on the panel without the hardware.
+//!
+//! Readings follow the time of day, the soil dries out faster when it's warm
+//! and gets wetter while the pump or valve are on. The fake device follows the
+//! desired flags and commands from the panel like the real one would.
+
+use std::f32::consts::TAU;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, Timelike, Utc};
+use color_eyre::Result;
+use garden_shared::{
+    BME688SensorReport, Command, DeviceConfig, DeviceStatus, Diagnostics, LinkQuality, Message,
+    MoistureReading, MoistureSensorReport, RadioHealth, RadioState, ResetCause, StatusFlags, EU868,
+};
+use uom::si::electrical_resistance::ohm;
+use uom::si::f32::{ElectricalResistance, Pressure, Ratio, ThermodynamicTemperature};
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::backend::Frame;
+use crate::diagnostics;
+use crate::radio::Exporter;
+use crate::shared::Shared;
+
+const TICK: Duration = Duration::from_secs(1);
+
+/// How far each sensor reads from the average, they never quite agree
+const SENSOR_OFFSETS: [f32; 3] = [0.0, 12.0, -8.0];
+
+/// Moisture levels are oscillator frequencies, higher is drier
+const WETTEST: f32 = 180.0;
+const DRIEST: f32 = 450.0;
+
+/// How fast the soil gets wetter, in Hz per minute
+const PUMP_RATE: f32 = 10.0;
+const VALVE_RATE: f32 = 3.0;
+
+struct Demo {
+    rng: u32,
+    moisture: f32,
+    flags: StatusFlags,
+    config: DeviceConfig,
+    booted: Instant,
+    reset_cause: ResetCause,
+    last_step: Instant,
+    next_moisture: Instant,
+    next_bme: Instant,
+    next_status: Instant,
+}
+
+impl Demo {
+    fn new() -> Self {
+        let now = Instant::now();
+
+        Self {
+            rng: 0x2545_f491,
+            moisture: 260.0,
+            flags: StatusFlags::empty(),
+            config: DeviceConfig::default(),
+            booted: now,
+            reset_cause: ResetCause::PowerOn,
+            last_step: now,
+            next_moisture: now,
+            next_bme: now,
+            next_status: now,
+        }
+    }
+
+    /// Uniformly distributed in -1..1
+    fn noise(&mut self) -> f32 {
+        // xorshift, good enough for wobbling some numbers
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+
+        self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    /// Where we are in the day, 0 at midnight and 1 at the next
+    fn day_phase() -> f32 {
+        Local::now().num_seconds_from_midnight() as f32 / 86_400.0
+    }
+
+    /// Warmest mid afternoon, coldest before dawn
+    fn temperature(&self) -> f32 {
+        15.0 + 8.0 * (TAU * (Self::day_phase() - 0.375)).sin()
+    }
+
+    fn humidity(&self) -> f32 {
+        70.0 - 20.0 * (TAU * (Self::day_phase() - 0.375)).sin()
+    }
+
+    /// Drifts slowly as weather comes through, over a few days
+    fn pressure(&self) -> f32 {
+        let days = Utc::now().timestamp() as f32 / 86_400.0;
+        1013.0 + 6.0 * (TAU * days / 3.0).sin()
+    }
+
+    fn step(&mut self, now: Instant) {
+        let minutes = (now - self.last_step).as_secs_f32() / 60.0;
+        self.last_step = now;
+
+        // the soil loses a few Hz an hour, more when it's warm
+        let drying = (2.0 + 0.5 * (self.temperature() - 10.0).max(0.0)) / 60.0;
+        let mut rate = drying;
+        if self.flags.contains(StatusFlags::PUMP_ON) {
+            rate -= PUMP_RATE;
+        }
+        if self.flags.contains(StatusFlags::VALVE_OPEN) {
+            rate -= VALVE_RATE;
+        }
+
+        self.moisture = (self.moisture + rate * minutes).clamp(WETTEST, DRIEST);
+    }
+
+    fn moisture_report(&mut self) -> MoistureSensorReport {
+        let moisture = SENSOR_OFFSETS
+            .iter()
+            .map(|offset| {
+                let duration = Duration::from_millis((1000.0 + self.noise() * 3.0) as u64);
+                let level = self.moisture + offset + self.noise() * 1.5;
+
+                MoistureReading {
+                    clocks: (level * duration.as_secs_f32()) as u16,
+                    duration,
+                }
+            })
+            .collect();
+
+        MoistureSensorReport { moisture }
+    }
+
+    fn bme_report(&mut self) -> BME688SensorReport {
+        BME688SensorReport {
+            temp: ThermodynamicTemperature::new::<degree_celsius>(
+                self.temperature() + self.noise() * 0.2,
+            ),
+            pressure: Pressure::new::<hectopascal>(self.pressure() + self.noise() * 0.3),
+            humidity: Ratio::new::<percent>(self.humidity() + self.noise()),
+            gas_resistance: ElectricalResistance::new::<ohm>(50_000.0 + self.noise() * 2_000.0),
+        }
+    }
+
+    /// A frame from the device as the radio might have heard it, and how the
+    /// device heard us
+    fn link(&mut self) -> (Frame, LinkQuality) {
+        let frame = Frame {
+            data: Vec::new(),
+            rssi: Some((-92.0 + self.noise() * 4.0) as i32),
+            snr: Some(4.0 + self.noise() * 3.0),
+            freq_error: Some((-1_200.0 + self.noise() * 150.0) as i32),
+            crc_ok: Some(true),
+        };
+
+        let downlink = LinkQuality {
+            rssi: (-95.0 + self.noise() * 4.0) as i16,
+            snr: (2.0 + self.noise() * 3.0) as i16,
+        };
+
+        (frame, downlink)
+    }
+
+    fn diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            reset_cause: self.reset_cause,
+            uptime: self.booted.elapsed(),
+            version: "demo".into(),
+            git_hash: "demo".into(),
+            bme_failures: 0,
+            dropped_messages: 0,
+            radio_errors: 0,
+            link_fallbacks: 0,
+            airtime: Duration::ZERO,
+            airtime_deferred: 0,
+            channel_deferred: 0,
+            channel_collisions: 0,
+            last_panic: None,
+        }
+    }
+
+    /// Deal with what the panel asked for, returning the messages the device
+    /// would answer with
+    fn handle_commands(&mut self, shared: &Shared, now: Instant) -> Vec<Message> {
+        let mut replies = Vec::new();
+
+        if std::mem::take(&mut *shared.reset_wanted.lock().unwrap()) {
+            self.booted = now;
+            self.reset_cause = ResetCause::System;
+            self.flags = StatusFlags::empty();
+            replies.push(Message::Diagnostics(self.diagnostics()));
+        }
+
+        let pending = shared.pending_commands.lock().unwrap().pop_front();
+        match pending {
+            Some(Command::SetConfig(field)) => {
+                let _ = self.config.set(field, &EU868);
+                replies.push(Message::Config(self.config));
+            }
+            Some(Command::GetConfig) => replies.push(Message::Config(self.config)),
+            _ => {}
+        }
+
+        let desired = *shared.desired_state.lock().unwrap();
+        if desired != self.flags {
+            self.flags = desired;
+            replies.push(Message::StatusUpdate(DeviceStatus { flags: self.flags }));
+        }
+
+        replies
+    }
+
+    /// The messages that are due
+    fn tick(&mut self, shared: &Shared, now: Instant) -> Vec<Message> {
+        self.step(now);
+
+        let mut messages = self.handle_commands(shared, now);
+
+        if now >= self.next_moisture {
+            self.next_moisture = now + self.config.measurement_interval;
+            messages.push(Message::MoistureReport(self.moisture_report()));
+        }
+
+        if now >= self.next_bme {
+            self.next_bme = now + self.config.bme_interval;
+            messages.push(Message::BME688Report(self.bme_report()));
+        }
+
+        if now >= self.next_status {
+            self.next_status = now + self.config.status_interval;
+            messages.push(Message::StatusUpdate(DeviceStatus { flags: self.flags }));
+        }
+
+        messages
+    }
+}
+
+/// Feed made up readings through the exporter forever
+pub fn run(exporter: &mut Exporter) -> Result<()> {
+    let mut demo = Demo::new();
+
+    exporter.report_radio(RadioHealth {
+        state: RadioState::Up,
+        restarts: 0,
+        errors: 0,
+    });
+
+    exporter.submit(Message::Config(demo.config), Utc::now(), false)?;
+    exporter.submit(Message::Diagnostics(demo.diagnostics()), Utc::now(), false)?;
+
+    loop {
+        for msg in demo.tick(exporter.shared(), Instant::now()) {
+            println!("demo: {:?}", msg);
+
+            if let Message::StatusUpdate(_) = msg {
+                let (frame, downlink) = demo.link();
+                exporter.record_link(&frame, Some(downlink), Utc::now());
+            }
+
+            if let Err(e) = exporter.submit(msg, Utc::now(), false) {
+                println!("{}", e);
+            }
+        }
+
+        let check = exporter.shared().pending_checks.lock().unwrap().pop_front();
+        if let Some(check) = check {
+            exporter.report_check(diagnostics::failed(check, "There's no radio in demo mode"));
+        }
+
+        std::thread::sleep(TICK);
+    }
+}
@@ -0,0 +1,227 @@
+//! Looking inside the SX127x for when the link misbehaves: reading out its
+//! registers, checking it's answering properly and listening to how loud the
+//! band is.
+
+use std::time::Duration;
+
+use color_eyre::Result;
+use garden_shared::{
+    FrequencyPlan, LoRaBandwidth, NoiseSample, RadioCheck, RadioRegister, RadioReport, SelfTest,
+    SX127X_REGISTERS,
+};
+
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_FIFO_ADDR_PTR: u8 = 0x0d;
+const REG_RSSI_VALUE: u8 = 0x1b;
+pub(crate) const REG_SYNC_WORD: u8 = 0x39;
+const REG_VERSION: u8 = 0x42;
+
+const MODE_MASK: u8 = 0b111;
+const MODE_SLEEP: u8 = 0b000;
+const MODE_STDBY: u8 = 0b001;
+const MODE_RX_CONTINUOUS: u8 = 0b101;
+
+/// The crystal the frequency registers count in steps of, over 2^19
+const FXOSC: u64 = 32_000_000;
+
+/// The most channels a noise floor scan listens on, wider bands are scanned
+/// in bigger steps
+const MAX_CHANNELS: u32 = 32;
+/// How long the RSSI takes to settle after retuning
+const SETTLE: Duration = Duration::from_millis(5);
+const SAMPLES_PER_CHANNEL: usize = 8;
+const SAMPLE_GAP: Duration = Duration::from_millis(1);
+
+/// A radio's registers, read and written one at a time
+pub trait Registers {
+    fn read(&mut self, addr: u8) -> Result<u8>;
+
+    fn write(&mut self, addr: u8, value: u8) -> Result<()>;
+}
+
+/// Run `check` on the radio behind `registers`, which is tuned to `plan`'s
+/// band. The radio is left wherever the check finished, it has to be set up
+/// again before it's listened on.
+pub fn run(
+    check: RadioCheck,
+    registers: &mut dyn Registers,
+    plan: &FrequencyPlan,
+) -> Result<RadioReport> {
+    Ok(match check {
+        RadioCheck::Registers => RadioReport::Registers(dump(registers)?),
+        RadioCheck::SelfTest => RadioReport::SelfTest(self_test(registers)?),
+        RadioCheck::NoiseFloor => {
+            let samples = noise_floor(registers, plan)?;
+            RadioReport::NoiseFloor {
+                floor: median(&samples),
+                samples,
+            }
+        }
+    })
+}
+
+/// The report for a check that couldn't be run because of `reason`
+pub fn failed(check: RadioCheck, reason: &str) -> RadioReport {
+    let mut short = heapless::String::new();
+    for c in reason.chars() {
+        if short.push(c).is_err() {
+            break;
+        }
+    }
+
+    RadioReport::Failed {
+        check,
+        reason: short,
+    }
+}
+
+/// Read out every register in [`SX127X_REGISTERS`]
+pub fn dump(registers: &mut dyn Registers) -> Result<heapless::Vec<RadioRegister, 64>> {
+    let mut dumped = heapless::Vec::new();
+    for &(addr, _) in SX127X_REGISTERS {
+        let value = registers.read(addr)?;
+        let _ = dumped.push(RadioRegister { addr, value });
+    }
+
+    Ok(dumped)
+}
+
+/// Switch the radio into `mode`, keeping it in LoRa mode and on its band
+fn set_mode(registers: &mut dyn Registers, mode: u8) -> Result<()> {
+    let op_mode = registers.read(REG_OP_MODE)?;
+    registers.write(REG_OP_MODE, (op_mode & !MODE_MASK) | mode)
+}
+
+/// Whether the radio goes into `mode` when told to
+fn enters_mode(registers: &mut dyn Registers, mode: u8) -> Result<bool> {
+    set_mode(registers, mode)?;
+    Ok(registers.read(REG_OP_MODE)? & MODE_MASK == mode)
+}
+
+/// Check the chip's version, that registers hold what's written to them,
+/// that it changes mode and that the FIFO reads back what's written to it.
+/// A bus with nothing on the other end fails all of them rather than
+/// erroring, errors are left to the SPI device itself going away.
+pub fn self_test(registers: &mut dyn Registers) -> Result<SelfTest> {
+    let version = registers.read(REG_VERSION)?;
+
+    // alternating bits, so a line stuck high or low, or two shorted
+    // together, doesn't read back right
+    let sync_word = registers.read(REG_SYNC_WORD)?;
+    let mut spi = true;
+    for pattern in [0x55, 0xaa] {
+        registers.write(REG_SYNC_WORD, pattern)?;
+        spi &= registers.read(REG_SYNC_WORD)? == pattern;
+    }
+    registers.write(REG_SYNC_WORD, sync_word)?;
+
+    let op_mode = registers.read(REG_OP_MODE)?;
+    let modes = enters_mode(registers, MODE_SLEEP)? && enters_mode(registers, MODE_STDBY)?;
+
+    // the FIFO is only there outside of sleep, which the mode check leaves
+    // us out of
+    let pattern = (0..16u8).map(|i| i.wrapping_mul(17) ^ 0x5a);
+    registers.write(REG_FIFO_ADDR_PTR, 0)?;
+    for byte in pattern.clone() {
+        registers.write(REG_FIFO, byte)?;
+    }
+    registers.write(REG_FIFO_ADDR_PTR, 0)?;
+    let mut fifo = true;
+    for byte in pattern {
+        fifo &= registers.read(REG_FIFO)? == byte;
+    }
+
+    registers.write(REG_OP_MODE, op_mode)?;
+
+    Ok(SelfTest {
+        version,
+        spi,
+        modes,
+        fifo,
+    })
+}
+
+/// The frequency registers' value for `frequency` in Hz
+fn frf(frequency: u32) -> u32 {
+    (((frequency as u64) << 19) / FXOSC) as u32
+}
+
+/// What to add to RegRssiValue for dBm, which depends on which of the chip's
+/// RF ports the frequency is on
+fn rssi_offset(frequency: u32) -> i16 {
+    if frequency > 525_000_000 {
+        -157
+    } else {
+        -164
+    }
+}
+
+/// The channels a noise floor scan of `plan`'s band listens on, the width of
+/// the plan's channel apart unless there'd be too many of them
+fn channels(plan: &FrequencyPlan) -> impl Iterator<Item = u32> {
+    let (low, high) = plan.band;
+    let width = match plan.radio.bandwidth {
+        LoRaBandwidth::Bw125kHz => 125_000,
+        LoRaBandwidth::Bw250kHz => 250_000,
+        LoRaBandwidth::Bw500kHz => 500_000,
+    };
+    let step = width.max((high - low).div_ceil(MAX_CHANNELS));
+
+    (low + step / 2..high)
+        .step_by(step as usize)
+        .take(MAX_CHANNELS as usize)
+}
+
+/// Listen on channels across `plan`'s band with nobody meant to be
+/// transmitting, and see how loud each of them is
+pub fn noise_floor(
+    registers: &mut dyn Registers,
+    plan: &FrequencyPlan,
+) -> Result<heapless::Vec<NoiseSample, 32>> {
+    let op_mode = registers.read(REG_OP_MODE)?;
+    let mut tuned = [0; 3];
+    for (i, value) in tuned.iter_mut().enumerate() {
+        *value = registers.read(REG_FRF_MSB + i as u8)?;
+    }
+
+    let mut samples = heapless::Vec::new();
+    for frequency in channels(plan) {
+        // the frequency only changes outside of receive
+        set_mode(registers, MODE_STDBY)?;
+        for (i, byte) in frf(frequency).to_be_bytes()[1..].iter().enumerate() {
+            registers.write(REG_FRF_MSB + i as u8, *byte)?;
+        }
+        set_mode(registers, MODE_RX_CONTINUOUS)?;
+        std::thread::sleep(SETTLE);
+
+        let mut heard = Vec::with_capacity(SAMPLES_PER_CHANNEL);
+        for _ in 0..SAMPLES_PER_CHANNEL {
+            heard.push(registers.read(REG_RSSI_VALUE)? as i16 + rssi_offset(frequency));
+            std::thread::sleep(SAMPLE_GAP);
+        }
+
+        let _ = samples.push(NoiseSample {
+            frequency,
+            rssi: heard.iter().sum::<i16>() / heard.len() as i16,
+            peak: *heard.iter().max().unwrap(),
+        });
+    }
+
+    set_mode(registers, MODE_STDBY)?;
+    for (i, value) in tuned.iter().enumerate() {
+        registers.write(REG_FRF_MSB + i as u8, *value)?;
+    }
+    registers.write(REG_OP_MODE, op_mode)?;
+
+    Ok(samples)
+}
+
+/// The middle of the channels' average signal strength, so a few busy
+/// channels don't drag the floor up
+fn median(samples: &[NoiseSample]) -> i16 {
+    let mut rssi = samples.iter().map(|s| s.rssi).collect::<Vec<_>>();
+    rssi.sort_unstable();
+    rssi.get(rssi.len() / 2).copied().unwrap_or(i16::MIN)
+}
@@ -0,0 +1,109 @@
+//! Sending new firmware to the device. It only listens just after it's sent
+//! us something, so one firmware command goes out with each frame we hear and
+//! the device's answer says what it wants next.
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use garden_core::firmware::{is_app_image, Layout};
+use garden_shared::{
+    crc32, Command, FirmwareChunk, FirmwareManifest, FirmwareProgress, FirmwareState,
+    FirmwareStatus, FirmwareUpload, FIRMWARE_CHUNK,
+};
+use sha2::{Digest, Sha256};
+
+/// The firmware being sent and what the device last said about it
+pub struct Upload {
+    image: Vec<u8>,
+    manifest: FirmwareManifest,
+    device: Option<FirmwareStatus>,
+}
+
+impl Upload {
+    /// Check `image` is firmware for the device's app slot, as made by
+    /// `cargo objcopy --release -- -O binary`
+    pub fn new(image: Vec<u8>) -> Result<Self> {
+        let layout = Layout::SAMD21;
+        if image.is_empty() || image.len() > layout.slot_len as usize {
+            return Err(eyre!(
+                "Firmware is {} bytes, the device has room for {}",
+                image.len(),
+                layout.slot_len
+            ));
+        }
+        if !is_app_image(&layout, &image) {
+            return Err(eyre!(
+                "Not firmware for the device, it should start with a vector table for {:#x}",
+                layout.app
+            ));
+        }
+
+        let manifest = FirmwareManifest {
+            size: image.len() as u32,
+            sha256: Sha256::digest(&image).into(),
+        };
+
+        Ok(Self {
+            image,
+            manifest,
+            device: None,
+        })
+    }
+
+    pub fn manifest(&self) -> &FirmwareManifest {
+        &self.manifest
+    }
+
+    /// Note what the device said about its firmware
+    pub fn observe(&mut self, status: FirmwareStatus) {
+        self.device = Some(status);
+    }
+
+    /// Whether the device has stopped wanting this firmware, either because
+    /// it has all of it or because it gave up on it
+    pub fn is_finished(&self) -> bool {
+        match self.device {
+            Some(FirmwareStatus { image, state }) if image == self.manifest.image() => {
+                !matches!(state, FirmwareState::Receiving { .. })
+            }
+            _ => false,
+        }
+    }
+
+    /// What to send the device next, the manifest until it's receiving this
+    /// firmware and then whichever chunk it asked for
+    pub fn next_command(&self) -> Option<Command> {
+        if self.is_finished() {
+            return None;
+        }
+
+        match self.device {
+            Some(FirmwareStatus {
+                image,
+                state: FirmwareState::Receiving { next },
+            }) if image == self.manifest.image() => self.chunk(next).map(Command::FirmwareChunk),
+            _ => Some(Command::FirmwareManifest(self.manifest)),
+        }
+    }
+
+    fn chunk(&self, index: u16) -> Option<FirmwareChunk> {
+        let start = index as usize * FIRMWARE_CHUNK;
+        let end = (start + FIRMWARE_CHUNK).min(self.image.len());
+        let data = self.image.get(start..end).filter(|d| !d.is_empty())?;
+
+        Some(FirmwareChunk {
+            index,
+            crc: crc32(data),
+            data: heapless::Vec::from_slice(data).unwrap(),
+        })
+    }
+
+    pub fn progress(&self) -> FirmwareProgress {
+        FirmwareProgress {
+            upload: Some(FirmwareUpload {
+                image: self.manifest.image(),
+                size: self.manifest.size,
+            }),
+            device: self.device,
+        }
+    }
+}
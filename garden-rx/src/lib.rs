@@ -0,0 +1,18 @@
+//! The base station: receives readings from the device over LoRa, stores them
+//! in influxdb and serves the control panel.
+
+pub mod backend;
+pub mod capture;
+pub mod clock;
+pub mod config;
+pub mod demo;
+pub mod diagnostics;
+pub mod firmware;
+pub mod link;
+pub mod radio;
+pub mod server;
+pub mod shared;
+pub mod sniffer;
+pub mod state;
+pub mod storage;
+pub mod supervisor;
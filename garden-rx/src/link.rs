@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use garden_shared::{LinkParams, LinkQuality, RadioConfig};
+
+/// How many frames to judge the link on before changing anything
+const SAMPLES: usize = 10;
+/// Headroom kept above the demodulation floor for fading and the like
+const MARGIN_DB: f32 = 10.0;
+/// How much margin each step of spreading factor or power is worth
+const STEP_DB: f32 = 3.0;
+const POWER_STEP: i8 = 3;
+/// The lowest power the device's PA_BOOST output does
+const MIN_TX_POWER: i8 = 2;
+/// The highest power the device can keep up without the high power mode
+const MAX_TX_POWER: i8 = 17;
+/// How many of the device's status intervals can pass without hearing from it
+/// before we go back to the rendezvous. Longer than the device takes to give
+/// up, so both ends end up there.
+const QUIET_INTERVALS: u32 = 5;
+/// How long to stick to the rendezvous after adapted settings lost the link
+const HOLD_OFF: Duration = Duration::from_secs(60 * 60);
+
+/// The lowest SNR a frame can be demodulated at with a spreading factor,
+/// from the SX127x datasheet
+fn demod_floor(spreading_factor: u8) -> f32 {
+    -7.5 - 2.5 * (spreading_factor.saturating_sub(7)) as f32
+}
+
+/// Picks the spreading factor and power the device should use from how well
+/// frames get through, aiming for the least airtime and power that still
+/// leaves [`MARGIN_DB`] to spare.
+///
+/// The device's configured radio settings are the rendezvous: both ends go
+/// back to them if adapted settings lose the link.
+pub struct LinkAdapter {
+    params: Option<LinkParams>,
+    /// The worse of the uplink and downlink SNR of recent frames, since the
+    /// settings were last changed
+    snrs: VecDeque<f32>,
+    last_heard: Instant,
+    hold_off_until: Option<Instant>,
+}
+
+impl Default for LinkAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LinkAdapter {
+    pub fn new() -> Self {
+        Self {
+            params: None,
+            snrs: VecDeque::with_capacity(SAMPLES),
+            last_heard: Instant::now(),
+            hold_off_until: None,
+        }
+    }
+
+    /// The settings the device should be on, `None` for the rendezvous
+    pub fn params(&self) -> Option<LinkParams> {
+        self.params
+    }
+
+    /// The radio settings to listen on
+    pub fn radio(&self, rendezvous: &RadioConfig) -> RadioConfig {
+        match self.params {
+            Some(params) => params.apply(rendezvous),
+            None => *rendezvous,
+        }
+    }
+
+    /// Note a frame from the device and how it says our last one came through
+    pub fn observe(
+        &mut self,
+        uplink_snr: Option<f32>,
+        downlink: Option<LinkQuality>,
+        now: Instant,
+    ) {
+        self.last_heard = now;
+
+        // radios that aren't on the air don't know the SNR
+        let uplink_snr = match uplink_snr {
+            Some(snr) => snr,
+            None => return,
+        };
+        let snr = match downlink {
+            Some(downlink) => uplink_snr.min(downlink.snr as f32),
+            None => uplink_snr,
+        };
+
+        if self.snrs.len() == SAMPLES {
+            self.snrs.pop_front();
+        }
+        self.snrs.push_back(snr);
+    }
+
+    /// New settings to send to the device, if the link has room to spare or
+    /// is running short, never slower than `max_spreading_factor`. They're
+    /// only used once [`LinkAdapter::adopt`]ed.
+    pub fn decide(
+        &self,
+        rendezvous: &RadioConfig,
+        max_spreading_factor: u8,
+        now: Instant,
+    ) -> Option<Option<LinkParams>> {
+        if self.snrs.len() < SAMPLES || self.hold_off_until.is_some_and(|t| now < t) {
+            return None;
+        }
+
+        let current = self.radio(rendezvous);
+        // judge on the worst frame, a lost frame costs more than the airtime
+        let worst = self.snrs.iter().copied().fold(f32::INFINITY, f32::min);
+        let margin = worst - demod_floor(current.spreading_factor) - MARGIN_DB;
+        let mut steps = (margin / STEP_DB).trunc() as i32;
+
+        let mut sf = current.spreading_factor;
+        let mut power = current.tx_power;
+
+        // airtime first, then power
+        while steps > 0 && sf > 7 {
+            sf -= 1;
+            steps -= 1;
+        }
+        while steps > 0 && power - POWER_STEP >= MIN_TX_POWER {
+            power -= POWER_STEP;
+            steps -= 1;
+        }
+        // and back the other way, power first
+        while steps < 0 && power + POWER_STEP <= MAX_TX_POWER {
+            power += POWER_STEP;
+            steps += 1;
+        }
+        while steps < 0 && sf < max_spreading_factor {
+            sf += 1;
+            steps += 1;
+        }
+
+        if (sf, power) == (current.spreading_factor, current.tx_power) {
+            return None;
+        }
+
+        if (sf, power) == (rendezvous.spreading_factor, rendezvous.tx_power) {
+            Some(None)
+        } else {
+            Some(Some(LinkParams {
+                spreading_factor: sf,
+                tx_power: power,
+            }))
+        }
+    }
+
+    /// Switch to settings the device has been sent
+    pub fn adopt(&mut self, params: Option<LinkParams>) {
+        self.params = params;
+        self.snrs.clear();
+    }
+
+    /// How long until we give up on the adapted settings, if we're on them
+    pub fn time_left(&self, status_interval: Duration, now: Instant) -> Option<Duration> {
+        self.params?;
+        let deadline = self.last_heard + status_interval * QUIET_INTERVALS;
+        Some(deadline.saturating_duration_since(now))
+    }
+
+    /// Go back to the rendezvous if the device has been quiet for too long on
+    /// the adapted settings, returning whether we did
+    pub fn check_quiet(&mut self, status_interval: Duration, now: Instant) -> bool {
+        if self.time_left(status_interval, now) != Some(Duration::ZERO) {
+            return false;
+        }
+
+        self.params = None;
+        self.snrs.clear();
+        self.hold_off_until = Some(now + HOLD_OFF);
+        true
+    }
+}
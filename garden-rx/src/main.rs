@@ -1,180 +1,104 @@
 use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use axum::body::{self, Empty, Full};
-use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{Path, WebSocketUpgrade};
-use axum::http::{header, HeaderValue, Response, StatusCode};
-use axum::response::{IntoResponse, Redirect};
-use axum::routing::get;
-use axum::{Extension, Router};
 use color_eyre::Result;
-use garden_shared::{DeviceStatus, PanelMessage, StatusFlags, UiCommand};
-use include_dir::{include_dir, Dir};
-use tokio::sync::watch;
-use tokio_stream::StreamExt;
-
-use crate::radio::{DESIRED_STATE, RESET_WANTED};
-
-mod radio;
-
-static PANEL_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/../garden-panel/dist");
+use garden_rx::config::Config;
+use garden_rx::shared::Shared;
+use garden_rx::storage::Influx;
+use garden_rx::{radio, server};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, watch};
+
+/// How long to give the radio to let go of the hat when shutting down. The
+/// hat lets go straight away, the other radios only notice once they've
+/// finished waiting for a frame.
+const RADIO_SHUTDOWN: Duration = Duration::from_secs(5);
+
+/// Exit status for when the radio can't be had, `EX_UNAVAILABLE` from
+/// sysexits.h. The unit has systemd restart us on it after a pause
+/// (`Restart=on-failure` and `RestartSec=30s`), in case the hat comes back.
+const EXIT_NO_RADIO: i32 = 69;
+
+/// Resolves on Ctrl-C or SIGTERM, which is what systemd stops us with
+async fn shutdown_signal() {
+    let mut term = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = term.recv() => {}
+    }
 
-#[derive(Clone)]
-struct State {
-    status_recv: watch::Receiver<Option<DeviceStatus>>,
+    println!("Shutting down");
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let config = Config::from_env()?;
+
     let (status_sender, status_recv) = watch::channel(None);
+    let (event_sender, _) = broadcast::channel(16);
+    let (shutdown_sender, shutdown_recv) = watch::channel(false);
+
+    let storage = Arc::new(Influx::new(
+        "http://localhost:8086",
+        "garden",
+        "IoyGBd5jH-RScuacNjlUBSToAHtlKu270PesRi9E5Gg4M516GittWr2w5QdJPkn4X8Wh_VA7zfhxByOaviMuCQ==",
+    ));
+
+    let shared = Arc::new(Shared::default());
 
     let rt_handle = tokio::runtime::Handle::current();
-    std::thread::spawn(move || {
+    let radio_shared = shared.clone();
+    let radio_event_sender = event_sender.clone();
+    let radio_thread = std::thread::spawn(move || {
         let _handle = rt_handle.enter();
-        if let Err(e) = radio::radio_side(status_sender) {
-            println!("{:?}", e);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            radio::radio_side(
+                config,
+                storage,
+                radio_shared,
+                status_sender,
+                radio_event_sender,
+                shutdown_recv,
+            )
+        }));
+
+        // the panel is no use without the radio, better to go down where
+        // someone will notice than keep showing stale data
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                eprintln!("Radio unavailable: {:?}", e);
+                std::process::exit(EXIT_NO_RADIO);
+            }
+            Err(_) => {
+                eprintln!("Radio thread panicked");
+                std::process::exit(EXIT_NO_RADIO);
+            }
         }
     });
 
-    let asset_router = Router::new().route("/*path", get(static_path));
-
-    let app = Router::new()
-        .route("/ws", get(root_ws))
-        .route("/", get(|| async { Redirect::to("/index.html") }))
-        .fallback(asset_router)
-        .layer(Extension(State { status_recv }))
-        .layer(tower_http::compression::CompressionLayer::new())
-        .layer(
-            tower_http::set_header::SetResponseHeaderLayer::if_not_present(
-                header::CACHE_CONTROL,
-                HeaderValue::from_static("public, max-age=300"),
-            ),
-        );
+    let app = server::app(shared, status_recv, event_sender);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown_signal())
         .await?;
 
-    Ok(())
-}
-
-async fn static_path(Path(path): Path<String>) -> impl IntoResponse {
-    let path = path.trim_start_matches('/');
-    let mime_type = mime_guess::from_path(path).first_or_text_plain();
-
-    match PANEL_DIR.get_file(path) {
-        None => Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(body::boxed(Empty::new()))
-            .unwrap(),
-        Some(file) => Response::builder()
-            .status(StatusCode::OK)
-            .header(
-                header::CONTENT_TYPE,
-                HeaderValue::from_str(mime_type.as_ref()).unwrap(),
-            )
-            .body(body::boxed(Full::from(file.contents())))
-            .unwrap(),
-    }
-}
-
-async fn root_ws(ws: WebSocketUpgrade, Extension(state): Extension<State>) -> impl IntoResponse {
-    ws.on_upgrade(|s| async {
-        if let Err(e) = handle_socket(s, state).await {
-            eprintln!("{e:?}");
-        } else {
-            println!("Websocket exited");
+    let _ = shutdown_sender.send(true);
+    let deadline = Instant::now() + RADIO_SHUTDOWN;
+    while !radio_thread.is_finished() {
+        if Instant::now() > deadline {
+            println!("Radio didn't shut down in time, leaving it");
+            break;
         }
-    })
-}
-
-async fn handle_socket(mut socket: WebSocket, mut state: State) -> Result<()> {
-    println!("Websocket connected!");
-
-    socket
-        .send(Message::Text(
-            serde_json::to_string(&PanelMessage::Hello).unwrap(),
-        ))
-        .await?;
-
-    let v = state.status_recv.borrow_and_update().clone();
-    if let Some(v) = v {
-        let c = PanelMessage::Status(v);
-        socket
-            .send(Message::Text(serde_json::to_string(&c).unwrap()))
-            .await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
     }
 
-    let c = PanelMessage::DesiredStatus(*DESIRED_STATE.lock().unwrap());
-    socket
-        .send(Message::Text(serde_json::to_string(&c).unwrap()))
-        .await?;
-
-    let mut status_stream = tokio_stream::wrappers::WatchStream::new(state.status_recv);
-
-    loop {
-        tokio::select! {
-            v = status_stream.next() => {
-                println!("Got status message: {:?}", v);
-                if let Some(Some(v)) = v {
-                    socket
-                        .send(Message::Text(
-                            serde_json::to_string(&PanelMessage::Status(v)).unwrap(),
-                        ))
-                        .await?;
-                }
-            }
-
-            cmd = socket.next() => {
-                if let Some(cmd) = cmd {
-                    println!("Got cmd: {:?}", cmd);
-                    let cmd = cmd?;
-
-                    match cmd {
-                        Message::Text(t) => {
-                            println!("got message: {}", t);
-                            let cmd: UiCommand = serde_json::from_str(&t)?;
-
-                            let c = {
-                                let mut desired_state = DESIRED_STATE.lock().unwrap();
-
-                                match cmd {
-                                    UiCommand::PumpOn => {
-                                        desired_state.set(StatusFlags::PUMP_ON, true);
-                                    },
-                                    UiCommand::PumpOff => {
-                                        desired_state.set(StatusFlags::PUMP_ON, false);
-                                    },
-                                    UiCommand::ValveOpen => {
-                                        desired_state.set(StatusFlags::VALVE_OPEN, true);
-                                    },
-                                    UiCommand::ValveClose => {
-                                        desired_state.set(StatusFlags::VALVE_OPEN, false);
-                                    },
-                                    UiCommand::Reset => {
-                                        *RESET_WANTED.lock().unwrap() = true;
-                                    }
-                                }
-
-                                PanelMessage::DesiredStatus(*desired_state)
-                            };
-                            println!("Sending message {:?}", c);
-                            socket
-                                .send(Message::Text(serde_json::to_string(&c).unwrap()))
-                                .await?;
-                        }
-                        Message::Ping(msg) => {
-                            socket.send(Message::Pong(msg)).await?;
-                        }
-                        Message::Close(_) => {
-                            println!("Closing websocket");
-                            return Ok(());
-                        },
-                        _ => {}
-                    }
-                }
-            }
-        }
-    }
+    Ok(())
 }
@@ -1,92 +1,301 @@
-use std::sync::Mutex;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use chrono::{DateTime, Utc};
 use color_eyre::Result;
-use embedded_radio::EmbeddedRadio;
+use garden_core::airtime::{self, Airtime};
+use garden_core::outbox;
+use garden_core::repeater::{self, Seen, HOP_MARGIN};
 use garden_shared::{
-    BME688SensorReport, Command, DevAddr, DeviceStatus, Message, MoistureSensorReport, StatusFlags,
-    Transmission,
+    BME688SensorReport, Command, DevAddr, DeviceConfig, DeviceStatus, FirmwareStatus, FrameTime,
+    FrequencyPlan, Header, LinkQuality, Message, MoistureSensorReport, PanelMessage, RadioCheck,
+    RadioConfig, RadioHealth, RadioReport, Route, StatusFlags, Transmission, WateringEvent,
+    BASE_ADDR, EU868,
 };
-use influxdb2::{models::DataPoint, Client};
-use linux_embedded_hal as hal;
-
-use hal::spidev::{self, SpidevOptions};
-use hal::sysfs_gpio::Direction;
-use hal::Delay;
-use hal::{Pin, Spidev};
-use once_cell::sync::Lazy;
-use tokio::sync::watch;
+use tokio::sync::{broadcast, watch};
 use uom::si::pressure::pascal;
 use uom::si::ratio::percent;
 use uom::si::thermodynamic_temperature::degree_celsius;
 
-const LORA_CS_PIN: u64 = 26;
-const LORA_RESET_PIN: u64 = 22;
-const FREQUENCY: i64 = 868;
-
-pub static DESIRED_STATE: Lazy<Mutex<StatusFlags>> = Lazy::new(|| Mutex::new(StatusFlags::empty()));
-pub static RESET_WANTED: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
-
-pub fn radio_side(status_sender: watch::Sender<Option<DeviceStatus>>) -> Result<()> {
-    color_eyre::install()?;
-
-    let mut spi = Spidev::open("/dev/spidev0.1").unwrap();
-    let options = SpidevOptions::new()
-        .bits_per_word(8)
-        .max_speed_hz(20_000)
-        .mode(spidev::SpiModeFlags::SPI_MODE_0)
-        .build();
-
-    spi.configure(&options).unwrap();
-
-    let cs = Pin::new(LORA_CS_PIN);
-    cs.export().unwrap();
-    cs.set_direction(Direction::Out).unwrap();
+use crate::backend::{Frame, Radio};
+use crate::capture::{Capture, Direction, Record};
+use crate::clock::ClockSync;
+use crate::config::Config;
+use crate::diagnostics;
+use crate::firmware::Upload;
+use crate::link::LinkAdapter;
+use crate::shared::{Latest, Shared};
+use crate::sniffer::{self, Heard, Sniffer};
+use crate::state::{State, StateFile};
+use crate::storage::{Point, Storage};
+use crate::supervisor::Supervisor;
+
+/// How long to wait for a frame before checking in again
+const RECEIVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(100);
+
+/// How many stored messages to remember for spotting replays of them
+const RECENT_DELIVERED: usize = 64;
+/// How many crash reports to keep around for the panel
+const RECENT_CRASHES: usize = 16;
+/// How many frames to remember for spotting copies that came through a
+/// repeater as well
+const RECENT_FRAMES: usize = 16;
+
+/// Listen to the device until `shutdown` goes true, or return an error if the
+/// radio can't be set up
+pub fn radio_side(
+    config: Config,
+    storage: Arc<dyn Storage>,
+    shared: Arc<Shared>,
+    status_sender: watch::Sender<Option<DeviceStatus>>,
+    event_sender: broadcast::Sender<PanelMessage>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let check_wanted = shared.check_wanted.clone();
+    let mut exporter = Exporter::new(storage, shared, status_sender, event_sender);
+
+    if let Some(path) = &config.capture {
+        exporter.capture_to(Capture::open(path)?);
+        println!("Capturing frames to {}", path.display());
+    }
 
-    let reset = Pin::new(LORA_RESET_PIN);
-    reset.export().unwrap();
-    reset.set_direction(Direction::Out).unwrap();
+    if config.sniff {
+        exporter.sniff();
+        println!("Sniffing every frame on the channel");
+    }
 
-    let mut lora = embedded_radio::LoRa::new(spi, cs, reset, FREQUENCY, &mut Delay)
-        .expect("Failed to communicate with radio module!");
-    lora.set_tx_power(17, 1).unwrap();
+    if let Some(path) = &config.state {
+        exporter.keep_state_in(StateFile::new(path.clone()))?;
+    }
 
-    let mut exporter = Exporter::new(status_sender);
+    if cfg!(feature = "demo") {
+        println!("Running in demo mode, readings are made up");
+        return crate::demo::run(&mut exporter);
+    }
 
-    println!("Radio initialized");
+    let plan = config.region.plan();
+    exporter.use_plan(plan);
+    let open = || {
+        let radio =
+            config
+                .radio
+                .open(plan, &config.gpio, shutdown.clone(), check_wanted.clone())?;
+        println!("Radio initialized ({:?})", plan.region);
+        Ok(radio)
+    };
+
+    Supervisor::default().run(&mut exporter, open, &shutdown)?;
+    println!("Radio shut down");
+
+    Ok(())
+}
 
-    loop {
-        match exporter.inner(&mut lora) {
-            Ok(()) => {}
-            Err(e) => {
-                println!("{}", e);
-            }
-        }
+/// Keep hold of `report` if it's newer than the one we have, returning whether
+/// it was
+fn update_latest<T: Clone>(latest: &Mutex<Latest<T>>, at: DateTime<Utc>, report: &T) -> bool {
+    let mut latest = latest.lock().unwrap();
+    if matches!(&*latest, Some((prev, _)) if *prev > at) {
+        return false;
     }
+
+    *latest = Some((at, report.clone()));
+    true
 }
 
-struct Exporter {
+pub struct Exporter {
     last_bme_reading: Option<BME688SensorReport>,
     last_moisture_reading: Option<MoistureSensorReport>,
     status_sender: watch::Sender<Option<DeviceStatus>>,
-    client: influxdb2::Client,
+    event_sender: broadcast::Sender<PanelMessage>,
+    storage: Arc<dyn Storage>,
+    /// What the panel sees of the device and wants of it
+    shared: Arc<Shared>,
+    next_seq: u16,
+    /// Messages the device would replay if it missed our ack, by sender,
+    /// sequence number and when they were captured. The device starts its
+    /// sequence numbers over when it resets, so they aren't enough on their
+    /// own.
+    recent_delivered: VecDeque<(DevAddr, u16, u64)>,
+    /// Frames from the device, which can reach us more than one way
+    seen: Seen<RECENT_FRAMES>,
+    clock: ClockSync,
+    link: LinkAdapter,
+    /// Where we meet a device we haven't heard the config of yet
+    plan: &'static FrequencyPlan,
+    /// What the radio was last set up for
+    tuned: Option<RadioConfig>,
+    /// Our own airtime, the device keeps count of its own
+    airtime: Airtime,
+    /// Commands held back to stay within the duty cycle
+    airtime_deferred: u32,
+    capture: Option<Capture>,
+    sniffer: Option<Sniffer>,
+    /// What we know about the device, and where to keep it
+    state: State,
+    state_file: Option<StateFile>,
 }
 
 impl Exporter {
-    fn new(status_sender: watch::Sender<Option<DeviceStatus>>) -> Self {
-        let client =
-            Client::new("http://localhost:8086", "garden", "IoyGBd5jH-RScuacNjlUBSToAHtlKu270PesRi9E5Gg4M516GittWr2w5QdJPkn4X8Wh_VA7zfhxByOaviMuCQ==");
-
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        shared: Arc<Shared>,
+        status_sender: watch::Sender<Option<DeviceStatus>>,
+        event_sender: broadcast::Sender<PanelMessage>,
+    ) -> Self {
         Self {
             last_bme_reading: None,
             last_moisture_reading: None,
             status_sender,
-            client,
+            event_sender,
+            storage,
+            shared,
+            next_seq: 0,
+            recent_delivered: VecDeque::with_capacity(RECENT_DELIVERED),
+            seen: Seen::new(),
+            clock: ClockSync::new(),
+            link: LinkAdapter::new(),
+            plan: &EU868,
+            tuned: None,
+            airtime: Airtime::new(EU868.airtime_per_hour),
+            airtime_deferred: 0,
+            capture: None,
+            sniffer: None,
+            state: State::default(),
+            state_file: None,
         }
     }
 
-    fn submit(&mut self, msg: Message) -> Result<()> {
+    pub fn shared(&self) -> &Shared {
+        &self.shared
+    }
+
+    pub fn use_plan(&mut self, plan: &'static FrequencyPlan) {
+        self.plan = plan;
+        self.airtime = Airtime::new(plan.airtime_per_hour);
+    }
+
+    /// Pick up the state from before a restart, and write it down whenever
+    /// it changes from now on
+    pub fn keep_state_in(&mut self, file: StateFile) -> Result<()> {
+        self.state = file.load()?;
+        println!(
+            "Expecting the device at {:#x}, from {}",
+            self.state.device_addr.0,
+            file.path().display()
+        );
+        self.state_file = Some(file);
+        Ok(())
+    }
+
+    /// The device confirmed it now sends from `addr`
+    fn readdress(&mut self, addr: DevAddr) {
+        println!(
+            "Device moved from {:#x} to {:#x}",
+            self.state.device_addr.0, addr.0
+        );
+        self.state.device_addr = addr;
+
+        if let Some(file) = &self.state_file {
+            if let Err(e) = file.save(&self.state) {
+                eprintln!("Failed to save the device's new address: {:?}", e);
+            }
+        }
+    }
+
+    /// Write down every frame sent or received from now on
+    pub fn capture_to(&mut self, capture: Capture) {
+        self.capture = Some(capture);
+    }
+
+    /// Keep track of every frame on the channel from now on, foreign and
+    /// undecodable ones included
+    pub fn sniff(&mut self) {
+        self.sniffer = Some(Sniffer::new(sniffer::WINDOW));
+    }
+
+    /// Store a frame that was on the channel and how busy that leaves it, if
+    /// we're sniffing
+    fn sniffed(&mut self, heard: Heard, frame: &Frame, at: DateTime<Utc>) {
+        let sniffer = match &mut self.sniffer {
+            Some(sniffer) => sniffer,
+            None => return,
+        };
+
+        // anything we can hear is on the settings we're listening with
+        let settings = self.tuned.unwrap_or(self.plan.radio);
+        let on_air = airtime::time_on_air(&settings, frame.data.len());
+        let now = Instant::now();
+        let gap = sniffer.observe(heard, on_air, frame.rssi.map(|r| r as i16), now);
+        let stats = sniffer.stats(now);
+
+        let mut point = Point::new("frame")
+            .tag("kind", heard.name())
+            .field("len", frame.data.len() as i64)
+            .field("airtime_us", on_air.as_micros() as i64);
+        if let Heard::Foreign(src) = heard {
+            point = point.tag("src", format!("{:#x}", src.0));
+        }
+        if let Some(gap) = gap {
+            point = point.field("gap_ms", gap.as_millis() as i64);
+        }
+        if let Some(rssi) = frame.rssi {
+            point = point.field("rssi", rssi as i64);
+        }
+        if let Some(snr) = frame.snr {
+            point = point.field("snr", snr as f64);
+        }
+
+        let channel = Point::new("channel")
+            .field("utilisation", stats.utilisation() as f64)
+            .field("ours_ms", stats.ours.airtime.as_millis() as i64)
+            .field("sent_ms", stats.sent.airtime.as_millis() as i64)
+            .field("foreign_ms", stats.foreign.airtime.as_millis() as i64)
+            .field(
+                "undecodable_ms",
+                stats.undecodable.airtime.as_millis() as i64,
+            )
+            .field("neighbours", stats.neighbours.len() as i64);
+
+        self.storage.write(vec![
+            point.timestamp(at.timestamp_nanos()),
+            channel.timestamp(at.timestamp_nanos()),
+        ]);
+
+        *self.shared.channel_stats.lock().unwrap() = Some(stats.clone());
+        let _ = self.event_sender.send(PanelMessage::Channel(stats));
+    }
+
+    fn record(&mut self, record: Record) {
+        if let Some(capture) = &mut self.capture {
+            // losing the capture shouldn't take the base station down with it
+            if let Err(e) = capture.record(&record) {
+                println!("Failed to write capture: {}", e);
+            }
+        }
+    }
+
+    /// Handle frames from the device forever
+    pub fn run(&mut self, radio: &mut dyn Radio) -> ! {
+        loop {
+            if let Err(e) = self.inner(radio) {
+                println!("{}", e);
+            }
+        }
+    }
+
+    /// Store a message from the device, `at` is when it was produced.
+    ///
+    /// Replayed readings are older than the last ones we saw, so they are only
+    /// checked on their own merits.
+    pub fn submit(&mut self, msg: Message, at: DateTime<Utc>, replayed: bool) -> Result<()> {
+        let timestamp = at.timestamp_nanos();
+
         match msg {
+            Message::MoistureReport(r) if replayed => {
+                let r = r.sanity_check(None)?;
+                self.write_moisture(r, at)?;
+            }
             Message::MoistureReport(r) => {
                 let r = match r.sanity_check(self.last_moisture_reading.as_ref()) {
                     Ok(it) => it,
@@ -96,23 +305,11 @@ impl Exporter {
                     }
                 };
                 self.last_moisture_reading = Some(r.clone());
-
-                for (n, r) in r.moisture.into_iter().enumerate() {
-                    let level = r.per_second();
-
-                    let reading = DataPoint::builder("moisture")
-                        .tag("sensor", n.to_string())
-                        .field("moisture", level as f64)
-                        .build()?;
-
-                    let client = self.client.clone();
-                    tokio::spawn(async move {
-                        client
-                            .write("garden", futures::stream::iter([reading]))
-                            .await
-                            .unwrap();
-                    });
-                }
+                self.write_moisture(r, at)?;
+            }
+            Message::BME688Report(r) if replayed => {
+                let r = r.sanity_check(None)?;
+                self.write_bme(r, at)?;
             }
             Message::BME688Report(r) => {
                 let r = match r.sanity_check(self.last_bme_reading.as_ref()) {
@@ -123,97 +320,596 @@ impl Exporter {
                     }
                 };
                 self.last_bme_reading = Some(r.clone());
-
-                let temp = r.temp.get::<degree_celsius>();
-                let pressure = r.pressure.get::<pascal>();
-                let humidity = r.humidity.get::<percent>();
-
-                let temp_reading = DataPoint::builder("temp")
-                    .field("temp", temp as f64)
-                    .build()?;
-
-                let pressure_reading = DataPoint::builder("pressure")
-                    .field("pressure", pressure as f64)
-                    .build()?;
-
-                let humidity_reading = DataPoint::builder("humidity")
-                    .field("humidity", humidity as f64)
-                    .build()?;
-
-                let client = self.client.clone();
-                tokio::spawn(async move {
-                    client
-                        .write(
-                            "garden",
-                            futures::stream::iter([
-                                temp_reading,
-                                pressure_reading,
-                                humidity_reading,
-                            ]),
-                        )
-                        .await
-                        .unwrap();
-                });
+                self.write_bme(r, at)?;
             }
             Message::StatusUpdate(upd) => {
                 self.status_sender.send(Some(upd))?;
             }
+            Message::Watering(event) => {
+                let reading = match event {
+                    WateringEvent::Started { level } => Point::new("watering")
+                        .tag("event", "started")
+                        .field("level", level as f64),
+                    WateringEvent::Stopped { level, reason } => Point::new("watering")
+                        .tag("event", "stopped")
+                        .tag("reason", format!("{:?}", reason))
+                        .field("level", level.unwrap_or(f32::NAN) as f64),
+                }
+                .timestamp(timestamp);
+
+                self.storage.write(vec![reading]);
+
+                // nobody listening is fine
+                let _ = self.event_sender.send(PanelMessage::Watering(event));
+            }
+            Message::Config(config) => {
+                *self.shared.device_config.lock().unwrap() = Some(config);
+                let _ = self.event_sender.send(PanelMessage::Config(config));
+            }
+            Message::Diagnostics(diagnostics) => {
+                let reading = Point::new("diagnostics")
+                    .tag("reset_cause", format!("{:?}", diagnostics.reset_cause))
+                    .tag("git_hash", diagnostics.git_hash.as_str())
+                    .field("uptime", diagnostics.uptime.as_secs() as i64)
+                    .field("bme_failures", diagnostics.bme_failures as i64)
+                    .field("dropped_messages", diagnostics.dropped_messages as i64)
+                    .field("radio_errors", diagnostics.radio_errors as i64)
+                    .field("link_fallbacks", diagnostics.link_fallbacks as i64)
+                    .field("airtime_ms", diagnostics.airtime.as_millis() as i64)
+                    .field("airtime_deferred", diagnostics.airtime_deferred as i64)
+                    .field("channel_deferred", diagnostics.channel_deferred as i64)
+                    .field("channel_collisions", diagnostics.channel_collisions as i64)
+                    .timestamp(timestamp);
+
+                self.storage.write(vec![reading]);
+
+                *self.shared.device_diagnostics.lock().unwrap() = Some(diagnostics.clone());
+                let _ = self
+                    .event_sender
+                    .send(PanelMessage::Diagnostics(diagnostics));
+            }
+            Message::Firmware(status) => {
+                let reading = Point::new("firmware")
+                    .tag("image", format!("{:08x}", status.image))
+                    .tag("state", format!("{:?}", status.state))
+                    .timestamp(timestamp);
+
+                self.storage.write(vec![reading]);
+            }
+            Message::CrashReport(report) => {
+                println!(
+                    "Device panicked at {}:{}:{}: {}",
+                    report.location.file,
+                    report.location.line,
+                    report.location.column,
+                    report.message
+                );
+
+                let reading = Point::new("crash")
+                    .tag("file", report.location.file.as_str())
+                    .field("line", report.location.line as i64)
+                    .field("column", report.location.column as i64)
+                    .field("message", report.message.as_str())
+                    .timestamp(timestamp);
+
+                self.storage.write(vec![reading]);
+
+                {
+                    let mut crashes = self.shared.device_crashes.lock().unwrap();
+                    if crashes.len() == RECENT_CRASHES {
+                        crashes.pop_front();
+                    }
+                    crashes.push_back((at, report.clone()));
+                }
+
+                let _ = self.event_sender.send(PanelMessage::CrashReport {
+                    at: at.timestamp_millis() as u64,
+                    report,
+                });
+            }
         }
 
         Ok(())
     }
 
-    fn inner(&mut self, lora: &mut embedded_radio::LoRa<Spidev, Pin, Pin>) -> Result<()> {
-        if let Some(buffer) = lora
-            .read_packet_timeout(100000, &mut Delay)
-            .map_err(|e| color_eyre::eyre::eyre!("Oops: {:?}", e))?
+    fn write_moisture(&self, r: MoistureSensorReport, at: DateTime<Utc>) -> Result<()> {
+        let timestamp = at.timestamp_nanos();
+
+        for (n, r) in r.moisture.iter().enumerate() {
+            let level = r.per_second();
+
+            let reading = Point::new("moisture")
+                .tag("sensor", n.to_string())
+                .field("moisture", level as f64)
+                .timestamp(timestamp);
+
+            self.storage.write(vec![reading]);
+        }
+
+        // replayed readings can be older than what we already have
+        if update_latest(&self.shared.latest_moisture, at, &r) {
+            let _ = self.event_sender.send(PanelMessage::Moisture {
+                at: at.timestamp_millis() as u64,
+                report: r,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn write_bme(&self, r: BME688SensorReport, at: DateTime<Utc>) -> Result<()> {
+        let timestamp = at.timestamp_nanos();
+        let temp = r.temp.get::<degree_celsius>();
+        let pressure = r.pressure.get::<pascal>();
+        let humidity = r.humidity.get::<percent>();
+
+        let temp_reading = Point::new("temp")
+            .field("temp", temp as f64)
+            .timestamp(timestamp);
+
+        let pressure_reading = Point::new("pressure")
+            .field("pressure", pressure as f64)
+            .timestamp(timestamp);
+
+        let humidity_reading = Point::new("humidity")
+            .field("humidity", humidity as f64)
+            .timestamp(timestamp);
+
+        self.storage
+            .write(vec![temp_reading, pressure_reading, humidity_reading]);
+
+        if update_latest(&self.shared.latest_bme, at, &r) {
+            let _ = self.event_sender.send(PanelMessage::Bme {
+                at: at.timestamp_millis() as u64,
+                report: r,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Send a command to the device whose frame came in with `to` if there's
+    /// airtime left for it, returning whether it went out. It goes back the
+    /// way the frame came, through the last repeater that passed it on.
+    fn transmit(&mut self, radio: &mut dyn Radio, to: &Header, cmd: Command) -> Result<bool> {
+        let t = Transmission {
+            src: BASE_ADDR,
+            route: Route {
+                dst: to.src,
+                hops: 0,
+                via: to.route.via,
+            },
+            seq: self.next_seq,
+            time: FrameTime::now(Utc::now().timestamp_millis() as u64, true),
+            // the device doesn't do anything with it
+            link: None,
+            msg: cmd,
+        };
+        let ser = postcard::to_stdvec(&t).unwrap();
+
+        let now_ms = Utc::now().timestamp_millis() as u64;
+        let settings = self.tuned.unwrap_or(self.plan.radio);
+        let on_air = airtime::time_on_air(&settings, ser.len());
+        if !self
+            .airtime
+            .allows(now_ms, on_air, airtime::command_priority(&t.msg))
+        {
+            println!("Out of airtime, holding back {:?}", t.msg);
+            self.airtime_deferred += 1;
+            return Ok(false);
+        }
+        self.airtime.record(now_ms, on_air);
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        // the device needs a moment to switch over to listening, frames are
+        // handed over as soon as DIO0 goes up so this is all the delay there is
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        println!("Transmitting command: {:?}", t);
+
+        let frame = Frame {
+            data: ser,
+            rssi: None,
+            snr: None,
+            freq_error: None,
+            crc_ok: None,
+        };
+        self.record(Record::new(
+            Direction::Tx,
+            Utc::now(),
+            &frame,
+            Ok(format!("{:?}", t)),
+        ));
+
+        radio.transmit(&frame.data)?;
+        self.sniffed(Heard::Sent, &frame, Utc::now());
+
+        // repeaters can't listen while they pass it on, so give them time to
+        // before the next one
+        if to.route.hops > 0 {
+            std::thread::sleep((on_air + HOP_MARGIN) * to.route.hops as u32);
+        }
+
+        Ok(true)
+    }
+
+    /// Note what the device said about its firmware, ending the upload once
+    /// the device stops asking for more of it
+    fn firmware_status(&mut self, status: FirmwareStatus) {
+        *self.shared.device_firmware.lock().unwrap() = Some(status);
+
         {
-            let msg: Transmission<Message> = postcard::from_bytes(&buffer)?;
+            let mut upload = self.shared.firmware_upload.lock().unwrap();
+            if let Some(u) = &mut *upload {
+                u.observe(status);
+                if u.is_finished() {
+                    println!("Firmware upload finished: {:?}", status.state);
+                    *upload = None;
+                }
+            }
+        }
+
+        let _ = self
+            .event_sender
+            .send(PanelMessage::Firmware(self.shared.firmware_progress()));
+    }
+
+    /// Store how much of the airtime budget we've used
+    fn record_airtime(&mut self, at: DateTime<Utc>) {
+        let used = self.airtime.used(at.timestamp_millis() as u64);
+
+        let mut point = Point::new("airtime")
+            .tag("side", "base")
+            .field("used_ms", used.as_millis() as i64)
+            .field("deferred", self.airtime_deferred as i64);
+        if let Some(budget) = self.airtime.budget() {
+            point = point.field("budget_ms", budget.as_millis() as i64);
+        }
+
+        self.storage
+            .write(vec![point.timestamp(at.timestamp_nanos())]);
+    }
+
+    /// Store how the frame from the device came through, and how the device
+    /// says our last one did
+    pub(crate) fn record_link(
+        &self,
+        frame: &Frame,
+        downlink: Option<LinkQuality>,
+        at: DateTime<Utc>,
+    ) {
+        let uplink = frame.rssi.zip(frame.snr).map(|(rssi, snr)| LinkQuality {
+            rssi: rssi as i16,
+            snr: snr.round() as i16,
+        });
+
+        if uplink.is_none() && downlink.is_none() {
+            return;
+        }
+
+        let timestamp = at.timestamp_nanos();
+        let mut points = Vec::new();
+
+        if let Some(uplink) = uplink {
+            let mut point = Point::new("link")
+                .tag("direction", "uplink")
+                .field("rssi", uplink.rssi as i64)
+                .field("snr", uplink.snr as i64);
+            if let Some(freq_error) = frame.freq_error {
+                point = point.field("freq_error", freq_error as i64);
+            }
+            points.push(point.timestamp(timestamp));
+        }
+
+        if let Some(downlink) = downlink {
+            points.push(
+                Point::new("link")
+                    .tag("direction", "downlink")
+                    .field("rssi", downlink.rssi as i64)
+                    .field("snr", downlink.snr as i64)
+                    .timestamp(timestamp),
+            );
+        }
+
+        self.storage.write(points);
+
+        let status = {
+            let mut status = self.shared.link_status.lock().unwrap();
+            // keep the last known quality of a direction we didn't hear about
+            status.uplink = uplink.or(status.uplink);
+            status.downlink = downlink.or(status.downlink);
+            *status
+        };
+        let _ = self.event_sender.send(PanelMessage::Link(status));
+    }
+
+    /// Forget what the radio was set up for, it's been swapped for a fresh one
+    pub(crate) fn radio_replaced(&mut self) {
+        self.tuned = None;
+    }
+
+    /// Let the panel and storage know how the radio is doing
+    pub(crate) fn report_radio(&self, health: RadioHealth) {
+        self.storage.write(vec![Point::new("radio")
+            .tag("state", format!("{:?}", health.state))
+            .field("restarts", health.restarts as i64)
+            .field("errors", health.errors as i64)
+            .timestamp(Utc::now().timestamp_nanos())]);
+
+        *self.shared.radio_health.lock().unwrap() = health;
+        let _ = self.event_sender.send(PanelMessage::Radio(health));
+    }
+
+    /// Run `check` on `radio` and let the panel know what it found
+    pub fn check_radio(&mut self, radio: &mut dyn Radio, check: RadioCheck) {
+        let report = match radio.registers() {
+            Some(registers) => diagnostics::run(check, registers, self.plan)
+                .unwrap_or_else(|e| diagnostics::failed(check, &e.to_string())),
+            None => diagnostics::failed(check, "This radio has no registers to look at"),
+        };
+
+        // the checks leave it tuned wherever they finished
+        self.tuned = None;
+        self.report_check(report);
+    }
+
+    /// Run the checks the panel asked for
+    fn run_checks(&mut self, radio: &mut dyn Radio) {
+        loop {
+            let check = self.shared.pending_checks.lock().unwrap().pop_front();
+            match check {
+                Some(check) => self.check_radio(radio, check),
+                None => break,
+            }
+        }
+    }
+
+    pub(crate) fn report_check(&self, report: RadioReport) {
+        let at = Utc::now();
+
+        match &report {
+            RadioReport::NoiseFloor { floor, samples } => {
+                println!("Noise floor is {}dBm", floor);
+
+                let loudest = samples.iter().map(|s| s.peak).max().unwrap_or(*floor);
+                self.storage.write(vec![Point::new("noise")
+                    .tag("region", format!("{:?}", self.plan.region))
+                    .field("floor", *floor as i64)
+                    .field("loudest", loudest as i64)
+                    .timestamp(at.timestamp_nanos())]);
+            }
+            RadioReport::Failed { check, reason } => {
+                println!("Radio check {:?} failed: {}", check, reason);
+            }
+            _ => {}
+        }
+
+        let _ = self.event_sender.send(PanelMessage::RadioReport {
+            at: at.timestamp_millis() as u64,
+            report,
+        });
+    }
 
-            if msg.src != DevAddr(0x69) {
+    /// Switch the radio over to `settings` if it isn't on them already
+    fn tune(&mut self, radio: &mut dyn Radio, settings: RadioConfig) -> Result<()> {
+        if self.tuned == Some(settings) {
+            return Ok(());
+        }
+
+        radio.configure(&settings)?;
+        self.tuned = Some(settings);
+
+        Ok(())
+    }
+
+    pub(crate) fn inner(&mut self, radio: &mut dyn Radio) -> Result<()> {
+        self.run_checks(radio);
+
+        // the device's configured radio settings are where we meet it
+        let DeviceConfig {
+            radio: rendezvous,
+            status_interval,
+            ..
+        } = self
+            .shared
+            .device_config
+            .lock()
+            .unwrap()
+            .unwrap_or(DeviceConfig {
+                radio: self.plan.radio,
+                ..Default::default()
+            });
+
+        if self.link.check_quiet(status_interval, Instant::now()) {
+            println!("Lost the device on adapted link settings, back to the rendezvous");
+        }
+
+        // follows the device's config changing too
+        self.tune(radio, self.link.radio(&rendezvous))?;
+
+        let timeout = self
+            .link
+            .time_left(status_interval, Instant::now())
+            .map_or(RECEIVE_TIMEOUT, |t| t.min(RECEIVE_TIMEOUT));
+
+        if let Some(frame) = radio.receive(timeout)? {
+            let received_at = Utc::now();
+            let decoded = postcard::from_bytes::<Transmission<Message>>(&frame.data);
+
+            self.record(Record::new(
+                Direction::Rx,
+                received_at,
+                &frame,
+                decoded
+                    .as_ref()
+                    .map(|msg| format!("{:?}", msg))
+                    .map_err(|e| e.to_string()),
+            ));
+
+            // the device answers an address change with its config, from the
+            // new address. Until then, and if that answer never makes it,
+            // it's still on the old one.
+            if let Ok(msg) = &decoded {
+                if let Message::Config(config) = &msg.msg {
+                    if config.address == msg.src && msg.src != self.state.device_addr {
+                        self.readdress(msg.src);
+                    }
+                }
+            }
+
+            let split = repeater::split(&frame.data);
+            // our own commands, on their way through a repeater
+            let passed_on = matches!(split, Some((header, _)) if header.src == BASE_ADDR);
+
+            let heard = match &decoded {
+                _ if frame.crc_ok == Some(false) => Heard::Corrupt,
+                _ if passed_on => Heard::Sent,
+                Ok(msg) if msg.src == self.state.device_addr => Heard::Ours,
+                Ok(msg) => Heard::Foreign(msg.src),
+                Err(_) => Heard::Undecodable,
+            };
+            self.sniffed(heard, &frame, received_at);
+
+            if passed_on {
+                return Ok(());
+            }
+
+            let msg = decoded?;
+
+            if msg.src != self.state.device_addr {
                 println!("Discarding transmission (wrong src addr) {:?}", msg);
                 return Ok(());
             }
 
-            if *RESET_WANTED.lock().unwrap() {
-                let t = Transmission {
-                    src: DevAddr(69),
-                    msg: Command::Reset,
-                };
+            // the first copy came the shortest way, answer that one
+            if let Some((_, body)) = split {
+                if self.seen.check(msg.src, body) {
+                    println!("Discarding copy of {} from another repeater", msg.seq);
+                    return Ok(());
+                }
+            }
+
+            let from = Header {
+                src: msg.src,
+                route: msg.route,
+            };
+            if let Some(via) = msg.route.via {
+                println!(
+                    "Heard the device through {:#x}, {} hop(s) away",
+                    via.0, msg.route.hops
+                );
+            }
+
+            // ack first, the device keeps hold of readings until we do
+            self.transmit(radio, &from, Command::Ack(msg.seq))?;
 
-                *RESET_WANTED.lock().unwrap() = false;
+            // repeaters stay on the rendezvous, so the link is only adapted
+            // while we hear the device ourselves
+            let direct = msg.route.hops == 0;
+            if direct {
+                self.link.observe(frame.snr, msg.link, Instant::now());
+            }
 
-                std::thread::sleep(std::time::Duration::from_millis(10));
+            self.record_link(&frame, msg.link, received_at);
 
-                let ser = postcard::to_stdvec(&t).unwrap();
+            self.clock.observe(&msg.time, received_at);
+            let at = self.clock.to_local(msg.time.captured);
 
-                println!("Transmitting command: {:?}", t);
+            if self.clock.needs_set(received_at) {
+                println!(
+                    "Setting device clock (offset: {:?}ms, drift: {:?}ppm)",
+                    self.clock.offset_ms(),
+                    self.clock.drift_ppm()
+                );
 
-                lora.transmit_payload(&ser)
-                    .map_err(|e| color_eyre::eyre::eyre!("Opps: {:?}", e))?;
+                let time = Command::SetTime(Utc::now().timestamp_millis() as u64);
+                if self.transmit(radio, &from, time)? {
+                    self.clock.mark_set(received_at);
+                }
             }
 
-            if let Message::StatusUpdate(upd) = msg.msg {
-                let desired_status = *DESIRED_STATE.lock().unwrap();
-                if upd.flags != desired_status {
-                    let t = Transmission {
-                        src: DevAddr(69),
-                        msg: Command::SyncFlags(desired_status),
-                    };
+            if *self.shared.reset_wanted.lock().unwrap()
+                && self.transmit(radio, &from, Command::Reset)?
+            {
+                *self.shared.reset_wanted.lock().unwrap() = false;
+            }
 
-                    std::thread::sleep(std::time::Duration::from_millis(10));
+            let pending = self.shared.pending_commands.lock().unwrap().pop_front();
+            if let Some(cmd) = pending {
+                if !self.transmit(radio, &from, cmd.clone())? {
+                    // try again next time
+                    self.shared.pending_commands.lock().unwrap().push_front(cmd);
+                }
+            }
+
+            // a replayed status is old news, the device has moved on since
+            if let Message::Firmware(status) = msg.msg {
+                if !msg.time.is_replay() {
+                    self.firmware_status(status);
+                }
+            }
 
-                    let ser = postcard::to_stdvec(&t).unwrap();
+            // the device answers with the piece it wants next, which goes out
+            // with the answer to that
+            let firmware = self
+                .shared
+                .firmware_upload
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(Upload::next_command);
+            if let Some(cmd) = firmware {
+                self.transmit(radio, &from, cmd)?;
+            }
 
-                    println!("Transmitting command: {:?}", t);
+            if let Message::StatusUpdate(upd) = msg.msg {
+                let desired_status = *self.shared.desired_state.lock().unwrap();
+                // the device owns the outputs while it is watering on its own
+                let auto_watering = upd.flags.contains(StatusFlags::AUTO_WATERING);
+                if !auto_watering && upd.flags != desired_status {
+                    self.transmit(radio, &from, Command::SyncFlags(desired_status))?;
+                }
+            }
 
-                    lora.transmit_payload(&ser)
-                        .map_err(|e| color_eyre::eyre::eyre!("Opps: {:?}", e))?;
+            // last, the device only switches once it's done listening
+            let decided = self
+                .link
+                .decide(&rendezvous, self.plan.max_spreading_factor, Instant::now())
+                .filter(|_| direct);
+            if let Some(params) = decided {
+                let new = params.map_or(rendezvous, |p| p.apply(&rendezvous));
+                println!(
+                    "Adapting link to SF{} at {} dBm",
+                    new.spreading_factor, new.tx_power
+                );
+
+                if self.transmit(radio, &from, Command::SetLink(params))? {
+                    self.link.adopt(params);
+                    self.tune(radio, self.link.radio(&rendezvous))?;
                 }
             }
 
+            self.record_airtime(received_at);
+
             println!("msg: {:?}", msg);
-            self.submit(msg.msg)?;
+
+            let replayed = msg.time.is_replay();
+            if outbox::is_durable(&msg.msg) {
+                // our ack for an earlier attempt may have been the thing that
+                // got lost
+                let key = (msg.src, msg.seq, msg.time.captured);
+                if self.recent_delivered.contains(&key) {
+                    println!("Discarding duplicate replay of {}", msg.seq);
+                    return Ok(());
+                }
+
+                if self.recent_delivered.len() == RECENT_DELIVERED {
+                    self.recent_delivered.pop_front();
+                }
+                self.recent_delivered.push_back(key);
+            }
+
+            self.submit(msg.msg, at, replayed)?;
         }
 
         Ok(())
@@ -0,0 +1,305 @@
+use std::sync::Arc;
+
+use axum::body::{self, Empty, Full};
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{Path, WebSocketUpgrade};
+use axum::http::{header, HeaderValue, Response, StatusCode};
+use axum::response::{IntoResponse, Redirect};
+use axum::routing::get;
+use axum::{Extension, Router};
+use color_eyre::Result;
+use garden_shared::{Command, DeviceStatus, PanelMessage, StatusFlags, UiCommand};
+use include_dir::{include_dir, Dir};
+use tokio::sync::{broadcast, watch};
+use tokio_stream::StreamExt;
+
+use crate::firmware::Upload;
+use crate::shared::Shared;
+
+static PANEL_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/../garden-panel/dist");
+
+#[derive(Clone)]
+struct State {
+    shared: Arc<Shared>,
+    status_recv: watch::Receiver<Option<DeviceStatus>>,
+    event_sender: broadcast::Sender<PanelMessage>,
+}
+
+/// The control panel and the websocket it talks to the base station over
+pub fn app(
+    shared: Arc<Shared>,
+    status_recv: watch::Receiver<Option<DeviceStatus>>,
+    event_sender: broadcast::Sender<PanelMessage>,
+) -> Router {
+    let asset_router = Router::new().route("/*path", get(static_path));
+
+    Router::new()
+        .route("/ws", get(root_ws))
+        .route("/", get(|| async { Redirect::to("/index.html") }))
+        .fallback(asset_router)
+        .layer(Extension(State {
+            shared,
+            status_recv,
+            event_sender,
+        }))
+        .layer(tower_http::compression::CompressionLayer::new())
+        .layer(
+            tower_http::set_header::SetResponseHeaderLayer::if_not_present(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static("public, max-age=300"),
+            ),
+        )
+}
+
+async fn static_path(Path(path): Path<String>) -> impl IntoResponse {
+    let path = path.trim_start_matches('/');
+    let mime_type = mime_guess::from_path(path).first_or_text_plain();
+
+    match PANEL_DIR.get_file(path) {
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(body::boxed(Empty::new()))
+            .unwrap(),
+        Some(file) => Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_str(mime_type.as_ref()).unwrap(),
+            )
+            .body(body::boxed(Full::from(file.contents())))
+            .unwrap(),
+    }
+}
+
+async fn root_ws(ws: WebSocketUpgrade, Extension(state): Extension<State>) -> impl IntoResponse {
+    ws.on_upgrade(|s| async {
+        if let Err(e) = handle_socket(s, state).await {
+            eprintln!("{e:?}");
+        } else {
+            println!("Websocket exited");
+        }
+    })
+}
+
+async fn handle_socket(mut socket: WebSocket, mut state: State) -> Result<()> {
+    println!("Websocket connected!");
+
+    socket
+        .send(Message::Text(
+            serde_json::to_string(&PanelMessage::Hello).unwrap(),
+        ))
+        .await?;
+
+    let v = *state.status_recv.borrow_and_update();
+    if let Some(v) = v {
+        let c = PanelMessage::Status(v);
+        socket
+            .send(Message::Text(serde_json::to_string(&c).unwrap()))
+            .await?;
+    }
+
+    let c = PanelMessage::DesiredStatus(*state.shared.desired_state.lock().unwrap());
+    socket
+        .send(Message::Text(serde_json::to_string(&c).unwrap()))
+        .await?;
+
+    let config = *state.shared.device_config.lock().unwrap();
+    if let Some(config) = config {
+        let c = PanelMessage::Config(config);
+        socket
+            .send(Message::Text(serde_json::to_string(&c).unwrap()))
+            .await?;
+    }
+
+    let diagnostics = state.shared.device_diagnostics.lock().unwrap().clone();
+    if let Some(diagnostics) = diagnostics {
+        let c = PanelMessage::Diagnostics(diagnostics);
+        socket
+            .send(Message::Text(serde_json::to_string(&c).unwrap()))
+            .await?;
+    }
+
+    let crashes = state.shared.device_crashes.lock().unwrap().clone();
+    for (at, report) in crashes {
+        let c = PanelMessage::CrashReport {
+            at: at.timestamp_millis() as u64,
+            report,
+        };
+        socket
+            .send(Message::Text(serde_json::to_string(&c).unwrap()))
+            .await?;
+    }
+
+    let moisture = state.shared.latest_moisture.lock().unwrap().clone();
+    if let Some((at, report)) = moisture {
+        let c = PanelMessage::Moisture {
+            at: at.timestamp_millis() as u64,
+            report,
+        };
+        socket
+            .send(Message::Text(serde_json::to_string(&c).unwrap()))
+            .await?;
+    }
+
+    let bme = state.shared.latest_bme.lock().unwrap().clone();
+    if let Some((at, report)) = bme {
+        let c = PanelMessage::Bme {
+            at: at.timestamp_millis() as u64,
+            report,
+        };
+        socket
+            .send(Message::Text(serde_json::to_string(&c).unwrap()))
+            .await?;
+    }
+
+    let link = *state.shared.link_status.lock().unwrap();
+    if link.uplink.is_some() || link.downlink.is_some() {
+        let c = PanelMessage::Link(link);
+        socket
+            .send(Message::Text(serde_json::to_string(&c).unwrap()))
+            .await?;
+    }
+
+    let channel = state.shared.channel_stats.lock().unwrap().clone();
+    if let Some(channel) = channel {
+        let c = PanelMessage::Channel(channel);
+        socket
+            .send(Message::Text(serde_json::to_string(&c).unwrap()))
+            .await?;
+    }
+
+    let c = PanelMessage::Radio(*state.shared.radio_health.lock().unwrap());
+    socket
+        .send(Message::Text(serde_json::to_string(&c).unwrap()))
+        .await?;
+
+    let progress = state.shared.firmware_progress();
+    if progress.upload.is_some() || progress.device.is_some() {
+        let c = PanelMessage::Firmware(progress);
+        socket
+            .send(Message::Text(serde_json::to_string(&c).unwrap()))
+            .await?;
+    }
+
+    let mut status_stream = tokio_stream::wrappers::WatchStream::new(state.status_recv);
+    let mut event_stream =
+        tokio_stream::wrappers::BroadcastStream::new(state.event_sender.subscribe());
+
+    loop {
+        tokio::select! {
+            v = status_stream.next() => {
+                println!("Got status message: {:?}", v);
+                if let Some(Some(v)) = v {
+                    socket
+                        .send(Message::Text(
+                            serde_json::to_string(&PanelMessage::Status(v)).unwrap(),
+                        ))
+                        .await?;
+                }
+            }
+
+            Some(Ok(event)) = event_stream.next() => {
+                socket
+                    .send(Message::Text(serde_json::to_string(&event).unwrap()))
+                    .await?;
+            }
+
+            cmd = socket.next() => {
+                if let Some(cmd) = cmd {
+                    println!("Got cmd: {:?}", cmd);
+                    let cmd = cmd?;
+
+                    match cmd {
+                        Message::Text(t) => {
+                            println!("got message: {}", t);
+                            let cmd: UiCommand = serde_json::from_str(&t)?;
+
+                            let c = {
+                                let mut desired_state = state.shared.desired_state.lock().unwrap();
+
+                                match cmd {
+                                    UiCommand::PumpOn => {
+                                        desired_state.set(StatusFlags::PUMP_ON, true);
+                                    },
+                                    UiCommand::PumpOff => {
+                                        desired_state.set(StatusFlags::PUMP_ON, false);
+                                    },
+                                    UiCommand::ValveOpen => {
+                                        desired_state.set(StatusFlags::VALVE_OPEN, true);
+                                    },
+                                    UiCommand::ValveClose => {
+                                        desired_state.set(StatusFlags::VALVE_OPEN, false);
+                                    },
+                                    UiCommand::Reset => {
+                                        *state.shared.reset_wanted.lock().unwrap() = true;
+                                    }
+                                    UiCommand::SetConfig(field) => {
+                                        state.shared.pending_commands
+                                            .lock()
+                                            .unwrap()
+                                            .push_back(Command::SetConfig(field));
+                                    }
+                                    UiCommand::RequestConfig => {
+                                        state.shared.pending_commands
+                                            .lock()
+                                            .unwrap()
+                                            .push_back(Command::GetConfig);
+                                    }
+                                    UiCommand::CheckRadio(check) => {
+                                        state.shared.pending_checks.lock().unwrap().push_back(check);
+                                        state.shared.check_wanted.notify_one();
+                                    }
+                                    UiCommand::CancelFirmware => {
+                                        *state.shared.firmware_upload.lock().unwrap() = None;
+                                        // it may have all of it already
+                                        state.shared.pending_commands
+                                            .lock()
+                                            .unwrap()
+                                            .push_back(Command::FirmwareCancel);
+                                        let _ = state
+                                            .event_sender
+                                            .send(PanelMessage::Firmware(state.shared.firmware_progress()));
+                                    }
+                                }
+
+                                PanelMessage::DesiredStatus(*desired_state)
+                            };
+                            println!("Sending message {:?}", c);
+                            socket
+                                .send(Message::Text(serde_json::to_string(&c).unwrap()))
+                                .await?;
+                        }
+                        Message::Binary(image) => {
+                            println!("Got {} bytes of firmware", image.len());
+
+                            match Upload::new(image) {
+                                Ok(mut upload) => {
+                                    let image = upload.manifest().image();
+                                    println!("Uploading firmware {:08x}", image);
+                                    // carry on where the device got to with it
+                                    if let Some(status) = *state.shared.device_firmware.lock().unwrap() {
+                                        upload.observe(status);
+                                    }
+                                    *state.shared.firmware_upload.lock().unwrap() = Some(upload);
+                                }
+                                Err(e) => println!("Not uploading firmware: {}", e),
+                            }
+
+                            let _ = state
+                                .event_sender
+                                .send(PanelMessage::Firmware(state.shared.firmware_progress()));
+                        }
+                        Message::Ping(msg) => {
+                            socket.send(Message::Pong(msg)).await?;
+                        }
+                        Message::Close(_) => {
+                            println!("Closing websocket");
+                            return Ok(());
+                        },
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! What the radio side and the control panel both get at: what the panel has
+//! asked of the device, and the latest of what the device has told us.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use garden_shared::{
+    BME688SensorReport, ChannelStats, Command, CrashReport, DeviceConfig, Diagnostics,
+    FirmwareProgress, FirmwareStatus, LinkStatus, MoistureSensorReport, RadioCheck, RadioHealth,
+    RadioState, StatusFlags,
+};
+use tokio::sync::Notify;
+
+use crate::firmware::Upload;
+
+/// Crash reports from the device and when they happened, oldest first
+pub type CrashLog = VecDeque<(DateTime<Utc>, CrashReport)>;
+/// The newest readings of a kind that were stored and when they were taken
+pub type Latest<T> = Option<(DateTime<Utc>, T)>;
+
+/// One per base station, handed to its [`Exporter`](crate::radio::Exporter)
+/// and to the panel's [`app`](crate::server::app)
+pub struct Shared {
+    pub desired_state: Mutex<StatusFlags>,
+    pub reset_wanted: Mutex<bool>,
+    /// Commands waiting for the device to make contact, one is sent per
+    /// received message
+    pub pending_commands: Mutex<VecDeque<Command>>,
+    /// Checks of our own radio the panel asked for, they're run between frames
+    pub pending_checks: Mutex<VecDeque<RadioCheck>>,
+    /// Cuts the wait for a frame short so checks don't wait for the device
+    pub check_wanted: Arc<Notify>,
+    /// The config most recently reported by the device
+    pub device_config: Mutex<Option<DeviceConfig>>,
+    /// The diagnostics most recently reported by the device
+    pub device_diagnostics: Mutex<Option<Diagnostics>>,
+    /// The most recent crash reports from the device
+    pub device_crashes: Mutex<CrashLog>,
+    pub latest_moisture: Mutex<Latest<MoistureSensorReport>>,
+    pub latest_bme: Mutex<Latest<BME688SensorReport>>,
+    /// Firmware on its way to the device, a piece is sent per received message
+    pub firmware_upload: Mutex<Option<Upload>>,
+    /// What the device last said about its firmware
+    pub device_firmware: Mutex<Option<FirmwareStatus>>,
+    /// How the last frames in each direction came through
+    pub link_status: Mutex<LinkStatus>,
+    /// How busy the channel has been, if we're sniffing
+    pub channel_stats: Mutex<Option<ChannelStats>>,
+    /// How the radio is holding up
+    pub radio_health: Mutex<RadioHealth>,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Self {
+            desired_state: Mutex::new(StatusFlags::empty()),
+            reset_wanted: Mutex::new(false),
+            pending_commands: Mutex::new(VecDeque::new()),
+            pending_checks: Mutex::new(VecDeque::new()),
+            check_wanted: Arc::new(Notify::new()),
+            device_config: Mutex::new(None),
+            device_diagnostics: Mutex::new(None),
+            device_crashes: Mutex::new(VecDeque::new()),
+            latest_moisture: Mutex::new(None),
+            latest_bme: Mutex::new(None),
+            firmware_upload: Mutex::new(None),
+            device_firmware: Mutex::new(None),
+            link_status: Mutex::new(LinkStatus {
+                uplink: None,
+                downlink: None,
+            }),
+            channel_stats: Mutex::new(None),
+            radio_health: Mutex::new(RadioHealth {
+                state: RadioState::Starting,
+                restarts: 0,
+                errors: 0,
+            }),
+        }
+    }
+}
+
+impl Shared {
+    /// How sending firmware to the device is going, for the panel
+    pub fn firmware_progress(&self) -> FirmwareProgress {
+        match &*self.firmware_upload.lock().unwrap() {
+            Some(upload) => upload.progress(),
+            None => FirmwareProgress {
+                upload: None,
+                device: *self.device_firmware.lock().unwrap(),
+            },
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Keeping track of everything on the channel, not just our device, to see
+//! how much of it the neighbours are using.
+
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use garden_shared::{ChannelStats, DevAddr, Neighbour, Traffic};
+
+/// How far back channel stats go, the same hour duty cycles are counted over
+pub const WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// What a frame on the channel turned out to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heard {
+    /// From our device
+    Ours,
+    /// Sent by us, or passed on for us by a repeater
+    Sent,
+    /// Decoded, but from another address
+    Foreign(DevAddr),
+    /// Failed its CRC
+    Corrupt,
+    /// Didn't decode as anything of ours
+    Undecodable,
+}
+
+impl Heard {
+    /// What it's tagged as in storage
+    pub fn name(&self) -> &'static str {
+        match self {
+            Heard::Ours => "ours",
+            Heard::Sent => "sent",
+            Heard::Foreign(_) => "foreign",
+            Heard::Corrupt => "corrupt",
+            Heard::Undecodable => "undecodable",
+        }
+    }
+}
+
+struct Entry {
+    at: Instant,
+    heard: Heard,
+    airtime: Duration,
+    rssi: Option<i16>,
+}
+
+/// Adds up frames into [`Traffic`]
+#[derive(Default)]
+struct Tally {
+    frames: u32,
+    airtime: Duration,
+    rssi_sum: i32,
+    rssi_count: i32,
+}
+
+impl Tally {
+    fn add(&mut self, entry: &Entry) {
+        self.frames += 1;
+        self.airtime += entry.airtime;
+        if let Some(rssi) = entry.rssi {
+            self.rssi_sum += rssi as i32;
+            self.rssi_count += 1;
+        }
+    }
+
+    fn traffic(&self) -> Traffic {
+        Traffic {
+            frames: self.frames,
+            airtime: self.airtime,
+            rssi: (self.rssi_count > 0).then(|| (self.rssi_sum / self.rssi_count) as i16),
+        }
+    }
+}
+
+/// The frames heard over the last [`WINDOW`]
+pub struct Sniffer {
+    window: Duration,
+    heard: VecDeque<Entry>,
+    last: Option<Instant>,
+}
+
+impl Sniffer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            heard: VecDeque::new(),
+            last: None,
+        }
+    }
+
+    /// Note down a frame that was on the air for `airtime`, returning how
+    /// long it's been since the one before it
+    pub fn observe(
+        &mut self,
+        heard: Heard,
+        airtime: Duration,
+        rssi: Option<i16>,
+        now: Instant,
+    ) -> Option<Duration> {
+        let gap = self.last.map(|last| now.saturating_duration_since(last));
+        self.last = Some(now);
+
+        self.heard.push_back(Entry {
+            at: now,
+            heard,
+            airtime,
+            rssi,
+        });
+        self.forget(now);
+
+        gap
+    }
+
+    fn forget(&mut self, now: Instant) {
+        while let Some(entry) = self.heard.front() {
+            if now.saturating_duration_since(entry.at) <= self.window {
+                break;
+            }
+            self.heard.pop_front();
+        }
+    }
+
+    pub fn stats(&mut self, now: Instant) -> ChannelStats {
+        self.forget(now);
+
+        let mut ours = Tally::default();
+        let mut sent = Tally::default();
+        let mut foreign = Tally::default();
+        let mut undecodable = Tally::default();
+        let mut neighbours: Vec<(DevAddr, Tally)> = Vec::new();
+        for entry in &self.heard {
+            let tally: &mut Tally = match entry.heard {
+                Heard::Ours => &mut ours,
+                Heard::Sent => &mut sent,
+                Heard::Corrupt | Heard::Undecodable => &mut undecodable,
+                Heard::Foreign(src) => {
+                    match neighbours.iter_mut().position(|(addr, _)| *addr == src) {
+                        Some(i) => neighbours[i].1.add(entry),
+                        None => {
+                            let mut tally = Tally::default();
+                            tally.add(entry);
+                            neighbours.push((src, tally));
+                        }
+                    }
+                    &mut foreign
+                }
+            };
+            tally.add(entry);
+        }
+
+        neighbours.sort_by_key(|(_, tally)| Reverse(tally.airtime));
+
+        ChannelStats {
+            window: self.window,
+            ours: ours.traffic(),
+            sent: sent.traffic(),
+            foreign: foreign.traffic(),
+            undecodable: undecodable.traffic(),
+            neighbours: neighbours
+                .iter()
+                .take(8)
+                .map(|(src, tally)| Neighbour {
+                    src: *src,
+                    traffic: tally.traffic(),
+                })
+                .collect(),
+        }
+    }
+}
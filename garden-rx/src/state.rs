@@ -0,0 +1,64 @@
+//! What the base station has learned about the device that it can't ask
+//! again for, kept in a file across restarts.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use garden_shared::DevAddr;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct State {
+    /// The address the device sends from. It keeps the one it was last given
+    /// in flash, so we have to as well.
+    pub device_addr: DevAddr,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            device_addr: DevAddr(0x69),
+        }
+    }
+}
+
+/// The state, written back to a file whenever it changes
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The state in the file, or the defaults if there's no file yet
+    pub fn load(&self) -> Result<State> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => {
+                return Err(e)
+                    .wrap_err_with(|| format!("Failed to read state {}", self.path.display()))
+            }
+        };
+
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Bad state in {}", self.path.display()))
+    }
+
+    /// Write the state out, replacing the file in one go so a crash midway
+    /// leaves the old one
+    pub fn save(&self, state: &State) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(state)?)
+            .wrap_err_with(|| format!("Failed to write state {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .wrap_err_with(|| format!("Failed to replace state {}", self.path.display()))
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::Mutex;
+
+use influxdb2::models::DataPoint;
+use influxdb2::Client;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    F64(f64),
+    I64(i64),
+    Bool(bool),
+    String(String),
+}
+
+impl From<f64> for FieldValue {
+    fn from(v: f64) -> Self {
+        Self::F64(v)
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(v: i64) -> Self {
+        Self::I64(v)
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(v: bool) -> Self {
+        Self::Bool(v)
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(v: String) -> Self {
+        Self::String(v)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(v: &str) -> Self {
+        Self::String(v.to_owned())
+    }
+}
+
+/// A reading to be stored, laid out the way influxdb wants it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: Vec<(String, String)>,
+    pub fields: Vec<(String, FieldValue)>,
+    /// Nanoseconds since the unix epoch
+    pub timestamp: i64,
+}
+
+impl Point {
+    pub fn new(measurement: impl Into<String>) -> Self {
+        Self {
+            measurement: measurement.into(),
+            tags: Vec::new(),
+            fields: Vec::new(),
+            timestamp: 0,
+        }
+    }
+
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn field(mut self, key: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: i64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn get_tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_field(&self, key: &str) -> Option<&FieldValue> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
+/// Where readings end up
+pub trait Storage: Send + Sync {
+    /// Store some points, without waiting around for it to finish
+    fn write(&self, points: Vec<Point>);
+}
+
+pub struct Influx {
+    client: Client,
+}
+
+impl Influx {
+    pub fn new(url: &str, org: &str, token: &str) -> Self {
+        Self {
+            client: Client::new(url, org, token),
+        }
+    }
+}
+
+impl Storage for Influx {
+    fn write(&self, points: Vec<Point>) {
+        let points = points
+            .into_iter()
+            .filter_map(|p| {
+                let mut builder = DataPoint::builder(p.measurement);
+                for (k, v) in p.tags {
+                    builder = builder.tag(k, v);
+                }
+                for (k, v) in p.fields {
+                    builder = match v {
+                        FieldValue::F64(v) => builder.field(k, v),
+                        FieldValue::I64(v) => builder.field(k, v),
+                        FieldValue::Bool(v) => builder.field(k, v),
+                        FieldValue::String(v) => builder.field(k, v),
+                    };
+                }
+
+                match builder.timestamp(p.timestamp).build() {
+                    Ok(p) => Some(p),
+                    Err(e) => {
+                        println!("Failed to build data point: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            if let Err(e) = client.write("garden", futures::stream::iter(points)).await {
+                println!("Failed to write to influxdb: {}", e);
+            }
+        });
+    }
+}
+
+/// Keeps everything in memory, for tests
+#[derive(Default)]
+pub struct Memory {
+    points: Mutex<Vec<Point>>,
+}
+
+impl Memory {
+    pub fn points(&self) -> Vec<Point> {
+        self.points.lock().unwrap().clone()
+    }
+}
+
+impl Storage for Memory {
+    fn write(&self, points: Vec<Point>) {
+        self.points.lock().unwrap().extend(points);
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use garden_shared::{RadioConfig, RadioHealth, RadioState};
+use tokio::sync::watch;
+
+use crate::backend::{Frame, Radio};
+use crate::diagnostics::Registers;
+use crate::radio::Exporter;
+
+/// Keeps the radio going: sets it up, sets it up again from scratch when it
+/// keeps failing, and gives up on it if it can't be set up at all. How it's
+/// doing goes to the panel and storage as it changes.
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    /// Errors in a row before the radio is set up again
+    pub max_errors: u32,
+    /// Failed attempts at setting the radio up in a row before giving up
+    pub max_attempts: u32,
+    /// How long to wait before setting the radio up again, doubling each
+    /// time it fails straight away
+    pub first_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self {
+            max_errors: 5,
+            max_attempts: 8,
+            first_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Counts a radio's errors, in a row and in total
+struct Watched {
+    radio: Box<dyn Radio>,
+    in_a_row: u32,
+    errors: u32,
+    /// Whether anything has worked since it was set up
+    worked: bool,
+}
+
+impl Watched {
+    fn new(radio: Box<dyn Radio>) -> Self {
+        Self {
+            radio,
+            in_a_row: 0,
+            errors: 0,
+            worked: false,
+        }
+    }
+
+    fn check<T>(&mut self, result: Result<T>) -> Result<T> {
+        match result {
+            Ok(_) => {
+                self.in_a_row = 0;
+                self.worked = true;
+            }
+            Err(_) => {
+                self.in_a_row += 1;
+                self.errors += 1;
+            }
+        }
+
+        result
+    }
+}
+
+impl Radio for Watched {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        let result = self.radio.receive(timeout);
+        self.check(result)
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        let result = self.radio.transmit(frame);
+        self.check(result)
+    }
+
+    fn configure(&mut self, radio: &RadioConfig) -> Result<()> {
+        let result = self.radio.configure(radio);
+        self.check(result)
+    }
+
+    // a check failing is what it's there to find out, not a reason to set
+    // the radio up again
+    fn registers(&mut self) -> Option<&mut dyn Registers> {
+        self.radio.registers()
+    }
+}
+
+/// Sleep for `duration` unless `shutdown` goes true first, returning whether
+/// it did
+fn sleep_unless(duration: Duration, shutdown: &watch::Receiver<bool>) -> bool {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        if *shutdown.borrow() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(50).min(duration));
+    }
+
+    *shutdown.borrow()
+}
+
+impl Supervisor {
+    /// Handle frames from the device with radios from `open` until `shutdown`
+    /// goes true, or return an error if a radio can't be had
+    pub fn run(
+        &self,
+        exporter: &mut Exporter,
+        mut open: impl FnMut() -> Result<Box<dyn Radio>>,
+        shutdown: &watch::Receiver<bool>,
+    ) -> Result<()> {
+        let mut health = RadioHealth {
+            state: RadioState::Starting,
+            restarts: 0,
+            errors: 0,
+        };
+        let mut backoff = self.first_backoff;
+        let mut attempts = 0;
+
+        exporter.report_radio(health);
+
+        while !*shutdown.borrow() {
+            let radio = match open() {
+                Ok(radio) => radio,
+                Err(e) => {
+                    attempts += 1;
+                    if attempts >= self.max_attempts {
+                        health.state = RadioState::Failed;
+                        exporter.report_radio(health);
+                        return Err(e).wrap_err_with(|| {
+                            format!("Giving up on the radio after {} attempts", attempts)
+                        });
+                    }
+
+                    println!("Failed to set up the radio, trying again in {backoff:?}: {e:?}");
+                    if sleep_unless(backoff, shutdown) {
+                        break;
+                    }
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    continue;
+                }
+            };
+
+            attempts = 0;
+            // the radio starts out on the plan's settings
+            exporter.radio_replaced();
+            health.state = RadioState::Up;
+            health.errors = 0;
+            exporter.report_radio(health);
+
+            let mut radio = Watched::new(radio);
+            while !*shutdown.borrow() && radio.in_a_row < self.max_errors {
+                if let Err(e) = exporter.inner(&mut radio) {
+                    println!("{}", e);
+                }
+
+                if radio.errors != health.errors {
+                    health.errors = radio.errors;
+                    exporter.report_radio(health);
+                }
+            }
+
+            if *shutdown.borrow() {
+                break;
+            }
+
+            // a radio that was working is worth trying again straight away,
+            // one that never did gets longer and longer to sort itself out
+            if radio.worked {
+                backoff = self.first_backoff;
+            }
+            drop(radio);
+
+            println!(
+                "Radio failed {} times in a row, setting it up again in {:?}",
+                self.max_errors, backoff
+            );
+            health.state = RadioState::Recovering;
+            health.restarts += 1;
+            exporter.report_radio(health);
+
+            if sleep_unless(backoff, shutdown) {
+                break;
+            }
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+
+        Ok(())
+    }
+}
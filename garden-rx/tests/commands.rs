@@ -0,0 +1,177 @@
+//! Commands from the panel make it to the device and its answers come back,
+//! including the device moving to a new address
+
+mod common;
+
+use std::time::Duration;
+
+use common::{BaseStation, Device, Panel};
+use garden_shared::{
+    Command, ConfigField, DevAddr, DeviceConfig, DeviceStatus, FrameTime, Message, PanelMessage,
+    Route, StatusFlags, Transmission, UiCommand, BASE_ADDR, EU868,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_round_trip() {
+    let base = BaseStation::start().await;
+    let mut device = Device::new(&base);
+    let mut panel = Panel::connect(&base).await;
+
+    assert!(matches!(panel.next().await, PanelMessage::Hello));
+    let desired = panel
+        .expect(|m| match m {
+            PanelMessage::DesiredStatus(flags) => Some(flags),
+            _ => None,
+        })
+        .await;
+    assert_eq!(desired, StatusFlags::empty());
+
+    // the desired flags are synced once the device reports something else
+    panel.send(UiCommand::PumpOn).await;
+    let desired = panel
+        .expect(|m| match m {
+            PanelMessage::DesiredStatus(flags) => Some(flags),
+            _ => None,
+        })
+        .await;
+    assert_eq!(desired, StatusFlags::PUMP_ON);
+
+    let replies = device.send(Message::StatusUpdate(DeviceStatus {
+        flags: StatusFlags::empty(),
+    }));
+    assert!(
+        matches!(replies[..], [Command::Ack(0), Command::SyncFlags(f)] if f == StatusFlags::PUMP_ON),
+        "{:?}",
+        replies
+    );
+
+    // but not while the device is watering on its own
+    let replies = device.send(Message::StatusUpdate(DeviceStatus {
+        flags: StatusFlags::VALVE_OPEN | StatusFlags::AUTO_WATERING,
+    }));
+    assert!(matches!(replies[..], [Command::Ack(1)]), "{:?}", replies);
+
+    // config changes are queued up and the device's answer goes to the panel
+    let field = ConfigField::StatusInterval(Duration::from_secs(30));
+    panel.send(UiCommand::SetConfig(field)).await;
+    panel
+        .expect(|m| matches!(m, PanelMessage::DesiredStatus(_)).then(|| ()))
+        .await;
+
+    let replies = device.send(Message::StatusUpdate(DeviceStatus {
+        flags: StatusFlags::PUMP_ON,
+    }));
+    assert!(
+        matches!(
+            replies[..],
+            [
+                Command::Ack(2),
+                Command::SetConfig(ConfigField::StatusInterval(d))
+            ] if d == Duration::from_secs(30)
+        ),
+        "{:?}",
+        replies
+    );
+
+    let mut config = DeviceConfig::default();
+    config.set(field, &EU868).unwrap();
+    device.send(Message::Config(config));
+    let reported = panel
+        .expect(|m| match m {
+            PanelMessage::Config(c) => Some(c),
+            _ => None,
+        })
+        .await;
+    assert_eq!(reported, config);
+
+    panel.send(UiCommand::Reset).await;
+    panel
+        .expect(|m| matches!(m, PanelMessage::DesiredStatus(_)).then(|| ()))
+        .await;
+    let replies = device.send(Message::StatusUpdate(DeviceStatus {
+        flags: StatusFlags::PUMP_ON,
+    }));
+    assert!(
+        matches!(replies[..], [Command::Ack(4), Command::Reset]),
+        "{:?}",
+        replies
+    );
+
+    // a device that doesn't know the time gets told it
+    let replies = device.send_frame(&Transmission {
+        src: device.addr,
+        route: Route::to(BASE_ADDR),
+        seq: 5,
+        time: FrameTime::now(1_000, false),
+        link: None,
+        msg: Message::StatusUpdate(DeviceStatus {
+            flags: StatusFlags::PUMP_ON,
+        }),
+    });
+    assert!(
+        matches!(replies[..], [Command::Ack(5), Command::SetTime(_)]),
+        "{:?}",
+        replies
+    );
+}
+
+fn status() -> Message {
+    Message::StatusUpdate(DeviceStatus {
+        flags: StatusFlags::empty(),
+    })
+}
+
+fn acked(replies: &[Command]) -> bool {
+    matches!(replies, [Command::Ack(_), ..])
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn follows_the_device_to_a_new_address() {
+    let path = std::env::temp_dir().join(format!("garden-state-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let base = BaseStation::start_keeping_state(&path).await;
+    let mut device = Device::new(&base);
+    let mut panel = Panel::connect(&base).await;
+    assert!(matches!(panel.next().await, PanelMessage::Hello));
+
+    let moved = DevAddr(0x70);
+    panel
+        .send(UiCommand::SetConfig(ConfigField::Address(moved)))
+        .await;
+    panel
+        .expect(|m| matches!(m, PanelMessage::DesiredStatus(_)).then(|| ()))
+        .await;
+
+    let replies = device.send(status());
+    assert!(
+        matches!(
+            replies[..],
+            [Command::Ack(_), Command::SetConfig(ConfigField::Address(a))] if a == moved
+        ),
+        "{:?}",
+        replies
+    );
+
+    // the device's answer got lost, so it's still where it was
+    assert!(acked(&device.send(status())));
+
+    // until its config comes through from the new address
+    device.addr = moved;
+    let config = DeviceConfig {
+        address: moved,
+        ..DeviceConfig::default()
+    };
+    assert!(acked(&device.send(Message::Config(config))));
+    assert!(acked(&device.send(status())));
+    device.addr = DevAddr(0x69);
+    assert!(device.send(status()).is_empty());
+
+    // a restarted base station still knows where to find it
+    let restarted = BaseStation::start_keeping_state(&path).await;
+    let mut device = Device::new(&restarted);
+    device.addr = moved;
+    assert!(acked(&device.send(status())));
+
+    let _ = std::fs::remove_file(&path);
+}
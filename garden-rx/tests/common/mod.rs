@@ -0,0 +1,372 @@
+//! A base station running against a virtual radio and in-memory storage, along
+//! with a fake device and panel to poke it with.
+#![allow(dead_code)]
+
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use color_eyre::Result;
+use futures::{SinkExt, StreamExt};
+use garden_rx::backend::{Frame, Radio, Replay, VirtualRadio};
+use garden_rx::capture::Capture;
+use garden_rx::radio::Exporter;
+use garden_rx::server;
+use garden_rx::shared::Shared;
+use garden_rx::state::StateFile;
+use garden_rx::storage::{Memory, Point};
+use garden_shared::{
+    Command, DevAddr, DeviceStatus, FrameTime, FrequencyPlan, LinkParams, LinkQuality, Message,
+    PanelMessage, RadioConfig, Route, StatusFlags, Transmission, UiCommand, BASE_ADDR, EU868,
+};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// How long to wait for anything to turn up before failing the test
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the device listens for replies after transmitting, and after
+/// each reply for the next
+const RX_WINDOW: Duration = Duration::from_millis(300);
+
+pub struct BaseStation {
+    pub storage: Arc<Memory>,
+    /// Where the base station's virtual radio listens, if it has one
+    pub radio_addr: Option<SocketAddr>,
+    pub http_addr: SocketAddr,
+}
+
+impl BaseStation {
+    /// Needs to be called from a multi threaded runtime, the radio side blocks
+    pub async fn start() -> Self {
+        Self::start_with(None, false, None).await
+    }
+
+    /// Start a base station that captures every frame to `path`
+    pub async fn start_capturing(path: &Path) -> Self {
+        Self::start_with(Some(Capture::open(path).unwrap()), false, None).await
+    }
+
+    /// Start a base station that keeps track of every frame on the channel
+    pub async fn start_sniffing() -> Self {
+        Self::start_with(None, true, None).await
+    }
+
+    /// Start a base station that keeps what it knows about the device in
+    /// `path` across restarts
+    pub async fn start_keeping_state(path: &Path) -> Self {
+        Self::start_with(None, false, Some(StateFile::new(path.to_owned()))).await
+    }
+
+    async fn start_with(capture: Option<Capture>, sniff: bool, state: Option<StateFile>) -> Self {
+        let radio = VirtualRadio::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let radio_addr = radio.local_addr().unwrap();
+
+        let (storage, http_addr) = launch(radio, &EU868, capture, sniff, state);
+
+        Self {
+            storage,
+            radio_addr: Some(radio_addr),
+            http_addr,
+        }
+    }
+
+    /// Start a base station on a radio of the test's making, which the device
+    /// reaches at `radio_addr`
+    pub async fn start_on(radio: impl Radio + Send + 'static, radio_addr: SocketAddr) -> Self {
+        Self::start_on_plan(radio, radio_addr, &EU868).await
+    }
+
+    /// Start a base station on a radio of the test's making, meeting the
+    /// device on `plan`
+    pub async fn start_on_plan(
+        radio: impl Radio + Send + 'static,
+        radio_addr: SocketAddr,
+        plan: &'static FrequencyPlan,
+    ) -> Self {
+        let (storage, http_addr) = launch(radio, plan, None, false, None);
+
+        Self {
+            storage,
+            radio_addr: Some(radio_addr),
+            http_addr,
+        }
+    }
+
+    /// Start a base station that is fed the frames received in a capture
+    pub async fn replay(path: &Path) -> Self {
+        let radio = Replay::open(path).unwrap();
+
+        let (storage, http_addr) = launch(radio, &EU868, None, false, None);
+
+        Self {
+            storage,
+            radio_addr: None,
+            http_addr,
+        }
+    }
+
+    /// Wait for points to be written that satisfy `pred`
+    pub async fn wait_for_points(&self, pred: impl Fn(&[Point]) -> bool) -> Vec<Point> {
+        let deadline = Instant::now() + TIMEOUT;
+
+        loop {
+            let points = self.storage.points();
+            if pred(&points) {
+                return points;
+            }
+
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting for points, got: {:#?}",
+                points
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+fn launch(
+    mut radio: impl Radio + Send + 'static,
+    plan: &'static FrequencyPlan,
+    capture: Option<Capture>,
+    sniff: bool,
+    state: Option<StateFile>,
+) -> (Arc<Memory>, SocketAddr) {
+    let (status_sender, status_recv) = watch::channel(None);
+    let (event_sender, _) = broadcast::channel(16);
+    let storage = Arc::new(Memory::default());
+    let shared = Arc::new(Shared::default());
+
+    let exporter_storage = storage.clone();
+    let exporter_shared = shared.clone();
+    let radio_event_sender = event_sender.clone();
+    let rt_handle = tokio::runtime::Handle::current();
+    std::thread::spawn(move || {
+        let _handle = rt_handle.enter();
+        let mut exporter = Exporter::new(
+            exporter_storage,
+            exporter_shared,
+            status_sender,
+            radio_event_sender,
+        );
+        exporter.use_plan(plan);
+        if let Some(capture) = capture {
+            exporter.capture_to(capture);
+        }
+        if sniff {
+            exporter.sniff();
+        }
+        if let Some(state) = state {
+            exporter.keep_state_in(state).unwrap();
+        }
+        exporter.run(&mut radio)
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let http_addr = listener.local_addr().unwrap();
+    let app = server::app(shared, status_recv, event_sender);
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+
+    (storage, http_addr)
+}
+
+/// A virtual radio that makes out frames came through with a given SNR and
+/// remembers what it was switched to
+pub struct OnAir {
+    inner: VirtualRadio,
+    pub snr: Arc<Mutex<f32>>,
+    pub configured: Arc<Mutex<Vec<RadioConfig>>>,
+}
+
+impl OnAir {
+    /// The radio, and where the device reaches it
+    pub fn bind(snr: f32) -> (Self, SocketAddr) {
+        let inner = VirtualRadio::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let radio_addr = inner.local_addr().unwrap();
+
+        let radio = Self {
+            inner,
+            snr: Arc::new(Mutex::new(snr)),
+            configured: Arc::new(Mutex::new(Vec::new())),
+        };
+        (radio, radio_addr)
+    }
+}
+
+impl Radio for OnAir {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        Ok(self.inner.receive(timeout)?.map(|frame| Frame {
+            rssi: Some(-100),
+            snr: Some(*self.snr.lock().unwrap()),
+            ..frame
+        }))
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        self.inner.transmit(frame)
+    }
+
+    fn configure(&mut self, radio: &RadioConfig) -> Result<()> {
+        self.configured.lock().unwrap().push(*radio);
+        Ok(())
+    }
+}
+
+/// Send status updates until the base station asks for new link settings
+pub fn send_until_set_link(device: &mut Device) -> Option<LinkParams> {
+    for _ in 0..20 {
+        let replies = device.send(Message::StatusUpdate(DeviceStatus {
+            flags: StatusFlags::empty(),
+        }));
+
+        if let Some(params) = replies.iter().find_map(|c| match c {
+            Command::SetLink(params) => Some(*params),
+            _ => None,
+        }) {
+            return params;
+        }
+    }
+
+    panic!("the base station never changed the link settings");
+}
+
+/// Pretends to be the transmitter, sending frames by hand
+pub struct Device {
+    radio: garden_sim::radio::VirtualRadio,
+    pub addr: DevAddr,
+    /// Reported with the next message sent
+    pub link: Option<LinkQuality>,
+    next_seq: u16,
+}
+
+impl Device {
+    pub fn new(base: &BaseStation) -> Self {
+        let radio = garden_sim::radio::VirtualRadio::new(
+            "127.0.0.1:0".parse().unwrap(),
+            base.radio_addr
+                .expect("the base station has no radio to talk to"),
+        )
+        .unwrap();
+
+        Self {
+            radio,
+            addr: DevAddr(0x69),
+            link: None,
+            next_seq: 0,
+        }
+    }
+
+    /// Send a fresh message with an in sync clock, returning the commands
+    /// that came back
+    pub fn send(&mut self, msg: Message) -> Vec<Command> {
+        let now = Utc::now().timestamp_millis() as u64;
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        let link = self.link.take();
+
+        self.send_frame(&Transmission {
+            src: self.addr,
+            route: Route::to(BASE_ADDR),
+            seq,
+            time: FrameTime::now(now, true),
+            link,
+            msg,
+        })
+    }
+
+    pub fn send_frame(&mut self, frame: &Transmission<Message>) -> Vec<Command> {
+        self.send_raw(&postcard::to_stdvec(frame).unwrap())
+    }
+
+    /// Send whatever bytes, returning the commands that came back
+    pub fn send_raw(&mut self, frame: &[u8]) -> Vec<Command> {
+        self.exchange(frame).into_iter().map(|t| t.msg).collect()
+    }
+
+    /// Send whatever bytes, returning the frames that came back
+    pub fn exchange(&mut self, frame: &[u8]) -> Vec<Transmission<Command>> {
+        tokio::task::block_in_place(|| {
+            self.radio.transmit(frame).unwrap();
+
+            let mut replies = Vec::new();
+            let mut deadline = Instant::now() + RX_WINDOW;
+            while let Some(reply) = self.radio.receive(deadline).unwrap() {
+                let reply: Transmission<Command> = postcard::from_bytes(&reply).unwrap();
+                assert_eq!(reply.src, BASE_ADDR);
+                replies.push(reply);
+                deadline = Instant::now() + RX_WINDOW;
+            }
+
+            replies
+        })
+    }
+}
+
+/// A websocket connection like the one the panel makes
+pub struct Panel {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl Panel {
+    pub async fn connect(base: &BaseStation) -> Self {
+        let (ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", base.http_addr))
+            .await
+            .unwrap();
+
+        Self { ws }
+    }
+
+    pub async fn send(&mut self, cmd: UiCommand) {
+        self.ws
+            .send(tungstenite::Message::Text(
+                serde_json::to_string(&cmd).unwrap(),
+            ))
+            .await
+            .unwrap();
+    }
+
+    /// Send firmware for the base station to pass on to the device
+    pub async fn upload(&mut self, image: &[u8]) {
+        self.ws
+            .send(tungstenite::Message::Binary(image.to_vec()))
+            .await
+            .unwrap();
+    }
+
+    pub async fn next(&mut self) -> PanelMessage {
+        loop {
+            let msg = tokio::time::timeout(TIMEOUT, self.ws.next())
+                .await
+                .expect("timed out waiting for a panel message")
+                .expect("websocket closed")
+                .unwrap();
+
+            if let tungstenite::Message::Text(t) = msg {
+                return serde_json::from_str(&t).unwrap();
+            }
+        }
+    }
+
+    /// Skip over messages until one matches
+    pub async fn expect<T>(&mut self, mut pred: impl FnMut(PanelMessage) -> Option<T>) -> T {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                if let Some(it) = pred(self.next().await) {
+                    return it;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for the expected panel message")
+    }
+}
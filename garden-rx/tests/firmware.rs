@@ -0,0 +1,94 @@
+//! Firmware uploaded through the panel makes it to the device a piece at a
+//! time and is swapped in when it next boots
+
+mod common;
+
+use common::{BaseStation, Device, Panel};
+use garden_core::firmware::{self, Journal, Layout, Updater};
+use garden_core::nvm::{Nvm, ROW_SIZE};
+use garden_shared::{Command, FirmwareState, Message, PanelMessage};
+
+const LAYOUT: Layout = Layout::SAMD21;
+
+/// The device's flash, all of it erased
+struct Flash(Vec<u8>);
+
+impl Nvm for Flash {
+    type Error = ();
+
+    fn read(&self, addr: u32, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.0[addr as usize..addr as usize + buf.len()]);
+    }
+
+    fn erase_row(&mut self, addr: u32) -> Result<(), ()> {
+        self.0[addr as usize..addr as usize + ROW_SIZE].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), ()> {
+        for (old, new) in self.0[addr as usize..].iter_mut().zip(data) {
+            *old &= new;
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn firmware_upload() {
+    let base = BaseStation::start().await;
+    let mut device = Device::new(&base);
+    let mut panel = Panel::connect(&base).await;
+
+    let mut flash = Flash(vec![0xff; 256 * 1024]);
+    let mut updater = Updater::open(&flash, LAYOUT);
+
+    // three chunks, the last one short
+    let mut image: Vec<u8> = (0..300).map(|i| (i * 7) as u8).collect();
+    image[..4].copy_from_slice(&0x2000_8000u32.to_le_bytes());
+    image[4..8].copy_from_slice(&(LAYOUT.app + 0x101).to_le_bytes());
+
+    panel.upload(&image).await;
+    let upload = panel
+        .expect(|m| match m {
+            PanelMessage::Firmware(p) => p.upload,
+            _ => None,
+        })
+        .await;
+    assert_eq!(upload.size, 300);
+
+    // the device asks for the next piece in answer to each one
+    let mut status = updater.status();
+    for _ in 0..5 {
+        let replies = device.send(Message::Firmware(status));
+        for cmd in &replies {
+            match cmd {
+                Command::FirmwareManifest(m) => status = updater.manifest(&mut flash, m),
+                Command::FirmwareChunk(c) => status = updater.chunk(&mut flash, c),
+                _ => {}
+            }
+        }
+    }
+    assert_eq!(status.image, upload.image);
+    assert_eq!(status.state, FirmwareState::Verified);
+
+    // nothing more to send once it has all of it
+    let replies = device.send(Message::Firmware(status));
+    assert!(matches!(replies[..], [Command::Ack(_)]), "{:?}", replies);
+
+    let progress = panel
+        .expect(|m| match m {
+            PanelMessage::Firmware(p) if p.upload.is_none() => Some(p),
+            _ => None,
+        })
+        .await;
+    assert_eq!(progress.device, Some(status));
+
+    let mut journal = Journal::open(&flash, LAYOUT);
+    firmware::boot(&mut flash, &mut journal).unwrap();
+    let app = LAYOUT.app as usize;
+    assert_eq!(flash.0[app..app + image.len()], image[..]);
+    assert_eq!(
+        Updater::open(&flash, LAYOUT).status().state,
+        FirmwareState::Trial
+    );
+}
@@ -0,0 +1,60 @@
+//! The base station trades spare link margin for less airtime and power, and
+//! gives it back when the link gets worse. In the US it's never slowed down
+//! past what a frame may spend on a channel.
+
+mod common;
+
+use common::{send_until_set_link, BaseStation, Device, OnAir};
+use garden_shared::{LinkParams, LinkQuality, RadioConfig, US915};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn adapts_to_the_link_margin() {
+    let (radio, radio_addr) = OnAir::bind(10.0);
+    let (snr, configured) = (radio.snr.clone(), radio.configured.clone());
+
+    let base = BaseStation::start_on(radio, radio_addr).await;
+    let mut device = Device::new(&base);
+    let rendezvous = RadioConfig::default();
+
+    // plenty of margin at the lowest spreading factor, so the power comes down
+    let params = send_until_set_link(&mut device).expect("expected adapted settings");
+    assert_eq!(
+        params,
+        LinkParams {
+            spreading_factor: 7,
+            tx_power: 9
+        }
+    );
+    assert_eq!(
+        configured.lock().unwrap().last(),
+        Some(&params.apply(&rendezvous))
+    );
+
+    // the device hearing us badly counts as much as us hearing it badly
+    device.link = Some(LinkQuality {
+        rssi: -120,
+        snr: -5,
+    });
+    let params = send_until_set_link(&mut device);
+    assert_eq!(params, None);
+    assert_eq!(configured.lock().unwrap().last(), Some(&rendezvous));
+
+    // past what more power can make up for, the spreading factor goes up
+    *snr.lock().unwrap() = -12.0;
+    let params = send_until_set_link(&mut device).expect("expected adapted settings");
+    assert_eq!(params.spreading_factor, 11);
+    assert_eq!(params.tx_power, rendezvous.tx_power);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn caps_the_spreading_factor_for_dwell_time() {
+    // far worse than even SF12 would make up for
+    let (radio, radio_addr) = OnAir::bind(-20.0);
+
+    let base = BaseStation::start_on_plan(radio, radio_addr, &US915).await;
+    let mut device = Device::new(&base);
+
+    let params = send_until_set_link(&mut device).expect("expected adapted settings");
+    assert_eq!(params.spreading_factor, US915.max_spreading_factor);
+    assert_eq!(params.tx_power, US915.radio.tx_power);
+}
@@ -0,0 +1,195 @@
+//! The panel can look inside the base station's radio: read out its
+//! registers, self-test it and listen for the noise floor across the band
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use color_eyre::Result;
+use common::{BaseStation, Panel};
+use garden_rx::backend::{Frame, Radio, VirtualRadio};
+use garden_rx::diagnostics::Registers;
+use garden_rx::storage::FieldValue;
+use garden_shared::{PanelMessage, RadioCheck, RadioReport, UiCommand, SX127X_VERSION};
+
+/// Where somebody else is transmitting, and how loud they are
+const BUSY_CHANNEL: u32 = 868_100_000;
+const BUSY_RSSI: i16 = -77;
+const QUIET_RSSI: i16 = -120;
+
+/// Enough of an SX127x's registers to be looked inside of
+struct Chip {
+    registers: [u8; 128],
+    fifo: [u8; 256],
+    /// Nothing answering on the bus, reads come back all ones
+    missing: bool,
+}
+
+impl Chip {
+    fn new() -> Self {
+        let mut registers = [0; 128];
+        // LoRa mode, receiving on 868MHz
+        registers[0x01] = 0x85;
+        registers[0x06..0x09].copy_from_slice(&[0xd9, 0x00, 0x00]);
+        registers[0x39] = 0x12;
+        registers[0x42] = SX127X_VERSION;
+
+        Self {
+            registers,
+            fifo: [0; 256],
+            missing: false,
+        }
+    }
+
+    fn frequency(&self) -> u32 {
+        let frf = u32::from_be_bytes([0, self.registers[6], self.registers[7], self.registers[8]]);
+        ((frf as u64 * 32_000_000) >> 19) as u32
+    }
+}
+
+/// A virtual radio with a [`Chip`] to look inside of. It never waits long
+/// for a frame, so checks are run soon after they're asked for.
+struct OnAir {
+    inner: VirtualRadio,
+    chip: Arc<Mutex<Chip>>,
+}
+
+impl Radio for OnAir {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<Frame>> {
+        self.inner.receive(timeout.min(Duration::from_millis(50)))
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<()> {
+        self.inner.transmit(frame)
+    }
+
+    fn registers(&mut self) -> Option<&mut dyn Registers> {
+        Some(self)
+    }
+}
+
+impl Registers for OnAir {
+    fn read(&mut self, addr: u8) -> Result<u8> {
+        let mut chip = self.chip.lock().unwrap();
+        if chip.missing {
+            return Ok(0xff);
+        }
+
+        Ok(match addr {
+            0x00 => {
+                let ptr = chip.registers[0x0d];
+                chip.registers[0x0d] = ptr.wrapping_add(1);
+                chip.fifo[ptr as usize]
+            }
+            0x1b => {
+                let rssi = if chip.frequency().abs_diff(BUSY_CHANNEL) < 100_000 {
+                    BUSY_RSSI
+                } else {
+                    QUIET_RSSI
+                };
+                (rssi + 157) as u8
+            }
+            _ => chip.registers[addr as usize],
+        })
+    }
+
+    fn write(&mut self, addr: u8, value: u8) -> Result<()> {
+        let mut chip = self.chip.lock().unwrap();
+        if chip.missing {
+            return Ok(());
+        }
+
+        if addr == 0x00 {
+            let ptr = chip.registers[0x0d];
+            chip.registers[0x0d] = ptr.wrapping_add(1);
+            chip.fifo[ptr as usize] = value;
+        } else {
+            chip.registers[addr as usize] = value;
+        }
+
+        Ok(())
+    }
+}
+
+async fn check(panel: &mut Panel, check: RadioCheck) -> RadioReport {
+    panel.send(UiCommand::CheckRadio(check)).await;
+    panel
+        .expect(|msg| match msg {
+            PanelMessage::RadioReport { report, .. } => Some(report),
+            _ => None,
+        })
+        .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn checks_the_radio() {
+    let inner = VirtualRadio::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let radio_addr = inner.local_addr().unwrap();
+    let chip = Arc::new(Mutex::new(Chip::new()));
+    let radio = OnAir {
+        inner,
+        chip: chip.clone(),
+    };
+
+    let base = BaseStation::start_on(radio, radio_addr).await;
+    let mut panel = Panel::connect(&base).await;
+
+    let test = match check(&mut panel, RadioCheck::SelfTest).await {
+        RadioReport::SelfTest(test) => test,
+        report => panic!("unexpected report {:?}", report),
+    };
+    assert!(test.passed(), "{:?}", test);
+
+    // the self-test puts back what it changed
+    let registers = match check(&mut panel, RadioCheck::Registers).await {
+        RadioReport::Registers(registers) => registers,
+        report => panic!("unexpected report {:?}", report),
+    };
+    let register = |addr| registers.iter().find(|r| r.addr == addr).unwrap().value;
+    assert_eq!(register(0x42), SX127X_VERSION);
+    assert_eq!(register(0x39), 0x12);
+    assert_eq!(register(0x01), 0x85);
+
+    let (floor, samples) = match check(&mut panel, RadioCheck::NoiseFloor).await {
+        RadioReport::NoiseFloor { floor, samples } => (floor, samples),
+        report => panic!("unexpected report {:?}", report),
+    };
+    assert_eq!(floor, QUIET_RSSI);
+    // EU868's 7MHz is more channels than are scanned, so they're spread out
+    assert_eq!(samples.len(), 32);
+    assert!(samples
+        .iter()
+        .all(|s| (863_000_000..870_000_000).contains(&s.frequency)));
+    let busy = samples
+        .iter()
+        .filter(|s| s.rssi == BUSY_RSSI)
+        .collect::<Vec<_>>();
+    assert_eq!(busy.len(), 1);
+    assert!(busy[0].frequency.abs_diff(BUSY_CHANNEL) < 100_000);
+    // and it's tuned back where it was
+    assert_eq!(chip.lock().unwrap().frequency(), 868_000_000);
+
+    let points = base
+        .wait_for_points(|points| points.iter().any(|p| p.measurement == "noise"))
+        .await;
+    let noise = points.iter().find(|p| p.measurement == "noise").unwrap();
+    assert_eq!(
+        noise.get_field("floor"),
+        Some(&FieldValue::I64(QUIET_RSSI as i64))
+    );
+    assert_eq!(
+        noise.get_field("loudest"),
+        Some(&FieldValue::I64(BUSY_RSSI as i64))
+    );
+
+    // with nothing answering on the bus every part of the self-test fails
+    chip.lock().unwrap().missing = true;
+    let test = match check(&mut panel, RadioCheck::SelfTest).await {
+        RadioReport::SelfTest(test) => test,
+        report => panic!("unexpected report {:?}", report),
+    };
+    assert_eq!(test.version, 0xff);
+    assert!(!test.passed());
+    assert!(!test.spi && !test.modes && !test.fifo);
+}
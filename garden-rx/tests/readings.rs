@@ -0,0 +1,186 @@
+//! Readings sent by the device end up in storage and on the panel
+
+mod common;
+
+use std::time::Duration;
+
+use chrono::Utc;
+use common::{BaseStation, Device, Panel};
+use garden_rx::storage::FieldValue;
+use garden_shared::{
+    BME688SensorReport, Command, DeviceStatus, FrameTime, LinkQuality, Message, MoistureReading,
+    MoistureSensorReport, PanelMessage, Route, StatusFlags, Transmission, WateringEvent, BASE_ADDR,
+};
+use uom::si::electrical_resistance::ohm;
+use uom::si::f32::{ElectricalResistance, Pressure, Ratio, ThermodynamicTemperature};
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+fn moisture(levels: &[u16]) -> MoistureSensorReport {
+    MoistureSensorReport {
+        moisture: levels
+            .iter()
+            .map(|&clocks| MoistureReading {
+                clocks,
+                duration: Duration::from_secs(1),
+            })
+            .collect(),
+    }
+}
+
+fn field(
+    points: &[garden_rx::storage::Point],
+    measurement: &str,
+    tag: Option<(&str, &str)>,
+) -> Option<FieldValue> {
+    points
+        .iter()
+        .find(|p| p.measurement == measurement && tag.is_none_or(|(k, v)| p.get_tag(k) == Some(v)))
+        .and_then(|p| p.fields.first().map(|(_, v)| v.clone()))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn readings_round_trip() {
+    let base = BaseStation::start().await;
+    let mut device = Device::new(&base);
+    let mut panel = Panel::connect(&base).await;
+
+    assert!(matches!(panel.next().await, PanelMessage::Hello));
+
+    // every message is acked
+    let replies = device.send(Message::MoistureReport(moisture(&[250, 260, 270])));
+    assert!(matches!(replies[..], [Command::Ack(0)]), "{:?}", replies);
+
+    let points = base
+        .wait_for_points(|p| p.iter().filter(|p| p.measurement == "moisture").count() == 3)
+        .await;
+    for (sensor, level) in [("0", 250.0), ("1", 260.0), ("2", 270.0)] {
+        assert_eq!(
+            field(&points, "moisture", Some(("sensor", sensor))),
+            Some(FieldValue::F64(level))
+        );
+    }
+
+    // the ack came out of the base station's airtime budget
+    let points = base
+        .wait_for_points(|p| p.iter().any(|p| p.measurement == "airtime"))
+        .await;
+    let airtime = points.iter().find(|p| p.measurement == "airtime").unwrap();
+    assert_eq!(airtime.get_tag("side"), Some("base"));
+    assert!(matches!(airtime.get_field("used_ms"), Some(FieldValue::I64(ms)) if *ms > 0));
+    assert_eq!(
+        airtime.get_field("budget_ms"),
+        Some(&FieldValue::I64(36_000))
+    );
+
+    device.send(Message::BME688Report(BME688SensorReport {
+        temp: ThermodynamicTemperature::new::<degree_celsius>(21.5),
+        pressure: Pressure::new::<hectopascal>(1013.0),
+        humidity: Ratio::new::<percent>(60.0),
+        gas_resistance: ElectricalResistance::new::<ohm>(0.0),
+    }));
+
+    let points = base
+        .wait_for_points(|p| p.iter().any(|p| p.measurement == "humidity"))
+        .await;
+    assert!(
+        matches!(field(&points, "temp", None), Some(FieldValue::F64(t)) if (t - 21.5).abs() < 0.01)
+    );
+    assert!(
+        matches!(field(&points, "pressure", None), Some(FieldValue::F64(p)) if (p - 101_300.0).abs() < 1.0)
+    );
+    assert!(
+        matches!(field(&points, "humidity", None), Some(FieldValue::F64(h)) if (h - 60.0).abs() < 0.01)
+    );
+
+    // status and watering events make it to the panel
+    device.send(Message::StatusUpdate(DeviceStatus {
+        flags: StatusFlags::PUMP_ON | StatusFlags::AUTO_WATERING,
+    }));
+    let flags = panel
+        .expect(|m| match m {
+            PanelMessage::Status(s) => Some(s.flags),
+            _ => None,
+        })
+        .await;
+    assert_eq!(flags, StatusFlags::PUMP_ON | StatusFlags::AUTO_WATERING);
+
+    device.send(Message::Watering(WateringEvent::Started { level: 320.0 }));
+    let event = panel
+        .expect(|m| match m {
+            PanelMessage::Watering(e) => Some(e),
+            _ => None,
+        })
+        .await;
+    assert_eq!(event, WateringEvent::Started { level: 320.0 });
+    base.wait_for_points(|p| {
+        p.iter()
+            .any(|p| p.measurement == "watering" && p.get_tag("event") == Some("started"))
+    })
+    .await;
+
+    // how our frames reached the device is stored and passed on
+    let downlink = LinkQuality { rssi: -97, snr: -4 };
+    device.link = Some(downlink);
+    device.send(Message::StatusUpdate(DeviceStatus {
+        flags: StatusFlags::empty(),
+    }));
+    let link = panel
+        .expect(|m| match m {
+            PanelMessage::Link(l) => Some(l),
+            _ => None,
+        })
+        .await;
+    assert_eq!(link.downlink, Some(downlink));
+    assert_eq!(link.uplink, None);
+    let points = base
+        .wait_for_points(|p| p.iter().any(|p| p.measurement == "link"))
+        .await;
+    let link = points.iter().find(|p| p.measurement == "link").unwrap();
+    assert_eq!(link.get_tag("direction"), Some("downlink"));
+    assert_eq!(link.get_field("rssi"), Some(&FieldValue::I64(-97)));
+    assert_eq!(link.get_field("snr"), Some(&FieldValue::I64(-4)));
+
+    // a replayed reading is stored at the time it was captured, and only once
+    let now = Utc::now().timestamp_millis() as u64;
+    let replay = Transmission {
+        src: device.addr,
+        route: Route::to(BASE_ADDR),
+        seq: 0,
+        time: FrameTime {
+            captured: now - 60_000,
+            sent: now,
+            synced: true,
+        },
+        link: None,
+        msg: Message::MoistureReport(moisture(&[400, 400, 400])),
+    };
+
+    let replies = device.send_frame(&replay);
+    assert!(matches!(replies[..], [Command::Ack(0)]), "{:?}", replies);
+    // tried again later, as the device would if our ack went missing
+    device.send_frame(&Transmission {
+        time: FrameTime {
+            sent: now + 10_000,
+            ..replay.time
+        },
+        ..replay
+    });
+
+    let points = base
+        .wait_for_points(|p| p.iter().filter(|p| p.measurement == "moisture").count() >= 6)
+        .await;
+    let replayed = points
+        .iter()
+        .filter(|p| {
+            p.measurement == "moisture" && p.get_field("moisture") == Some(&FieldValue::F64(400.0))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(replayed.len(), 3);
+
+    let captured_ns = (now - 60_000) as i64 * 1_000_000;
+    for p in replayed {
+        assert!((p.timestamp - captured_ns).abs() < 1_000_000_000, "{:?}", p);
+    }
+}
@@ -0,0 +1,62 @@
+//! A message replayed because our ack went missing is only stored once, but
+//! one replayed after the device reset and started its sequence numbers over
+//! is still stored
+
+mod common;
+
+use chrono::Utc;
+use common::{BaseStation, Device};
+use garden_rx::storage::FieldValue;
+use garden_shared::{Command, FrameTime, Message, Route, Transmission, WateringEvent, BASE_ADDR};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replays_across_a_reboot() {
+    let base = BaseStation::start().await;
+    let mut device = Device::new(&base);
+
+    let now = Utc::now().timestamp_millis() as u64;
+    let src = device.addr;
+    let watering = |seq, captured, sent, level| Transmission {
+        src,
+        route: Route::to(BASE_ADDR),
+        seq,
+        time: FrameTime {
+            captured,
+            sent,
+            synced: true,
+        },
+        link: None,
+        msg: Message::Watering(WateringEvent::Started { level }),
+    };
+
+    let first = watering(0, now - 120_000, now - 120_000, 300.0);
+    let replies = device.send_frame(&first);
+    assert!(
+        matches!(replies[..], [Command::Ack(0), ..]),
+        "{:?}",
+        replies
+    );
+
+    // the device didn't hear the ack and tries again
+    let again = watering(0, now - 120_000, now - 110_000, 300.0);
+    device.send_frame(&again);
+
+    // then resets, and its first message since goes missing too
+    let after_reset = watering(0, now - 60_000, now, 310.0);
+    let replies = device.send_frame(&after_reset);
+    assert!(
+        matches!(replies[..], [Command::Ack(0), ..]),
+        "{:?}",
+        replies
+    );
+
+    let points = base
+        .wait_for_points(|p| p.iter().filter(|p| p.measurement == "watering").count() >= 2)
+        .await;
+    let levels = points
+        .iter()
+        .filter(|p| p.measurement == "watering")
+        .map(|p| p.get_field("level").unwrap().clone())
+        .collect::<Vec<_>>();
+    assert_eq!(levels, [FieldValue::F64(300.0), FieldValue::F64(310.0)]);
+}
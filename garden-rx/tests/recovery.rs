@@ -0,0 +1,121 @@
+//! The radio is set up again when it keeps failing, and given up on when it
+//! can't be set up at all
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use garden_rx::backend::{Frame, Radio};
+use garden_rx::radio::Exporter;
+use garden_rx::shared::Shared;
+use garden_rx::storage::{FieldValue, Memory};
+use garden_rx::supervisor::Supervisor;
+use garden_shared::{PanelMessage, RadioHealth, RadioState};
+use tokio::sync::{broadcast, watch};
+
+/// A radio that fails every time, or hears nothing every time
+struct Scripted {
+    failing: bool,
+}
+
+impl Radio for Scripted {
+    fn receive(&mut self, _timeout: Duration) -> Result<Option<Frame>> {
+        if self.failing {
+            return Err(eyre!("SPI went away"));
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+        Ok(None)
+    }
+
+    fn transmit(&mut self, _frame: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn supervisor() -> Supervisor {
+    Supervisor {
+        max_errors: 3,
+        max_attempts: 3,
+        first_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(40),
+    }
+}
+
+#[test]
+fn recovers_then_gives_up() {
+    let storage = Arc::new(Memory::default());
+    let (status_sender, _) = watch::channel(None);
+    let (event_sender, mut events) = broadcast::channel(64);
+    let shared = Arc::new(Shared::default());
+    let mut exporter = Exporter::new(storage.clone(), shared.clone(), status_sender, event_sender);
+
+    // the hat isn't there at first, then it's flaky, then it's fine
+    let opened = Arc::new(Mutex::new(0));
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let handle = {
+        let opened = opened.clone();
+        std::thread::spawn(move || {
+            let result = supervisor().run(
+                &mut exporter,
+                || {
+                    let mut opened = opened.lock().unwrap();
+                    *opened += 1;
+                    match *opened {
+                        1 => Err(eyre!("no such device")),
+                        2 => Ok(Box::new(Scripted { failing: true }) as Box<dyn Radio>),
+                        _ => Ok(Box::new(Scripted { failing: false })),
+                    }
+                },
+                &shutdown,
+            );
+            (result, exporter)
+        })
+    };
+
+    let mut seen = Vec::new();
+    while seen.last().map(|h: &RadioHealth| (h.state, h.restarts)) != Some((RadioState::Up, 1)) {
+        match events.blocking_recv().unwrap() {
+            PanelMessage::Radio(health) => seen.push(health),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    assert_eq!(seen[0].state, RadioState::Starting);
+    // the flaky radio's errors are counted before it's replaced
+    assert!(seen
+        .iter()
+        .any(|h| h.state == RadioState::Up && h.errors == 3));
+    assert!(seen
+        .iter()
+        .any(|h| h.state == RadioState::Recovering && h.restarts == 1));
+    assert_eq!(*opened.lock().unwrap(), 3);
+
+    let points = storage.points();
+    let radio = points
+        .iter()
+        .filter(|p| p.measurement == "radio")
+        .collect::<Vec<_>>();
+    assert_eq!(radio.len(), seen.len());
+    assert_eq!(radio.last().unwrap().get_tag("state"), Some("Up"));
+    assert_eq!(
+        radio.last().unwrap().get_field("restarts"),
+        Some(&FieldValue::I64(1))
+    );
+
+    shutdown_sender.send(true).unwrap();
+    let (result, mut exporter) = handle.join().unwrap();
+    assert!(result.is_ok());
+
+    // a hat that never turns up is given up on
+    let (_shutdown_sender, shutdown) = watch::channel(false);
+    let result = supervisor().run(&mut exporter, || Err(eyre!("no such device")), &shutdown);
+
+    let err = format!("{:?}", result.unwrap_err());
+    assert!(err.contains("Giving up on the radio after 3 attempts"));
+    assert_eq!(
+        shared.radio_health.lock().unwrap().state,
+        RadioState::Failed
+    );
+}
@@ -0,0 +1,79 @@
+//! A device out of earshot is answered through the repeater that passed its
+//! frames on, and a copy of the same frame heard directly is only answered
+//! once
+
+mod common;
+
+use chrono::Utc;
+use common::{BaseStation, Device};
+use garden_core::repeater::Repeater;
+use garden_shared::{
+    Command, DevAddr, DeviceStatus, FrameTime, Message, Route, StatusFlags, Transmission, BASE_ADDR,
+};
+
+const REPEATER: DevAddr = DevAddr(0x70);
+
+#[tokio::test(flavor = "multi_thread")]
+async fn answers_through_the_repeater() {
+    let base = BaseStation::start().await;
+    let mut device = Device::new(&base);
+    let mut repeater = Repeater::new();
+
+    let frame = postcard::to_stdvec(&Transmission {
+        src: device.addr,
+        route: Route::to(BASE_ADDR),
+        seq: 3,
+        time: FrameTime::now(Utc::now().timestamp_millis() as u64, true),
+        link: None,
+        msg: Message::StatusUpdate(DeviceStatus {
+            flags: StatusFlags::empty(),
+        }),
+    })
+    .unwrap();
+
+    let mut passed_on = [0; 255];
+    let len = repeater
+        .forward(REPEATER, &frame, &mut passed_on)
+        .expect("the repeater didn't pass the device's frame on");
+
+    let replies = device.exchange(&passed_on[..len]);
+    assert!(
+        matches!(replies.first(), Some(t) if matches!(t.msg, Command::Ack(3))),
+        "{:?}",
+        replies
+    );
+
+    for reply in &replies {
+        assert_eq!(reply.route.dst, device.addr);
+        assert_eq!(reply.route.via, Some(REPEATER));
+
+        // and the repeater takes it the rest of the way
+        let mut out = [0; 255];
+        let frame = postcard::to_stdvec(reply).unwrap();
+        let len = repeater.forward(REPEATER, &frame, &mut out).unwrap();
+        let delivered: Transmission<Command> = postcard::from_bytes(&out[..len]).unwrap();
+        assert_eq!(delivered.route.dst, device.addr);
+        assert_eq!(delivered.route.via, None);
+        assert_eq!(delivered.route.hops, 1);
+    }
+
+    // the same frame heard straight from the device has already been answered
+    assert!(device.send_raw(&frame).is_empty());
+
+    // and once it's in earshot it's answered directly
+    let replies = device.exchange(
+        &postcard::to_stdvec(&Transmission {
+            src: device.addr,
+            route: Route::to(BASE_ADDR),
+            seq: 4,
+            time: FrameTime::now(Utc::now().timestamp_millis() as u64, true),
+            link: None,
+            msg: Message::StatusUpdate(DeviceStatus {
+                flags: StatusFlags::empty(),
+            }),
+        })
+        .unwrap(),
+    );
+    assert!(!replies.is_empty());
+    assert!(replies.iter().all(|t| t.route == Route::to(device.addr)));
+}
@@ -0,0 +1,74 @@
+//! The simulated transmitter firmware talking to the base station
+
+mod common;
+
+use common::{BaseStation, Panel};
+use garden_rx::storage::FieldValue;
+use garden_shared::{PanelMessage, ResetCause};
+use garden_sim::device::{SimClock, Simulator};
+use garden_sim::radio::VirtualRadio;
+use garden_sim::script::Script;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn simulated_device() {
+    let base = BaseStation::start().await;
+    let mut panel = Panel::connect(&base).await;
+
+    let script = Script::parse(
+        "
+        0s moisture 250 260 255
+        0s bme 21.5 60 1013
+        ",
+    )
+    .unwrap();
+    let radio =
+        VirtualRadio::new("127.0.0.1:0".parse().unwrap(), base.radio_addr.unwrap()).unwrap();
+
+    // 30s of the device's time, long enough for every task to have run
+    tokio::task::spawn_blocking(move || {
+        Simulator::new(SimClock::new(20.0), radio, script)
+            .run(Some(30_000))
+            .unwrap()
+    })
+    .await
+    .unwrap();
+
+    let points = base.storage.points();
+    let moisture = points
+        .iter()
+        .filter(|p| p.measurement == "moisture")
+        .filter_map(|p| match p.get_field("moisture") {
+            Some(FieldValue::F64(level)) => Some((p.get_tag("sensor").unwrap(), *level)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(moisture.len(), 3, "{:?}", points);
+    for ((sensor, level), expected) in moisture.into_iter().zip([250.0, 260.0, 255.0]) {
+        // the measuring windows get stretched by the radio, a pulse either way
+        // of the expected count is fine
+        assert!(
+            (level - expected).abs() < 3.0,
+            "sensor {}: {}",
+            sensor,
+            level
+        );
+    }
+
+    // the default config takes 5 degrees off the temperature
+    assert!(points.iter().any(|p| p.measurement == "temp"
+        && matches!(p.get_field("temp"), Some(FieldValue::F64(t)) if (t - 16.5).abs() < 0.01)));
+
+    panel
+        .expect(|m| match m {
+            PanelMessage::Status(_) => Some(()),
+            _ => None,
+        })
+        .await;
+    let diagnostics = panel
+        .expect(|m| match m {
+            PanelMessage::Diagnostics(d) => Some(d),
+            _ => None,
+        })
+        .await;
+    assert_eq!(diagnostics.reset_cause, ResetCause::PowerOn);
+}
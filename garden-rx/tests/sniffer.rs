@@ -0,0 +1,80 @@
+//! While sniffing, the base station keeps track of every frame on the
+//! channel, not just the ones from our device
+
+mod common;
+
+use common::{BaseStation, Device, Panel};
+use garden_rx::storage::FieldValue;
+use garden_shared::{DevAddr, DeviceStatus, Message, PanelMessage, StatusFlags};
+
+fn status() -> Message {
+    Message::StatusUpdate(DeviceStatus {
+        flags: StatusFlags::empty(),
+    })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sniffs_the_whole_channel() {
+    let base = BaseStation::start_sniffing().await;
+    let mut panel = Panel::connect(&base).await;
+
+    let mut device = Device::new(&base);
+    assert!(!device.send(status()).is_empty());
+
+    // a neighbour on the same settings isn't answered, but is noticed
+    let mut neighbour = Device::new(&base);
+    neighbour.addr = DevAddr(0x42);
+    assert!(neighbour.send(status()).is_empty());
+    assert!(neighbour.send(status()).is_empty());
+
+    assert!(device.send_raw(&[0xde, 0xad, 0xbe, 0xef]).is_empty());
+
+    let stats = panel
+        .expect(|msg| match msg {
+            PanelMessage::Channel(stats) if stats.undecodable.frames == 1 => Some(stats),
+            _ => None,
+        })
+        .await;
+
+    assert_eq!(stats.ours.frames, 1);
+    // the ack at least
+    assert!(stats.sent.frames >= 1);
+    assert_eq!(stats.foreign.frames, 2);
+    assert_eq!(stats.neighbours.len(), 1);
+    assert_eq!(stats.neighbours[0].src, DevAddr(0x42));
+    assert_eq!(stats.neighbours[0].traffic.frames, 2);
+    assert!(stats.utilisation() > 0.0);
+    assert_eq!(stats.neighbours[0].traffic.airtime, stats.foreign.airtime);
+
+    let points = base
+        .wait_for_points(|points| {
+            points
+                .iter()
+                .any(|p| p.measurement == "frame" && p.get_tag("kind") == Some("undecodable"))
+        })
+        .await;
+    let frames = points
+        .iter()
+        .filter(|p| p.measurement == "frame")
+        .collect::<Vec<_>>();
+    let kinds = |kind| {
+        frames
+            .iter()
+            .filter(|p| p.get_tag("kind") == Some(kind))
+            .count()
+    };
+    assert_eq!(kinds("ours"), 1);
+    assert_eq!(kinds("foreign"), 2);
+    assert!(kinds("sent") >= 1);
+    assert!(frames
+        .iter()
+        .filter(|p| p.get_tag("kind") == Some("foreign"))
+        .all(|p| p.get_tag("src") == Some("0x42")));
+    // every frame after the first knows how long it's been since the last
+    assert!(frames[1..].iter().all(|p| p.get_field("gap_ms").is_some()));
+    assert!(frames
+        .iter()
+        .all(|p| matches!(p.get_field("airtime_us"), Some(FieldValue::I64(us)) if *us > 0)));
+
+    assert!(points.iter().any(|p| p.measurement == "channel"));
+}
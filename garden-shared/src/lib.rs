@@ -1,4 +1,6 @@
-#[cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(feature = "std"), no_std)]
+// `div_ceil` and `is_multiple_of` are newer than the firmware's toolchain
+#![allow(clippy::manual_div_ceil, clippy::manual_is_multiple_of)]
 use core::time::Duration;
 
 #[allow(unused_imports)]
@@ -11,9 +13,12 @@ use uom::si::{
     thermodynamic_temperature::degree_celsius,
 };
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
 pub struct DevAddr(pub u16);
 
+/// The base station's address, every frame from a device is for it
+pub const BASE_ADDR: DevAddr = DevAddr(69);
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct MoistureReading {
     pub clocks: u16,
@@ -121,8 +126,10 @@ impl BME688SensorReport {
 bitflags::bitflags! {
     #[derive(serde::Serialize, serde::Deserialize)]
     pub struct StatusFlags: u8 {
-        const PUMP_ON    = 0b01;
-        const VALVE_OPEN = 0b10;
+        const PUMP_ON       = 0b001;
+        const VALVE_OPEN    = 0b010;
+        /// Set while the device's watering controller is driving the outputs
+        const AUTO_WATERING = 0b100;
     }
 }
 
@@ -131,17 +138,629 @@ pub struct DeviceStatus {
     pub flags: StatusFlags,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+/// Configuration of the autonomous watering controller running on the device.
+///
+/// Moisture levels are in sensor clocks per second, higher readings mean drier
+/// soil.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct WateringConfig {
+    pub enabled: bool,
+    /// Bitmask of the moisture sensors that are averaged to get the level
+    pub sensors: u8,
+    /// Watering starts once the level rises to this value
+    pub dry_threshold: f32,
+    /// Watering stops once the level falls to `dry_threshold - hysteresis`
+    pub hysteresis: f32,
+    /// The longest a single watering run may last
+    pub max_run_time: Duration,
+    /// The shortest gap between the end of one run and the start of the next
+    pub min_interval: Duration,
+    /// The outputs that are switched on while watering
+    pub outputs: StatusFlags,
+}
+
+impl Default for WateringConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sensors: 0b111,
+            dry_threshold: 300.0,
+            hysteresis: 20.0,
+            max_run_time: Duration::from_secs(5 * 60),
+            min_interval: Duration::from_secs(60 * 60),
+            outputs: StatusFlags::PUMP_ON,
+        }
+    }
+}
+
+impl WateringConfig {
+    /// The averaged moisture level of the selected sensors, if any of them
+    /// are present in the report
+    pub fn level(&self, report: &MoistureSensorReport) -> Option<f32> {
+        let (sum, count) = report
+            .moisture
+            .iter()
+            .enumerate()
+            .filter(|(n, _)| self.sensors & (1 << n) != 0)
+            .fold((0.0, 0u8), |(sum, count), (_, r)| {
+                (sum + r.per_second(), count + 1)
+            });
+
+        if count == 0 {
+            None
+        } else {
+            Some(sum / count as f32)
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WateringStopReason {
+    /// The moisture level fell below the stop threshold
+    Wet,
+    /// The run hit the configured maximum run time
+    MaxRunTime,
+    /// The controller was disabled while running
+    Disabled,
+    /// The outputs it drives were changed while running
+    Reconfigured,
+}
+
+/// An action taken by the device's watering controller on its own
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum WateringEvent {
+    Started {
+        level: f32,
+    },
+    Stopped {
+        level: Option<f32>,
+        reason: WateringStopReason,
+    },
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoRaBandwidth {
+    Bw125kHz,
+    Bw250kHz,
+    Bw500kHz,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoRaCodingRate {
+    Cr4_5,
+    Cr4_6,
+    Cr4_7,
+    Cr4_8,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RadioConfig {
+    /// Carrier frequency in Hz
+    pub frequency: u32,
+    pub bandwidth: LoRaBandwidth,
+    pub spreading_factor: u8,
+    pub coding_rate: LoRaCodingRate,
+    /// Transmit power in dBm
+    pub tx_power: i8,
+}
+
+impl Default for RadioConfig {
+    fn default() -> Self {
+        EU868.radio
+    }
+}
+
+/// Preamble length in symbols. This and the rest of the framing isn't
+/// configurable, a device that disagreed on it would never be heard again.
+pub const PREAMBLE_LEN: u16 = 8;
+/// The LoRa sync word for private networks, which LoRaWAN gateways ignore.
+/// It's also what the SX127x resets to.
+pub const SYNC_WORD: u8 = 0x12;
+/// Whether frames carry a payload CRC
+pub const PAYLOAD_CRC: bool = true;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Eu868,
+    Us915,
+    As923,
+}
+
+impl Region {
+    pub fn plan(&self) -> &'static FrequencyPlan {
+        match self {
+            Region::Eu868 => &EU868,
+            Region::Us915 => &US915,
+            Region::As923 => &AS923,
+        }
+    }
+}
+
+impl core::str::FromStr for Region {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "eu868" => Ok(Region::Eu868),
+            "us915" => Ok(Region::Us915),
+            "as923" => Ok(Region::As923),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The band a region lets us use and the radio settings to start out on in
+/// it, which devices fresh out of flashing and the base station share.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrequencyPlan {
+    pub region: Region,
+    /// Lowest and highest carrier frequency in Hz
+    pub band: (u32, u32),
+    /// The frequency is a whole number of MHz, the base station's driver
+    /// can't tune any finer
+    pub radio: RadioConfig,
+    /// How much each end may transmit in an hour, if the band limits it
+    pub airtime_per_hour: Option<Duration>,
+    /// The slowest spreading factor the link may be adapted up to
+    pub max_spreading_factor: u8,
+}
+
+impl FrequencyPlan {
+    pub fn contains(&self, frequency: u32) -> bool {
+        (self.band.0..=self.band.1).contains(&frequency)
+    }
+}
+
+/// The 868 MHz band in Europe, on a sub-band with a 1% duty cycle
+pub const EU868: FrequencyPlan = FrequencyPlan {
+    region: Region::Eu868,
+    band: (863_000_000, 870_000_000),
+    radio: RadioConfig {
+        frequency: 868_000_000,
+        bandwidth: LoRaBandwidth::Bw125kHz,
+        spreading_factor: 7,
+        coding_rate: LoRaCodingRate::Cr4_8,
+        tx_power: 15,
+    },
+    airtime_per_hour: Some(Duration::from_secs(36)),
+    max_spreading_factor: 12,
+};
+
+/// The 915 MHz ISM band in the US, which limits dwell time per channel rather
+/// than duty cycle
+pub const US915: FrequencyPlan = FrequencyPlan {
+    region: Region::Us915,
+    band: (902_000_000, 928_000_000),
+    radio: RadioConfig {
+        frequency: 915_000_000,
+        bandwidth: LoRaBandwidth::Bw125kHz,
+        spreading_factor: 7,
+        coding_rate: LoRaCodingRate::Cr4_8,
+        tx_power: 17,
+    },
+    airtime_per_hour: None,
+    // frames may only dwell 400 ms on a channel, which at 125 kHz rules out
+    // SF11 and SF12 like LoRaWAN does
+    max_spreading_factor: 10,
+};
+
+/// The 923 MHz band used around much of Asia, held to a 1% duty cycle
+/// like Europe
+pub const AS923: FrequencyPlan = FrequencyPlan {
+    region: Region::As923,
+    band: (915_000_000, 928_000_000),
+    radio: RadioConfig {
+        frequency: 923_000_000,
+        bandwidth: LoRaBandwidth::Bw125kHz,
+        spreading_factor: 7,
+        coding_rate: LoRaCodingRate::Cr4_8,
+        tx_power: 14,
+    },
+    airtime_per_hour: Some(Duration::from_secs(36)),
+    max_spreading_factor: 12,
+};
+
+impl RadioConfig {
+    /// Reject settings the radio can't do
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(7..=12).contains(&self.spreading_factor) {
+            return Err(ConfigError::InvalidSpreadingFactor(self.spreading_factor));
+        }
+        if !(2..=20).contains(&self.tx_power) {
+            return Err(ConfigError::InvalidTxPower(self.tx_power));
+        }
+        // the base station's driver only tunes to whole MHz, anything else
+        // would leave it listening off to one side
+        if !(137_000_000..=1_020_000_000).contains(&self.frequency)
+            || self.frequency % 1_000_000 != 0
+        {
+            return Err(ConfigError::InvalidFrequency(self.frequency));
+        }
+
+        Ok(())
+    }
+}
+
+/// The spreading factor and power the base station has asked the device to
+/// use in place of its configured ones. The configured radio settings stay as
+/// the rendezvous both ends fall back to if the link is lost.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkParams {
+    pub spreading_factor: u8,
+    /// Transmit power in dBm
+    pub tx_power: i8,
+}
+
+impl LinkParams {
+    pub fn apply(&self, radio: &RadioConfig) -> RadioConfig {
+        RadioConfig {
+            spreading_factor: self.spreading_factor,
+            tx_power: self.tx_power,
+            ..*radio
+        }
+    }
+}
+
+/// What a device is there for
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Looks after the greenhouse and talks to the base station
+    EndNode,
+    /// Passes frames on between end nodes and the base station for range,
+    /// without any sensors of its own
+    Repeater,
+}
+
+/// The tunables of the device, persisted in its flash
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DeviceConfig {
+    pub address: DevAddr,
+    pub role: Role,
+    /// Time between each round of moisture measurements
+    pub measurement_interval: Duration,
+    pub bme_interval: Duration,
+    pub status_interval: Duration,
+    /// Added to the temperature read from the BME688
+    pub temperature_offset: f32,
+    /// How often the device reboots itself, if at all
+    pub reset_interval: Option<Duration>,
+    pub radio: RadioConfig,
+    pub watering: WateringConfig,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            address: DevAddr(0x69),
+            role: Role::EndNode,
+            measurement_interval: Duration::from_secs(60),
+            bme_interval: Duration::from_secs(60),
+            status_interval: Duration::from_secs(10),
+            temperature_offset: -5.0,
+            reset_interval: Some(Duration::from_secs(60 * 60)),
+            radio: RadioConfig::default(),
+            watering: WateringConfig::default(),
+        }
+    }
+}
+
+/// A single field of [`DeviceConfig`]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+pub enum ConfigField {
+    Address(DevAddr),
+    Role(Role),
+    MeasurementInterval(Duration),
+    BmeInterval(Duration),
+    StatusInterval(Duration),
+    TemperatureOffset(f32),
+    ResetInterval(Option<Duration>),
+    Radio(RadioConfig),
+    Watering(WateringConfig),
+}
+
+/// The longest interval the device can schedule, its RTC counts 32.768kHz
+/// ticks in 32 bits
+pub const MAX_INTERVAL: Duration = Duration::from_secs(u32::MAX as u64 / 32_768);
+
+#[derive(displaydoc::Display, Debug)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum ConfigError {
+    /// The interval of {0:?} is too short
+    IntervalTooShort(Duration),
+
+    /// The interval of {0:?} is longer than the device can schedule
+    IntervalTooLong(Duration),
+
+    /// The spreading factor {0} is not supported
+    InvalidSpreadingFactor(u8),
+
+    /// The transmit power of {0} dBm is outside of what the radio can do
+    InvalidTxPower(i8),
+
+    /// The frequency {0} Hz is outside of what the radio can do or not whole MHz
+    InvalidFrequency(u32),
+
+    /// The frequency {0} Hz is outside of the band the region allows
+    OutOfBand(u32),
+}
+
+impl DeviceConfig {
+    /// Update a single field, rejecting values that would leave the device
+    /// unreachable or spinning, or radio settings outside of `plan`'s band
+    pub fn set(&mut self, field: ConfigField, plan: &FrequencyPlan) -> Result<(), ConfigError> {
+        fn interval(d: Duration) -> Result<Duration, ConfigError> {
+            if d < Duration::from_secs(1) {
+                Err(ConfigError::IntervalTooShort(d))
+            } else if d > MAX_INTERVAL {
+                Err(ConfigError::IntervalTooLong(d))
+            } else {
+                Ok(d)
+            }
+        }
+
+        match field {
+            ConfigField::Address(addr) => self.address = addr,
+            ConfigField::Role(role) => self.role = role,
+            ConfigField::MeasurementInterval(d) => self.measurement_interval = interval(d)?,
+            ConfigField::BmeInterval(d) => self.bme_interval = interval(d)?,
+            ConfigField::StatusInterval(d) => self.status_interval = interval(d)?,
+            ConfigField::TemperatureOffset(offset) => self.temperature_offset = offset,
+            ConfigField::ResetInterval(d) => {
+                // anything shorter than a minute and we'd never get a word in
+                if let Some(d) = d {
+                    if d < Duration::from_secs(60) {
+                        return Err(ConfigError::IntervalTooShort(d));
+                    }
+                    interval(d)?;
+                }
+                self.reset_interval = d;
+            }
+            ConfigField::Radio(radio) => {
+                radio.validate()?;
+                if !plan.contains(radio.frequency) {
+                    return Err(ConfigError::OutOfBand(radio.frequency));
+                }
+                self.radio = radio;
+            }
+            ConfigField::Watering(watering) => {
+                for d in [watering.max_run_time, watering.min_interval] {
+                    if d > MAX_INTERVAL {
+                        return Err(ConfigError::IntervalTooLong(d));
+                    }
+                }
+                self.watering = watering;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// CRC-32 (IEEE), computed bitwise to keep it small on the device
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+/// Why the device last came out of reset, as recorded by the power manager
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+    PowerOn,
+    /// Brown-out of the 1.2V core supply
+    Brownout12,
+    /// Brown-out of the 3.3V supply
+    Brownout33,
+    External,
+    Watchdog,
+    /// A software reset, the hourly reset, too many sensor failures, a panic
+    /// or a reset command
+    System,
+    Unknown,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct PanicLocation {
+    /// The tail end of the path if it didn't fit
+    pub file: heapless::String<48>,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Sent by the device after it was reset by a panic
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct CrashReport {
+    pub location: PanicLocation,
+    /// The panic message, cut short if it didn't fit
+    pub message: heapless::String<64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Diagnostics {
+    pub reset_cause: ResetCause,
+    pub uptime: Duration,
+    pub version: heapless::String<8>,
+    /// Short hash of the commit the firmware was built from
+    pub git_hash: heapless::String<16>,
+    /// Total failed reads of the BME688 since boot
+    pub bme_failures: u32,
+    /// Messages that didn't fit in the transmit queue
+    pub dropped_messages: u32,
+    pub radio_errors: u32,
+    /// Times the device gave up on the link settings from the base station
+    /// and went back to its configured ones
+    pub link_fallbacks: u32,
+    /// Time spent transmitting over the last hour
+    pub airtime: Duration,
+    /// Messages held back or dropped to stay within the band's duty cycle
+    pub airtime_deferred: u32,
+    /// Transmissions that waited for someone else to get off the channel
+    pub channel_deferred: u32,
+    /// Transmissions that went out with the channel still busy after every
+    /// retry, and likely collided
+    pub channel_collisions: u32,
+    /// Where the device panicked before the last reset, if it did
+    pub last_panic: Option<PanicLocation>,
+}
+
+/// How much of a firmware image goes in each [`Command::FirmwareChunk`]
+pub const FIRMWARE_CHUNK: usize = 128;
+
+/// Short name for a firmware image, the start of its SHA-256
+pub fn image_id(sha256: &[u8; 32]) -> u32 {
+    u32::from_be_bytes([sha256[0], sha256[1], sha256[2], sha256[3]])
+}
+
+/// What the device is told about new firmware before any of it is sent
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareManifest {
+    /// Length of the image in bytes
+    pub size: u32,
+    pub sha256: [u8; 32],
+}
+
+impl FirmwareManifest {
+    pub fn image(&self) -> u32 {
+        image_id(&self.sha256)
+    }
+
+    /// How many chunks the image is sent in
+    pub fn chunks(&self) -> u16 {
+        ((self.size as usize + FIRMWARE_CHUNK - 1) / FIRMWARE_CHUNK) as u16
+    }
+}
+
+/// A piece of a firmware image, the last one can be short
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareChunk {
+    pub index: u16,
+    /// [`crc32`] of `data`
+    pub crc: u32,
+    pub data: heapless::Vec<u8, FIRMWARE_CHUNK>,
+}
+
+/// Why the device gave up on new firmware
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareError {
+    /// It doesn't fit in the staging slot
+    TooLarge,
+    /// It doesn't start with a vector table for the app slot
+    NotAnImage,
+    /// What arrived doesn't match the manifest's hash
+    HashMismatch,
+    /// Writing it to flash failed
+    Flash,
+    /// The device is still trying out the last update, the old firmware
+    /// can't be written over until it's kept
+    OnTrial,
+}
+
+/// Where the device is with its firmware
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareState {
+    /// Running firmware that's been kept, nothing on its way
+    Idle,
+    /// Waiting for chunk `next` of new firmware
+    Receiving {
+        next: u16,
+    },
+    /// All of the new firmware arrived intact, the device resets into it
+    Verified,
+    /// Running new firmware that hasn't been kept yet, it's rolled back if
+    /// it resets a few times before getting through to the base station
+    Trial,
+    /// The last update never got through to the base station and was rolled
+    /// back
+    RolledBack,
+    Failed(FirmwareError),
+}
+
+/// Sent by the device in answer to firmware commands and after booting
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareStatus {
+    /// The [`image_id`] of the firmware on its way while receiving, verified
+    /// or failed, otherwise of the firmware running. 0 for firmware that
+    /// wasn't sent over the air.
+    pub image: u32,
+    pub state: FirmwareState,
+}
+
+/// New firmware the base station is sending to the device
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareUpload {
+    pub image: u32,
+    /// Length of the image in bytes
+    pub size: u32,
+}
+
+/// How sending new firmware to the device is going
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareProgress {
+    /// The firmware being sent, until the device has all of it
+    pub upload: Option<FirmwareUpload>,
+    /// What the device last said about its firmware
+    pub device: Option<FirmwareStatus>,
+}
+
+impl FirmwareProgress {
+    /// Bytes of the upload the device has so far
+    pub fn received(&self) -> Option<u32> {
+        let upload = self.upload?;
+        match self.device {
+            Some(FirmwareStatus {
+                image,
+                state: FirmwareState::Receiving { next },
+            }) if image == upload.image => {
+                Some((next as u32 * FIRMWARE_CHUNK as u32).min(upload.size))
+            }
+            _ => Some(0),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub enum Message {
     MoistureReport(MoistureSensorReport),
     BME688Report(BME688SensorReport),
     StatusUpdate(DeviceStatus),
+    Watering(WateringEvent),
+    Config(DeviceConfig),
+    Diagnostics(Diagnostics),
+    CrashReport(CrashReport),
+    Firmware(FirmwareStatus),
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub enum Command {
     SyncFlags(StatusFlags),
     Reset,
+    /// Update and persist one field of the device config, the device replies
+    /// with the resulting config
+    SetConfig(ConfigField),
+    GetConfig,
+    /// Acknowledges the transmission with the given sequence number
+    Ack(u16),
+    /// Set the device's clock to the given number of milliseconds since the
+    /// unix epoch
+    SetTime(u64),
+    /// Switch to new link settings after this exchange, or back to the
+    /// configured ones with `None`. Not persisted, the device falls back by
+    /// itself if the base station stops answering.
+    SetLink(Option<LinkParams>),
+    /// New firmware is on its way, the device answers this and every chunk
+    /// with a [`Message::Firmware`] saying which chunk it wants next
+    FirmwareManifest(FirmwareManifest),
+    FirmwareChunk(FirmwareChunk),
+    /// Give up on the firmware on its way
+    FirmwareCancel,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
@@ -151,17 +770,500 @@ pub enum UiCommand {
     ValveOpen,
     ValveClose,
     Reset,
+    SetConfig(ConfigField),
+    RequestConfig,
+    /// Look inside the base station's radio, the report comes back as a
+    /// [`PanelMessage::RadioReport`]
+    CheckRadio(RadioCheck),
+    /// Stop sending new firmware to the device. Firmware is uploaded as a
+    /// binary websocket message holding the image.
+    CancelFirmware,
+}
+
+/// When a message was produced and when it was put on air, both in
+/// milliseconds on the sender's clock.
+///
+/// The device's clock counts up from boot until the base station sends it the
+/// time, after which it counts from the unix epoch.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTime {
+    pub captured: u64,
+    pub sent: u64,
+    /// Whether the clock has been set by the base station
+    pub synced: bool,
+}
+
+impl FrameTime {
+    pub fn now(ms: u64, synced: bool) -> Self {
+        Self {
+            captured: ms,
+            sent: ms,
+            synced,
+        }
+    }
+
+    /// Whether this is a message being sent again after it wasn't
+    /// acknowledged the first time around
+    pub fn is_replay(&self) -> bool {
+        self.sent != self.captured
+    }
+
+    /// How long the message waited between being produced and transmitted
+    pub fn age(&self) -> Duration {
+        Duration::from_millis(self.sent.saturating_sub(self.captured))
+    }
+}
+
+/// How well a frame came through the air
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkQuality {
+    /// Signal strength in dBm
+    pub rssi: i16,
+    /// Signal to noise ratio in dB
+    pub snr: i16,
+}
+
+/// The quality of the link in both directions, as far as the base station
+/// knows
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkStatus {
+    /// The last frame the base station received from the device
+    pub uplink: Option<LinkQuality>,
+    /// The last frame the device received from the base station, as it
+    /// reported it
+    pub downlink: Option<LinkQuality>,
+}
+
+/// What the base station's radio is up to
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadioState {
+    /// Being set up for the first time
+    Starting,
+    /// Listening for the device
+    Up,
+    /// Being set up again after it failed
+    Recovering,
+    /// Given up on, the base station is on its way out
+    Failed,
+}
+
+/// How the base station's radio is holding up
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RadioHealth {
+    pub state: RadioState,
+    /// Times the radio has been set up again after failing
+    pub restarts: u32,
+    /// Errors from the radio since it was last set up
+    pub errors: u32,
+}
+
+/// Something to find out about the base station's radio when the link
+/// misbehaves
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadioCheck {
+    /// Read out the modem's registers
+    Registers,
+    /// Check the chip is there and answering properly
+    SelfTest,
+    /// Listen across the band for how loud it is with nobody transmitting
+    NoiseFloor,
+}
+
+/// The silicon revision an SX1276/7/8/9 reports
+pub const SX127X_VERSION: u8 = 0x12;
+
+/// The SX127x's registers worth reading out in LoRa mode and their names in
+/// the datasheet. The FIFO is left out, reading it moves it along.
+pub const SX127X_REGISTERS: &[(u8, &str)] = &[
+    (0x01, "RegOpMode"),
+    (0x06, "RegFrfMsb"),
+    (0x07, "RegFrfMid"),
+    (0x08, "RegFrfLsb"),
+    (0x09, "RegPaConfig"),
+    (0x0a, "RegPaRamp"),
+    (0x0b, "RegOcp"),
+    (0x0c, "RegLna"),
+    (0x0d, "RegFifoAddrPtr"),
+    (0x0e, "RegFifoTxBaseAddr"),
+    (0x0f, "RegFifoRxBaseAddr"),
+    (0x10, "RegFifoRxCurrentAddr"),
+    (0x11, "RegIrqFlagsMask"),
+    (0x12, "RegIrqFlags"),
+    (0x13, "RegRxNbBytes"),
+    (0x14, "RegRxHeaderCntValueMsb"),
+    (0x15, "RegRxHeaderCntValueLsb"),
+    (0x16, "RegRxPacketCntValueMsb"),
+    (0x17, "RegRxPacketCntValueLsb"),
+    (0x18, "RegModemStat"),
+    (0x19, "RegPktSnrValue"),
+    (0x1a, "RegPktRssiValue"),
+    (0x1b, "RegRssiValue"),
+    (0x1c, "RegHopChannel"),
+    (0x1d, "RegModemConfig1"),
+    (0x1e, "RegModemConfig2"),
+    (0x1f, "RegSymbTimeoutLsb"),
+    (0x20, "RegPreambleMsb"),
+    (0x21, "RegPreambleLsb"),
+    (0x22, "RegPayloadLength"),
+    (0x23, "RegMaxPayloadLength"),
+    (0x24, "RegHopPeriod"),
+    (0x25, "RegFifoRxByteAddr"),
+    (0x26, "RegModemConfig3"),
+    (0x28, "RegFeiMsb"),
+    (0x29, "RegFeiMid"),
+    (0x2a, "RegFeiLsb"),
+    (0x2c, "RegRssiWideband"),
+    (0x31, "RegDetectOptimize"),
+    (0x33, "RegInvertIQ"),
+    (0x37, "RegDetectionThreshold"),
+    (0x39, "RegSyncWord"),
+    (0x40, "RegDioMapping1"),
+    (0x41, "RegDioMapping2"),
+    (0x42, "RegVersion"),
+    (0x4b, "RegTcxo"),
+    (0x4d, "RegPaDac"),
+];
+
+/// What the datasheet calls the register at `addr`, if it's one we read out
+pub fn sx127x_register_name(addr: u8) -> Option<&'static str> {
+    SX127X_REGISTERS
+        .iter()
+        .find(|(a, _)| *a == addr)
+        .map(|(_, name)| *name)
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RadioRegister {
+    pub addr: u8,
+    pub value: u8,
+}
+
+/// How the base station's radio got on with its self-test
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfTest {
+    /// What the chip says its silicon revision is, nothing there at all
+    /// usually reads as 0x00 or 0xff
+    pub version: u8,
+    /// Whether a register read back what was written to it
+    pub spi: bool,
+    /// Whether the chip went into sleep and standby when told to
+    pub modes: bool,
+    /// Whether bytes written to the FIFO read back the same
+    pub fifo: bool,
+}
+
+impl SelfTest {
+    pub fn passed(&self) -> bool {
+        self.version == SX127X_VERSION && self.spi && self.modes && self.fifo
+    }
+}
+
+/// How loud a channel was with nobody transmitting on it
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoiseSample {
+    /// Carrier frequency in Hz
+    pub frequency: u32,
+    /// Average signal strength in dBm
+    pub rssi: i16,
+    /// Loudest signal strength heard in dBm
+    pub peak: i16,
+}
+
+/// What a [`RadioCheck`] found
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RadioReport {
+    Registers(heapless::Vec<RadioRegister, 64>),
+    SelfTest(SelfTest),
+    NoiseFloor {
+        /// The median of the channels' average signal strength in dBm
+        floor: i16,
+        samples: heapless::Vec<NoiseSample, 32>,
+    },
+    /// The check couldn't be run
+    Failed {
+        check: RadioCheck,
+        reason: heapless::String<96>,
+    },
+}
+
+impl RadioReport {
+    /// Which check this is the report of
+    pub fn check(&self) -> RadioCheck {
+        match self {
+            RadioReport::Registers(_) => RadioCheck::Registers,
+            RadioReport::SelfTest(_) => RadioCheck::SelfTest,
+            RadioReport::NoiseFloor { .. } => RadioCheck::NoiseFloor,
+            RadioReport::Failed { check, .. } => *check,
+        }
+    }
+}
+
+/// Frames of one kind heard on the channel over a [`ChannelStats`] window
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Traffic {
+    pub frames: u32,
+    /// How long they were on the air for altogether
+    pub airtime: Duration,
+    /// Average signal strength in dBm, if the radio said
+    pub rssi: Option<i16>,
+}
+
+/// Someone else using our channel
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Neighbour {
+    pub src: DevAddr,
+    pub traffic: Traffic,
+}
+
+/// Who the base station heard on its channel recently, and for how long.
+/// Only frames with our sync word, spreading factor and bandwidth can be
+/// heard at all.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelStats {
+    /// How far back the stats go
+    pub window: Duration,
+    /// Frames from our device
+    pub ours: Traffic,
+    /// Frames the base station sent
+    pub sent: Traffic,
+    /// Frames that decoded but came from another address
+    pub foreign: Traffic,
+    /// Frames that failed their CRC or didn't decode
+    pub undecodable: Traffic,
+    /// The other addresses heard, busiest first
+    pub neighbours: heapless::Vec<Neighbour, 8>,
+}
+
+impl ChannelStats {
+    /// Time spent on the air by everything heard and sent
+    pub fn airtime(&self) -> Duration {
+        self.ours.airtime + self.sent.airtime + self.foreign.airtime + self.undecodable.airtime
+    }
+
+    /// Percentage of the window the channel was in use
+    pub fn utilisation(&self) -> f32 {
+        if self.window.is_zero() {
+            return 0.0;
+        }
+
+        100.0 * self.airtime().as_secs_f32() / self.window.as_secs_f32()
+    }
+}
+
+/// Where a frame is going and how it's getting there
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub dst: DevAddr,
+    /// Repeaters it's been passed on by so far
+    pub hops: u8,
+    /// The repeater that should pass it on next. Frames the base station
+    /// heard through a repeater are answered through it, and the last
+    /// repeater clears it so the device knows the frame is for it to take.
+    pub via: Option<DevAddr>,
+}
+
+impl Route {
+    /// Straight to `dst`, without any repeaters
+    pub fn to(dst: DevAddr) -> Self {
+        Self {
+            dst,
+            hops: 0,
+            via: None,
+        }
+    }
+}
+
+/// The start of every [`Transmission`], which is all a repeater needs to
+/// look at to pass one on. Whatever comes after it is passed on untouched.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub src: DevAddr,
+    pub route: Route,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct Transmission<T> {
     pub src: DevAddr,
+    /// Kept straight after `src` so the two line up with [`Header`]
+    pub route: Route,
+    pub seq: u16,
+    pub time: FrameTime,
+    /// How the last frame the sender received from the other end came
+    /// through, if it knows
+    pub link: Option<LinkQuality>,
     pub msg: T,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub enum PanelMessage {
     Hello,
     Status(DeviceStatus),
     DesiredStatus(StatusFlags),
+    Watering(WateringEvent),
+    Config(DeviceConfig),
+    Diagnostics(Diagnostics),
+    /// A crash report from the device, `at` is when the device sent it in
+    /// milliseconds since the unix epoch
+    CrashReport {
+        at: u64,
+        report: CrashReport,
+    },
+    /// The latest moisture readings that made it to storage, `at` is when
+    /// they were taken in milliseconds since the unix epoch
+    Moisture {
+        at: u64,
+        report: MoistureSensorReport,
+    },
+    /// The latest BME688 readings that made it to storage, `at` is when they
+    /// were taken in milliseconds since the unix epoch
+    Bme {
+        at: u64,
+        report: BME688SensorReport,
+    },
+    Link(LinkStatus),
+    Radio(RadioHealth),
+    /// What a check of the base station's radio found, `at` is when it was
+    /// run in milliseconds since the unix epoch
+    RadioReport {
+        at: u64,
+        report: RadioReport,
+    },
+    /// How busy the channel has been, sent as frames are heard while
+    /// sniffing
+    Channel(ChannelStats),
+    Firmware(FirmwareProgress),
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn regions_parse_by_name() {
+        for (name, region) in [
+            ("eu868", Region::Eu868),
+            ("us915", Region::Us915),
+            ("as923", Region::As923),
+        ] {
+            assert_eq!(Region::from_str(name), Ok(region));
+            assert_eq!(region.plan().region, region);
+        }
+
+        assert_eq!(Region::from_str("EU868"), Err(()));
+        assert_eq!(Region::from_str(""), Err(()));
+    }
+
+    #[test]
+    fn rejects_what_the_radio_cant_do() {
+        let radio = EU868.radio;
+        assert!(radio.validate().is_ok());
+
+        for spreading_factor in [6, 13] {
+            let bad = RadioConfig {
+                spreading_factor,
+                ..radio
+            };
+            assert!(matches!(
+                bad.validate(),
+                Err(ConfigError::InvalidSpreadingFactor(sf)) if sf == spreading_factor
+            ));
+        }
+
+        for tx_power in [1, 21] {
+            let bad = RadioConfig { tx_power, ..radio };
+            assert!(matches!(
+                bad.validate(),
+                Err(ConfigError::InvalidTxPower(_))
+            ));
+        }
+
+        for frequency in [868_100_000, 100_000_000] {
+            let bad = RadioConfig { frequency, ..radio };
+            assert!(matches!(
+                bad.validate(),
+                Err(ConfigError::InvalidFrequency(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn radio_settings_have_to_be_in_the_band() {
+        let us = ConfigField::Radio(US915.radio);
+
+        let mut config = DeviceConfig::default();
+        assert!(matches!(
+            config.set(us, &EU868),
+            Err(ConfigError::OutOfBand(915_000_000))
+        ));
+        assert_eq!(config.radio, EU868.radio);
+
+        config.set(us, &US915).unwrap();
+        assert_eq!(config.radio, US915.radio);
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn firmware_received_follows_the_device() {
+        let upload = FirmwareUpload {
+            image: 7,
+            size: 1000,
+        };
+        let receiving = |image, next| FirmwareProgress {
+            upload: Some(upload),
+            device: Some(FirmwareStatus {
+                image,
+                state: FirmwareState::Receiving { next },
+            }),
+        };
+
+        assert_eq!(
+            FirmwareProgress {
+                upload: None,
+                device: None
+            }
+            .received(),
+            None
+        );
+        assert_eq!(
+            FirmwareProgress {
+                upload: Some(upload),
+                device: None
+            }
+            .received(),
+            Some(0)
+        );
+        assert_eq!(receiving(7, 3).received(), Some(3 * FIRMWARE_CHUNK as u32));
+        // the last chunk is short
+        assert_eq!(receiving(7, 8).received(), Some(1000));
+        // still on some other image
+        assert_eq!(receiving(6, 3).received(), Some(0));
+    }
+
+    #[test]
+    fn utilisation_is_a_percentage_of_the_window() {
+        let traffic = |secs| Traffic {
+            frames: 1,
+            airtime: Duration::from_secs(secs),
+            rssi: None,
+        };
+        let mut stats = ChannelStats {
+            window: Duration::from_secs(100),
+            ours: traffic(2),
+            sent: traffic(1),
+            foreign: traffic(1),
+            undecodable: traffic(1),
+            neighbours: heapless::Vec::new(),
+        };
+        assert_eq!(stats.utilisation(), 5.0);
+
+        stats.window = Duration::ZERO;
+        assert_eq!(stats.utilisation(), 0.0);
+    }
 }
@@ -0,0 +1,808 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::time::Instant as RealInstant;
+
+use color_eyre::Result;
+use garden_core::{
+    airtime::{self, Airtime, Priority},
+    bme::{BmeMonitor, Outcome},
+    clock::Clock,
+    control,
+    firmware::{self, Journal, Layout, Updater},
+    lbt::{Backoff, Clearance},
+    link::{Kept, Link},
+    outbox::{self, Outbox},
+    repeater::Distance,
+    time::Instant,
+    watering::Watering,
+};
+use garden_shared::{
+    BME688SensorReport, Command, CrashReport, DeviceConfig, Diagnostics, FirmwareState, FrameTime,
+    LinkQuality, Message, PanicLocation, ResetCause, Route, StatusFlags, Transmission,
+    WateringEvent, BASE_ADDR, EU868,
+};
+use uom::si::electrical_resistance::ohm;
+use uom::si::f32::{ElectricalResistance, Pressure, Ratio, ThermodynamicTemperature};
+use uom::si::pressure::hectopascal;
+use uom::si::ratio::percent;
+use uom::si::thermodynamic_temperature::degree_celsius;
+
+use crate::hw::{SimFlash, SimPin, SimPulses};
+use crate::radio::{self, VirtualRadio};
+use crate::script::{Action, Script};
+
+/// The shortest real time to listen for, however fast the simulation runs,
+/// so that a base station on the same machine has time to answer
+const MIN_RX_WINDOW: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// The capacity of the firmware's `broadcast_message` and `handle_msg` queues
+const QUEUE_CAPACITY: usize = 3;
+
+const DIAGNOSTICS_INTERVAL: u64 = 15 * 60 * 1000;
+
+const RTC_HZ: u64 = 32_768;
+
+type Moisture = garden_core::moisture::Moisture<SimPulses, SimPin, SimPin, SimPin, 3>;
+type Outputs = garden_core::control::Outputs<SimPin, SimPin>;
+
+/// The firmware's tasks that get scheduled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Task {
+    Moisture,
+    Bme,
+    Status,
+    Diagnostics,
+    WateringTimeout,
+    Reset,
+}
+
+/// Time in the simulation, which can run faster than real time
+pub struct SimClock {
+    start: RealInstant,
+    speed: f64,
+}
+
+impl SimClock {
+    pub fn new(speed: f64) -> Self {
+        Self {
+            start: RealInstant::now(),
+            speed,
+        }
+    }
+
+    /// Milliseconds since the simulation started
+    pub fn now(&self) -> u64 {
+        (self.start.elapsed().as_secs_f64() * self.speed * 1000.0) as u64
+    }
+
+    /// How long a stretch of simulated time lasts in real time
+    pub fn real(&self, d: std::time::Duration) -> std::time::Duration {
+        d.div_f64(self.speed)
+    }
+
+    pub fn sleep_until(&self, at: u64) {
+        let real = std::time::Duration::from_secs_f64(at as f64 / self.speed / 1000.0);
+        if let Some(wait) = real.checked_sub(self.start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+fn fmt_time(ms: u64) -> String {
+    format!("{:>9.1}s", ms as f64 / 1000.0)
+}
+
+/// What the sensors currently read
+struct Sensors {
+    moisture: Vec<f32>,
+    bme: BME688SensorReport,
+    bme_failing: bool,
+    soak_rate: f32,
+    last_update: u64,
+}
+
+impl Sensors {
+    fn new() -> Self {
+        Self {
+            moisture: vec![250.0; 3],
+            bme: bme_report(20.0, 60.0, 1013.0),
+            bme_failing: false,
+            soak_rate: 0.0,
+            last_update: 0,
+        }
+    }
+
+    /// Let the soil take up water for however long the pump has been on
+    fn update(&mut self, now: u64, pump_on: bool) {
+        let minutes = now.saturating_sub(self.last_update) as f32 / 60_000.0;
+        self.last_update = now;
+
+        if pump_on {
+            for hz in &mut self.moisture {
+                *hz = (*hz - self.soak_rate * minutes).max(0.0);
+            }
+        }
+    }
+}
+
+fn bme_report(temp: f32, humidity: f32, pressure: f32) -> BME688SensorReport {
+    BME688SensorReport {
+        temp: ThermodynamicTemperature::new::<degree_celsius>(temp),
+        pressure: Pressure::new::<hectopascal>(pressure),
+        humidity: Ratio::new::<percent>(humidity),
+        gas_resistance: ElectricalResistance::new::<ohm>(0.0),
+    }
+}
+
+/// What survives a reset: the config and firmware in flash and a panic
+/// record and the link settings in the uninitialised RAM
+#[derive(Default)]
+struct Persistent {
+    config: Option<DeviceConfig>,
+    crash: Option<CrashReport>,
+    link: Option<Kept>,
+    flash: SimFlash,
+}
+
+/// The firmware's state from one boot
+struct Device {
+    booted_at: u64,
+    reset_cause: ResetCause,
+    last_panic: Option<PanicLocation>,
+    moisture: Moisture,
+    mux: [SimPin; 3],
+    pulses: SimPulses,
+    last_step: u64,
+    status: Outputs,
+    valve: SimPin,
+    pump: SimPin,
+    watering: Watering,
+    config: DeviceConfig,
+    clock: Clock,
+    bme_monitor: BmeMonitor,
+    outbox: Outbox<32>,
+    link: Link,
+    distance: Distance,
+    bme_failures: u32,
+    dropped_messages: u32,
+    radio_errors: u32,
+    link_fallbacks: u32,
+    airtime: Airtime,
+    airtime_deferred: u32,
+    backoff: Backoff,
+    channel_deferred: u32,
+    channel_collisions: u32,
+    messages: VecDeque<Message>,
+    commands: VecDeque<Command>,
+    tasks: BinaryHeap<Reverse<(u64, Task)>>,
+    updater: Updater,
+}
+
+impl Device {
+    fn boot(now: u64, persistent: &mut Persistent, reset_cause: ResetCause) -> Self {
+        let config = persistent.config.unwrap_or_default();
+        let crash = persistent.crash.take();
+        let link = Link::resume(persistent.link.take(), &config.radio);
+
+        // what the bootloader does before the firmware starts
+        let mut journal = Journal::open(&persistent.flash, Layout::SAMD21);
+        let _ = firmware::boot(&mut persistent.flash, &mut journal);
+        let updater = Updater::open(&persistent.flash, Layout::SAMD21);
+
+        let pulses = SimPulses::default();
+        let mux = [SimPin::default(), SimPin::default(), SimPin::default()];
+        let valve = SimPin::default();
+        let pump = SimPin::default();
+
+        let mut device = Self {
+            booted_at: now,
+            reset_cause,
+            last_panic: crash.as_ref().map(|c| c.location.clone()),
+            moisture: Moisture::new(
+                pulses.clone(),
+                mux[0].clone(),
+                mux[1].clone(),
+                mux[2].clone(),
+            ),
+            mux,
+            pulses,
+            last_step: now,
+            status: Outputs::new(valve.clone(), pump.clone()),
+            valve,
+            pump,
+            watering: Watering::new(config.watering),
+            config,
+            clock: Clock::new(),
+            bme_monitor: BmeMonitor::new(),
+            outbox: Outbox::new(),
+            link,
+            distance: Distance::new(),
+            bme_failures: 0,
+            dropped_messages: 0,
+            radio_errors: 0,
+            link_fallbacks: 0,
+            airtime: Airtime::new(EU868.airtime_per_hour),
+            airtime_deferred: 0,
+            backoff: Backoff::new(radio::noise()),
+            channel_deferred: 0,
+            channel_collisions: 0,
+            messages: VecDeque::new(),
+            commands: VecDeque::new(),
+            tasks: BinaryHeap::new(),
+            updater,
+        };
+
+        device.schedule(now + 3_000, Task::Moisture);
+        device.schedule(now + 5_000, Task::Bme);
+        device.schedule(now + 10_000, Task::Status);
+        device.schedule(now + 15_000, Task::Diagnostics);
+        if let Some(crash) = crash {
+            device.broadcast(Message::CrashReport(crash));
+        }
+        device.broadcast(Message::Firmware(device.updater.status()));
+        if let Some(interval) = config.reset_interval {
+            device.schedule(now + interval.as_millis() as u64, Task::Reset);
+        }
+
+        device
+    }
+
+    fn schedule(&mut self, at: u64, task: Task) {
+        self.tasks.push(Reverse((at, task)));
+    }
+
+    fn next_task_at(&self) -> Option<u64> {
+        self.tasks.peek().map(|Reverse((at, _))| *at)
+    }
+
+    /// The RTC monotonic as the firmware would see it
+    fn instant(&self, now: u64) -> Instant {
+        Instant::from_ticks(((now - self.booted_at) * RTC_HZ / 1000) as u32)
+    }
+
+    fn broadcast(&mut self, msg: Message) {
+        if self.messages.len() == QUEUE_CAPACITY {
+            self.dropped_messages += 1;
+        } else {
+            self.messages.push_back(msg);
+        }
+    }
+
+    fn apply_watering_event(&mut self, outputs: StatusFlags, event: WateringEvent) {
+        self.status.apply_watering_event(outputs, &event);
+
+        self.broadcast(Message::Watering(event));
+        self.broadcast(Message::StatusUpdate(self.status.status()));
+    }
+}
+
+/// Runs the transmitter's tasks against simulated sensors and a virtual radio
+pub struct Simulator {
+    clock: SimClock,
+    radio: VirtualRadio,
+    script: Script,
+    sensors: Sensors,
+    persistent: Persistent,
+    device: Device,
+    last_flags: StatusFlags,
+    /// How frames from the base station come through
+    signal: LinkQuality,
+}
+
+impl Simulator {
+    pub fn new(clock: SimClock, radio: VirtualRadio, script: Script) -> Self {
+        let mut persistent = Persistent::default();
+        let device = Device::boot(clock.now(), &mut persistent, ResetCause::PowerOn);
+
+        Self {
+            clock,
+            radio,
+            script,
+            sensors: Sensors::new(),
+            persistent,
+            device,
+            last_flags: StatusFlags::empty(),
+            signal: LinkQuality { rssi: -80, snr: 7 },
+        }
+    }
+
+    fn log(&self, what: impl std::fmt::Display) {
+        println!("[{}] {}", fmt_time(self.clock.now()), what);
+    }
+
+    /// Run until the script stops or `until` milliseconds have passed
+    pub fn run(&mut self, until: Option<u64>) -> Result<()> {
+        self.log("Device booted");
+
+        loop {
+            let task_at = self.device.next_task_at();
+            let step_at = self.script.next_at();
+
+            let next = match (task_at, step_at) {
+                (Some(t), Some(s)) => t.min(s),
+                (Some(t), None) => t,
+                (None, Some(s)) => s,
+                (None, None) => return Ok(()),
+            };
+
+            if until.is_some_and(|u| next > u) {
+                return Ok(());
+            }
+
+            self.clock.sleep_until(next);
+
+            let now = self.clock.now();
+            self.sensors.update(now, self.device.pump.is_high());
+
+            if step_at == Some(next) {
+                let step = self.script.pop().unwrap();
+                if !self.apply(step.action, step.line)? {
+                    self.log("Script finished");
+                    return Ok(());
+                }
+            } else {
+                let Reverse((_, task)) = self.device.tasks.pop().unwrap();
+                self.run_task(task);
+            }
+
+            self.flush()?;
+        }
+    }
+
+    /// Apply a step of the script, returning whether to carry on
+    fn apply(&mut self, action: Action, line: u32) -> Result<bool> {
+        self.log(format_args!("Script: {:?}", action));
+
+        match action {
+            Action::Moisture(hz) => self.sensors.moisture = hz,
+            Action::Bme {
+                temp,
+                humidity,
+                pressure,
+            } => self.sensors.bme = bme_report(temp, humidity, pressure),
+            Action::BmeFailing(failing) => self.sensors.bme_failing = failing,
+            Action::Soak(rate) => self.sensors.soak_rate = rate,
+            Action::Link(up) => self.radio.set_link(up),
+            Action::Signal { rssi, snr } => self.signal = LinkQuality { rssi, snr },
+            Action::Busy(share) => self.radio.set_busy(share),
+            Action::Reset => self.reboot(ResetCause::External),
+            Action::Panic(message) => {
+                let mut file = heapless::String::new();
+                let _ = file.push_str("script");
+                let mut msg = heapless::String::new();
+                for c in message.chars() {
+                    if msg.push(c).is_err() {
+                        break;
+                    }
+                }
+
+                self.log(format_args!("Device panicked: {}", message));
+                self.persistent.crash = Some(CrashReport {
+                    location: PanicLocation {
+                        file,
+                        line,
+                        column: 0,
+                    },
+                    message: msg,
+                });
+                self.reboot(ResetCause::System);
+            }
+            Action::Stop => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn reboot(&mut self, reset_cause: ResetCause) {
+        self.log(format_args!("Device reset ({:?})", reset_cause));
+        self.device = Device::boot(self.clock.now(), &mut self.persistent, reset_cause);
+    }
+
+    fn run_task(&mut self, task: Task) {
+        let now = self.clock.now();
+
+        match task {
+            Task::Moisture => self.moisture_ticker(now),
+            Task::Bme => self.bme_task(now),
+            Task::Status => {
+                let d = &mut self.device;
+                d.broadcast(Message::StatusUpdate(d.status.status()));
+                let interval = d.config.status_interval.as_millis() as u64;
+                d.schedule(now + interval, Task::Status);
+            }
+            Task::Diagnostics => self.diagnostics_task(now),
+            Task::WateringTimeout => {
+                let d = &mut self.device;
+                let outputs = d.watering.outputs();
+                if let Some(event) = d.watering.check_timeout(d.instant(now)) {
+                    d.apply_watering_event(outputs, event);
+                }
+            }
+            Task::Reset => self.reboot(ResetCause::System),
+        }
+    }
+
+    fn moisture_ticker(&mut self, now: u64) {
+        let d = &mut self.device;
+
+        // count the oscillator's edges for however long the selected sensor
+        // has been connected
+        if d.pulses.is_enabled() {
+            let channel = d
+                .mux
+                .iter()
+                .enumerate()
+                .fold(0, |n, (bit, pin)| n | ((pin.is_high() as usize) << bit));
+            let hz = self.sensors.moisture.get(channel).copied().unwrap_or(0.0);
+            let edges = (hz as f64 * (now - d.last_step) as f64 / 1000.0) as u64;
+
+            for _ in 0..edges.min(u16::MAX as u64) {
+                d.moisture.tick_count();
+            }
+        }
+        d.last_step = now;
+
+        let interval = garden_core::time::secs(d.config.measurement_interval);
+        let delay = d.moisture.step_state(&mut (), d.instant(now), interval);
+
+        if d.moisture.is_reading_ready() {
+            let report = d.moisture.format_message();
+
+            let outputs = d.watering.outputs();
+            if let Some(event) = d.watering.on_reading(&report, d.instant(now)) {
+                if let WateringEvent::Started { .. } = event {
+                    let max_run_time = d.config.watering.max_run_time.as_millis() as u64;
+                    d.schedule(now + max_run_time, Task::WateringTimeout);
+                }
+                d.apply_watering_event(outputs, event);
+            }
+
+            d.broadcast(Message::MoistureReport(report));
+        }
+
+        d.schedule(now + delay.to_millis() as u64, Task::Moisture);
+    }
+
+    fn bme_task(&mut self, now: u64) {
+        let d = &mut self.device;
+
+        let reading = if self.sensors.bme_failing {
+            None
+        } else {
+            let mut reading = self.sensors.bme.clone();
+            reading.temp += uom::si::f32::TemperatureInterval::new::<
+                uom::si::temperature_interval::degree_celsius,
+            >(d.config.temperature_offset);
+            Some(reading)
+        };
+
+        match d.bme_monitor.on_reading(reading) {
+            Outcome::Reading(reading) => d.broadcast(Message::BME688Report(reading)),
+            Outcome::Failed => d.bme_failures += 1,
+            Outcome::Reset => {
+                self.reboot(ResetCause::System);
+                return;
+            }
+        }
+
+        let interval = d.config.bme_interval.as_millis() as u64;
+        d.schedule(now + interval, Task::Bme);
+    }
+
+    fn diagnostics_task(&mut self, now: u64) {
+        let d = &mut self.device;
+        let uptime = d.clock.uptime_ms(d.instant(now));
+
+        let mut version = heapless::String::new();
+        let _ = version.push_str(env!("CARGO_PKG_VERSION"));
+        let mut git_hash = heapless::String::new();
+        let _ = git_hash.push_str("sim");
+
+        let diagnostics = Diagnostics {
+            reset_cause: d.reset_cause,
+            uptime: std::time::Duration::from_millis(uptime),
+            version,
+            git_hash,
+            bme_failures: d.bme_failures,
+            dropped_messages: d.dropped_messages,
+            radio_errors: d.radio_errors,
+            link_fallbacks: d.link_fallbacks,
+            airtime: d.airtime.used(uptime),
+            airtime_deferred: d.airtime_deferred,
+            channel_deferred: d.channel_deferred,
+            channel_collisions: d.channel_collisions,
+            last_panic: d.last_panic.clone(),
+        };
+        d.broadcast(Message::Diagnostics(diagnostics));
+
+        d.schedule(now + DIAGNOSTICS_INTERVAL, Task::Diagnostics);
+    }
+
+    /// Send everything the tasks queued up and handle the commands that come
+    /// back, which may queue up more
+    fn flush(&mut self) -> Result<()> {
+        loop {
+            let flags = self.device.status.flags();
+            if flags != self.last_flags {
+                self.log(format_args!(
+                    "Outputs: pump {}, valve {}",
+                    if self.device.pump.is_high() {
+                        "on"
+                    } else {
+                        "off"
+                    },
+                    if self.device.valve.is_high() {
+                        "open"
+                    } else {
+                        "closed"
+                    },
+                ));
+                self.last_flags = flags;
+            }
+
+            if let Some(msg) = self.device.messages.pop_front() {
+                self.broadcast_message(msg)?;
+            } else if let Some(cmd) = self.device.commands.pop_front() {
+                self.handle_msg(cmd);
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn broadcast_message(&mut self, msg: Message) -> Result<()> {
+        let now = self.clock.now();
+        let d = &mut self.device;
+
+        let uptime = d.clock.uptime_ms(d.instant(now));
+        let seq = if outbox::is_durable(&msg) {
+            d.outbox.push(msg.clone(), uptime)
+        } else {
+            d.outbox.next_seq()
+        };
+
+        let trans = Transmission {
+            src: d.config.address,
+            route: Route::to(BASE_ADDR),
+            seq,
+            time: FrameTime::now(d.clock.stamp(uptime), d.clock.is_synced()),
+            link: d.link.take_downlink(),
+            msg,
+        };
+
+        let priority = airtime::message_priority(&trans.msg);
+        if !self.use_airtime(&trans, uptime, priority)? {
+            return Ok(());
+        }
+
+        let acked = self.exchange(&trans)?;
+
+        // the link is up, so follow up with the oldest message the base
+        // station missed
+        if !acked {
+            return Ok(());
+        }
+
+        // getting through to the base station is how new firmware shows it
+        // works
+        if let Ok(true) = self.device.updater.confirm(&mut self.persistent.flash) {
+            self.log("Firmware kept");
+            let d = &mut self.device;
+            d.broadcast(Message::Firmware(d.updater.status()));
+        }
+
+        let d = &mut self.device;
+        let trans = match d.outbox.oldest() {
+            Some(entry) => Transmission {
+                src: d.config.address,
+                route: Route::to(BASE_ADDR),
+                seq: entry.seq,
+                time: FrameTime {
+                    captured: d.clock.stamp(entry.captured),
+                    sent: d.clock.stamp(uptime),
+                    synced: d.clock.is_synced(),
+                },
+                link: d.link.take_downlink(),
+                msg: entry.msg.clone(),
+            },
+            None => return Ok(()),
+        };
+
+        if self.use_airtime(&trans, uptime, Priority::Low)? {
+            self.exchange(&trans)?;
+        }
+
+        Ok(())
+    }
+
+    /// Take the frame's airtime out of the budget if there's room for it.
+    /// Durable messages wait in the outbox, the rest are dropped.
+    fn use_airtime(
+        &mut self,
+        trans: &Transmission<Message>,
+        uptime: u64,
+        priority: Priority,
+    ) -> Result<bool> {
+        let d = &mut self.device;
+        let frame = postcard::to_stdvec(trans)?;
+        let on_air = airtime::time_on_air(&d.link.radio(&d.config.radio), frame.len());
+
+        if !d.airtime.allows(uptime, on_air, priority) {
+            d.airtime_deferred += 1;
+            self.log(format_args!("Out of airtime, holding back {:?}", trans.msg));
+            return Ok(false);
+        }
+
+        d.airtime.record(uptime, on_air);
+        Ok(true)
+    }
+
+    /// Transmit a frame once the channel is clear and listen for replies,
+    /// returning whether the base station acknowledged it
+    fn exchange(&mut self, trans: &Transmission<Message>) -> Result<bool> {
+        let frame = postcard::to_stdvec(trans)?;
+        let mut acked = false;
+        let on_air = airtime::time_on_air(
+            &self.device.link.radio(&self.device.config.radio),
+            frame.len(),
+        );
+
+        let clock = &self.clock;
+        let clearance = self.device.backoff.wait_for_clear(
+            &mut self.radio,
+            |radio| radio.channel_busy(),
+            |_, ms| std::thread::sleep(clock.real(std::time::Duration::from_millis(ms as u64))),
+        );
+        match clearance {
+            Clearance::Clear => {}
+            Clearance::Deferred => self.device.channel_deferred += 1,
+            Clearance::Busy => {
+                self.device.channel_deferred += 1;
+                self.device.channel_collisions += 1;
+            }
+        }
+
+        if clearance == Clearance::Busy {
+            // the base station hears the two of us on top of each other
+            self.log(format_args!("-> (collided) {:?}", trans));
+        } else if self.radio.is_link_up() {
+            self.log(format_args!("-> {:?}", trans));
+        } else {
+            self.log(format_args!("-> (link down) {:?}", trans));
+        }
+
+        if clearance != Clearance::Busy {
+            if let Err(e) = self.radio.transmit(&frame) {
+                self.log(format_args!("Transmit failed: {}", e));
+                self.device.radio_errors += 1;
+            }
+        }
+
+        let window = self.device.distance.reply_window(on_air);
+        let mut deadline = RealInstant::now() + self.clock.real(window).max(MIN_RX_WINDOW);
+        loop {
+            let buffer = match self.radio.receive(deadline) {
+                Ok(Some(buffer)) => buffer,
+                Ok(None) => break,
+                Err(e) => {
+                    self.log(format_args!("Receive failed: {}", e));
+                    self.device.radio_errors += 1;
+                    break;
+                }
+            };
+
+            // frames still on their way through a repeater aren't ours to
+            // take yet
+            let cmd = match postcard::from_bytes::<Transmission<Command>>(&buffer) {
+                Ok(cmd)
+                    if cmd.src == BASE_ADDR
+                        && cmd.route.dst == trans.src
+                        && cmd.route.via.is_none() =>
+                {
+                    cmd
+                }
+                _ => continue,
+            };
+
+            self.log(format_args!("<- {:?}", cmd));
+
+            let d = &mut self.device;
+            d.link.heard(self.signal);
+            // replies through repeaters are spaced out for them to keep up
+            d.distance.heard(cmd.route.hops);
+            deadline =
+                deadline.max(RealInstant::now() + self.clock.real(d.distance.round_trip(on_air)));
+            match cmd.msg {
+                Command::Ack(s) => {
+                    acked |= s == trans.seq;
+                    d.outbox.ack(s);
+                }
+                Command::SetLink(params) => {
+                    d.link.set(params, &d.config.radio);
+                }
+                msg => {
+                    if d.commands.len() < QUEUE_CAPACITY {
+                        d.commands.push_back(msg);
+                    }
+                }
+            }
+        }
+
+        let d = &mut self.device;
+        if !acked {
+            d.distance.missed();
+        }
+        let before = d.link.params();
+        if d.link.on_exchange(acked) {
+            d.link_fallbacks += 1;
+        }
+        self.persistent.link = d.link.keep(&d.config.radio);
+        if d.link.params() != before {
+            let radio = d.link.radio(&d.config.radio);
+            self.log(format_args!(
+                "Link: SF{} at {} dBm",
+                radio.spreading_factor, radio.tx_power
+            ));
+        }
+
+        Ok(acked)
+    }
+
+    fn handle_msg(&mut self, cmd: Command) {
+        let now = self.clock.now();
+        let d = &mut self.device;
+        let instant = d.instant(now);
+
+        let persistent = &mut self.persistent;
+        let flash = &mut persistent.flash;
+        let status = match &cmd {
+            Command::FirmwareManifest(manifest) => Some(d.updater.manifest(flash, manifest)),
+            Command::FirmwareChunk(chunk) => Some(d.updater.chunk(flash, chunk)),
+            Command::FirmwareCancel => Some(d.updater.cancel(flash)),
+            _ => None,
+        };
+        if let Some(status) = status {
+            d.broadcast(Message::Firmware(status));
+
+            // the bootloader takes it from here, once the base station has
+            // had a chance to hear it's all arrived
+            if status.state == FirmwareState::Verified {
+                d.schedule(now + 10_000, Task::Reset);
+            }
+            return;
+        }
+
+        let response = control::handle_command(
+            cmd,
+            &mut d.status,
+            &mut d.watering,
+            &mut d.config,
+            &EU868,
+            instant,
+            |new_config| {
+                persistent.config = Some(*new_config);
+                true
+            },
+        );
+
+        if response.reset {
+            self.reboot(ResetCause::System);
+            return;
+        }
+
+        if let Some(unix_ms) = response.set_time {
+            d.clock.set_time(instant, unix_ms);
+            return;
+        }
+
+        if let Some(event) = response.watering {
+            d.broadcast(Message::Watering(event));
+        }
+
+        if let Some(config) = response.config {
+            d.broadcast(Message::Config(config));
+        }
+
+        d.broadcast(Message::StatusUpdate(d.status.status()));
+    }
+}
@@ -0,0 +1,90 @@
+use std::cell::Cell;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use embedded_hal::digital::v2::OutputPin;
+use garden_core::nvm::{Nvm, ROW_SIZE};
+use garden_core::moisture::PulseInput;
+
+/// An output pin whose level can be watched from the outside
+#[derive(Clone, Default)]
+pub struct SimPin(Rc<Cell<bool>>);
+
+impl SimPin {
+    pub fn is_high(&self) -> bool {
+        self.0.get()
+    }
+}
+
+impl OutputPin for SimPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set(true);
+        Ok(())
+    }
+}
+
+/// The moisture oscillator input. There's always an edge waiting while it's
+/// enabled, the simulator decides how many times to count it.
+#[derive(Clone, Default)]
+pub struct SimPulses(Rc<Cell<bool>>);
+
+impl SimPulses {
+    pub fn is_enabled(&self) -> bool {
+        self.0.get()
+    }
+}
+
+impl PulseInput for SimPulses {
+    type Controller = ();
+
+    fn enable(&mut self, _controller: &mut ()) {
+        self.0.set(true);
+    }
+
+    fn disable(&mut self, _controller: &mut ()) {
+        self.0.set(false);
+    }
+
+    fn take_edge(&mut self) -> bool {
+        self.0.get()
+    }
+}
+
+/// The SAMD21's 256k of flash, erased to start with
+pub struct SimFlash(Vec<u8>);
+
+impl Default for SimFlash {
+    fn default() -> Self {
+        Self(vec![0xff; 256 * 1024])
+    }
+}
+
+impl Nvm for SimFlash {
+    type Error = Infallible;
+
+    fn read(&self, addr: u32, buf: &mut [u8]) {
+        let addr = addr as usize;
+        buf.copy_from_slice(&self.0[addr..addr + buf.len()]);
+    }
+
+    fn erase_row(&mut self, addr: u32) -> Result<(), Self::Error> {
+        let addr = addr as usize;
+        self.0[addr..addr + ROW_SIZE].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, addr: u32, data: &[u8]) -> Result<(), Self::Error> {
+        // programming only ever clears bits
+        for (old, new) in self.0[addr as usize..].iter_mut().zip(data) {
+            *old &= new;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+//! Runs the transmitter's task logic on a Linux machine, against sensors
+//! driven by a script and a radio that's really a UDP socket, so the firmware
+//! and the base station can be exercised without any hardware.
+
+pub mod device;
+pub mod hw;
+pub mod radio;
+pub mod script;
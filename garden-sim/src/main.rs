@@ -0,0 +1,86 @@
+use std::net::SocketAddr;
+
+use color_eyre::eyre::{bail, eyre};
+use color_eyre::Result;
+use garden_sim::device::{SimClock, Simulator};
+use garden_sim::radio::VirtualRadio;
+use garden_sim::script::{parse_duration, Script};
+
+const USAGE: &str = "\
+Usage: garden-sim [options]
+
+Options:
+  --script <file>    Scenario to run, see garden-sim/src/script.rs
+  --base <addr>      Where the base station's virtual radio listens [default: 127.0.0.1:8680]
+  --bind <addr>      Where to listen for replies [default: 127.0.0.1:8681]
+  --speed <n>        How many times faster than real time to run [default: 1]
+  --until <time>     Stop after this long, like 90s or 2h
+";
+
+struct Args {
+    script: Option<String>,
+    base: SocketAddr,
+    bind: SocketAddr,
+    speed: f64,
+    until: Option<u64>,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = Args {
+        script: None,
+        base: "127.0.0.1:8680".parse()?,
+        bind: "127.0.0.1:8681".parse()?,
+        speed: 1.0,
+        until: None,
+    };
+
+    let mut argv = std::env::args().skip(1);
+    while let Some(flag) = argv.next() {
+        if flag == "-h" || flag == "--help" {
+            print!("{}", USAGE);
+            std::process::exit(0);
+        }
+
+        let value = argv
+            .next()
+            .ok_or_else(|| eyre!("{} needs a value\n\n{}", flag, USAGE))?;
+
+        match flag.as_str() {
+            "--script" => args.script = Some(value),
+            "--base" => args.base = value.parse()?,
+            "--bind" => args.bind = value.parse()?,
+            "--speed" => args.speed = value.parse()?,
+            "--until" => {
+                args.until =
+                    Some(parse_duration(&value).ok_or_else(|| eyre!("Bad duration: {}", value))?)
+            }
+            _ => bail!("Unknown option {}\n\n{}", flag, USAGE),
+        }
+    }
+
+    if args.speed <= 0.0 {
+        bail!("--speed must be positive");
+    }
+
+    Ok(args)
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let args = parse_args()?;
+
+    let script = match &args.script {
+        Some(path) => Script::parse(&std::fs::read_to_string(path)?)?,
+        None => Script::default(),
+    };
+
+    let radio = VirtualRadio::new(args.bind, args.base)?;
+
+    println!(
+        "Simulating the transmitter at {}x, base station at {}",
+        args.speed, args.base
+    );
+
+    Simulator::new(SimClock::new(args.speed), radio, script).run(args.until)
+}
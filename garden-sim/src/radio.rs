@@ -0,0 +1,89 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Instant;
+
+/// Stands in for the LoRa radio, each frame is sent as a single UDP datagram
+/// to the base station and replies are read from the same socket.
+pub struct VirtualRadio {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    link_up: bool,
+    /// How much of the time a neighbour is on the air, in percent
+    busy: f32,
+}
+
+/// Stands in for the noise on the radio the firmware seeds its backoff from
+pub fn noise() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
+}
+
+impl VirtualRadio {
+    pub fn new(bind: SocketAddr, peer: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(bind)?;
+
+        Ok(Self {
+            socket,
+            peer,
+            link_up: true,
+            busy: 0.0,
+        })
+    }
+
+    /// While the link is down frames go nowhere, as if the base station was
+    /// out of range
+    pub fn set_link(&mut self, up: bool) {
+        self.link_up = up;
+    }
+
+    pub fn is_link_up(&self) -> bool {
+        self.link_up
+    }
+
+    /// Have a neighbour on the same channel for `percent` of the time
+    pub fn set_busy(&mut self, percent: f32) {
+        self.busy = percent;
+    }
+
+    /// Channel activity detection, whether a neighbour is on the air
+    pub fn channel_busy(&mut self) -> bool {
+        (noise() % 10_000) as f32 / 100.0 < self.busy
+    }
+
+    pub fn transmit(&mut self, frame: &[u8]) -> io::Result<()> {
+        if !self.link_up {
+            return Ok(());
+        }
+
+        match self.socket.send_to(frame, self.peer) {
+            Ok(_) => Ok(()),
+            // nobody listening is the same as nobody in range
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Wait for a frame until `deadline`
+    pub fn receive(&mut self, deadline: Instant) -> io::Result<Option<Vec<u8>>> {
+        let mut buffer = [0; 255];
+
+        loop {
+            let timeout = match deadline.checked_duration_since(Instant::now()) {
+                Some(t) if !t.is_zero() => t,
+                _ => return Ok(None),
+            };
+            self.socket.set_read_timeout(Some(timeout))?;
+
+            match self.socket.recv_from(&mut buffer) {
+                Ok((_, from)) if from != self.peer || !self.link_up => continue,
+                Ok((n, _)) => return Ok(Some(buffer[..n].to_vec())),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Scenarios for the simulator, one step per line:
+//!
+//! ```text
+//! # the soil starts out damp
+//! 0s    moisture 250 260 255
+//! 0s    bme 21.5 60 1013
+//! 10m   moisture 310 320 305
+//! 12m   soak 20
+//! 30m   bme fail
+//! 35m   bme ok
+//! 1h    link down
+//! 90m   link up
+//! 100m  signal -110 -8
+//! 110m  busy 30
+//! 2h    panic valve stuck
+//! 3h    stop
+//! ```
+//!
+//! Times are from the start of the simulation and take `ms`, `s`, `m` or `h`
+//! suffixes, which can be chained like `1h30m`.
+
+use std::collections::VecDeque;
+
+/// Something that happens to the simulated device
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Set the oscillator frequency of each moisture sensor, in Hz. Drier
+    /// soil oscillates faster.
+    Moisture(Vec<f32>),
+    /// Set the temperature (°C), humidity (%) and pressure (hPa) the BME688
+    /// reads, before the configured offset is applied
+    Bme {
+        temp: f32,
+        humidity: f32,
+        pressure: f32,
+    },
+    /// Make the BME688 stop or start answering
+    BmeFailing(bool),
+    /// How fast the moisture sensors fall while the pump is on, in Hz per
+    /// minute
+    Soak(f32),
+    /// Take the link to the base station down or bring it back
+    Link(bool),
+    /// Set the signal strength (dBm) and signal to noise ratio (dB) frames
+    /// from the base station arrive with
+    Signal { rssi: i16, snr: i16 },
+    /// Put a neighbour on the channel for a percentage of the time, the
+    /// device has to wait for it or collide with it
+    Busy(f32),
+    /// Reset the device, as if the reset button was pressed
+    Reset,
+    /// Panic with a message, the device resets and reports it
+    Panic(String),
+    /// End the simulation
+    Stop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// Milliseconds since the start of the simulation
+    pub at: u64,
+    /// The line of the script the step came from
+    pub line: u32,
+    pub action: Action,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("line {line}: {message}")]
+pub struct ParseError {
+    pub line: u32,
+    pub message: String,
+}
+
+/// The steps of a scenario in the order they happen
+#[derive(Debug, Default)]
+pub struct Script {
+    steps: VecDeque<Step>,
+}
+
+impl Script {
+    pub fn parse(src: &str) -> Result<Self, ParseError> {
+        let mut steps = Vec::new();
+
+        for (n, line) in src.lines().enumerate() {
+            let line_no = n as u32 + 1;
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let err = |message: &str| ParseError {
+                line: line_no,
+                message: message.to_owned(),
+            };
+
+            let mut words = line.split_whitespace();
+            let at = words
+                .next()
+                .and_then(parse_duration)
+                .ok_or_else(|| err("expected a time like 90s or 5m"))?;
+            let action = words.next().ok_or_else(|| err("expected an action"))?;
+            let args = words.collect::<Vec<_>>();
+
+            let numbers = || {
+                args.iter()
+                    .map(|a| a.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| err("expected numbers"))
+            };
+
+            let on_off = |on: &str, off: &str| match args.as_slice() {
+                [a] if *a == on => Ok(true),
+                [a] if *a == off => Ok(false),
+                _ => Err(err(&format!("expected {} or {}", on, off))),
+            };
+
+            let action = match action {
+                "moisture" => {
+                    let hz = numbers()?;
+                    if hz.is_empty() || hz.len() > 7 {
+                        return Err(err("expected between 1 and 7 frequencies"));
+                    }
+                    Action::Moisture(hz)
+                }
+                "bme" if args.len() == 1 => Action::BmeFailing(on_off("fail", "ok")?),
+                "bme" => match numbers()?.as_slice() {
+                    [temp, humidity, pressure] => Action::Bme {
+                        temp: *temp,
+                        humidity: *humidity,
+                        pressure: *pressure,
+                    },
+                    _ => return Err(err("expected temperature, humidity and pressure")),
+                },
+                "soak" => match numbers()?.as_slice() {
+                    [rate] => Action::Soak(*rate),
+                    _ => return Err(err("expected a rate in Hz per minute")),
+                },
+                "link" => Action::Link(on_off("up", "down")?),
+                "signal" => match numbers()?.as_slice() {
+                    [rssi, snr] => Action::Signal {
+                        rssi: *rssi as i16,
+                        snr: *snr as i16,
+                    },
+                    _ => return Err(err("expected rssi and snr")),
+                },
+                "busy" => match numbers()?.as_slice() {
+                    [percent] if (0.0..=100.0).contains(percent) => Action::Busy(*percent),
+                    _ => return Err(err("expected a percentage of the time")),
+                },
+                "reset" => Action::Reset,
+                "panic" => Action::Panic(args.join(" ")),
+                "stop" => Action::Stop,
+                _ => return Err(err("unknown action")),
+            };
+
+            steps.push(Step {
+                at,
+                line: line_no,
+                action,
+            });
+        }
+
+        // steps at the same time keep the order they were written in
+        steps.sort_by_key(|s| s.at);
+
+        Ok(Self {
+            steps: steps.into(),
+        })
+    }
+
+    /// When the next step is due
+    pub fn next_at(&self) -> Option<u64> {
+        self.steps.front().map(|s| s.at)
+    }
+
+    pub fn pop(&mut self) -> Option<Step> {
+        self.steps.pop_front()
+    }
+}
+
+/// Parse a duration like `500ms`, `90s`, `5m`, `2h` or `1h30m` into
+/// milliseconds
+pub fn parse_duration(mut s: &str) -> Option<u64> {
+    let mut total = 0;
+
+    while !s.is_empty() {
+        let split = s.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let (value, rest) = s.split_at(split);
+        let value = value.parse::<f64>().ok()?;
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (unit, rest) = rest.split_at(unit_len);
+
+        let scale = match unit {
+            "ms" => 1.0,
+            "s" => 1_000.0,
+            "m" => 60_000.0,
+            "h" => 3_600_000.0,
+            _ => return None,
+        };
+
+        total += (value * scale) as u64;
+        s = rest;
+    }
+
+    Some(total)
+}
@@ -1,6 +1,18 @@
 #!/bin/bash
 
+# Firmware updates over the air need garden-boot in front of the firmware, so
+# the two are put together into one image for the Feather's bootloader. The
+# firmware on its own (target/build.bin) is what `garden-cli firmware upload`
+# takes.
+
 set -e
 
+(cd ../garden-boot && cargo objcopy --release -- -O binary target/boot.bin)
 cargo objcopy --release -- -O binary target/build.bin
-bossac -i -d --port=${1:-ttyACM0} -o 0x2000 -U -e -w -v target/build.bin -R
+
+# garden-boot gets the 8K in front of the firmware
+cp ../garden-boot/target/boot.bin target/image.bin
+truncate -s 8K target/image.bin
+cat target/build.bin >> target/image.bin
+
+bossac -i -d --port=${1:-ttyACM0} -o 0x2000 -U -e -w -v target/image.bin -R
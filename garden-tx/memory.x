@@ -1,7 +1,10 @@
 MEMORY
 {
-  /* Leave 8k for the default bootloader on the Feather M0 */
-  FLASH (rx) : ORIGIN = 0x00000000 + 8K, LENGTH = 256K - 8K
+  /* The Feather M0's own bootloader takes the first 8k and garden-boot the
+     next 8k. The firmware runs from the app slot, the staging slot for over
+     the air updates, their state and the device config come after it (see
+     garden-core/src/firmware.rs and src/config.rs) */
+  FLASH (rx) : ORIGIN = 0x00000000 + 16K, LENGTH = 116K
   RAM (xrw)  : ORIGIN = 0x20000000, LENGTH = 32K
 }
 _stack_start = ORIGIN(RAM) + LENGTH(RAM);
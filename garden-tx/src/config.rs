@@ -0,0 +1,10 @@
+use garden_core::config::ConfigStore;
+
+/// The last 4k of flash, past the firmware slots and their state (see memory.x)
+const AREA_START: u32 = 0x0004_0000 - AREA_LEN;
+const AREA_LEN: u32 = 4 * 1024;
+
+/// Where the device config is kept
+pub const fn store() -> ConfigStore {
+    ConfigStore::new(AREA_START, AREA_LEN)
+}
@@ -0,0 +1,53 @@
+use atomic_polyfill::{AtomicU32, Ordering};
+use atsamd_hal::pac::PM;
+use garden_shared::ResetCause;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("GIT_HASH");
+
+/// Counts events from any task, thumbv6m doesn't have atomic read-modify-write
+/// instructions so this goes through a critical section
+pub struct Counter(AtomicU32);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub fn incr(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Failed reads of the BME688 since boot
+pub static BME_FAILURES: Counter = Counter::new();
+/// Messages that couldn't be queued for transmission
+pub static DROPPED_MESSAGES: Counter = Counter::new();
+/// Errors from the radio while transmitting or receiving
+pub static RADIO_ERRORS: Counter = Counter::new();
+/// Times the base station stopped answering on adapted link settings
+pub static LINK_FALLBACKS: Counter = Counter::new();
+/// Messages held back or dropped to stay within the band's duty cycle
+pub static AIRTIME_DEFERRED: Counter = Counter::new();
+/// Transmissions that backed off because the channel was busy
+pub static CHANNEL_DEFERRED: Counter = Counter::new();
+/// Transmissions sent into a busy channel after running out of retries
+pub static CHANNEL_COLLISIONS: Counter = Counter::new();
+
+pub fn reset_cause(pm: &PM) -> ResetCause {
+    use atsamd_hal::ResetCause as Cause;
+
+    match atsamd_hal::reset_cause(pm) {
+        Cause::POR => ResetCause::PowerOn,
+        Cause::BOD12 => ResetCause::Brownout12,
+        Cause::BOD33 => ResetCause::Brownout33,
+        Cause::External => ResetCause::External,
+        Cause::Watchdog => ResetCause::Watchdog,
+        Cause::System => ResetCause::System,
+        Cause::Unknown => ResetCause::Unknown,
+    }
+}
@@ -1,9 +1,13 @@
 #![no_main]
 #![no_std]
-#![feature(generic_const_exprs)]
+#![feature(panic_info_message)]
 
 pub mod moisture;
 pub mod bme688;
+pub mod config;
+pub mod diagnostics;
+pub mod link;
+pub mod panic;
 
 #[cfg(feature = "debugger")]
 use defmt_rtt as _;
@@ -11,10 +15,14 @@ use defmt_rtt as _;
 // #[cfg(feature = "debugger")]
 // use panic_probe as _;
 
-use panic_reset as _;
-
 use feather_m0 as _;
 
+#[panic_handler]
+fn on_panic(info: &core::panic::PanicInfo) -> ! {
+    panic::record(info);
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
 #[defmt::panic_handler]
 fn panic() -> ! {
     cortex_m::asm::udf()
@@ -0,0 +1,67 @@
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
+
+use garden_core::link::Kept;
+
+const MAGIC: u32 = 0x11CE_5A7E;
+const BODY_LEN: usize = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    magic: u32,
+    len: u32,
+    body: [u8; BODY_LEN],
+    crc: u32,
+}
+
+/// Left alone by the runtime on boot like the panic record, so the link
+/// settings survive a reset. After a power cycle it's garbage, which the
+/// magic and crc catch.
+#[link_section = ".uninit.LINK"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+fn checksum(record: &Record) -> u32 {
+    let len = core::mem::size_of::<Record>() - core::mem::size_of::<u32>();
+    let bytes = unsafe { core::slice::from_raw_parts(record as *const Record as *const u8, len) };
+    garden_shared::crc32(bytes)
+}
+
+/// Note the link settings to pick back up with after a reset
+pub fn keep(kept: Option<Kept>) {
+    let mut record = Record {
+        magic: MAGIC,
+        len: 0,
+        body: [0; BODY_LEN],
+        crc: 0,
+    };
+
+    if let Some(kept) = kept {
+        match postcard::to_slice(&kept, &mut record.body) {
+            Ok(body) => record.len = body.len() as u32,
+            Err(_) => record.magic = 0,
+        }
+    }
+    record.crc = checksum(&record);
+
+    unsafe { addr_of_mut!(RECORD).write_volatile(MaybeUninit::new(record)) };
+}
+
+/// Take the link settings from before the last reset, if there were any
+pub fn take() -> Option<Kept> {
+    let record = unsafe {
+        let record = addr_of!(RECORD).read_volatile().assume_init();
+        addr_of_mut!(RECORD).write_volatile(MaybeUninit::zeroed());
+        record
+    };
+
+    if record.magic != MAGIC
+        || record.crc != checksum(&record)
+        || record.len == 0
+        || record.len as usize > BODY_LEN
+    {
+        return None;
+    }
+
+    postcard::from_bytes(&record.body[..record.len as usize]).ok()
+}
@@ -9,15 +9,19 @@ use garden as _;
 
 use bsp::hal::watchdog::{Watchdog, WatchdogTimeout};
 use feather_m0 as bsp;
-use garden_shared::StatusFlags;
-use radio::{Receive, Transmit};
+use garden_shared::{
+    DevAddr, FrequencyPlan, LoRaBandwidth, LoRaCodingRate, RadioConfig, Role, StatusFlags,
+    PAYLOAD_CRC, PREAMBLE_LEN, SYNC_WORD,
+};
+use radio::{Receive, State as _, Transmit};
 use radio_sx127x::base::Base;
 
 use embedded_hal_compat::{Forward, ForwardCompat};
 use radio_sx127x::device::lora::{
     Bandwidth, CodingRate, FrequencyHopping, PayloadCrc, PayloadLength, SpreadingFactor,
 };
-use radio_sx127x::device::{Channel, Modem, PaConfig, PaSelect};
+use radio_sx127x::device::regs;
+use radio_sx127x::device::{Channel, Modem, PaConfig, PaSelect, State};
 use radio_sx127x::prelude::{LoRaChannel, LoRaConfig};
 
 type LoRa = radio_sx127x::Sx127x<
@@ -75,41 +79,109 @@ where
     }
 }
 
-const CONFIG_CH: LoRaChannel = LoRaChannel {
-    freq: 868_000_000,
-    bw: Bandwidth::Bw125kHz,
-    sf: SpreadingFactor::Sf7,
-    cr: CodingRate::Cr4_8,
-};
+/// The frequency plan the device starts out on until it's configured otherwise
+#[cfg(not(any(feature = "us915", feature = "as923")))]
+const PLAN: &FrequencyPlan = &garden_shared::EU868;
+#[cfg(feature = "us915")]
+const PLAN: &FrequencyPlan = &garden_shared::US915;
+#[cfg(feature = "as923")]
+const PLAN: &FrequencyPlan = &garden_shared::AS923;
+
+/// What the device starts out as until it's configured otherwise
+#[cfg(not(feature = "repeater"))]
+const ROLE: Role = Role::EndNode;
+#[cfg(feature = "repeater")]
+const ROLE: Role = Role::Repeater;
+/// Repeaters need an address of their own for frames to be sent through them
+const REPEATER_ADDR: DevAddr = DevAddr(0x70);
 
 const CONFIG_LORA: LoRaConfig = LoRaConfig {
-    preamble_len: 0x8,
+    preamble_len: PREAMBLE_LEN,
     symbol_timeout: 0x64,
     payload_len: PayloadLength::Variable,
-    payload_crc: PayloadCrc::Enabled,
+    payload_crc: if PAYLOAD_CRC {
+        PayloadCrc::Enabled
+    } else {
+        PayloadCrc::Disabled
+    },
     frequency_hop: FrequencyHopping::Disabled,
     invert_iq: false,
 };
 
-const CONFIG_PA: PaConfig = PaConfig {
-    output: PaSelect::Boost,
-    power: 15,
-};
+fn radio_config(radio: &RadioConfig) -> radio_sx127x::device::Config {
+    let channel = LoRaChannel {
+        freq: radio.frequency,
+        bw: match radio.bandwidth {
+            LoRaBandwidth::Bw125kHz => Bandwidth::Bw125kHz,
+            LoRaBandwidth::Bw250kHz => Bandwidth::Bw250kHz,
+            LoRaBandwidth::Bw500kHz => Bandwidth::Bw500kHz,
+        },
+        sf: match radio.spreading_factor {
+            8 => SpreadingFactor::Sf8,
+            9 => SpreadingFactor::Sf9,
+            10 => SpreadingFactor::Sf10,
+            11 => SpreadingFactor::Sf11,
+            12 => SpreadingFactor::Sf12,
+            _ => SpreadingFactor::Sf7,
+        },
+        cr: match radio.coding_rate {
+            LoRaCodingRate::Cr4_5 => CodingRate::Cr4_5,
+            LoRaCodingRate::Cr4_6 => CodingRate::Cr4_6,
+            LoRaCodingRate::Cr4_7 => CodingRate::Cr4_7,
+            LoRaCodingRate::Cr4_8 => CodingRate::Cr4_8,
+        },
+    };
 
-const CONFIG_RADIO: radio_sx127x::device::Config = radio_sx127x::device::Config {
-    modem: Modem::LoRa(CONFIG_LORA),
-    channel: Channel::LoRa(CONFIG_CH),
-    pa_config: CONFIG_PA,
-    xtal_freq: 32_000_000,
-    timeout_ms: 100,
-};
+    radio_sx127x::device::Config {
+        modem: Modem::LoRa(CONFIG_LORA),
+        channel: Channel::LoRa(channel),
+        pa_config: PaConfig {
+            output: PaSelect::Boost,
+            power: radio.tx_power,
+        },
+        xtal_freq: 32_000_000,
+        timeout_ms: 100,
+    }
+}
+
+/// Set the radio up for `radio` along with the framing that isn't configurable,
+/// returning whether it took
+fn configure(lora: &mut LoRa, radio: &RadioConfig) -> bool {
+    lora.configure(&radio_config(radio)).is_ok()
+        && lora.write_reg(regs::LoRa::SYNCWORD, SYNC_WORD).is_ok()
+}
+
+/// LoRa mode interrupt flags for channel activity detection, the driver only
+/// names the bits for FSK
+const IRQ_CAD_DONE: u8 = 0b0000_0100;
+const IRQ_CAD_DETECTED: u8 = 0b0000_0001;
+/// LoRa modem status bit for a frame whose header has come in, while the
+/// rest of it is still arriving
+const MODEM_HEADER_VALID: u8 = 0b0000_1000;
+
+/// Whether the radio is partway through receiving a frame
+fn receiving(lora: &mut LoRa) -> bool {
+    matches!(lora.read_reg(regs::LoRa::MODEMSTAT), Ok(stat) if stat & MODEM_HEADER_VALID != 0)
+}
+
+/// Gather a seed from noise on the radio, the low bit of the wideband RSSI is
+/// random while it's receiving
+fn radio_noise(lora: &mut LoRa) -> u32 {
+    let _ = lora.start_receive();
 
-pub struct DeviceStatus {
-    flags: StatusFlags,
-    valve_pin: Pin<PA18, PushPullOutput>,
-    pump_pin: Pin<PA16, PushPullOutput>,
+    let mut seed = 0;
+    for _ in 0..32 {
+        let rssi = lora.read_reg(regs::LoRa::RSSIWIDEBAND).unwrap_or(0);
+        seed = seed << 1 | (rssi & 1) as u32;
+    }
+
+    let _ = lora.set_state(State::Standby);
+    seed
 }
 
+type DeviceStatus =
+    garden_core::control::Outputs<Pin<PA18, PushPullOutput>, Pin<PA16, PushPullOutput>>;
+
 #[rtic::app(device = bsp::pac, peripherals = true, dispatchers = [EVSYS, USB])]
 mod app {
     use core::sync::atomic::AtomicBool;
@@ -125,25 +197,66 @@ mod app {
         timer::{TimerCounter, TimerCounter5},
     };
     use bsp::{i2c_master, periph_alias, pin_alias};
-    use garden::{bme688::Bme688, moisture::Moisture};
-    use garden_shared::{Command, DevAddr, Message, Transmission};
+    use garden::{
+        bme688::{self, Bme688},
+        config,
+        diagnostics::{
+            self, AIRTIME_DEFERRED, BME_FAILURES, CHANNEL_COLLISIONS, CHANNEL_DEFERRED,
+            DROPPED_MESSAGES, LINK_FALLBACKS, RADIO_ERRORS,
+        },
+        moisture::{Moisture, MoistureInput},
+    };
+    use garden_core::{
+        airtime::{self, Airtime, Priority},
+        bme::{BmeMonitor, Outcome},
+        clock::Clock,
+        config::ConfigStore,
+        control,
+        firmware::{Layout, Updater},
+        lbt::{Backoff, Clearance},
+        link::Link,
+        outbox::{self, Outbox},
+        repeater::{Distance, Repeater},
+        time::secs,
+        watering::Watering,
+    };
+    use garden_flash::Flash;
+    use garden_shared::{
+        Command, DeviceConfig, Diagnostics, FirmwareState, FrameTime, LinkQuality, Message,
+        PanicLocation, ResetCause, Route, Transmission, WateringEvent, BASE_ADDR,
+    };
 
     static TC5_FIRED: AtomicBool = AtomicBool::new(false);
 
+    const DIAGNOSTICS_INTERVAL: Duration = Duration::minutes(15);
+
     #[local]
     struct Local {
-        red_led: bsp::RedLed,
-        lora: LoRa,
-        lora_delay: SleepingDelay<TimerCounter5>,
         eic: EIC,
         bme: Bme688,
         wdt: Watchdog,
+        config_store: ConfigStore,
+        link: Link,
+        reset_cause: ResetCause,
+        last_panic: Option<PanicLocation>,
     }
 
     #[shared]
     struct Shared {
         moisture: Moisture<3>,
         status: DeviceStatus,
+        watering: Watering,
+        config: DeviceConfig,
+        clock: Clock,
+        airtime: Airtime,
+        // the radio is shared between our own exchanges and repeating
+        red_led: bsp::RedLed,
+        lora: LoRa,
+        lora_delay: SleepingDelay<TimerCounter5>,
+        backoff: Backoff,
+        // shared between the config and firmware updates
+        flash: Flash,
+        updater: Updater,
     }
 
     #[monotonic(binds = RTC, default = true)]
@@ -153,6 +266,10 @@ mod app {
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
         let mut p: Peripherals = cx.device;
 
+        let reset_cause = diagnostics::reset_cause(&p.PM);
+        let crash = garden::panic::take();
+        let last_panic = crash.as_ref().map(|c| c.location.clone());
+
         // do a little dance with the brownout detector
         p.SYSCTRL.bod33.write(|w| w.enable().clear_bit());
         while !p.SYSCTRL.pclksr.read().b33srdy().bit_is_set() {}
@@ -184,6 +301,23 @@ mod app {
             &mut p.SYSCTRL,
             &mut p.NVMCTRL,
         );
+        let mut flash = Flash::new(p.NVMCTRL);
+        let mut config_store = config::store();
+        let mut updater = Updater::open(&flash, Layout::SAMD21);
+        let config = config_store.load(&flash).unwrap_or_else(|| match ROLE {
+            Role::EndNode => DeviceConfig {
+                radio: PLAN.radio,
+                ..Default::default()
+            },
+            Role::Repeater => DeviceConfig {
+                address: REPEATER_ADDR,
+                role: Role::Repeater,
+                radio: PLAN.radio,
+                ..Default::default()
+            },
+        });
+        let link = Link::resume(garden::link::take(), &config.radio);
+
         let gclk1 = clocks.gclk1();
         let rtc_clock_src = clocks
             .configure_gclk_divider_and_source(ClockGenId::GCLK2, 1, ClockSource::XOSC32K, false)
@@ -209,7 +343,7 @@ mod app {
         p.PM.apbbmask.modify(|_, w| {
             w.usb_().clear_bit();
             w.dmac_().clear_bit();
-            w.nvmctrl_().clear_bit();
+            // nvmctrl stays clocked so the config can be saved
             w.dsu_().clear_bit();
             w.pac1_().clear_bit()
         });
@@ -237,16 +371,18 @@ mod app {
             pins.miso,
         );
 
-        let lora = radio_sx127x::Sx127x::spi(
+        let mut lora = radio_sx127x::Sx127x::spi(
             TransferInPlaceFwd(spi.forward()),
             pins.rfm_cs.into_readable_output().forward(),
             pins.rfm_irq.into_floating_input().forward(),
             pins.d12.into_floating_input().forward(),
             pins.rfm_reset.into_readable_output().forward(),
             delay.forward(),
-            &CONFIG_RADIO,
+            &radio_config(&link.radio(&config.radio)),
         )
         .unwrap();
+        lora.write_reg(regs::LoRa::SYNCWORD, SYNC_WORD).unwrap();
+        let backoff = Backoff::new(radio_noise(&mut lora) ^ config.address.0 as u32);
 
         let mut a0 = pins.a0.into_floating_ei();
         a0.sense(&mut eic, Sense::RISE);
@@ -255,7 +391,7 @@ mod app {
         let a2 = pins.a2.into_push_pull_output();
         let a3 = pins.a3.into_push_pull_output();
 
-        let moisture = Moisture::<3>::new(a0, a1, a2, a3);
+        let moisture = Moisture::<3>::new(MoistureInput(a0), a1, a2, a3);
 
         let i2c = i2c_master(
             &mut clocks,
@@ -265,136 +401,545 @@ mod app {
             pins.sda,
             pins.scl,
         );
-        let bme = Bme688::new(i2c, p.TC4, &tc45, &mut p.PM);
+        let bme = bme688::new(i2c, p.TC4, &tc45, &mut p.PM);
 
-        let mut valve_pin = pins.d10.into_push_pull_output();
-        let mut pump_pin = pins.d11.into_push_pull_output();
-
-        valve_pin.set_low().unwrap();
-        pump_pin.set_low().unwrap();
+        let status = DeviceStatus::new(
+            pins.d10.into_push_pull_output(),
+            pins.d11.into_push_pull_output(),
+        );
 
-        let status = DeviceStatus {
-            flags: StatusFlags::empty(),
-            valve_pin,
-            pump_pin,
-        };
+        let watering = Watering::new(config.watering);
 
-        moisture_ticker::spawn_after(Duration::secs(3)).unwrap();
-        bme_task::spawn_after(Duration::secs(5)).unwrap();
-        status_task::spawn_after(Duration::secs(10)).unwrap();
         wdt_task::spawn().unwrap();
-        reset_task::spawn_after(Duration::hours(1)).unwrap();
+        match config.role {
+            Role::EndNode => {
+                moisture_ticker::spawn_after(Duration::secs(3)).unwrap();
+                bme_task::spawn_after(Duration::secs(5)).unwrap();
+                status_task::spawn_after(Duration::secs(10)).unwrap();
+                diagnostics_task::spawn_after(Duration::secs(15)).unwrap();
+                if let Some(crash) = crash {
+                    broadcast(Message::CrashReport(crash));
+                }
+                broadcast(Message::Firmware(updater.status()));
+            }
+            // the base station only listens to its own device, so a repeater
+            // keeps to passing frames on. It never hears back to know its
+            // firmware works, so it's kept straight away.
+            Role::Repeater => {
+                let _ = updater.confirm(&mut flash);
+                repeat::spawn().unwrap();
+            }
+        }
+        if let Some(interval) = config.reset_interval {
+            reset_task::spawn_after(secs(interval)).unwrap();
+        }
 
         (
-            Shared { moisture, status },
-            Local {
+            Shared {
+                moisture,
+                status,
+                watering,
+                config,
+                clock: Clock::new(),
+                airtime: Airtime::new(PLAN.airtime_per_hour),
                 red_led,
                 lora,
                 lora_delay,
+                backoff,
+                flash,
+                updater,
+            },
+            Local {
                 eic,
                 bme,
                 wdt,
+                config_store,
+                link,
+                reset_cause,
+                last_panic,
             },
             init::Monotonics(rtc),
         )
     }
 
-    #[task(local = [lora, lora_delay, red_led], capacity = 3)]
-    fn broadcast_message(cx: broadcast_message::Context, msg: Message) {
-        let addr = DevAddr(0x69);
-        let addr_recv = DevAddr(69);
-
-        let mut buffer = [0; 255];
-
-        let trans = Transmission { src: addr, msg };
+    /// Queue a message for transmission, counting it if the queue is full
+    fn broadcast(msg: Message) {
+        if broadcast_message::spawn(msg).is_err() {
+            DROPPED_MESSAGES.incr();
+        }
+    }
 
-        let s = postcard::to_slice(&trans, &mut buffer).unwrap();
+    /// Transmit a frame once the channel is clear
+    fn transmit(
+        lora: &mut LoRa,
+        lora_delay: &mut SleepingDelay<TimerCounter5>,
+        red_led: &mut bsp::RedLed,
+        backoff: &mut Backoff,
+        frame: &[u8],
+    ) {
+        let clearance = backoff.wait_for_clear(
+            &mut (&mut *lora, &mut *lora_delay),
+            |(lora, lora_delay)| match channel_busy(lora, lora_delay) {
+                Ok(busy) => busy,
+                // better to risk it than to never send anything
+                Err(_) => {
+                    RADIO_ERRORS.incr();
+                    false
+                }
+            },
+            |(_, lora_delay), ms| lora_delay.delay_ms(ms),
+        );
+        match clearance {
+            Clearance::Clear => {}
+            Clearance::Deferred => CHANNEL_DEFERRED.incr(),
+            Clearance::Busy => {
+                CHANNEL_DEFERRED.incr();
+                CHANNEL_COLLISIONS.incr();
+            }
+        }
 
-        cx.local.red_led.set_high().unwrap();
-        cx.local.lora.start_transmit(s).unwrap();
+        red_led.set_high().unwrap();
+        if lora.start_transmit(frame).is_err() {
+            RADIO_ERRORS.incr();
+        }
 
         loop {
-            if !matches!(cx.local.lora.check_transmit(), Ok(false)) {
-                break;
+            match lora.check_transmit() {
+                Ok(false) => {}
+                Ok(true) => break,
+                Err(_) => {
+                    RADIO_ERRORS.incr();
+                    break;
+                }
             }
 
-            cx.local.lora_delay.delay_ms(10u32);
+            lora_delay.delay_ms(10u32);
         }
 
-        cx.local.red_led.set_low().unwrap();
+        red_led.set_low().unwrap();
+    }
+
+    /// Transmit a frame from `addr` and listen for the base station's replies
+    /// afterwards, returning whether it acknowledged `seq`. The radio is left
+    /// set up for the next exchange, on whatever settings `link` has settled
+    /// on.
+    #[allow(clippy::too_many_arguments)]
+    fn exchange<const N: usize>(
+        lora: &mut LoRa,
+        lora_delay: &mut SleepingDelay<TimerCounter5>,
+        red_led: &mut bsp::RedLed,
+        outbox: &mut Outbox<N>,
+        radio: &RadioConfig,
+        link: &mut Link,
+        distance: &mut Distance,
+        backoff: &mut Backoff,
+        addr: DevAddr,
+        frame: &[u8],
+        seq: u16,
+    ) -> bool {
+        let mut acked = false;
 
-        cx.local.lora.start_receive().unwrap();
+        let mut buffer = [0; 255];
 
-        for _ in 0..50 {
-            match cx.local.lora.check_receive(true) {
+        let on_air = airtime::time_on_air(&link.radio(radio), frame.len());
+        transmit(lora, lora_delay, red_led, backoff, frame);
+
+        if lora.start_receive().is_err() {
+            RADIO_ERRORS.incr();
+        }
+
+        // replies through repeaters take longer to come back, and are spaced
+        // out for the repeaters to keep up
+        let mut window = distance.reply_window(on_air).as_millis() as u32;
+        // and a long one still coming in as the window closes is waited for,
+        // firmware chunks take a while at higher spreading factors
+        let longest = airtime::time_on_air(&link.radio(radio), 255).as_millis() as u32;
+        let mut waited = 0;
+        while waited < window || (waited < window + longest && receiving(lora)) {
+            match lora.check_receive(true) {
                 Ok(true) => {
-                    if let Ok((n, _)) = cx.local.lora.get_received(&mut buffer) {
+                    if let Ok((n, info)) = lora.get_received(&mut buffer) {
                         if let Ok(cmd) = postcard::from_bytes::<Transmission<Command>>(&buffer[..n])
                         {
-                            if cmd.src == addr_recv {
-                                let _ = handle_msg::spawn(cmd.msg);
+                            // frames still on their way through a repeater
+                            // aren't ours to take yet
+                            if cmd.src == BASE_ADDR
+                                && cmd.route.dst == addr
+                                && cmd.route.via.is_none()
+                            {
+                                link.heard(LinkQuality {
+                                    rssi: info.rssi,
+                                    // always there in LoRa mode
+                                    snr: info.snr.unwrap_or_default(),
+                                });
+                                distance.heard(cmd.route.hops);
+                                window = window
+                                    .max(waited + distance.round_trip(on_air).as_millis() as u32);
+
+                                match cmd.msg {
+                                    Command::Ack(s) => {
+                                        acked |= s == seq;
+                                        outbox.ack(s);
+                                    }
+                                    // takes effect once we're done listening
+                                    Command::SetLink(params) => {
+                                        link.set(params, radio);
+                                    }
+                                    msg => {
+                                        let _ = handle_msg::spawn(msg);
+                                    }
+                                }
                             }
                         }
                     } else {
+                        RADIO_ERRORS.incr();
                         break;
                     }
                 }
                 Ok(false) => {}
                 Err(_) => {
+                    RADIO_ERRORS.incr();
                     break;
                 }
             }
 
-            cx.local.lora_delay.delay_ms(10u32);
+            lora_delay.delay_ms(10u32);
+            waited += 10;
+        }
+
+        if !acked {
+            distance.missed();
+        }
+
+        if link.on_exchange(acked) {
+            LINK_FALLBACKS.incr();
+        }
+        garden::link::keep(link.keep(radio));
+
+        if lora.reset().is_err() || !configure(lora, &link.radio(radio)) {
+            RADIO_ERRORS.incr();
+        }
+
+        // ensure we leave a gap between transmissions
+        red_led.set_high().unwrap();
+        lora_delay.delay_ms(50u32);
+        red_led.set_low().unwrap();
+
+        acked
+    }
+
+    /// Run channel activity detection, returning whether anyone else is
+    /// transmitting with our settings
+    fn channel_busy(
+        lora: &mut LoRa,
+        lora_delay: &mut SleepingDelay<TimerCounter5>,
+    ) -> Result<bool, ()> {
+        lora.write_reg(regs::LoRa::IRQFLAGS, 0xff).map_err(|_| ())?;
+        lora.set_state(State::Cad).map_err(|_| ())?;
+
+        // a couple of symbols, up to 70ms at SF12
+        for _ in 0..50 {
+            let irq = lora.read_reg(regs::LoRa::IRQFLAGS).map_err(|_| ())?;
+            if irq & IRQ_CAD_DONE != 0 {
+                lora.write_reg(regs::LoRa::IRQFLAGS, irq).map_err(|_| ())?;
+                return Ok(irq & IRQ_CAD_DETECTED != 0);
+            }
+
+            lora_delay.delay_ms(5u32);
+        }
+
+        lora.set_state(State::Standby).map_err(|_| ())?;
+        Err(())
+    }
+
+    /// Take `on_air` out of the airtime budget if there's room for it
+    fn use_airtime(
+        airtime: &mut impl rtic::Mutex<T = Airtime>,
+        uptime: u64,
+        on_air: core::time::Duration,
+        priority: Priority,
+    ) -> bool {
+        let allowed = airtime.lock(|a| {
+            let allowed = a.allows(uptime, on_air, priority);
+            if allowed {
+                a.record(uptime, on_air);
+            }
+            allowed
+        });
+
+        if !allowed {
+            AIRTIME_DEFERRED.incr();
+        }
+
+        allowed
+    }
+
+    #[task(
+        shared = [config, clock, airtime, lora, lora_delay, red_led, backoff, flash, updater],
+        local = [
+            outbox: Outbox<32> = Outbox::new(),
+            link,
+            distance: Distance = Distance::new(),
+        ],
+        capacity = 3
+    )]
+    fn broadcast_message(mut cx: broadcast_message::Context, msg: Message) {
+        let (addr, radio) = cx.shared.config.lock(|c| (c.address, c.radio));
+        let outbox = cx.local.outbox;
+
+        let (uptime, now, synced) = cx.shared.clock.lock(|c| {
+            let uptime = c.uptime_ms(monotonics::now());
+            (uptime, c.stamp(uptime), c.is_synced())
+        });
+
+        let seq = if outbox::is_durable(&msg) {
+            outbox.push(msg.clone(), uptime)
+        } else {
+            outbox.next_seq()
+        };
+
+        let mut buffer = [0; 255];
+
+        let trans = Transmission {
+            src: addr,
+            route: Route::to(BASE_ADDR),
+            seq,
+            time: FrameTime::now(now, synced),
+            // only reported once, so the base station doesn't store it twice
+            link: cx.local.link.take_downlink(),
+            msg,
+        };
+
+        let s = postcard::to_slice(&trans, &mut buffer).unwrap();
+
+        // durable messages wait in the outbox until there's airtime to catch
+        // up with, the rest are dropped
+        let on_air = airtime::time_on_air(&cx.local.link.radio(&radio), s.len());
+        let priority = airtime::message_priority(&trans.msg);
+        if !use_airtime(&mut cx.shared.airtime, uptime, on_air, priority) {
+            return;
+        }
+
+        let link = cx.local.link;
+        let distance = cx.local.distance;
+        let acked = (
+            &mut cx.shared.lora,
+            &mut cx.shared.lora_delay,
+            &mut cx.shared.red_led,
+            &mut cx.shared.backoff,
+        )
+            .lock(|lora, lora_delay, red_led, backoff| {
+                exchange(
+                    lora, lora_delay, red_led, outbox, &radio, link, distance, backoff, addr, s,
+                    seq,
+                )
+            });
+
+        // the link is up, so follow up with the oldest message the base
+        // station missed. Sending one per fresh message keeps the catch up
+        // from hogging the channel.
+        if !acked {
+            return;
+        }
+
+        // getting through to the base station is how new firmware shows it
+        // works
+        let kept = (&mut cx.shared.flash, &mut cx.shared.updater).lock(|flash, updater| {
+            updater
+                .confirm(flash)
+                .unwrap_or(false)
+                .then(|| updater.status())
+        });
+        if let Some(status) = kept {
+            broadcast(Message::Firmware(status));
         }
 
-        let _ = cx.local.lora.reset();
-        let _ = cx.local.lora.configure(&CONFIG_RADIO);
+        let trans = match outbox.oldest() {
+            Some(entry) => Transmission {
+                src: addr,
+                route: Route::to(BASE_ADDR),
+                seq: entry.seq,
+                time: cx.shared.clock.lock(|c| {
+                    let uptime = c.uptime_ms(monotonics::now());
+                    FrameTime {
+                        // the stamps are taken with the clock as it is now so
+                        // they still line up if it was set in the meantime
+                        captured: c.stamp(entry.captured),
+                        sent: c.stamp(uptime),
+                        synced: c.is_synced(),
+                    }
+                }),
+                link: link.take_downlink(),
+                msg: entry.msg.clone(),
+            },
+            None => return,
+        };
+
+        let s = postcard::to_slice(&trans, &mut buffer).unwrap();
+
+        let on_air = airtime::time_on_air(&link.radio(&radio), s.len());
+        if !use_airtime(&mut cx.shared.airtime, uptime, on_air, Priority::Low) {
+            return;
+        }
+
+        (
+            &mut cx.shared.lora,
+            &mut cx.shared.lora_delay,
+            &mut cx.shared.red_led,
+            &mut cx.shared.backoff,
+        )
+            .lock(|lora, lora_delay, red_led, backoff| {
+                exchange(
+                    lora, lora_delay, red_led, outbox, &radio, link, distance, backoff, addr, s,
+                    trans.seq,
+                )
+            });
+    }
+
+    /// Pass on frames between end nodes and the base station. The radio is
+    /// polled rather than waited on, it's left listening in between.
+    #[task(
+        shared = [config, clock, airtime, lora, lora_delay, red_led, backoff],
+        local = [repeater: Repeater = Repeater::new()]
+    )]
+    fn repeat(mut cx: repeat::Context) {
+        let (addr, radio) = cx.shared.config.lock(|c| (c.address, c.radio));
+
+        let mut frame = [0; 255];
+        let mut out = [0; 255];
+
+        // starts listening again if it wasn't
+        let received = cx.shared.lora.lock(|lora| match lora.check_receive(true) {
+            Ok(true) => match lora.get_received(&mut frame) {
+                Ok((n, _)) => Some(n),
+                Err(_) => {
+                    RADIO_ERRORS.incr();
+                    None
+                }
+            },
+            Ok(false) => None,
+            Err(_) => {
+                RADIO_ERRORS.incr();
+                None
+            }
+        });
 
-        // if let Ok(Some(msg)) = cx.local.lora.read_packet_timeout(500, cx.local.lora_delay) {
-        // }
+        let repeater = cx.local.repeater;
+        if let Some(len) = received.and_then(|n| repeater.forward(addr, &frame[..n], &mut out)) {
+            let uptime = cx.shared.clock.lock(|c| c.uptime_ms(monotonics::now()));
+            let on_air = airtime::time_on_air(&radio, len);
+
+            // somebody is waiting on whatever we pass on
+            if use_airtime(&mut cx.shared.airtime, uptime, on_air, Priority::High) {
+                (
+                    &mut cx.shared.lora,
+                    &mut cx.shared.lora_delay,
+                    &mut cx.shared.red_led,
+                    &mut cx.shared.backoff,
+                )
+                    .lock(|lora, lora_delay, red_led, backoff| {
+                        transmit(lora, lora_delay, red_led, backoff, &out[..len]);
+
+                        // the answer follows close behind
+                        if lora.start_receive().is_err() {
+                            RADIO_ERRORS.incr();
+                        }
+                    });
+            }
+        }
 
-        // // ensure we leave a gap between transmissions
-        cx.local.red_led.set_high().unwrap();
-        cx.local.lora_delay.delay_ms(50u32);
-        cx.local.red_led.set_low().unwrap();
+        repeat::spawn_after(Duration::millis(10)).unwrap();
     }
 
-    #[task(priority = 1)]
+    // the hourly reset and one into new firmware can both be waiting
+    #[task(priority = 1, capacity = 2)]
     fn reset_task(_cx: reset_task::Context) {
         // cya on the other side
         cortex_m::peripheral::SCB::sys_reset();
     }
 
-    #[task(local = [bme, fails: u8 = 0], priority = 1)]
-    fn bme_task(cx: bme_task::Context) {
-        if let Some(reading) = cx.local.bme.read() {
-            let _ = broadcast_message::spawn(Message::BME688Report(reading));
-            *cx.local.fails = 0;
-        } else {
-            *cx.local.fails += 1;
-            if *cx.local.fails > 4 {
+    #[task(shared = [config], local = [bme, monitor: BmeMonitor = BmeMonitor::new()], priority = 1)]
+    fn bme_task(mut cx: bme_task::Context) {
+        let (offset, interval) = cx
+            .shared
+            .config
+            .lock(|c| (c.temperature_offset, c.bme_interval));
+
+        match cx.local.monitor.on_reading(cx.local.bme.measure(offset)) {
+            Outcome::Reading(reading) => broadcast(Message::BME688Report(reading)),
+            Outcome::Failed => BME_FAILURES.incr(),
+            Outcome::Reset => {
+                BME_FAILURES.incr();
                 cortex_m::peripheral::SCB::sys_reset();
             }
         }
 
-        bme_task::spawn_after(Duration::secs(60)).unwrap();
+        bme_task::spawn_after(secs(interval)).unwrap();
     }
 
-    #[task(shared = [status], priority = 1)]
+    #[task(shared = [status, config], priority = 1)]
     fn status_task(mut cx: status_task::Context) {
-        let flags = cx.shared.status.lock(|s| s.flags);
+        let status = cx.shared.status.lock(|s| s.status());
+        let interval = cx.shared.config.lock(|c| c.status_interval);
+
+        broadcast(Message::StatusUpdate(status));
+
+        status_task::spawn_after(secs(interval)).unwrap();
+    }
 
-        let _ =
-            broadcast_message::spawn(Message::StatusUpdate(garden_shared::DeviceStatus { flags }));
+    #[task(shared = [clock, airtime], local = [reset_cause, last_panic], priority = 1)]
+    fn diagnostics_task(mut cx: diagnostics_task::Context) {
+        let uptime = cx.shared.clock.lock(|c| c.uptime_ms(monotonics::now()));
+        let airtime = cx.shared.airtime.lock(|a| a.used(uptime));
+
+        let mut version = heapless::String::new();
+        let _ = version.push_str(diagnostics::VERSION);
+        let mut git_hash = heapless::String::new();
+        let _ = git_hash.push_str(diagnostics::GIT_HASH);
+
+        broadcast(Message::Diagnostics(Diagnostics {
+            reset_cause: *cx.local.reset_cause,
+            uptime: core::time::Duration::from_millis(uptime),
+            version,
+            git_hash,
+            bme_failures: BME_FAILURES.get(),
+            dropped_messages: DROPPED_MESSAGES.get(),
+            radio_errors: RADIO_ERRORS.get(),
+            link_fallbacks: LINK_FALLBACKS.get(),
+            airtime,
+            airtime_deferred: AIRTIME_DEFERRED.get(),
+            channel_deferred: CHANNEL_DEFERRED.get(),
+            channel_collisions: CHANNEL_COLLISIONS.get(),
+            last_panic: cx.local.last_panic.clone(),
+        }));
+
+        diagnostics_task::spawn_after(DIAGNOSTICS_INTERVAL).unwrap();
+    }
+
+    /// Switch the outputs for a watering event and let the base station know
+    fn apply_watering_event(status: &mut DeviceStatus, outputs: StatusFlags, event: WateringEvent) {
+        status.apply_watering_event(outputs, &event);
 
-        status_task::spawn_after(Duration::secs(10)).unwrap();
+        broadcast(Message::Watering(event));
+        broadcast(Message::StatusUpdate(status.status()));
+    }
+
+    #[task(shared = [status, watering], capacity = 2)]
+    fn watering_timeout(cx: watering_timeout::Context) {
+        (cx.shared.status, cx.shared.watering).lock(|status, watering| {
+            let outputs = watering.outputs();
+            if let Some(event) = watering.check_timeout(monotonics::now()) {
+                apply_watering_event(status, outputs, event);
+            }
+        });
     }
 
-    #[task(shared = [moisture], local = [eic], priority = 2)]
+    #[task(shared = [moisture, status, watering, config], local = [eic], priority = 2)]
     fn moisture_ticker(mut cx: moisture_ticker::Context) {
+        let interval = cx.shared.config.lock(|c| secs(c.measurement_interval));
+
         let (delay, report) = cx.shared.moisture.lock(|m| {
-            let delay = m.step_state(cx.local.eic, monotonics::now());
+            let delay = m.step_state(cx.local.eic, monotonics::now(), interval);
 
             let reading = if m.is_reading_ready() {
                 Some(m.format_message())
@@ -406,38 +951,92 @@ mod app {
         });
 
         if let Some(report) = report {
-            let _ = broadcast_message::spawn(Message::MoistureReport(report));
+            (&mut cx.shared.status, &mut cx.shared.watering).lock(|status, watering| {
+                let outputs = watering.outputs();
+                if let Some(event) = watering.on_reading(&report, monotonics::now()) {
+                    if let WateringEvent::Started { .. } = event {
+                        let _ = watering_timeout::spawn_after(watering.max_run_time());
+                    }
+                    apply_watering_event(status, outputs, event);
+                }
+            });
+
+            broadcast(Message::MoistureReport(report));
         }
 
         moisture_ticker::spawn_after(delay).unwrap();
     }
 
-    #[task(shared = [status], capacity = 3)]
+    #[task(
+        shared = [status, watering, config, clock, flash, updater],
+        local = [config_store],
+        capacity = 3
+    )]
     fn handle_msg(mut cx: handle_msg::Context, cmd: Command) {
-        let flags = cx.shared.status.lock(|s| {
-            match cmd {
-                Command::SyncFlags(flags) => {
-                    let pump_state = flags.contains(StatusFlags::PUMP_ON);
-                    let valve_state = flags.contains(StatusFlags::VALVE_OPEN);
-                    s.pump_pin.set_state(pump_state.into()).unwrap();
-                    s.valve_pin.set_state(valve_state.into()).unwrap();
-                    s.flags = flags;
-                }
-                Command::Reset => {
-                    cortex_m::peripheral::SCB::sys_reset();
-                }
-            };
-            s.flags
+        if let Command::FirmwareManifest(_) | Command::FirmwareChunk(_) | Command::FirmwareCancel =
+            cmd
+        {
+            let status =
+                (&mut cx.shared.flash, &mut cx.shared.updater).lock(|flash, updater| match &cmd {
+                    Command::FirmwareManifest(manifest) => updater.manifest(flash, manifest),
+                    Command::FirmwareChunk(chunk) => updater.chunk(flash, chunk),
+                    _ => updater.cancel(flash),
+                });
+            broadcast(Message::Firmware(status));
+
+            // the bootloader takes it from here, once the base station has
+            // had a chance to hear it's all arrived
+            if status.state == FirmwareState::Verified {
+                let _ = reset_task::spawn_after(Duration::secs(10));
+            }
+            return;
+        }
+
+        let config_store = cx.local.config_store;
+        let mut flash = cx.shared.flash;
+        let mut shared = (cx.shared.status, cx.shared.watering, cx.shared.config);
+        let (status, response) = shared.lock(|s, watering, config| {
+            let response = control::handle_command(
+                cmd,
+                s,
+                watering,
+                config,
+                PLAN,
+                monotonics::now(),
+                |new_config| flash.lock(|flash| config_store.save(flash, new_config).is_ok()),
+            );
+            (s.status(), response)
         });
 
-        let _ =
-            broadcast_message::spawn(Message::StatusUpdate(garden_shared::DeviceStatus { flags }));
+        if response.reset {
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+
+        if let Some(unix_ms) = response.set_time {
+            cx.shared
+                .clock
+                .lock(|c| c.set_time(monotonics::now(), unix_ms));
+            return;
+        }
+
+        if let Some(event) = response.watering {
+            broadcast(Message::Watering(event));
+        }
+
+        if let Some(config) = response.config {
+            broadcast(Message::Config(config));
+        }
+
+        broadcast(Message::StatusUpdate(status));
     }
 
-    #[task(priority = 2, local = [wdt])]
-    fn wdt_task(cx: wdt_task::Context) {
+    #[task(priority = 2, shared = [clock], local = [wdt])]
+    fn wdt_task(mut cx: wdt_task::Context) {
         cx.local.wdt.feed();
 
+        // keeps the uptime counting past the rtc wrapping
+        cx.shared.clock.lock(|c| c.uptime_ms(monotonics::now()));
+
         let _ = wdt_task::spawn_after(Duration::millis(100));
     }
 
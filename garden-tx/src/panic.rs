@@ -0,0 +1,136 @@
+use core::fmt::Write;
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::ptr::{addr_of, addr_of_mut};
+
+use garden_shared::{CrashReport, PanicLocation};
+
+const MAGIC: u32 = 0x9A41_C0DE;
+const FILE_LEN: usize = 48;
+const MESSAGE_LEN: usize = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Record {
+    magic: u32,
+    line: u32,
+    column: u32,
+    file_len: u32,
+    file: [u8; FILE_LEN],
+    message_len: u32,
+    message: [u8; MESSAGE_LEN],
+    crc: u32,
+}
+
+/// Left alone by the runtime on boot, so it survives the reset after a panic.
+/// After a power cycle it's garbage, which the magic and crc catch.
+#[link_section = ".uninit.PANIC"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+fn checksum(record: &Record) -> u32 {
+    let len = core::mem::size_of::<Record>() - core::mem::size_of::<u32>();
+    let bytes = unsafe { core::slice::from_raw_parts(record as *const Record as *const u8, len) };
+    garden_shared::crc32(bytes)
+}
+
+/// Formats into the message buffer, dropping whatever doesn't fit rather than
+/// failing, we're in no position to handle errors
+struct MessageWriter<'a> {
+    buf: &'a mut [u8; MESSAGE_LEN],
+    len: usize,
+}
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            let mut encoded = [0; 4];
+            let encoded = c.encode_utf8(&mut encoded).as_bytes();
+
+            if self.len + encoded.len() > MESSAGE_LEN {
+                break;
+            }
+
+            self.buf[self.len..self.len + encoded.len()].copy_from_slice(encoded);
+            self.len += encoded.len();
+        }
+
+        Ok(())
+    }
+}
+
+/// Note where and why we panicked, to be picked up by [`take`] after the reset
+pub fn record(info: &PanicInfo) {
+    let mut record = Record {
+        magic: MAGIC,
+        line: 0,
+        column: 0,
+        file_len: 0,
+        file: [0; FILE_LEN],
+        message_len: 0,
+        message: [0; MESSAGE_LEN],
+        crc: 0,
+    };
+
+    if let Some(location) = info.location() {
+        let file = location.file();
+
+        // keep the end of long paths, that's the interesting bit
+        let mut start = file.len().saturating_sub(FILE_LEN);
+        while !file.is_char_boundary(start) {
+            start += 1;
+        }
+        let file = &file.as_bytes()[start..];
+
+        record.line = location.line();
+        record.column = location.column();
+        record.file_len = file.len() as u32;
+        record.file[..file.len()].copy_from_slice(file);
+    }
+
+    if let Some(message) = info.message() {
+        let mut writer = MessageWriter {
+            buf: &mut record.message,
+            len: 0,
+        };
+        let _ = writer.write_fmt(*message);
+        record.message_len = writer.len as u32;
+    }
+
+    record.crc = checksum(&record);
+
+    unsafe { addr_of_mut!(RECORD).write_volatile(MaybeUninit::new(record)) };
+}
+
+/// Take the report of the panic before the last reset, if there was one. It's
+/// cleared afterwards so it's only reported once.
+pub fn take() -> Option<CrashReport> {
+    let record = unsafe {
+        let record = addr_of!(RECORD).read_volatile().assume_init();
+        addr_of_mut!(RECORD).write_volatile(MaybeUninit::zeroed());
+        record
+    };
+
+    if record.magic != MAGIC
+        || record.crc != checksum(&record)
+        || record.file_len as usize > FILE_LEN
+        || record.message_len as usize > MESSAGE_LEN
+    {
+        return None;
+    }
+
+    let file = core::str::from_utf8(&record.file[..record.file_len as usize]).ok()?;
+    let message = core::str::from_utf8(&record.message[..record.message_len as usize]).ok()?;
+
+    let mut report = CrashReport {
+        location: PanicLocation {
+            file: heapless::String::new(),
+            line: record.line,
+            column: record.column,
+        },
+        message: heapless::String::new(),
+    };
+    report.location.file.push_str(file).ok()?;
+    report.message.push_str(message).ok()?;
+
+    Some(report)
+}